@@ -0,0 +1,236 @@
+//! Settings for `dolly agent`, which applies the manifest periodically:
+//!
+//! ```toml
+//! [agent]
+//! interval = 1800
+//! splay = 300
+//! maintenance_windows = ["* 2-4 * * sat,sun"]
+//! ```
+//!
+//! Outside every maintenance window a run only reports what it would change.
+
+use anyhow::{Context, Result, anyhow};
+use serde::Deserialize;
+use std::hash::{BuildHasher, RandomState};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AgentConfig {
+    /// Seconds between the start of two runs.
+    pub interval: u64,
+    /// Upper bound in seconds of the random delay before each run, so that a fleet of
+    /// agents does not hit shared infrastructure at the same moment.
+    pub splay: u64,
+    /// When changes may be made; no windows means always.
+    pub maintenance_windows: Vec<MaintenanceWindow>,
+}
+
+impl Default for AgentConfig {
+    fn default() -> Self {
+        Self {
+            interval: 1800,
+            splay: 0,
+            maintenance_windows: vec![],
+        }
+    }
+}
+
+impl AgentConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval)
+    }
+
+    /// A random delay between zero and the splay, different for every run.
+    pub fn jitter(&self) -> Duration {
+        match self.splay {
+            0 => Duration::ZERO,
+            splay => {
+                Duration::from_secs(RandomState::new().hash_one(SystemTime::now()) % (splay + 1))
+            }
+        }
+    }
+
+    /// Whether a run at `time` may change the system.
+    pub fn allows_changes(&self, time: SystemTime) -> bool {
+        self.maintenance_windows.is_empty()
+            || self
+                .maintenance_windows
+                .iter()
+                .any(|window| window.contains(time))
+    }
+}
+
+/// A cron-like expression of five fields, `minute hour day-of-month month day-of-week`,
+/// matching every minute that belongs to the window. Fields accept `*`, numbers, ranges
+/// `a-b`, steps `*/n` or `a-b/n` and comma-separated lists; days of the week also accept
+/// `sun` to `sat`, with Sunday as 0 or 7. Times are in UTC.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct MaintenanceWindow {
+    expression: String,
+    fields: [Vec<bool>; 5],
+}
+
+/// Name, lowest and highest value of each field.
+const FIELDS: [(&str, u32, u32); 5] = [
+    ("minute", 0, 59),
+    ("hour", 0, 23),
+    ("day of month", 1, 31),
+    ("month", 1, 12),
+    ("day of week", 0, 7),
+];
+
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+impl MaintenanceWindow {
+    pub fn contains(&self, time: SystemTime) -> bool {
+        let Ok(since_epoch) = time.duration_since(UNIX_EPOCH) else {
+            return false;
+        };
+        let seconds = since_epoch.as_secs();
+        let days = seconds / 86_400;
+        let (_, month, day) = civil_from_days(days);
+        let weekday = (days + 4) % 7; // 1970-01-01 was a Thursday.
+        let values = [
+            (seconds / 60 % 60) as u32,
+            (seconds / 3600 % 24) as u32,
+            day,
+            month,
+            weekday as u32,
+        ];
+        values
+            .iter()
+            .zip(&self.fields)
+            .all(|(value, allowed)| allowed[*value as usize])
+    }
+}
+
+impl FromStr for MaintenanceWindow {
+    type Err = anyhow::Error;
+
+    fn from_str(expression: &str) -> Result<Self> {
+        let parts: Vec<&str> = expression.split_whitespace().collect();
+        if parts.len() != FIELDS.len() {
+            return Err(anyhow!(
+                "Invalid maintenance window {expression:?}: expected 5 fields, found {}",
+                parts.len()
+            ));
+        }
+        let mut fields: [Vec<bool>; 5] = Default::default();
+        for ((part, (name, min, max)), field) in parts.iter().zip(FIELDS).zip(&mut fields) {
+            *field = parse_field(part, min, max)
+                .with_context(|| format!("Invalid {name} in maintenance window {expression:?}"))?;
+        }
+        // Sunday is both 0 and 7.
+        let sunday = fields[4][0] || fields[4][7];
+        fields[4][0] = sunday;
+        Ok(Self {
+            expression: expression.to_string(),
+            fields,
+        })
+    }
+}
+
+impl TryFrom<String> for MaintenanceWindow {
+    type Error = anyhow::Error;
+
+    fn try_from(expression: String) -> Result<Self> {
+        expression.parse()
+    }
+}
+
+/// Which values from 0 to `max` one field allows.
+fn parse_field(field: &str, min: u32, max: u32) -> Result<Vec<bool>> {
+    let mut allowed = vec![false; max as usize + 1];
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>()?),
+            None => (item, 1),
+        };
+        if step == 0 {
+            return Err(anyhow!("step must be positive"));
+        }
+        let (low, high) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((low, high)) => (parse_value(low)?, parse_value(high)?),
+            None => (parse_value(range)?, parse_value(range)?),
+        };
+        if low < min || high > max || low > high {
+            return Err(anyhow!("{item} is not within {min}-{max}"));
+        }
+        for value in (low..=high).step_by(step as usize) {
+            allowed[value as usize] = true;
+        }
+    }
+    Ok(allowed)
+}
+
+fn parse_value(value: &str) -> Result<u32> {
+    match WEEKDAYS
+        .iter()
+        .position(|day| value.eq_ignore_ascii_case(day))
+    {
+        Some(day) => Ok(day as u32),
+        None => value
+            .parse()
+            .map_err(|_| anyhow!("{value} is not a number")),
+    }
+}
+
+/// Year, month and day of the `days`th day after 1970-01-01 (Howard Hinnant's algorithm).
+fn civil_from_days(days: u64) -> (u64, u32, u32) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn test_maintenance_windows() -> Result<()> {
+        // 2024-03-02 is a Saturday; 2024-03-04 a Monday.
+        let saturday_3am = at(1_709_348_400);
+        let saturday_5am = at(1_709_355_600);
+        let monday_3am = at(1_709_521_200);
+        assert_eq!(civil_from_days(1_709_348_400 / 86_400), (2024, 3, 2));
+
+        let weekend_nights: MaintenanceWindow = "* 2-4 * * sat,sun".parse()?;
+        assert!(weekend_nights.contains(saturday_3am));
+        assert!(!weekend_nights.contains(saturday_5am));
+        assert!(!weekend_nights.contains(monday_3am));
+
+        let sunday_as_seven: MaintenanceWindow = "*/15 3 * 3 6-7".parse()?;
+        assert!(sunday_as_seven.contains(saturday_3am));
+        assert!(!sunday_as_seven.contains(monday_3am));
+
+        assert!("* * * *".parse::<MaintenanceWindow>().is_err());
+        assert!("60 * * * *".parse::<MaintenanceWindow>().is_err());
+        assert!("* * * * */0".parse::<MaintenanceWindow>().is_err());
+
+        let config = AgentConfig {
+            splay: 60,
+            maintenance_windows: vec![weekend_nights],
+            ..AgentConfig::default()
+        };
+        assert!(config.allows_changes(saturday_3am));
+        assert!(!config.allows_changes(monday_3am));
+        assert!(AgentConfig::default().allows_changes(monday_3am));
+        assert!(config.jitter() <= Duration::from_secs(60));
+        Ok(())
+    }
+}
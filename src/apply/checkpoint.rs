@@ -0,0 +1,93 @@
+use crate::Plan;
+use crate::system::FileSystem;
+use anyhow::{Context, Result, anyhow};
+use petgraph::Direction;
+use serde::{Deserialize, Serialize};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Progress of an apply run, saved after every resource so an interrupted run can resume
+/// without enforcing again what it already did.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Fingerprint of the plan the run applies; a checkpoint only resumes the same plan.
+    pub plan: u64,
+    /// Resources applied successfully, in order.
+    pub completed: Vec<String>,
+    /// The completed resources that changed something, so their notifications still fire.
+    pub changed: Vec<String>,
+    /// Resources already refreshed.
+    pub refreshed: Vec<String>,
+    /// Resources not yet applied whose dependencies are all completed.
+    pub pending: Vec<String>,
+}
+
+impl Checkpoint {
+    pub fn new(plan: &Plan) -> Self {
+        Self {
+            plan: fingerprint(plan),
+            ..Self::default()
+        }
+    }
+
+    /// Reads the checkpoint at `path`, `None` if there is none.
+    pub fn load(fs: &dyn FileSystem, path: &Path) -> Result<Option<Self>> {
+        match fs.read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json)
+                .map(Some)
+                .with_context(|| format!("Invalid checkpoint {}", path.display())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Cannot read {}", path.display())),
+        }
+    }
+
+    /// Writes the checkpoint to a temporary file renamed over `path`, so an interruption
+    /// never leaves a partial checkpoint.
+    pub fn save(&self, fs: &dyn FileSystem, path: &Path) -> Result<()> {
+        let temporary = PathBuf::from(format!("{}.tmp", path.display()));
+        fs.write(&temporary, serde_json::to_string_pretty(self)?.as_bytes())
+            .and_then(|()| fs.rename(&temporary, path))
+            .with_context(|| format!("Cannot write checkpoint {}", path.display()))
+    }
+
+    /// Fails when the checkpoint was written for a different plan.
+    pub fn check_plan(&self, plan: &Plan) -> Result<()> {
+        if self.plan != fingerprint(plan) {
+            return Err(anyhow!(
+                "The checkpoint was written for a different plan; apply without resuming"
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn is_completed(&self, id: &str) -> bool {
+        self.completed.iter().any(|completed| completed == id)
+    }
+
+    /// Records that `id` was applied and recomputes the frontier.
+    pub(super) fn complete(&mut self, plan: &Plan, id: String, changed: bool) {
+        if changed {
+            self.changed.push(id.clone());
+        }
+        self.completed.push(id);
+        let graph = plan.graph.inner();
+        self.pending = graph
+            .node_indices()
+            .filter(|index| !self.is_completed(&graph[*index].id()))
+            .filter(|index| {
+                graph
+                    .neighbors_directed(*index, Direction::Incoming)
+                    .all(|dependency| self.is_completed(&graph[dependency].id()))
+            })
+            .map(|index| graph[index].id())
+            .collect();
+        self.pending.sort();
+    }
+}
+
+fn fingerprint(plan: &Plan) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    plan.to_canonical_text().hash(&mut hasher);
+    hasher.finish()
+}
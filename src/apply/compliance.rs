@@ -0,0 +1,233 @@
+use crate::Plan;
+use crate::facts::Facts;
+use crate::plan::{Origin, Provenance};
+use crate::resources::{PropertyChange, Resource};
+use indexmap::IndexMap;
+use serde::Serialize;
+use std::fmt;
+use std::fs;
+
+/// Whether one resource matches the manifest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "status", content = "detail")]
+pub enum Compliance {
+    Compliant,
+    /// The properties that differ from the manifest.
+    NonCompliant(Vec<String>),
+    /// The resource could not be checked, which counts against compliance.
+    Unknown(String),
+}
+
+impl fmt::Display for Compliance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Compliant => write!(f, "compliant"),
+            Self::NonCompliant(changes) => write!(f, "non-compliant: {}", changes.join(", ")),
+            Self::Unknown(error) => write!(f, "unknown: {error}"),
+        }
+    }
+}
+
+/// Compliant resources out of all resources of a group.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Score {
+    pub compliant: usize,
+    pub total: usize,
+}
+
+impl Score {
+    /// The share of compliant resources, 100 for an empty group.
+    pub fn percentage(&self) -> f64 {
+        match self.total {
+            0 => 100.0,
+            total => 100.0 * self.compliant as f64 / total as f64,
+        }
+    }
+
+    fn add(&mut self, compliance: &Compliance) {
+        self.total += 1;
+        if *compliance == Compliance::Compliant {
+            self.compliant += 1;
+        }
+    }
+}
+
+impl fmt::Display for Score {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.1}% ({}/{})",
+            self.percentage(),
+            self.compliant,
+            self.total
+        )
+    }
+}
+
+/// The outcome of a compliance scan, for audits.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ComplianceReport {
+    pub host: String,
+    /// Every resource, in apply order.
+    pub resources: Vec<(String, Compliance)>,
+    /// The score of the host as a whole.
+    pub score: Score,
+    /// The score of every tag, sorted by tag.
+    pub tags: IndexMap<String, Score>,
+}
+
+impl ComplianceReport {
+    pub fn compliant(&self) -> bool {
+        self.score.compliant == self.score.total
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+}
+
+impl fmt::Display for ComplianceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (id, compliance) in &self.resources {
+            writeln!(f, "{id}: {compliance}")?;
+        }
+        writeln!(f, "host {}: {}", self.host, self.score)?;
+        for (tag, score) in &self.tags {
+            writeln!(f, "tag {tag}: {score}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The tags of a resource: its lowercased type and the classes that declared it, like
+/// Puppet's automatic tags, and the comma-separated values of its `tag` attribute. Tags
+/// thereby also score classes.
+fn tags(resource: &dyn Resource, provenance: Option<&Provenance>) -> Vec<String> {
+    let mut tags = vec![resource.rtype().to_lowercase()];
+    for origin in provenance.iter().flat_map(|provenance| &provenance.0) {
+        if let Origin::Class { name, .. } = origin
+            && !tags.contains(name)
+        {
+            tags.push(name.clone());
+        }
+    }
+    if let Some(declared) = resource.attribute("tag") {
+        for tag in declared
+            .split(',')
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+        {
+            if !tags.iter().any(|t| t == tag) {
+                tags.push(tag.to_string());
+            }
+        }
+    }
+    tags
+}
+
+/// The host a scan runs on: the `hostname` fact, or the system's host name.
+fn host(facts: &Facts) -> String {
+    facts
+        .get("hostname")
+        .map(str::to_string)
+        .or_else(|| {
+            fs::read_to_string("/etc/hostname")
+                .ok()
+                .map(|name| name.trim().to_string())
+        })
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "localhost".to_string())
+}
+
+impl Plan {
+    /// Checks every resource against the system and scores how much of it complies. Only
+    /// [`Resource::check`] is called, so a scan cannot change anything whatever the
+    /// manifest says, and nothing is refreshed.
+    pub fn compliance(&self, facts: &Facts) -> ComplianceReport {
+        let graph = self.graph.inner();
+        let mut report = ComplianceReport {
+            host: host(facts),
+            resources: vec![],
+            score: Score::default(),
+            tags: IndexMap::new(),
+        };
+        let order = self
+            .sorted()
+            .unwrap_or_else(|_| graph.node_indices().collect());
+        for index in order {
+            let resource = graph[index].as_ref();
+            let compliance = match resource.check(self.ensure(index)) {
+                Ok(changes) if changes.is_empty() => Compliance::Compliant,
+                Ok(changes) => Compliance::NonCompliant(
+                    changes.iter().map(PropertyChange::to_string).collect(),
+                ),
+                Err(e) => Compliance::Unknown(format!("{e:#}")),
+            };
+            report.score.add(&compliance);
+            for tag in tags(resource, self.provenance.get(&index)) {
+                report.tags.entry(tag).or_default().add(&compliance);
+            }
+            report.resources.push((resource.id(), compliance));
+        }
+        report.tags.sort_keys();
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_puppet_manifest;
+    use crate::parser::pp::Manifest;
+    use anyhow::Result;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_compliance_scores_without_changing() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("dolly-compliance-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("good"), "ok")?;
+        let input = format!(
+            r#"
+            file {{ "{dir}/good": content => "ok", tag => "motd" }}
+            file {{ "{dir}/missing": content => "ok", tag => "motd, ssh" }}
+            exec {{ "/bin/true": creates => "{dir}/good" }}
+            "#,
+            dir = dir.display()
+        );
+        let plan = parse_puppet_manifest(&Manifest::from_str(&input)?)?;
+        let mut facts = Facts::new();
+        facts.insert("hostname", "web1");
+        let report = plan.compliance(&facts);
+
+        assert!(
+            !dir.join("missing").exists(),
+            "A scan never changes anything"
+        );
+        assert_eq!(report.host, "web1");
+        assert_eq!(
+            report.score,
+            Score {
+                compliant: 2,
+                total: 3
+            }
+        );
+        let tags: Vec<_> = report
+            .tags
+            .iter()
+            .map(|(tag, score)| format!("{tag} {score}"))
+            .collect();
+        assert_eq!(
+            tags,
+            vec![
+                "exec 100.0% (1/1)",
+                "file 50.0% (1/2)",
+                "motd 50.0% (1/2)",
+                "ssh 0.0% (0/1)"
+            ]
+        );
+        assert!(report.to_json().contains(r#""status": "non_compliant""#));
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,195 @@
+use crate::parser::pp::{AttrValue, PuppetString};
+use anyhow::{Context, Result, anyhow};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::{env, fs};
+
+type DeferredFn = Box<dyn Fn(&[String]) -> Result<String> + Send + Sync>;
+
+/// Evaluates `Deferred(...)` attribute values on the target at apply time.
+pub struct DeferredResolver {
+    functions: HashMap<String, DeferredFn>,
+}
+
+/// The stdout of the Execs with `capture_output` that ran in this run, by title.
+fn captured() -> &'static Mutex<HashMap<String, String>> {
+    static CAPTURED: OnceLock<Mutex<HashMap<String, String>>> = OnceLock::new();
+    CAPTURED.get_or_init(Default::default)
+}
+
+/// Keeps the output of `Exec[title]` for `Deferred('exec_output', [title])`, without the
+/// trailing newlines, as shell command substitution does.
+pub fn capture_output(title: &str, stdout: &str) -> Result<()> {
+    captured()
+        .lock()
+        .map_err(|_| anyhow!("Poisoned output of Exec[{title}]"))?
+        .insert(title.to_string(), stdout.trim_end_matches('\n').to_string());
+    Ok(())
+}
+
+impl Default for DeferredResolver {
+    /// A resolver with the built-in functions `file(path)`, `env(name)` and
+    /// `exec_output(title)`, the output an Exec captured earlier in the run.
+    fn default() -> Self {
+        let mut resolver = Self::empty();
+        resolver.register("file", |args| {
+            let [path] = args else {
+                return Err(anyhow!("file expects one argument, got {}", args.len()));
+            };
+            fs::read_to_string(path).with_context(|| format!("reading {path}"))
+        });
+        resolver.register("env", |args| {
+            let [name] = args else {
+                return Err(anyhow!("env expects one argument, got {}", args.len()));
+            };
+            env::var(name).with_context(|| format!("reading environment variable {name}"))
+        });
+        resolver.register("exec_output", |args| {
+            let [title] = args else {
+                return Err(anyhow!(
+                    "exec_output expects one argument, got {}",
+                    args.len()
+                ));
+            };
+            captured()
+                .lock()
+                .map_err(|_| anyhow!("Poisoned output of Exec[{title}]"))?
+                .get(title)
+                .cloned()
+                .ok_or_else(|| anyhow!("Exec[{title}] has not captured any output in this run"))
+        });
+        resolver
+    }
+}
+
+impl DeferredResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A resolver without any functions.
+    pub fn empty() -> Self {
+        Self {
+            functions: HashMap::new(),
+        }
+    }
+
+    pub fn register(
+        &mut self,
+        name: impl Into<String>,
+        function: impl Fn(&[String]) -> Result<String> + Send + Sync + 'static,
+    ) {
+        self.functions.insert(name.into(), Box::new(function));
+    }
+
+    /// Replaces a deferred value, including deferred arguments, with its result. Other
+    /// values are returned unchanged.
+    pub fn resolve(&self, value: &AttrValue) -> Result<AttrValue> {
+        let AttrValue::Deferred { function, args } = value else {
+            return Ok(value.clone());
+        };
+        let Some(call) = self.functions.get(function) else {
+            return Err(anyhow!("Unknown deferred function: {function}"));
+        };
+        let args = args
+            .iter()
+            .map(|arg| {
+                self.resolve(arg)?
+                    .as_literal()
+                    .ok_or_else(|| anyhow!("Argument of deferred {function} is not a string"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let result = call(&args).with_context(|| format!("Deferred {function}"))?;
+        Ok(AttrValue::String(PuppetString::literal(&result)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::ApplyOptions;
+    use crate::parse_puppet_manifest;
+    use crate::parser::pp::{Manifest, PuppetExpr};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_resolve_deferred_values() -> Result<()> {
+        let secret = env::temp_dir().join("dolly-deferred-secret");
+        fs::write(&secret, "s3cret")?;
+        let input = format!(
+            r#"
+            file {{ "/etc/app.conf":
+                content => Deferred('file', ['{}']),
+                mode    => '0600',
+                owner   => Deferred('upper', [Deferred('greeting', [])]),
+                group   => Deferred('nope'),
+            }}
+        "#,
+            secret.display()
+        );
+        let manifest = Manifest::from_str(&input)?;
+        let Some(PuppetExpr::Resource { attributes, .. }) = manifest.0.first() else {
+            return Err(anyhow!("Expected a Resource variant"));
+        };
+
+        let mut resolver = DeferredResolver::new();
+        resolver.register("greeting", |_| Ok("hello".to_string()));
+        resolver.register("upper", |args| Ok(args.concat().to_uppercase()));
+
+        let resolved: Vec<_> = attributes
+            .iter()
+            .map(|attr| resolver.resolve(&attr.value))
+            .collect();
+        assert_eq!(
+            resolved[0].as_ref().ok().map(|v| v.to_string()),
+            Some("s3cret".into())
+        );
+        assert_eq!(resolved[1].as_ref().ok(), Some(&attributes[1].value));
+        assert_eq!(
+            resolved[2].as_ref().ok().map(|v| v.to_string()),
+            Some("HELLO".into())
+        );
+        assert!(resolved[3].is_err(), "Unknown functions fail at apply time");
+        fs::remove_file(secret)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_exec_output_feeds_later_resources() -> Result<()> {
+        let conf = env::temp_dir().join(format!("dolly-token-{}.conf", std::process::id()));
+        let input = format!(
+            r#"
+            file {{ "{}": content => Deferred('exec_output', ['token']) }}
+            exec {{ "token": command => "echo s3cret", capture_output => true }}
+            "#,
+            conf.display()
+        );
+        let plan = parse_puppet_manifest(&Manifest::from_str(&input)?)?;
+        let order: Vec<_> = plan.sorted_weights()?.values().map(|r| r.id()).collect();
+        assert_eq!(
+            order,
+            [
+                "Exec[token]".to_string(),
+                format!("File[{}]", conf.display())
+            ],
+            "Using the output is a dependency on the Exec"
+        );
+        let report = plan.apply(ApplyOptions::default())?;
+        assert!(!report.failed(), "{report:?}");
+        assert_eq!(fs::read_to_string(&conf)?, "s3cret");
+        fs::remove_file(&conf)?;
+
+        let input = r#"
+            file { "/tmp/x": content => Deferred('exec_output', ['token']) }
+            exec { "token": command => "echo s3cret" }
+        "#;
+        let Err(e) = parse_puppet_manifest(&Manifest::from_str(input)?) else {
+            return Err(anyhow!("The Exec does not keep its output"));
+        };
+        assert_eq!(
+            e.to_string(),
+            "File[/tmp/x] uses the output of Exec[token], which needs capture_output => true"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,885 @@
+use super::checkpoint::Checkpoint;
+use super::refresh::{RefreshRecord, RefreshTracker};
+use crate::Plan;
+use crate::cache::{ChangeKind, WatchTrigger};
+use crate::plan::{RefreshMode, Step};
+use crate::resources::{
+    ApplyContext, Ensure, ExecOutput, ExecPolicy, OutputLog, PropertyChange, Relation, Resource,
+};
+use crate::system::{Clock, System};
+use anyhow::{Context, Result, anyhow};
+use petgraph::{Direction, graph::NodeIndex, visit::EdgeRef};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::ErrorKind;
+use std::panic;
+use std::path::Path;
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// What happens to the rest of the run when a resource fails.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnFailure {
+    /// Skip everything that depends on the failed resource, apply the rest.
+    #[default]
+    SkipDependents,
+    /// Skip every resource not yet applied.
+    Stop,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ApplyOptions {
+    pub refresh: RefreshMode,
+    pub on_failure: OnFailure,
+    /// Check every resource and report what would change, without enforcing anything.
+    pub noop: bool,
+    /// Watched paths that changed outside dolly, see [`StateCache::watch_triggers`]. Each
+    /// refreshes its resource as a Notify would.
+    ///
+    /// [`StateCache::watch_triggers`]: crate::cache::StateCache::watch_triggers
+    pub watch_triggers: Vec<WatchTrigger>,
+    /// Where checkpoints are kept. Resources act through the system of the registry that
+    /// built them.
+    pub system: System,
+    /// The policy every Exec runs under, their own settings taking precedence.
+    pub exec_policy: ExecPolicy,
+    /// Receives every line the commands run while enforcing print, labelled with the
+    /// resource.
+    pub log: OutputLog,
+    /// How many resources are enforced at once, as far as their dependencies and
+    /// concurrency groups allow. Zero or one applies them one after the other.
+    pub jobs: usize,
+}
+
+/// The end of what the commands run for a resource printed.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CommandOutput {
+    pub stdout: String,
+    pub stderr: String,
+    /// Whether the start of stdout or stderr was cut.
+    pub truncated: bool,
+}
+
+impl CommandOutput {
+    /// The output of the commands run under `context`, unless none printed anything.
+    fn kept(context: &ApplyContext) -> Option<Self> {
+        let commands = context
+            .outputs
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        Self::of(&commands)
+    }
+
+    /// The output of `commands` one after the other, unless none printed anything.
+    fn of(commands: &[ExecOutput]) -> Option<Self> {
+        let output = Self {
+            stdout: commands
+                .iter()
+                .map(|output| output.stdout.as_str())
+                .collect(),
+            stderr: commands
+                .iter()
+                .map(|output| output.stderr.as_str())
+                .collect(),
+            truncated: commands.iter().any(|output| output.truncated),
+        };
+        match output.stdout.is_empty() && output.stderr.is_empty() {
+            true => None,
+            false => Some(output),
+        }
+    }
+}
+
+impl ApplyOptions {
+    /// The context resources are enforced and refreshed under, one for each.
+    fn context(&self) -> ApplyContext {
+        ApplyContext {
+            exec_policy: self.exec_policy.clone(),
+            log: self.log.clone(),
+            ..ApplyContext::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "status", content = "detail")]
+pub enum Status {
+    /// Already in the desired state, so nothing was enforced.
+    InSync,
+    Changed(Vec<PropertyChange>),
+    /// In noop mode, what enforcing would have changed.
+    WouldChange(Vec<PropertyChange>),
+    Failed(String),
+    /// Not attempted because the named resource failed.
+    Skipped {
+        failed: String,
+    },
+    /// Applied by the interrupted run this one resumes.
+    Resumed,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InSync => write!(f, "in sync"),
+            Self::Changed(changes) => write!(f, "changed {}", join(changes)),
+            Self::WouldChange(changes) => write!(f, "would change {}", join(changes)),
+            Self::Failed(error) => write!(f, "failed: {error}"),
+            Self::Skipped { failed } => write!(f, "skipped: {failed} failed"),
+            Self::Resumed => write!(f, "applied before the interruption"),
+        }
+    }
+}
+
+impl Status {
+    /// Whether dependents may proceed.
+    fn succeeded(&self) -> bool {
+        matches!(
+            self,
+            Self::InSync | Self::Changed(_) | Self::WouldChange(_) | Self::Resumed
+        )
+    }
+}
+
+fn join(changes: &[PropertyChange]) -> String {
+    let changes: Vec<_> = changes.iter().map(ToString::to_string).collect();
+    changes.join(", ")
+}
+
+/// The outcome of every resource, in the order they were visited.
+#[derive(Debug, Default, Serialize)]
+pub struct ApplyReport {
+    pub resources: Vec<(String, Status)>,
+    /// Refreshes performed, or in noop mode the ones that would have been.
+    pub refreshes: Vec<RefreshRecord>,
+    pub noop: bool,
+    /// Whether each change corrected drift or enforced new desired state, when the
+    /// report was classified against a [`crate::cache::StateCache`].
+    pub change_kinds: HashMap<String, ChangeKind>,
+    /// How long checking and enforcing each resource took, by id.
+    pub durations: HashMap<String, Duration>,
+    /// In noop mode, what the providers of the resources that would change would run or
+    /// write, by id. See [`Resource::preview`].
+    pub previews: HashMap<String, Vec<String>>,
+    /// What the commands run for each resource printed, by id, for those that printed
+    /// anything.
+    pub outputs: HashMap<String, CommandOutput>,
+    /// How long the whole run took, refreshes included.
+    pub duration: Duration,
+}
+
+impl ApplyReport {
+    /// Whether a resource or a refresh failed, or a resource was skipped.
+    pub fn failed(&self) -> bool {
+        self.resources.iter().any(|(_, status)| !status.succeeded())
+            || self.refreshes.iter().any(|refresh| refresh.error.is_some())
+    }
+
+    /// The exit code of `puppet agent --detailed-exitcodes`: 2 when something changed (or
+    /// would have in noop mode), 4 when something failed or was skipped, 6 for both and 0
+    /// when everything was in sync.
+    pub fn detailed_exit_code(&self) -> i32 {
+        let changed = if self.changes().is_empty() { 0 } else { 2 };
+        let failed = if self.failed() { 4 } else { 0 };
+        changed | failed
+    }
+
+    /// Resources that changed, or would have in noop mode, with their changes.
+    pub fn changes(&self) -> Vec<(&str, &[PropertyChange])> {
+        self.resources
+            .iter()
+            .filter_map(|(id, status)| match status {
+                Status::Changed(changes) | Status::WouldChange(changes) => {
+                    Some((id.as_str(), changes.as_slice()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The diffs of the changes that have one, by resource, as `--show-diff` prints them.
+    pub fn diffs(&self) -> Vec<(&str, &str)> {
+        self.changes()
+            .into_iter()
+            .flat_map(|(id, changes)| {
+                changes
+                    .iter()
+                    .filter_map(move |change| Some((id, change.diff.as_deref()?)))
+            })
+            .collect()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+
+    /// How many changes were of `kind`.
+    pub fn count(&self, kind: ChangeKind) -> usize {
+        self.change_kinds.values().filter(|k| **k == kind).count()
+    }
+}
+
+impl fmt::Display for ApplyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (id, status) in &self.resources {
+            match self.change_kinds.get(id) {
+                Some(kind) => writeln!(f, "{id}: {status} ({kind})")?,
+                None => writeln!(f, "{id}: {status}")?,
+            }
+            for step in self.previews.get(id).into_iter().flatten() {
+                writeln!(f, "  {step}")?;
+            }
+        }
+        let refreshed = if self.noop {
+            "would be refreshed"
+        } else {
+            "refreshed"
+        };
+        for refresh in &self.refreshes {
+            match &refresh.error {
+                Some(error) => writeln!(
+                    f,
+                    "{}: refresh by {} failed: {error}",
+                    refresh.id,
+                    refresh.triggered_by.join(", ")
+                )?,
+                None => writeln!(
+                    f,
+                    "{}: {refreshed} by {}",
+                    refresh.id,
+                    refresh.triggered_by.join(", ")
+                )?,
+            }
+        }
+        if !self.change_kinds.is_empty() {
+            writeln!(
+                f,
+                "changes: {} corrective, {} intentional",
+                self.count(ChangeKind::Corrective),
+                self.count(ChangeKind::Intentional)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Plan {
+    /// Checks every resource in dependency order and enforces the ones out of sync. A
+    /// failing resource does not abort the run; its dependents (or, with
+    /// [`OnFailure::Stop`], everything after it) are skipped and the report says why. Only
+    /// a broken plan is an error. In noop mode nothing is enforced or refreshed.
+    pub fn apply(&self, options: ApplyOptions) -> Result<ApplyReport> {
+        self.run(options, None)
+    }
+
+    /// Like [`Plan::apply`], saving progress to the checkpoint at `path` after every
+    /// resource. With `resume`, continues the run recorded there without applying again
+    /// what it completed. The checkpoint is removed once a run has no failures.
+    pub fn apply_with_checkpoint(
+        &self,
+        options: ApplyOptions,
+        path: &Path,
+        resume: bool,
+    ) -> Result<ApplyReport> {
+        let mut checkpoint = match resume {
+            true => {
+                let checkpoint =
+                    Checkpoint::load(options.system.fs.as_ref(), path)?.ok_or_else(|| {
+                        anyhow!("There is no checkpoint to resume at {}", path.display())
+                    })?;
+                checkpoint.check_plan(self)?;
+                checkpoint
+            }
+            false => Checkpoint::new(self),
+        };
+        let fs = options.system.fs.clone();
+        let report = self.run(options, Some((&mut checkpoint, path)))?;
+        if !report.failed() {
+            match fs.remove_file(path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => {
+                    return Err(e).with_context(|| format!("Cannot remove {}", path.display()));
+                }
+                _ => {}
+            }
+        }
+        Ok(report)
+    }
+
+    fn run(
+        &self,
+        options: ApplyOptions,
+        mut checkpoint: Option<(&mut Checkpoint, &Path)>,
+    ) -> Result<ApplyReport> {
+        let graph = self.graph.inner();
+        let mut statuses: HashMap<NodeIndex, Status> = HashMap::new();
+        let mut stopped_by: Option<String> = None;
+        let mut refreshes = RefreshTracker::new();
+        let clock = options.system.clock.clone();
+        let started = clock.now();
+        let mut report = ApplyReport {
+            noop: options.noop,
+            ..ApplyReport::default()
+        };
+        for trigger in &options.watch_triggers {
+            if let Some(index) = self.node(&trigger.id) {
+                refreshes.notify(index, trigger.source());
+            }
+        }
+
+        let steps = match options.jobs {
+            0 | 1 => self.schedule(options.refresh)?,
+            _ => self.parallel_schedule(options.refresh)?,
+        };
+        for step in steps {
+            let batch = match step {
+                Step::Apply(index) => vec![index],
+                Step::ApplyBatch(batch) => batch,
+                Step::Refresh { target, .. } => {
+                    if !statuses.get(&target).is_some_and(Status::succeeded) {
+                        continue;
+                    }
+                    let id = graph[target].id();
+                    let Some(mut record) = refreshes.take(target, id.clone()) else {
+                        continue;
+                    };
+                    if checkpoint
+                        .as_ref()
+                        .is_some_and(|(checkpoint, _)| checkpoint.refreshed.contains(&id))
+                    {
+                        continue;
+                    }
+                    if !options.noop {
+                        let context = options.context();
+                        match graph[target].refresh_in(&context) {
+                            Ok(refreshed) => record.changes = refreshed.changes,
+                            Err(e) => record.error = Some(format!("{e:#}")),
+                        }
+                        record.output = CommandOutput::kept(&context);
+                    }
+                    // A failed refresh is left for the resumed run to retry.
+                    if let Some((checkpoint, path)) = &mut checkpoint
+                        && record.error.is_none()
+                    {
+                        checkpoint.refreshed.push(id);
+                        checkpoint.save(options.system.fs.as_ref(), path)?;
+                    }
+                    report.refreshes.push(record);
+                    continue;
+                }
+            };
+
+            // Skipped and resumed resources are settled up front, the others enforced
+            // together.
+            let settled: Vec<_> = batch
+                .iter()
+                .map(|&index| {
+                    let upstream_failure = graph
+                        .neighbors_directed(index, Direction::Incoming)
+                        .find_map(|dependency| match statuses.get(&dependency)? {
+                            Status::Failed(_) => Some(graph[dependency].id()),
+                            Status::Skipped { failed } => Some(failed.clone()),
+                            _ => None,
+                        });
+                    let resumed = checkpoint
+                        .as_ref()
+                        .is_some_and(|(checkpoint, _)| checkpoint.is_completed(&graph[index].id()));
+                    match upstream_failure.or_else(|| stopped_by.clone()) {
+                        Some(failed) => Some(Status::Skipped { failed }),
+                        None if resumed => Some(Status::Resumed),
+                        None => None,
+                    }
+                })
+                .collect();
+            let pending: Vec<_> = batch
+                .iter()
+                .zip(&settled)
+                .filter(|(_, status)| status.is_none())
+                .map(|(index, _)| *index)
+                .collect();
+            let mut enforced = self.enforce_all(&pending, &options).into_iter();
+
+            for (index, settled) in batch.into_iter().zip(settled) {
+                let resource = &graph[index];
+                let id = resource.id();
+                let resumed = settled == Some(Status::Resumed);
+                let status = match settled {
+                    Some(status) => {
+                        report.durations.insert(id.clone(), Duration::ZERO);
+                        status
+                    }
+                    None => {
+                        let enforced = enforced
+                            .next()
+                            .ok_or_else(|| anyhow!("{id} was not enforced"))?;
+                        report.durations.insert(id.clone(), enforced.duration);
+                        if let Some(output) = enforced.output {
+                            report.outputs.insert(id.clone(), output);
+                        }
+                        if !enforced.preview.is_empty() {
+                            report.previews.insert(id.clone(), enforced.preview);
+                        }
+                        enforced.status
+                    }
+                };
+                let changed_before = resumed
+                    && checkpoint
+                        .as_ref()
+                        .is_some_and(|(checkpoint, _)| checkpoint.changed.contains(&id));
+                match &status {
+                    Status::Changed(_) | Status::WouldChange(_) | Status::Resumed
+                        if !resumed || changed_before =>
+                    {
+                        for edge in graph.edges_directed(index, Direction::Outgoing) {
+                            if matches!(edge.weight(), Relation::Notify) {
+                                refreshes.notify(edge.target(), resource.id());
+                            }
+                        }
+                    }
+                    Status::Failed(_) if options.on_failure == OnFailure::Stop => {
+                        stopped_by = Some(resource.id());
+                    }
+                    _ => {}
+                }
+                if let Some((checkpoint, path)) = &mut checkpoint
+                    && !options.noop
+                    && matches!(status, Status::InSync | Status::Changed(_))
+                {
+                    checkpoint.complete(self, id.clone(), status != Status::InSync);
+                    checkpoint.save(options.system.fs.as_ref(), path)?;
+                }
+                report.resources.push((id, status.clone()));
+                statuses.insert(index, status);
+            }
+        }
+        report.duration = elapsed(clock.as_ref(), started);
+        Ok(report)
+    }
+}
+
+/// The outcome of enforcing one resource.
+struct Enforced {
+    status: Status,
+    duration: Duration,
+    output: Option<CommandOutput>,
+    /// In noop mode, what the provider would do.
+    preview: Vec<String>,
+}
+
+impl Plan {
+    /// Enforces the resources at `indices` on up to `options.jobs` threads, returning
+    /// their outcomes in the same order.
+    fn enforce_all(&self, indices: &[NodeIndex], options: &ApplyOptions) -> Vec<Enforced> {
+        // The plan itself is not shared between threads, only its graph.
+        let graph = self.graph.inner();
+        let enforce_one = |(index, ensure): (NodeIndex, Ensure)| {
+            let resource = graph[index].as_ref();
+            let started = options.system.clock.now();
+            let context = options.context();
+            let status = enforce(resource, ensure.clone(), &context, options.noop);
+            let preview = match status {
+                Status::WouldChange(_) => resource.preview(ensure),
+                _ => vec![],
+            };
+            Enforced {
+                status,
+                duration: elapsed(options.system.clock.as_ref(), started),
+                output: CommandOutput::kept(&context),
+                preview,
+            }
+        };
+        let work = indices.iter().map(|index| (*index, self.ensure(*index)));
+        let jobs = options.jobs.clamp(1, indices.len().max(1));
+        if jobs == 1 {
+            return work.map(enforce_one).collect();
+        }
+        let queue = Mutex::new(work.collect::<Vec<_>>().into_iter().enumerate());
+        let mut enforced: Vec<_> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..jobs)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut done = vec![];
+                        loop {
+                            let next = queue.lock().unwrap_or_else(PoisonError::into_inner).next();
+                            let Some((position, work)) = next else {
+                                return done;
+                            };
+                            done.push((position, enforce_one(work)));
+                        }
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|worker| {
+                    worker
+                        .join()
+                        .unwrap_or_else(|panic| panic::resume_unwind(panic))
+                })
+                .collect()
+        });
+        enforced.sort_by_key(|(position, _)| *position);
+        enforced.into_iter().map(|(_, enforced)| enforced).collect()
+    }
+}
+
+fn elapsed(clock: &dyn Clock, since: SystemTime) -> Duration {
+    clock.now().duration_since(since).unwrap_or_default()
+}
+
+fn enforce(resource: &dyn Resource, ensure: Ensure, context: &ApplyContext, noop: bool) -> Status {
+    let changes = match resource.check_in(ensure.clone(), context) {
+        Ok(changes) => changes,
+        Err(e) => return Status::Failed(format!("{e:#}")),
+    };
+    if changes.is_empty() {
+        return Status::InSync;
+    }
+    if noop {
+        return Status::WouldChange(changes);
+    }
+    match resource.ensure_in(ensure, context) {
+        Ok(report) if report.changed() => Status::Changed(report.changes),
+        Ok(_) => Status::InSync,
+        Err(e) => Status::Failed(format!("{e:#}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_puppet_manifest;
+    use crate::parser::pp::Manifest;
+    use crate::system::HostFileSystem;
+    use std::fs;
+    use std::str::FromStr;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_apply_skips_dependents_of_failures() -> Result<()> {
+        let input = r#"
+            exec { "/bin/true": }
+            exec { "/bin/false": }
+            exec { "echo after false": }
+            exec { "echo after skipped": }
+            exec { "echo refreshed": }
+            Exec["/bin/false"] -> Exec["echo after false"] -> Exec["echo after skipped"]
+            Exec["/bin/true"] ~> Exec["echo refreshed"]
+        "#;
+        let plan = parse_puppet_manifest(&Manifest::from_str(input)?)?;
+        let report = plan.apply(ApplyOptions::default())?;
+        let statuses: HashMap<_, _> = report.resources.iter().cloned().collect();
+        let ran = Status::Changed(vec![PropertyChange::new("returns", Some("notrun"), 0)]);
+        assert_eq!(statuses["Exec[/bin/true]"], ran);
+        assert_eq!(statuses["Exec[echo refreshed]"], ran);
+        assert!(matches!(statuses["Exec[/bin/false]"], Status::Failed(_)));
+        let skipped = Status::Skipped {
+            failed: "Exec[/bin/false]".to_string(),
+        };
+        assert_eq!(statuses["Exec[echo after false]"], skipped);
+        assert_eq!(
+            statuses["Exec[echo after skipped]"], skipped,
+            "The root cause is reported through skipped resources"
+        );
+        assert_eq!(
+            report.refreshes,
+            vec![RefreshRecord {
+                id: "Exec[echo refreshed]".to_string(),
+                triggered_by: vec!["Exec[/bin/true]".to_string()],
+                changes: vec![],
+                error: None,
+                output: None,
+            }]
+        );
+        assert!(report.failed());
+        assert_eq!(report.detailed_exit_code(), 6);
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_refresh_fails_the_run() {
+        let mut report = ApplyReport {
+            refreshes: vec![RefreshRecord {
+                id: "Service[nginx]".to_string(),
+                triggered_by: vec!["File[/etc/nginx/nginx.conf]".to_string()],
+                changes: vec![],
+                error: Some("Cannot restart Service[nginx]: exit 1".to_string()),
+                output: None,
+            }],
+            ..ApplyReport::default()
+        };
+        assert!(report.failed());
+        assert_eq!(report.detailed_exit_code(), 4);
+        assert_eq!(
+            report.to_string().trim_end(),
+            "Service[nginx]: refresh by File[/etc/nginx/nginx.conf] failed: \
+             Cannot restart Service[nginx]: exit 1"
+        );
+
+        report.refreshes[0].error = None;
+        assert!(!report.failed());
+    }
+
+    #[test]
+    fn test_apply_runs_execs_under_the_global_policy() -> Result<()> {
+        let input = r#"
+            exec { 'test -z "$CARGO"': }
+            exec { 'test -n "$CARGO"': clean_environment => false }
+        "#;
+        let plan = parse_puppet_manifest(&Manifest::from_str(input)?)?;
+        assert!(plan.apply(ApplyOptions::default())?.failed());
+        let report = plan.apply(ApplyOptions {
+            exec_policy: ExecPolicy {
+                clean_environment: Some(true),
+                ..ExecPolicy::default()
+            },
+            ..ApplyOptions::default()
+        })?;
+        assert!(!report.failed(), "{report}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_logs_and_keeps_command_output() -> Result<()> {
+        let input = r#"
+            exec { "echo one; echo two; echo oops >&2": }
+            exec { "echo failing >&2; exit 1": }
+            exec { "/bin/true": }
+        "#;
+        let plan = parse_puppet_manifest(&Manifest::from_str(input)?)?;
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let report = plan.apply(ApplyOptions {
+            exec_policy: ExecPolicy {
+                max_output: Some(4),
+                ..ExecPolicy::default()
+            },
+            log: OutputLog::new({
+                let lines = lines.clone();
+                move |line| {
+                    if let Ok(mut lines) = lines.lock() {
+                        lines.push(line.to_string());
+                    }
+                }
+            }),
+            ..ApplyOptions::default()
+        })?;
+        let mut lines = lines.lock().map_err(|_| anyhow!("poisoned"))?.clone();
+        lines.sort();
+        assert_eq!(
+            lines,
+            [
+                "Exec[echo failing >&2; exit 1] [stderr] fail",
+                "Exec[echo failing >&2; exit 1] [stderr] ing",
+                "Exec[echo one; echo two; echo oops >&2] [stderr] oops",
+                "Exec[echo one; echo two; echo oops >&2] [stdout] one",
+                "Exec[echo one; echo two; echo oops >&2] [stdout] two",
+            ]
+        );
+        assert_eq!(
+            report.outputs["Exec[echo one; echo two; echo oops >&2]"],
+            CommandOutput {
+                stdout: "two\n".to_string(),
+                stderr: "ops\n".to_string(),
+                truncated: true,
+            }
+        );
+        assert_eq!(
+            report.outputs["Exec[echo failing >&2; exit 1]"].stderr, "ing\n",
+            "Failures keep their output"
+        );
+        assert!(!report.outputs.contains_key("Exec[/bin/true]"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallel_apply_honors_concurrency_groups() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("dolly-jobs-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir)?;
+        // Each waits for the other, so both only succeed when run at the same time.
+        let meet = |own: &str, other: &str| {
+            format!(
+                "touch {0}/{own}; for i in $(seq 50); do test -e {0}/{other} && exit 0; \
+                 sleep 0.1; done; exit 1",
+                dir.display()
+            )
+        };
+        // Both fail when run at the same time.
+        let exclusive = |name: &str| {
+            format!(
+                "mkdir {0}/lock && sleep 0.2 && rmdir {0}/lock && echo {name}",
+                dir.display()
+            )
+        };
+        let input = format!(
+            r#"
+            exec {{ '{}': }}
+            exec {{ '{}': }}
+            exec {{ '{}': concurrency_group => 'lock' }}
+            exec {{ '{}': concurrency_group => 'lock' }}
+            "#,
+            meet("a", "b"),
+            meet("b", "a"),
+            exclusive("c"),
+            exclusive("d")
+        );
+        let plan = parse_puppet_manifest(&Manifest::from_str(&input)?)?;
+        let report = plan.apply(ApplyOptions {
+            jobs: 4,
+            ..ApplyOptions::default()
+        })?;
+        assert!(!report.failed(), "{report}");
+        assert_eq!(report.resources.len(), 4);
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_stop_on_failure() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("dolly-stop-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let input = format!(
+            r#"
+            exec {{ "/bin/false": }}
+            file {{ "{}": }}
+            file {{ "{}": }}
+            "#,
+            dir.join("unrelated").display(),
+            dir.join("also-unrelated").display()
+        );
+        let plan = parse_puppet_manifest(&Manifest::from_str(&input)?)?;
+        let options = ApplyOptions {
+            on_failure: OnFailure::Stop,
+            ..ApplyOptions::default()
+        };
+        let report = plan.apply(options)?;
+        let failed_at = report
+            .resources
+            .iter()
+            .position(|(_, status)| matches!(status, Status::Failed(_)))
+            .expect("Exec[/bin/false] fails");
+        assert!(
+            report.resources[failed_at + 1..]
+                .iter()
+                .all(|(_, status)| matches!(status, Status::Skipped { .. })),
+            "Nothing runs after the first failure, related or not"
+        );
+        assert_eq!(report.resources.len(), 3);
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_noop_reports_pending_changes() -> Result<()> {
+        let missing = std::env::temp_dir().join("dolly-noop-never-created");
+        let input = format!(
+            r#"
+            file {{ "{}": }}
+            file {{ "/": }}
+            exec {{ "touch {}": }}
+            Exec["touch {}"] ~> File["/"]
+            "#,
+            missing.display(),
+            missing.display(),
+            missing.display()
+        );
+        let plan = parse_puppet_manifest(&Manifest::from_str(&input)?)?;
+        let report = plan.apply(ApplyOptions {
+            noop: true,
+            ..ApplyOptions::default()
+        })?;
+        assert!(!missing.exists(), "Noop never enforces");
+        let statuses: HashMap<_, _> = report.resources.iter().cloned().collect();
+        assert_eq!(statuses["File[/]"], Status::InSync);
+        assert_eq!(
+            statuses[&format!("File[{}]", missing.display())],
+            Status::WouldChange(vec![PropertyChange::new(
+                "ensure",
+                Some("absent"),
+                "present"
+            )])
+        );
+        assert_eq!(report.changes().len(), 2);
+        assert_eq!(report.detailed_exit_code(), 2);
+        assert_eq!(
+            report.previews,
+            HashMap::from([
+                (
+                    format!("File[{}]", missing.display()),
+                    vec![format!("would write: {}", missing.display())]
+                ),
+                (
+                    format!("Exec[touch {}]", missing.display()),
+                    vec![format!("would run: touch {}", missing.display())]
+                ),
+            ])
+        );
+        assert!(report.to_string().contains(&format!(
+            "Exec[touch {}]: would change returns: notrun -> 0\n  would run: touch {}\n",
+            missing.display(),
+            missing.display()
+        )));
+        assert_eq!(report.refreshes.len(), 1);
+        assert!(
+            report
+                .to_string()
+                .contains("File[/]: would be refreshed by")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_resume_from_checkpoint() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("dolly-resume-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir)?;
+        let (log, flag, path) = (dir.join("log"), dir.join("flag"), dir.join("checkpoint"));
+        let input = format!(
+            r#"
+            exec {{ "echo once >> {}": }}
+            exec {{ "test -e {}": }}
+            Exec["echo once >> {}"] -> Exec["test -e {}"]
+            "#,
+            log.display(),
+            flag.display(),
+            log.display(),
+            flag.display()
+        );
+        let plan = parse_puppet_manifest(&Manifest::from_str(&input)?)?;
+
+        let first = plan.apply_with_checkpoint(ApplyOptions::default(), &path, false)?;
+        assert!(first.failed());
+        let checkpoint =
+            Checkpoint::load(&HostFileSystem, &path)?.expect("A failed run keeps its checkpoint");
+        assert_eq!(
+            checkpoint.pending,
+            vec![format!("Exec[test -e {}]", flag.display())]
+        );
+
+        fs::write(&flag, "")?;
+        let resumed = plan.apply_with_checkpoint(ApplyOptions::default(), &path, true)?;
+        assert_eq!(resumed.resources[0].1, Status::Resumed);
+        assert!(!resumed.failed());
+        assert_eq!(
+            fs::read_to_string(&log)?,
+            "once\n",
+            "Completed resources do not run again"
+        );
+        assert!(!path.exists(), "A successful run removes its checkpoint");
+
+        assert!(
+            plan.apply_with_checkpoint(ApplyOptions::default(), &path, true)
+                .is_err(),
+            "Nothing to resume"
+        );
+        Checkpoint::default().save(&HostFileSystem, &path)?;
+        assert!(
+            plan.apply_with_checkpoint(ApplyOptions::default(), &path, true)
+                .is_err(),
+            "A checkpoint of another plan is not resumed"
+        );
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,15 @@
+pub mod checkpoint;
+pub mod compliance;
+pub mod deferred;
+pub mod engine;
+pub mod refresh;
+pub mod sink;
+pub mod structured;
+
+pub use checkpoint::Checkpoint;
+pub use compliance::{Compliance, ComplianceReport, Score};
+pub use deferred::DeferredResolver;
+pub use engine::{ApplyOptions, ApplyReport, CommandOutput, OnFailure, Status};
+pub use refresh::{RefreshRecord, RefreshTracker};
+pub use sink::{FileSink, HttpSink, ReportSink, S3Sink, SinkConfig, deliver_all};
+pub use structured::{Outcome, StructuredReport, Totals};
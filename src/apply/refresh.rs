@@ -0,0 +1,104 @@
+use super::engine::CommandOutput;
+use crate::resources::PropertyChange;
+use petgraph::graph::NodeIndex;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+/// A refresh that was performed, with every changed resource that asked for it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RefreshRecord {
+    pub id: String,
+    pub triggered_by: Vec<String>,
+    /// What the refresh did.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub changes: Vec<PropertyChange>,
+    /// Why the refresh failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The tail of what the commands it ran printed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<CommandOutput>,
+}
+
+/// Collects Notify triggers during a run and hands each notified resource out for refresh
+/// at most once, however many changed resources notified it.
+#[derive(Debug, Default)]
+pub struct RefreshTracker {
+    pending: HashMap<NodeIndex, Vec<String>>,
+    refreshed: HashSet<NodeIndex>,
+}
+
+impl RefreshTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that `source` changed and notifies `target`.
+    pub fn notify(&mut self, target: NodeIndex, source: impl Into<String>) {
+        let triggers = self.pending.entry(target).or_default();
+        let source = source.into();
+        if !triggers.contains(&source) {
+            triggers.push(source);
+        }
+    }
+
+    pub fn is_pending(&self, target: NodeIndex) -> bool {
+        self.pending.contains_key(&target) && !self.refreshed.contains(&target)
+    }
+
+    /// Takes the triggers of `target` if it still needs a refresh in this run.
+    pub fn take(&mut self, target: NodeIndex, id: impl Into<String>) -> Option<RefreshRecord> {
+        if self.refreshed.contains(&target) {
+            return None;
+        }
+        let triggered_by = self.pending.remove(&target)?;
+        self.refreshed.insert(target);
+        Some(RefreshRecord {
+            id: id.into(),
+            triggered_by,
+            changes: vec![],
+            error: None,
+            output: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_refresh_once_per_run() {
+        let nginx = NodeIndex::new(3);
+        let mut tracker = RefreshTracker::new();
+        assert!(
+            tracker.take(nginx, "Service[nginx]").is_none(),
+            "Nothing changed"
+        );
+
+        tracker.notify(nginx, "File[/etc/nginx/a.conf]");
+        tracker.notify(nginx, "File[/etc/nginx/b.conf]");
+        tracker.notify(nginx, "File[/etc/nginx/a.conf]");
+        assert!(tracker.is_pending(nginx));
+        assert_eq!(
+            tracker.take(nginx, "Service[nginx]"),
+            Some(RefreshRecord {
+                id: "Service[nginx]".to_string(),
+                triggered_by: vec![
+                    "File[/etc/nginx/a.conf]".to_string(),
+                    "File[/etc/nginx/b.conf]".to_string()
+                ],
+                changes: vec![],
+                error: None,
+                output: None,
+            })
+        );
+
+        tracker.notify(nginx, "File[/etc/nginx/c.conf]");
+        assert!(!tracker.is_pending(nginx));
+        assert!(
+            tracker.take(nginx, "Service[nginx]").is_none(),
+            "A resource is refreshed at most once per run"
+        );
+    }
+}
@@ -0,0 +1,292 @@
+//! Delivery of apply reports to where they are collected, configured as `[[reports]]`:
+//!
+//! ```toml
+//! [[reports]]
+//! type = "file"
+//! path = "/var/lib/dolly/reports"
+//!
+//! [[reports]]
+//! type = "http"
+//! url = "https://reports.example.com/dolly"
+//!
+//! [[reports]]
+//! type = "s3"
+//! endpoint = "https://s3.eu-west-1.amazonaws.com"
+//! bucket = "fleet-reports"
+//! prefix = "web1/"
+//! region = "eu-west-1"
+//! ```
+//!
+//! HTTP and S3 uploads run `curl`; S3 credentials default to `AWS_ACCESS_KEY_ID` and
+//! `AWS_SECRET_ACCESS_KEY`.
+
+use super::ApplyReport;
+use anyhow::{Context, Result, anyhow};
+use serde::Deserialize;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Somewhere a finished report is sent.
+pub trait ReportSink: fmt::Debug + Send + Sync {
+    fn deliver(&self, report: &ApplyReport) -> Result<()>;
+}
+
+/// Writes the report as JSON to a file, or to a new timestamped file when `path` is a
+/// directory.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileSink {
+    pub path: PathBuf,
+}
+
+impl ReportSink for FileSink {
+    fn deliver(&self, report: &ApplyReport) -> Result<()> {
+        let path = match self.path.is_dir() {
+            true => self.path.join(report_name()),
+            false => self.path.clone(),
+        };
+        fs::write(&path, report.to_json())
+            .with_context(|| format!("Cannot write report {}", path.display()))
+    }
+}
+
+/// POSTs the report as JSON.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HttpSink {
+    pub url: String,
+    /// Extra request headers, e.g. `"Authorization: Bearer ..."`.
+    #[serde(default)]
+    pub headers: Vec<String>,
+}
+
+impl ReportSink for HttpSink {
+    fn deliver(&self, report: &ApplyReport) -> Result<()> {
+        let mut command = curl();
+        command.args(["-X", "POST", "-H", "Content-Type: application/json"]);
+        for header in &self.headers {
+            command.args(["-H", header]);
+        }
+        command.args(["--data-binary", "@-", &self.url]);
+        upload(command, report.to_json().as_bytes())
+    }
+}
+
+/// PUTs the report as a new object of an S3-compatible store, signed with AWS
+/// Signature Version 4.
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct S3Sink {
+    pub endpoint: String,
+    pub bucket: String,
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default = "default_region")]
+    pub region: String,
+    pub access_key: Option<String>,
+    pub secret_key: Option<String>,
+}
+
+/// The keys are left out, as sinks end up in delivery errors.
+impl fmt::Debug for S3Sink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let redacted = |key: &Option<String>| key.as_ref().map(|_| "[redacted]");
+        f.debug_struct("S3Sink")
+            .field("endpoint", &self.endpoint)
+            .field("bucket", &self.bucket)
+            .field("prefix", &self.prefix)
+            .field("region", &self.region)
+            .field("access_key", &redacted(&self.access_key))
+            .field("secret_key", &redacted(&self.secret_key))
+            .finish()
+    }
+}
+
+fn default_region() -> String {
+    "us-east-1".to_string()
+}
+
+impl S3Sink {
+    /// The URL of the object `name`, addressed by path so any endpoint works.
+    pub fn object_url(&self, name: &str) -> String {
+        format!(
+            "{}/{}/{}{name}",
+            self.endpoint.trim_end_matches('/'),
+            self.bucket,
+            self.prefix
+        )
+    }
+
+    fn credential(configured: &Option<String>, variable: &str) -> Result<String> {
+        configured
+            .clone()
+            .or_else(|| std::env::var(variable).ok())
+            .ok_or_else(|| anyhow!("S3 report sink needs {variable}"))
+    }
+}
+
+impl ReportSink for S3Sink {
+    fn deliver(&self, report: &ApplyReport) -> Result<()> {
+        let access_key = Self::credential(&self.access_key, "AWS_ACCESS_KEY_ID")?;
+        let secret_key = Self::credential(&self.secret_key, "AWS_SECRET_ACCESS_KEY")?;
+        let name = report_name();
+        // The credentials go through stdin as curl configuration, so that they never show
+        // in the process list; the report is uploaded from a file instead.
+        let body = std::env::temp_dir().join(format!("dolly-{}-{name}", std::process::id()));
+        fs::write(&body, report.to_json())
+            .with_context(|| format!("Cannot write {}", body.display()))?;
+        let mut command = curl();
+        command
+            .args(["--config", "-"])
+            .args(["--aws-sigv4", &format!("aws:amz:{}:s3", self.region)])
+            .args(["-H", "Content-Type: application/json"])
+            .arg("-T")
+            .arg(&body)
+            .arg(self.object_url(&name));
+        let config = format!(
+            "user = \"{}\"\n",
+            quoted(&format!("{access_key}:{secret_key}"))
+        );
+        let uploaded = upload(command, config.as_bytes());
+        let _ = fs::remove_file(&body);
+        uploaded
+    }
+}
+
+/// One configured sink.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SinkConfig {
+    File(FileSink),
+    Http(HttpSink),
+    S3(S3Sink),
+}
+
+impl SinkConfig {
+    pub fn sink(&self) -> Box<dyn ReportSink> {
+        match self {
+            Self::File(sink) => Box::new(sink.clone()),
+            Self::Http(sink) => Box::new(sink.clone()),
+            Self::S3(sink) => Box::new(sink.clone()),
+        }
+    }
+}
+
+/// Delivers `report` to every sink, returning the failures instead of stopping at the
+/// first so one unreachable sink does not hold back the others.
+pub fn deliver_all(sinks: &[Box<dyn ReportSink>], report: &ApplyReport) -> Vec<anyhow::Error> {
+    sinks
+        .iter()
+        .filter_map(|sink| {
+            sink.deliver(report)
+                .with_context(|| format!("Cannot deliver the report to {sink:?}"))
+                .err()
+        })
+        .collect()
+}
+
+/// `report-<seconds since the epoch>.<nanoseconds>.json`, so that runs within the same
+/// second do not overwrite each other.
+fn report_name() -> String {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    format!(
+        "report-{}.{:09}.json",
+        elapsed.as_secs(),
+        elapsed.subsec_nanos()
+    )
+}
+
+/// `value` escaped for a double-quoted curl configuration string.
+fn quoted(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn curl() -> Command {
+    let mut command = Command::new("curl");
+    command.args(["--silent", "--show-error", "--fail"]);
+    command
+}
+
+/// Runs `command` with `input` on its standard input.
+fn upload(mut command: Command, input: &[u8]) -> Result<()> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Cannot run curl")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input)?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "curl failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::Status;
+
+    #[test]
+    fn test_configured_sinks() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("dolly-sinks-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let configs: Vec<SinkConfig> = serde_json::from_value(serde_json::json!([
+            { "type": "file", "path": dir },
+            { "type": "file", "path": dir.join("latest.json") },
+            { "type": "s3", "endpoint": "http://minio:9000/", "bucket": "r", "prefix": "web1/" },
+        ]))?;
+        let SinkConfig::S3(s3) = &configs[2] else {
+            return Err(anyhow!("Expected an S3 sink"));
+        };
+        assert_eq!(s3.object_url("a.json"), "http://minio:9000/r/web1/a.json");
+        assert_eq!(s3.region, "us-east-1");
+        let keyed = S3Sink {
+            access_key: Some("AKIA".to_string()),
+            secret_key: Some("hunter2".to_string()),
+            ..s3.clone()
+        };
+        let debug = format!("{keyed:?}");
+        assert!(
+            !debug.contains("AKIA") && !debug.contains("hunter2"),
+            "{debug}"
+        );
+        assert_eq!(quoted(r#"a"b\c"#), r#"a\"b\\c"#);
+
+        let report = ApplyReport {
+            resources: vec![("Exec[/bin/true]".to_string(), Status::InSync)],
+            ..ApplyReport::default()
+        };
+        let sinks: Vec<_> = configs[..2].iter().map(SinkConfig::sink).collect();
+        assert!(deliver_all(&sinks, &report).is_empty());
+        let latest = fs::read_to_string(dir.join("latest.json"))?;
+        assert!(latest.contains(r#""status": "in_sync""#));
+        assert_eq!(
+            fs::read_dir(&dir)?.count(),
+            2,
+            "The directory gets its own report"
+        );
+
+        let broken = vec![
+            SinkConfig::File(FileSink {
+                path: dir.join("missing/report.json"),
+            })
+            .sink(),
+        ];
+        assert_eq!(deliver_all(&broken, &report).len(), 1);
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,192 @@
+//! The apply report in the shape CI pipelines consume: every resource with a plain
+//! outcome and its duration, then totals, as JSON or YAML.
+
+use super::engine::{ApplyReport, CommandOutput, Status};
+use super::refresh::RefreshRecord;
+use crate::resources::PropertyChange;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// What became of a resource, whatever the run mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// Changed, or in noop mode would have been.
+    Changed,
+    /// Already in sync, or applied by the interrupted run this one resumed.
+    Unchanged,
+    Skipped,
+    Failed,
+}
+
+impl From<&Status> for Outcome {
+    fn from(status: &Status) -> Self {
+        match status {
+            Status::Changed(_) | Status::WouldChange(_) => Self::Changed,
+            Status::InSync | Status::Resumed => Self::Unchanged,
+            Status::Skipped { .. } => Self::Skipped,
+            Status::Failed(_) => Self::Failed,
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Changed => write!(f, "changed"),
+            Self::Unchanged => write!(f, "unchanged"),
+            Self::Skipped => write!(f, "skipped"),
+            Self::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// Why a resource failed, or for a skipped resource the failure it was skipped for.
+pub(crate) fn error_of(status: &Status) -> Option<String> {
+    match status {
+        Status::Failed(error) => Some(error.clone()),
+        Status::Skipped { failed } => Some(format!("{failed} failed")),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResourceOutcome<'a> {
+    pub id: &'a str,
+    pub outcome: Outcome,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub changes: &'a [PropertyChange],
+    /// Why it failed, or for a skipped resource the failure it was skipped for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// In noop mode, what its provider would run or write.
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub preview: &'a [String],
+    /// The tail of what its commands printed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<&'a CommandOutput>,
+    pub duration_secs: f64,
+}
+
+/// How many resources had each outcome.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Totals {
+    pub changed: usize,
+    pub unchanged: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub total: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StructuredReport<'a> {
+    pub noop: bool,
+    pub duration_secs: f64,
+    pub totals: Totals,
+    pub resources: Vec<ResourceOutcome<'a>>,
+    pub refreshes: &'a [RefreshRecord],
+}
+
+impl StructuredReport<'_> {
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn to_yaml(&self) -> Result<String> {
+        Ok(serde_yaml::to_string(self)?)
+    }
+}
+
+impl ApplyReport {
+    pub fn structured(&self) -> StructuredReport<'_> {
+        let mut totals = Totals::default();
+        let resources: Vec<_> = self
+            .resources
+            .iter()
+            .map(|(id, status)| {
+                let outcome = Outcome::from(status);
+                match outcome {
+                    Outcome::Changed => totals.changed += 1,
+                    Outcome::Unchanged => totals.unchanged += 1,
+                    Outcome::Skipped => totals.skipped += 1,
+                    Outcome::Failed => totals.failed += 1,
+                }
+                ResourceOutcome {
+                    id,
+                    outcome,
+                    changes: match status {
+                        Status::Changed(changes) | Status::WouldChange(changes) => changes,
+                        _ => &[],
+                    },
+                    error: error_of(status),
+                    preview: self.previews.get(id).map_or(&[], Vec::as_slice),
+                    output: self.outputs.get(id),
+                    duration_secs: self
+                        .durations
+                        .get(id)
+                        .map_or(0.0, |duration| duration.as_secs_f64()),
+                }
+            })
+            .collect();
+        totals.total = resources.len();
+        StructuredReport {
+            noop: self.noop,
+            duration_secs: self.duration.as_secs_f64(),
+            totals,
+            resources,
+            refreshes: &self.refreshes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::ApplyOptions;
+    use crate::parse_puppet_manifest;
+    use crate::parser::pp::Manifest;
+    use crate::testing::FakeSystem;
+
+    #[test]
+    fn test_structured_report_counts_outcomes() -> Result<()> {
+        let manifest: Manifest = r#"
+            exec { "/bin/true": }
+            exec { "/bin/false": }
+            exec { "/bin/echo": require => Exec["/bin/false"] }
+        "#
+        .parse()?;
+        let plan = parse_puppet_manifest(&manifest)?;
+        let fake = FakeSystem::default();
+        let report = plan.apply(ApplyOptions {
+            system: fake.system(),
+            ..ApplyOptions::default()
+        })?;
+        let structured = report.structured();
+        assert_eq!(
+            structured.totals,
+            Totals {
+                changed: 1,
+                unchanged: 0,
+                skipped: 1,
+                failed: 1,
+                total: 3,
+            }
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&structured.to_json()?)?;
+        let echo = json["resources"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|resource| resource["id"] == "Exec[/bin/echo]");
+        assert_eq!(echo.map(|echo| &echo["outcome"]), Some(&"skipped".into()));
+        assert_eq!(
+            echo.map(|echo| &echo["error"]),
+            Some(&"Exec[/bin/false] failed".into())
+        );
+        let yaml = structured.to_yaml()?;
+        assert!(yaml.contains("outcome: failed"), "{yaml}");
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+/// A rough Puppet tokenizer for auditing statements the parser does not support. It only
+/// needs to tell names, variables and punctuation apart; string contents and comments are
+/// skipped so words inside them are not mistaken for features.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    /// A bare word, possibly namespaced (`foo::bar`, `::File`).
+    Name(String),
+    Variable(String),
+    String,
+    Number,
+    Punct(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Located {
+    pub token: Token,
+    pub line: usize,
+    pub col: usize,
+}
+
+/// Longest first, so `<<|` wins over `<|` and `<`.
+const PUNCTUATION: &[&str] = &[
+    "<<|", "|>>", "<|", "|>", "@@", "=>", "==", "!=", "=~", "->", "~>", "<-", "<~", "+>", "@", "{",
+    "}", "(", ")", "[", "]", ",", ":", ";", "=", "?", "|", ".", "<", ">", "!", "+", "-", "*", "/",
+    "%",
+];
+
+/// Tokenizes `text`, numbering lines and columns from `line` and `col` so locations match
+/// the surrounding file.
+pub fn tokenize(text: &str, line: usize, col: usize) -> Vec<Located> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let (mut i, mut line, mut col) = (0, line, col);
+    let advance = |i: &mut usize, line: &mut usize, col: &mut usize, n: usize| {
+        for c in &chars[*i..(*i + n).min(chars.len())] {
+            if *c == '\n' {
+                *line += 1;
+                *col = 1;
+            } else {
+                *col += 1;
+            }
+        }
+        *i += n;
+    };
+
+    while i < chars.len() {
+        let c = chars[i];
+        let (start_line, start_col) = (line, col);
+        let rest: String = chars[i..chars.len().min(i + 3)].iter().collect();
+        let token = if c.is_whitespace() {
+            advance(&mut i, &mut line, &mut col, 1);
+            None
+        } else if c == '#' {
+            let end = chars[i..]
+                .iter()
+                .position(|c| *c == '\n')
+                .unwrap_or(chars.len() - i);
+            advance(&mut i, &mut line, &mut col, end);
+            None
+        } else if rest.starts_with("/*") {
+            let end = text_position(&chars[i + 2..], "*/").map_or(chars.len() - i, |p| p + 4);
+            advance(&mut i, &mut line, &mut col, end);
+            None
+        } else if c == '"' || c == '\'' {
+            let mut end = 1;
+            while i + end < chars.len() && chars[i + end] != c {
+                end += if chars[i + end] == '\\' { 2 } else { 1 };
+            }
+            advance(&mut i, &mut line, &mut col, end + 1);
+            Some(Token::String)
+        } else if c == '$' {
+            let len = name_len(&chars[i + 1..]);
+            let name: String = chars[i + 1..i + 1 + len].iter().collect();
+            advance(&mut i, &mut line, &mut col, len + 1);
+            Some(Token::Variable(name))
+        } else if c.is_ascii_digit() {
+            let len = chars[i..]
+                .iter()
+                .take_while(|c| c.is_ascii_alphanumeric() || **c == '.')
+                .count();
+            advance(&mut i, &mut line, &mut col, len);
+            Some(Token::Number)
+        } else if c.is_alphabetic() || c == '_' || rest.starts_with("::") {
+            let len = name_len(&chars[i..]);
+            let name: String = chars[i..i + len].iter().collect();
+            advance(&mut i, &mut line, &mut col, len.max(1));
+            Some(Token::Name(name))
+        } else {
+            let punct = PUNCTUATION.iter().find(|p| rest.starts_with(**p));
+            let len = punct.map_or(1, |p| p.len());
+            advance(&mut i, &mut line, &mut col, len);
+            punct.map(|p| Token::Punct(p))
+        };
+        if let Some(token) = token {
+            tokens.push(Located {
+                token,
+                line: start_line,
+                col: start_col,
+            });
+        }
+    }
+    tokens
+}
+
+/// Length of a possibly namespaced name at the start of `chars`.
+fn name_len(chars: &[char]) -> usize {
+    let mut len = 0;
+    while len < chars.len() {
+        if chars[len].is_alphanumeric() || chars[len] == '_' {
+            len += 1;
+        } else if chars[len] == ':' && chars.get(len + 1) == Some(&':') {
+            len += 2;
+        } else {
+            break;
+        }
+    }
+    len
+}
+
+fn text_position(chars: &[char], needle: &str) -> Option<usize> {
+    let needle: Vec<char> = needle.chars().collect();
+    chars.windows(needle.len()).position(|w| w == needle)
+}
@@ -0,0 +1,463 @@
+//! Reports which Puppet features a codebase uses and which of them dolly cannot handle
+//! yet, so users know what blocks migrating it.
+
+pub mod lexer;
+
+use crate::parser::pp::{Manifest, PuppetExpr, normalize_rtype};
+use crate::resources::ResourceRegistry;
+use anyhow::{Context, Result};
+use lexer::{Located, Token, tokenize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Feature {
+    ClassDefinition,
+    ClassDeclaration,
+    DefinedType,
+    NodeDefinition,
+    ExportedResource,
+    VirtualResource,
+    Collector,
+    Conditional,
+    Selector,
+    VariableAssignment,
+    ResourceDefaults,
+    /// A file even lenient parsing rejects.
+    Unparseable,
+}
+
+impl Feature {
+    pub fn supported(self) -> bool {
+        match self {
+            Self::ClassDefinition
+            | Self::ClassDeclaration
+            | Self::DefinedType
+            | Self::Conditional
+            | Self::ResourceDefaults => true,
+            Self::NodeDefinition
+            | Self::ExportedResource
+            | Self::VirtualResource
+            | Self::Collector
+            | Self::Selector
+            | Self::VariableAssignment
+            | Self::Unparseable => false,
+        }
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::ClassDefinition => "class definitions",
+            Self::ClassDeclaration => "class declarations",
+            Self::DefinedType => "defined types",
+            Self::NodeDefinition => "node definitions",
+            Self::ExportedResource => "exported resources",
+            Self::VirtualResource => "virtual resources",
+            Self::Collector => "collectors",
+            Self::Conditional => "conditionals",
+            Self::Selector => "selectors",
+            Self::VariableAssignment => "variable assignments",
+            Self::ResourceDefaults => "resource defaults",
+            Self::Unparseable => "unparseable files",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Where something was used: a file and the 1-based line and column in it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub path: PathBuf,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.path.display(), self.line, self.col)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Audit {
+    pub files: usize,
+    pub features: BTreeMap<Feature, Vec<Location>>,
+    /// Resource types declared, by normalized name.
+    pub types: BTreeMap<String, Vec<Location>>,
+    pub functions: BTreeMap<String, Vec<Location>>,
+    /// Types defined with `define`, which are supported wherever they are declared.
+    pub defined: BTreeSet<String>,
+}
+
+/// Words that are followed by `(` or `{` without being a function or a resource type.
+const KEYWORDS: &[&str] = &[
+    "and", "case", "class", "default", "define", "else", "elsif", "false", "function", "if", "in",
+    "inherits", "node", "or", "true", "undef", "unless",
+];
+
+impl Audit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Scans every `.pp` file below `dir`.
+    pub fn scan_dir(dir: &Path) -> Result<Self> {
+        let mut audit = Self::new();
+        let mut paths = Vec::new();
+        collect_manifests(dir, &mut paths)?;
+        paths.sort();
+        for path in paths {
+            let source = fs::read_to_string(&path)
+                .with_context(|| format!("Cannot read {}", path.display()))?;
+            audit.add_source(&path, &source);
+        }
+        Ok(audit)
+    }
+
+    pub fn add_source(&mut self, path: &Path, source: &str) {
+        self.files += 1;
+        let at = |line, col| Location {
+            path: path.to_path_buf(),
+            line,
+            col,
+        };
+        let Ok(manifest) = Manifest::from_str_lenient(source) else {
+            self.features
+                .entry(Feature::Unparseable)
+                .or_default()
+                .push(at(1, 1));
+            return;
+        };
+        self.add_exprs(&manifest.0, &at);
+    }
+
+    fn add_exprs(&mut self, exprs: &[PuppetExpr], at: &impl Fn(usize, usize) -> Location) {
+        for expr in exprs {
+            match expr {
+                PuppetExpr::Resource { rtype, span, .. } if rtype == "Class" => self
+                    .features
+                    .entry(Feature::ClassDeclaration)
+                    .or_default()
+                    .push(at(span.line, span.col)),
+                PuppetExpr::Resource { rtype, span, .. } => self
+                    .types
+                    .entry(rtype.clone())
+                    .or_default()
+                    .push(at(span.line, span.col)),
+                PuppetExpr::Class { body, span, .. } => {
+                    self.features
+                        .entry(Feature::ClassDefinition)
+                        .or_default()
+                        .push(at(span.line, span.col));
+                    self.add_exprs(body, at);
+                }
+                PuppetExpr::Define {
+                    name, body, span, ..
+                } => {
+                    self.features
+                        .entry(Feature::DefinedType)
+                        .or_default()
+                        .push(at(span.line, span.col));
+                    self.defined.insert(normalize_rtype(name));
+                    self.add_exprs(body, at);
+                }
+                PuppetExpr::Each { body, .. } => self.add_exprs(body, at),
+                PuppetExpr::Conditional {
+                    branches,
+                    otherwise,
+                    span,
+                } => {
+                    self.features
+                        .entry(Feature::Conditional)
+                        .or_default()
+                        .push(at(span.line, span.col));
+                    for (_, body) in branches {
+                        self.add_exprs(body, at);
+                    }
+                    self.add_exprs(otherwise, at);
+                }
+                PuppetExpr::Defaults { span, .. } => self
+                    .features
+                    .entry(Feature::ResourceDefaults)
+                    .or_default()
+                    .push(at(span.line, span.col)),
+                PuppetExpr::Include { span, .. } => self
+                    .features
+                    .entry(Feature::ClassDeclaration)
+                    .or_default()
+                    .push(at(span.line, span.col)),
+                PuppetExpr::Opaque { text, span } => {
+                    self.scan(&tokenize(text, span.line, span.col), at)
+                }
+                PuppetExpr::Relation { .. } | PuppetExpr::Assert { .. } => {}
+            }
+        }
+    }
+
+    fn scan(&mut self, tokens: &[Located], at: &impl Fn(usize, usize) -> Location) {
+        let name = |i: usize| match tokens.get(i).map(|t| &t.token) {
+            Some(Token::Name(name)) => Some(name.as_str()),
+            _ => None,
+        };
+        let punct = |i: usize, p: &str| matches!(tokens.get(i).map(|t| &t.token), Some(Token::Punct(q)) if *q == p);
+
+        for (i, located) in tokens.iter().enumerate() {
+            let location = || at(located.line, located.col);
+            let previous = i.checked_sub(1).and_then(name);
+            let feature = match &located.token {
+                Token::Name(word) => match word.as_str() {
+                    "class" if name(i + 1).is_some() => Some(Feature::ClassDefinition),
+                    "class" if punct(i + 1, "{") => Some(Feature::ClassDeclaration),
+                    "include" | "contain" | "require"
+                        if name(i + 1).is_some()
+                            || matches!(
+                                tokens.get(i + 1).map(|t| &t.token),
+                                Some(Token::String)
+                            ) =>
+                    {
+                        Some(Feature::ClassDeclaration)
+                    }
+                    "define" if name(i + 1).is_some() => Some(Feature::DefinedType),
+                    "node" => Some(Feature::NodeDefinition),
+                    "if" | "unless" | "case" | "elsif" => Some(Feature::Conditional),
+                    _ => None,
+                },
+                Token::Punct("@@") => Some(Feature::ExportedResource),
+                Token::Punct("@") if name(i + 1).is_some() => Some(Feature::VirtualResource),
+                Token::Punct("<|" | "<<|") => Some(Feature::Collector),
+                Token::Punct("?") if punct(i + 1, "{") => Some(Feature::Selector),
+                // Parameter defaults follow `(`, `,` or a type, assignments do not.
+                Token::Variable(_)
+                    if punct(i + 1, "=")
+                        && !previous.is_some_and(|p| p.starts_with(char::is_uppercase))
+                        && !["(", ",", "]"].iter().any(|p| punct(i.wrapping_sub(1), p)) =>
+                {
+                    Some(Feature::VariableAssignment)
+                }
+                _ => None,
+            };
+            if let Some(feature) = feature {
+                self.features.entry(feature).or_default().push(location());
+            }
+
+            let Token::Name(word) = &located.token else {
+                continue;
+            };
+            if KEYWORDS.contains(&word.as_str())
+                || previous.is_some_and(|p| ["class", "define", "node", "inherits"].contains(&p))
+            {
+                continue;
+            }
+            let starts_upper = word
+                .trim_start_matches("::")
+                .starts_with(|c: char| c.is_uppercase());
+            if punct(i + 1, "{") && !punct(i.wrapping_sub(1), ".") {
+                if starts_upper {
+                    self.features
+                        .entry(Feature::ResourceDefaults)
+                        .or_default()
+                        .push(location());
+                } else {
+                    self.types
+                        .entry(normalize_rtype(word))
+                        .or_default()
+                        .push(location());
+                }
+            } else if !starts_upper
+                && (punct(i + 1, "(") || (punct(i.wrapping_sub(1), ".") && punct(i + 1, "|")))
+            {
+                self.functions
+                    .entry(word.clone())
+                    .or_default()
+                    .push(location());
+            }
+        }
+    }
+
+    /// Everything used that dolly does not support, by name.
+    pub fn blockers(&self) -> Vec<String> {
+        let features = self
+            .features
+            .keys()
+            .filter(|feature| !feature.supported())
+            .map(ToString::to_string);
+        let registry = ResourceRegistry::default();
+        let types = self
+            .types
+            .keys()
+            .filter(|rtype| !self.supports(&registry, rtype))
+            .cloned();
+        let functions = self
+            .functions
+            .keys()
+            .map(|function| format!("{function}()"));
+        features.chain(types).chain(functions).collect()
+    }
+
+    /// Whether `rtype` is built into `registry` or defined by the audited manifests.
+    fn supports(&self, registry: &ResourceRegistry, rtype: &str) -> bool {
+        registry.supports(rtype) || self.defined.contains(rtype)
+    }
+}
+
+impl fmt::Display for Audit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Scanned {} manifests", self.files)?;
+        let section = |f: &mut fmt::Formatter<'_>,
+                       title: &str,
+                       entries: Vec<(String, bool, &Vec<Location>)>|
+         -> fmt::Result {
+            if entries.is_empty() {
+                return Ok(());
+            }
+            writeln!(f, "{title}:")?;
+            for (name, supported, locations) in entries {
+                let support = if supported {
+                    "supported"
+                } else {
+                    "unsupported"
+                };
+                writeln!(
+                    f,
+                    "  {name:<24} {:>5}  {support:<11}  first at {}",
+                    locations.len(),
+                    locations[0]
+                )?;
+            }
+            Ok(())
+        };
+        section(
+            f,
+            "Features",
+            self.features
+                .iter()
+                .map(|(feature, l)| (feature.to_string(), feature.supported(), l))
+                .collect(),
+        )?;
+        let registry = ResourceRegistry::default();
+        section(
+            f,
+            "Types",
+            self.types
+                .iter()
+                .map(|(rtype, l)| (rtype.clone(), self.supports(&registry, rtype), l))
+                .collect(),
+        )?;
+        section(
+            f,
+            "Functions",
+            self.functions
+                .iter()
+                .map(|(function, l)| (function.clone(), false, l))
+                .collect(),
+        )?;
+        let blockers = self.blockers();
+        match blockers.is_empty() {
+            true => writeln!(f, "Nothing blocks migrating to dolly"),
+            false => writeln!(f, "Blocking migration: {}", blockers.join(", ")),
+        }
+    }
+}
+
+fn collect_manifests(dir: &Path, paths: &mut Vec<PathBuf>) -> Result<()> {
+    if dir.is_file() {
+        paths.push(dir.to_path_buf());
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir).with_context(|| format!("Cannot read {}", dir.display()))? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_manifests(&path, paths)?;
+        } else if path.extension().is_some_and(|e| e == "pp") {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audit_feature_usage() {
+        let source = r#"
+class profile::web (String $root = '/srv') inherits profile::base {
+  include nginx
+  $config = lookup('web::config')
+  if $facts['os']['family'] == 'Debian' {
+    package { 'nginx': ensure => installed }
+  }
+  @@nagios_service { "check_${facts['fqdn']}": }
+  Nagios_service <<| |>>
+  File { mode => '0644' }
+  $config['sites'].each |$site| {
+    file { "/etc/nginx/sites/${site}": }
+  }
+}
+# include commented::out
+file { '/etc/motd': content => "if include class" }
+"#;
+        let mut audit = Audit::new();
+        audit.add_source(Path::new("site.pp"), source);
+
+        let features: Vec<_> = audit
+            .features
+            .iter()
+            .map(|(feature, l)| (*feature, l.len()))
+            .collect();
+        assert_eq!(
+            features,
+            vec![
+                (Feature::ClassDefinition, 1),
+                (Feature::ClassDeclaration, 1),
+                (Feature::ExportedResource, 1),
+                (Feature::Collector, 1),
+                (Feature::Conditional, 1),
+                (Feature::VariableAssignment, 1),
+                (Feature::ResourceDefaults, 1),
+            ]
+        );
+        assert_eq!(
+            audit.types.keys().collect::<Vec<_>>(),
+            vec!["File", "Nagios_service", "Package"]
+        );
+        assert_eq!(audit.types["File"].len(), 2);
+        assert_eq!(
+            audit.functions.keys().collect::<Vec<_>>(),
+            vec!["each", "lookup"]
+        );
+        assert_eq!(
+            audit.features[&Feature::Conditional][0].to_string(),
+            "site.pp:5:3"
+        );
+        assert!(audit.blockers().contains(&"Nagios_service".to_string()));
+        assert!(!audit.blockers().contains(&"Package".to_string()));
+        assert!(!audit.blockers().contains(&"File".to_string()));
+    }
+
+    #[test]
+    fn test_audit_supports_registered_types() {
+        let source = r#"
+user { 'deploy': }
+group { 'deploy': }
+apt::source { 'nginx': location => 'https://nginx.org/packages/debian' }
+yumrepo { 'epel': baseurl => 'https://example.com/epel' }
+cron { 'backup': }
+"#;
+        let mut audit = Audit::new();
+        audit.add_source(Path::new("site.pp"), source);
+        assert_eq!(audit.blockers(), ["Cron"]);
+        assert!(
+            audit
+                .to_string()
+                .lines()
+                .any(|line| line.starts_with("  Yumrepo") && line.contains(" supported")),
+            "{audit}"
+        );
+    }
+}
@@ -0,0 +1,288 @@
+pub mod state;
+
+pub use state::{ChangeKind, LastApply, StateCache, WatchTrigger, watched_paths};
+
+use crate::Plan;
+use crate::facts::Facts;
+use anyhow::Result;
+use std::collections::BTreeSet;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/// Identifies a compilation by its inputs and the facts it used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub manifest: u64,
+    pub facts: u64,
+}
+
+impl CacheKey {
+    /// The key of the plan compiled from `source` that used `facts`, and only them.
+    pub fn new(source: &str, facts: &Facts) -> Self {
+        Self {
+            manifest: hash(source),
+            facts: hash(facts),
+        }
+    }
+}
+
+fn hash(value: impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The plan of the last run of an agent, reused by the next runs as long as the inputs
+/// and the facts the plan used are unchanged, so that facts the manifest never reads, like
+/// uptime or free memory, do not cause a compilation.
+pub struct LastCompile {
+    inputs: u64,
+    facts: Facts,
+    plan: Plan,
+}
+
+impl LastCompile {
+    /// Records `plan`, compiled from `inputs` (the manifest, and whatever else changes the
+    /// plan) with `facts`.
+    pub fn new(inputs: &str, facts: &Facts, plan: Plan) -> Self {
+        Self {
+            inputs: hash(inputs),
+            facts: facts.restricted_to(plan.facts_used()),
+            plan,
+        }
+    }
+
+    pub fn plan(&self) -> &Plan {
+        &self.plan
+    }
+
+    pub fn key(&self) -> CacheKey {
+        CacheKey {
+            manifest: self.inputs,
+            facts: hash(&self.facts),
+        }
+    }
+
+    /// The facts the plan used whose value differs in `facts`, or that were added or
+    /// removed.
+    pub fn changed_facts(&self, facts: &Facts) -> Vec<String> {
+        let current = facts.restricted_to(self.plan.facts_used());
+        let names: BTreeSet<_> = current.0.keys().chain(self.facts.0.keys()).collect();
+        names
+            .into_iter()
+            .filter(|name| current.get(name) != self.facts.get(name))
+            .cloned()
+            .collect()
+    }
+
+    /// Whether compiling `inputs` with `facts` would give the same plan.
+    pub fn is_current(&self, inputs: &str, facts: &Facts) -> bool {
+        self.inputs == hash(inputs) && self.changed_facts(facts).is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+    pub entries: usize,
+}
+
+/// Compiled plans keyed by their inputs and the facts they used, so unchanged inputs skip
+/// parsing and compilation. Past its capacity, the cache drops its oldest plan.
+#[derive(Default)]
+pub struct PlanCache {
+    /// Oldest first.
+    entries: Vec<LastCompile>,
+    capacity: Option<usize>,
+    hits: usize,
+    misses: usize,
+}
+
+impl PlanCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A cache holding at most `capacity` plans.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            capacity: Some(capacity),
+            ..Self::default()
+        }
+    }
+
+    /// The plan compiled from `inputs` with the same values of the facts it used as
+    /// `facts`, or the one `compile` returns, which is cached.
+    pub fn get_or_compile(
+        &mut self,
+        inputs: &str,
+        facts: &Facts,
+        compile: impl FnOnce() -> Result<Plan>,
+    ) -> Result<&Plan> {
+        match self.position(inputs, facts) {
+            Some(position) => {
+                self.hits += 1;
+                Ok(self.entries[position].plan())
+            }
+            None => {
+                self.misses += 1;
+                let plan = compile()?;
+                self.entries.push(LastCompile::new(inputs, facts, plan));
+                let excess = self
+                    .entries
+                    .len()
+                    .saturating_sub(self.capacity.unwrap_or(usize::MAX));
+                self.entries.drain(..excess);
+                Ok(self.entries[self.entries.len() - 1].plan())
+            }
+        }
+    }
+
+    pub fn get(&self, inputs: &str, facts: &Facts) -> Option<&Plan> {
+        Some(self.entries[self.position(inputs, facts)?].plan())
+    }
+
+    fn position(&self, inputs: &str, facts: &Facts) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| entry.is_current(inputs, facts))
+    }
+
+    /// The facts the latest plan used whose value differs in `facts`.
+    pub fn changed_facts(&self, facts: &Facts) -> Vec<String> {
+        self.entries
+            .last()
+            .map(|entry| entry.changed_facts(facts))
+            .unwrap_or_default()
+    }
+
+    /// The keys of the cached plans, oldest first.
+    pub fn keys(&self) -> impl Iterator<Item = CacheKey> {
+        self.entries.iter().map(LastCompile::key)
+    }
+
+    /// Drops one compiled plan, returning whether it was cached.
+    pub fn invalidate(&mut self, key: &CacheKey) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|entry| entry.key() != *key);
+        before != self.entries.len()
+    }
+
+    /// Drops every plan compiled from `inputs`, whatever the facts.
+    pub fn invalidate_manifest(&mut self, inputs: &str) -> usize {
+        let manifest = hash(inputs);
+        let before = self.entries.len();
+        self.entries.retain(|entry| entry.inputs != manifest);
+        before - self.entries.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            entries: self.entries.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CompileOptions, parse_puppet_manifest_with_options, parser::pp::Manifest};
+    use std::str::FromStr;
+
+    fn compile(source: &str, facts: &Facts) -> Result<Plan> {
+        let options = CompileOptions {
+            facts: facts.clone(),
+            ..CompileOptions::default()
+        };
+        Ok(parse_puppet_manifest_with_options(
+            &Manifest::from_str(source)?,
+            &options,
+        )?)
+    }
+
+    #[test]
+    fn test_cache_hits_and_invalidation() -> Result<()> {
+        let source = r#"file { "/tmp/${::osfamily}": }"#;
+        let mut facts = Facts::new();
+        facts.insert("osfamily", "Debian");
+        facts.insert("uptime_seconds", "100");
+
+        let mut cache = PlanCache::with_capacity(2);
+        let first: *const Plan =
+            cache.get_or_compile(source, &facts, || compile(source, &facts))?;
+        facts.insert("uptime_seconds", "200");
+        let second: *const Plan =
+            cache.get_or_compile(source, &facts, || compile(source, &facts))?;
+        assert_eq!(
+            first, second,
+            "Unchanged inputs and used facts reuse the plan"
+        );
+
+        facts.insert("osfamily", "RedHat");
+        assert_eq!(cache.changed_facts(&facts), ["osfamily"]);
+        assert!(cache.get(source, &facts).is_none());
+        cache.get_or_compile(source, &facts, || compile(source, &facts))?;
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 1,
+                misses: 2,
+                entries: 2
+            }
+        );
+        let other = r#"file { "/tmp/other": }"#;
+        cache.get_or_compile(other, &facts, || compile(other, &facts))?;
+        assert_eq!(cache.stats().entries, 2, "The oldest plan is dropped");
+
+        let used = facts.restricted_to(["osfamily".to_string()].iter());
+        assert!(cache.invalidate(&CacheKey::new(source, &used)));
+        assert!(!cache.invalidate(&CacheKey::new(source, &used)));
+        assert_eq!(cache.invalidate_manifest(other), 1);
+        assert_eq!(cache.keys().count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_recompile_on_used_facts_only() -> Result<()> {
+        let source = r#"
+            if $facts['os']['family'] == 'Debian' { file { "/etc/apt/apt.conf.d/99dolly": } }
+            if $::role == 'web' { package { "nginx": } }
+        "#;
+        let mut facts = Facts::new();
+        facts.insert("os.family", "Debian");
+        facts.insert("os.release.major", "12");
+        facts.insert("uptime_seconds", "100");
+        let options = CompileOptions {
+            facts: facts.clone(),
+            ..CompileOptions::default()
+        };
+        let plan = parse_puppet_manifest_with_options(&Manifest::from_str(source)?, &options)?;
+        assert_eq!(
+            plan.facts_used().iter().collect::<Vec<_>>(),
+            ["os.family", "role"],
+            "Facts are used even when the node does not have them"
+        );
+
+        let last = LastCompile::new(source, &facts, plan);
+        facts.insert("uptime_seconds", "200");
+        assert!(
+            last.is_current(source, &facts),
+            "Unused facts do not matter"
+        );
+        facts.insert("role", "web");
+        facts.insert("os.family", "RedHat");
+        assert_eq!(last.changed_facts(&facts), ["os.family", "role"]);
+        assert!(!last.is_current(source, &facts));
+        facts.0.remove("role");
+        facts.insert("os.family", "Debian");
+        assert!(last.is_current(source, &facts));
+        assert!(!last.is_current(r#"file { "/tmp/one": }"#, &facts));
+        Ok(())
+    }
+}
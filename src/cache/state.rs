@@ -0,0 +1,341 @@
+use crate::Plan;
+use crate::apply::structured::error_of;
+use crate::apply::{ApplyReport, Outcome, Status};
+use crate::resources::Resource;
+use anyhow::{Context, Result};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Why a resource had to change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    /// The system drifted from a state dolly had already enforced.
+    Corrective,
+    /// The resource is new or its declaration changed since it was last enforced.
+    Intentional,
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Corrective => write!(f, "corrective"),
+            Self::Intentional => write!(f, "intentional"),
+        }
+    }
+}
+
+/// How a resource fared the last time it was applied.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LastApply {
+    pub outcome: Outcome,
+    /// Why it failed, or for a skipped resource the failure it was skipped for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl fmt::Display for LastApply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.error {
+            Some(error) => write!(f, "{} ({error})", self.outcome),
+            None => write!(f, "{}", self.outcome),
+        }
+    }
+}
+
+/// The declaration of every resource the last time it was brought in sync, so the next
+/// run can tell drift from new desired state, the checksum of every watched path, and
+/// how every resource fared in the last run.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateCache {
+    /// Fingerprint of the declared attributes, by resource id.
+    pub resources: IndexMap<String, u64>,
+    /// Checksum of the paths named by `watch` metaparameters, by path.
+    #[serde(default)]
+    pub watches: IndexMap<String, u64>,
+    /// The outcome of the last run that applied it, by resource id.
+    #[serde(default)]
+    pub last_applied: IndexMap<String, LastApply>,
+}
+
+/// A path named by the `watch` metaparameter of a resource that changed since the last
+/// run, so the resource is refreshed as if notified.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchTrigger {
+    pub id: String,
+    pub path: String,
+}
+
+impl WatchTrigger {
+    /// How the trigger appears among the resources that notified a refresh.
+    pub fn source(&self) -> String {
+        format!("Watch[{}]", self.path)
+    }
+}
+
+impl StateCache {
+    /// Reads the state at `path`, empty if there is none yet.
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json)
+                .with_context(|| format!("Invalid state cache {}", path.display())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Cannot read {}", path.display())),
+        }
+    }
+
+    /// Writes the state to a temporary file renamed over `path`.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut temporary = PathBuf::from(path).into_os_string();
+        temporary.push(".tmp");
+        fs::write(&temporary, serde_json::to_string_pretty(self)?)
+            .and_then(|()| fs::rename(&temporary, path))
+            .with_context(|| format!("Cannot write state cache {}", path.display()))
+    }
+
+    /// A change is corrective when the resource is declared exactly as it was when it
+    /// was last in sync.
+    pub fn classify(&self, resource: &dyn Resource) -> ChangeKind {
+        match self.resources.get(&resource.id()) {
+            Some(applied) if *applied == fingerprint(resource) => ChangeKind::Corrective,
+            _ => ChangeKind::Intentional,
+        }
+    }
+
+    /// Remembers the outcome of every resource in `report`, the declaration of the ones it
+    /// left in sync, and the current checksum of the paths they watch, so a failed
+    /// resource is triggered again next run. Resources and paths no longer in the plan
+    /// are forgotten.
+    pub fn record(&mut self, plan: &Plan, report: &ApplyReport) {
+        if report.noop {
+            return;
+        }
+        let graph = plan.graph.inner();
+        self.resources.retain(|id, _| plan.node(id).is_some());
+        self.last_applied.retain(|id, _| plan.node(id).is_some());
+        let mut watches = IndexMap::new();
+        for (id, status) in &report.resources {
+            let Some(index) = plan.node(id) else {
+                continue;
+            };
+            self.last_applied.insert(
+                id.clone(),
+                LastApply {
+                    outcome: Outcome::from(status),
+                    error: error_of(status),
+                },
+            );
+            let in_sync = matches!(status, Status::InSync | Status::Changed(_));
+            if in_sync {
+                self.resources
+                    .insert(id.clone(), fingerprint(graph[index].as_ref()));
+            }
+            for path in watched_paths(graph[index].as_ref()) {
+                let checksum = match self.watches.get(&path) {
+                    Some(recorded) if !in_sync => *recorded,
+                    _ => checksum(&path),
+                };
+                watches.insert(path, checksum);
+            }
+        }
+        self.watches = watches;
+    }
+
+    /// The watched paths whose content changed since they were last recorded. A path seen
+    /// for the first time only establishes the baseline and triggers nothing.
+    pub fn watch_triggers(&self, plan: &Plan) -> Vec<WatchTrigger> {
+        let graph = plan.graph.inner();
+        let mut triggers = Vec::new();
+        for index in graph.node_indices() {
+            let resource = graph[index].as_ref();
+            for path in watched_paths(resource) {
+                if let Some(recorded) = self.watches.get(&path)
+                    && *recorded != checksum(&path)
+                {
+                    triggers.push(WatchTrigger {
+                        id: resource.id(),
+                        path,
+                    });
+                }
+            }
+        }
+        triggers
+    }
+}
+
+/// The paths named by the `watch` metaparameter of `resource`: one path or an array.
+pub fn watched_paths(resource: &dyn Resource) -> Vec<String> {
+    resource
+        .attributes()
+        .get("watch")
+        .map(|value| {
+            value
+                .as_array()
+                .into_iter()
+                .filter_map(|path| path.as_literal())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Checksum of the content at `path`; a missing or unreadable path has one too, so
+/// creating or removing it counts as a change.
+fn checksum(path: &str) -> u64 {
+    let mut hasher = Fnv::default();
+    match fs::read(path) {
+        Ok(content) => {
+            hasher.write(&[1]);
+            hasher.write(&content);
+        }
+        Err(_) => hasher.write(&[0]),
+    }
+    hasher.0
+}
+
+fn fingerprint(resource: &dyn Resource) -> u64 {
+    let mut hasher = Fnv::default();
+    for (name, value) in resource.attributes() {
+        hasher.write(name.as_bytes());
+        hasher.write(&[0]);
+        hasher.write(value.to_string().as_bytes());
+        hasher.write(&[0]);
+    }
+    hasher.0
+}
+
+/// 64-bit FNV-1a, for fingerprints and checksums alike. The state outlives the binary that
+/// saved it, so its hashes cannot come from `DefaultHasher`, whose output may change with
+/// the Rust release.
+struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+impl ApplyReport {
+    /// Classifies every change of the report against the state of earlier runs.
+    pub fn classify(&mut self, plan: &Plan, state: &StateCache) {
+        let graph = plan.graph.inner();
+        self.change_kinds = self
+            .changes()
+            .into_iter()
+            .filter_map(|(id, _)| {
+                let index = plan.node(id)?;
+                Some((id.to_string(), state.classify(graph[index].as_ref())))
+            })
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::ApplyOptions;
+    use crate::parse_puppet_manifest;
+    use crate::parser::pp::Manifest;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_drift_is_corrective() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("dolly-state-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let motd = dir.join("motd");
+        let manifest = |content: &str| -> Result<Plan> {
+            let input = format!(r#"file {{ "{}": content => "{content}" }}"#, motd.display());
+            Ok(parse_puppet_manifest(&Manifest::from_str(&input)?)?)
+        };
+        let run = |plan: &Plan, state: &mut StateCache| -> Result<ApplyReport> {
+            let mut report = plan.apply(ApplyOptions::default())?;
+            report.classify(plan, state);
+            state.record(plan, &report);
+            Ok(report)
+        };
+        let id = format!("File[{}]", motd.display());
+        let mut state = StateCache::default();
+
+        let hello = manifest("hello")?;
+        let report = run(&hello, &mut state)?;
+        assert_eq!(report.change_kinds[&id], ChangeKind::Intentional);
+
+        fs::write(&motd, "tampered")?;
+        let report = run(&hello, &mut state)?;
+        assert_eq!(
+            report.change_kinds[&id],
+            ChangeKind::Corrective,
+            "Undoing drift corrects the system"
+        );
+
+        let report = run(&manifest("goodbye")?, &mut state)?;
+        assert_eq!(report.change_kinds[&id], ChangeKind::Intentional);
+
+        let path = dir.join("state.json");
+        state.save(&path)?;
+        assert_eq!(StateCache::load(&path)?, state);
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_hashes_are_stable() {
+        let mut hasher = Fnv::default();
+        hasher.write(b"a");
+        assert_eq!(hasher.0, 0xaf63dc4c8601ec8c, "The FNV-1a test vector");
+        assert_eq!(checksum("/nonexistent/dolly"), 0xaf63bd4c8601b7df);
+    }
+
+    #[test]
+    fn test_watched_path_triggers_refresh() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("dolly-watch-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let generated = dir.join("generated.conf");
+        fs::write(&generated, "one")?;
+        let input = format!(
+            r#"exec {{ "/bin/true": watch => ["{}"] }}"#,
+            generated.display()
+        );
+        let plan = parse_puppet_manifest(&Manifest::from_str(&input)?)?;
+        let mut state = StateCache::default();
+        let run = |state: &mut StateCache| -> Result<ApplyReport> {
+            let report = plan.apply(ApplyOptions {
+                watch_triggers: state.watch_triggers(&plan),
+                ..ApplyOptions::default()
+            })?;
+            state.record(&plan, &report);
+            Ok(report)
+        };
+
+        assert!(
+            run(&mut state)?.refreshes.is_empty(),
+            "First run sets the baseline"
+        );
+        assert!(run(&mut state)?.refreshes.is_empty());
+
+        fs::write(&generated, "two")?;
+        let report = run(&mut state)?;
+        assert_eq!(report.refreshes.len(), 1);
+        assert_eq!(
+            report.refreshes[0].triggered_by,
+            vec![format!("Watch[{}]", generated.display())]
+        );
+        assert!(
+            run(&mut state)?.refreshes.is_empty(),
+            "Refreshed once per change"
+        );
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,182 @@
+//! Site-wide settings, read from a TOML file such as `dolly.toml`:
+//!
+//! ```toml
+//! [defaults.File]
+//! backup = true
+//!
+//! [defaults.Exec]
+//! timeout = 300
+//!
+//! [exec]
+//! user = "nobody"
+//! ulimit_memory = "512M"
+//!
+//! [agent]
+//! splay = 300
+//!
+//! [facts.ttls]
+//! "inventory.sh" = 3600
+//!
+//! [[reports]]
+//! type = "file"
+//! path = "/var/lib/dolly/reports"
+//! ```
+
+use crate::agent::AgentConfig;
+use crate::apply::SinkConfig;
+use crate::facts::FactsConfig;
+use crate::parser::data::Scalar;
+use crate::parser::pp::{Attribute, Manifest, PuppetExpr, normalize_rtype};
+use crate::resources::ExecPolicy;
+use anyhow::{Context, Result, anyhow};
+use indexmap::IndexMap;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DollyConfig {
+    /// Attributes every resource of a type gets unless it sets them itself, by type.
+    #[serde(default)]
+    pub defaults: IndexMap<String, IndexMap<String, Scalar>>,
+    /// The policy every Exec runs under, as Exec attributes such as `user` or
+    /// `ulimit_memory`. An Exec's own attributes take precedence.
+    #[serde(default)]
+    pub exec: IndexMap<String, Scalar>,
+    /// How `dolly agent` schedules its runs.
+    #[serde(default)]
+    pub agent: AgentConfig,
+    /// How long custom facts are cached.
+    #[serde(default)]
+    pub facts: FactsConfig,
+    /// Where apply reports are delivered.
+    #[serde(default)]
+    pub reports: Vec<SinkConfig>,
+}
+
+impl DollyConfig {
+    pub fn from_toml(source: &str) -> Result<Self> {
+        let mut config: Self = toml::from_str(source).context("Invalid dolly configuration")?;
+        config.defaults = config
+            .defaults
+            .into_iter()
+            .map(|(rtype, attributes)| (normalize_rtype(&rtype), attributes))
+            .collect();
+        config.exec_policy()?;
+        Ok(config)
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let source =
+            fs::read_to_string(path).with_context(|| format!("Cannot read {}", path.display()))?;
+        Self::from_toml(&source).with_context(|| format!("Cannot load {}", path.display()))
+    }
+
+    /// The global Exec policy from the `[exec]` table.
+    pub fn exec_policy(&self) -> Result<ExecPolicy> {
+        let attributes = self
+            .exec
+            .iter()
+            .map(
+                |(name, value)| match ExecPolicy::ATTRIBUTES.contains(&name.as_str()) {
+                    true => Ok(Attribute {
+                        name: name.clone(),
+                        value: value.to_attr_value(),
+                    }),
+                    false => Err(anyhow!("Unknown exec policy setting {name}")),
+                },
+            )
+            .collect::<Result<Vec<_>>>()?;
+        ExecPolicy::from_attributes(&attributes).context("Invalid exec policy")
+    }
+
+    /// `manifest` with the configured defaults added to every resource that does not set
+    /// the attribute itself.
+    pub fn with_defaults(&self, manifest: &Manifest) -> Manifest {
+        let expressions = manifest
+            .0
+            .iter()
+            .cloned()
+            .map(|mut expr| {
+                if let PuppetExpr::Resource {
+                    rtype, attributes, ..
+                } = &mut expr
+                    && let Some(defaults) = self.defaults.get(rtype)
+                {
+                    for (name, value) in defaults {
+                        if !attributes.iter().any(|attr| attr.name == *name) {
+                            attributes.push(Attribute {
+                                name: name.clone(),
+                                value: value.to_attr_value(),
+                            });
+                        }
+                    }
+                }
+                expr
+            })
+            .collect();
+        Manifest(expressions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_defaults_do_not_override_resources() -> Result<()> {
+        let config = DollyConfig::from_toml(
+            r#"
+            [defaults.file]
+            backup = true
+            mode = "0644"
+
+            [defaults.Exec]
+            timeout = 300
+            "#,
+        )?;
+        let manifest = Manifest::from_str(
+            r#"
+            file { "/etc/motd": mode => "0600" }
+            exec { "/bin/true": }
+            service { "nginx": }
+            "#,
+        )?;
+        let injected = config.with_defaults(&manifest);
+        let attributes: Vec<Vec<String>> = injected
+            .resources()
+            .map(|expr| match expr {
+                PuppetExpr::Resource { attributes, .. } => attributes
+                    .iter()
+                    .map(|attr| format!("{} => {}", attr.name, attr.value))
+                    .collect(),
+                _ => vec![],
+            })
+            .collect();
+        assert_eq!(
+            attributes,
+            vec![
+                vec!["mode => 0600", "backup => true"],
+                vec!["timeout => 300"],
+                vec![],
+            ],
+            "Resource attributes take precedence over configured defaults"
+        );
+        assert!(DollyConfig::from_toml("[default.File]\nbackup = true").is_err());
+        let agent =
+            DollyConfig::from_toml("[agent]\nsplay = 60\nmaintenance_windows = [\"* 2 * * *\"]")?
+                .agent;
+        assert_eq!((agent.interval, agent.splay), (1800, 60));
+        assert!(DollyConfig::from_toml("[agent]\nmaintenance_windows = [\"* 25 * * *\"]").is_err());
+
+        let policy = DollyConfig::from_toml("[exec]\nuser = \"nobody\"\nulimit_memory = \"1M\"")?
+            .exec_policy()?;
+        assert_eq!(policy.user.as_deref(), Some("nobody"));
+        assert_eq!(policy.memory_kb, Some(1024));
+        assert!(DollyConfig::from_toml("[exec]\ncommand = \"rm -rf /\"").is_err());
+        assert!(DollyConfig::from_toml("[exec]\ntimeout = \"soon\"").is_err());
+        Ok(())
+    }
+}
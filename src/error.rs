@@ -0,0 +1,143 @@
+//! The failures of parsing and compiling a manifest that callers may want to tell apart,
+//! returned by [`Manifest::from_str`] and the `parse_puppet_manifest` functions:
+//!
+//! ```
+//! # use dolly::{DollyError, parse_puppet_manifest};
+//! # use dolly::parser::pp::Manifest;
+//! let manifest: Manifest = "file { '/a': }\nfile { '/a': }".parse()?;
+//! let error = parse_puppet_manifest(&manifest).err();
+//! assert!(matches!(error, Some(DollyError::DuplicateResource { id, .. }) if id == "File[/a]"));
+//! # Ok::<(), DollyError>(())
+//! ```
+//!
+//! Other failures are [`DollyError::Other`], with their message.
+//!
+//! [`Manifest::from_str`]: crate::parser::pp::Manifest
+
+use crate::parser::diagnostic::Diagnostics;
+use crate::parser::pp::{ResourceRef, Span};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum DollyError {
+    /// Syntax errors and references to undeclared resources, each with the source line it
+    /// is on. `span` is where the first one is.
+    #[error("{diagnostics}")]
+    ParseError {
+        span: Span,
+        diagnostics: Diagnostics,
+    },
+    /// Relations to resources the manifest does not declare.
+    #[error("{}", unknown_resources(.references))]
+    UnknownResource { references: Vec<ResourceRef> },
+    /// Relations that would make a resource depend on itself, as the ids of the resources
+    /// on the cycle, which starts and ends with the same one.
+    #[error("Dependency cycle: {}{}", .path.join(" -> "), closing(.closed_by))]
+    CycleDetected {
+        path: Vec<String>,
+        /// The relation refused because it would close the cycle.
+        closed_by: Option<String>,
+    },
+    /// A resource declared twice; `declared` says where it first was.
+    #[error("Duplicate declaration: {id} is already {declared}")]
+    DuplicateResource { id: String, declared: String },
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+fn unknown_resources(references: &[ResourceRef]) -> String {
+    references
+        .iter()
+        .map(|reference| format!("Unknown resource: {} at {}", reference.id(), reference.span))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn closing(relation: &Option<String>) -> String {
+    relation
+        .as_ref()
+        .map(|relation| format!(" (closed by {relation})"))
+        .unwrap_or_default()
+}
+
+impl DollyError {
+    /// The located problems of a [`DollyError::ParseError`].
+    pub fn diagnostics(&self) -> Option<&Diagnostics> {
+        match self {
+            Self::ParseError { diagnostics, .. } => Some(diagnostics),
+            _ => None,
+        }
+    }
+}
+
+impl From<Diagnostics> for DollyError {
+    fn from(diagnostics: Diagnostics) -> Self {
+        Self::ParseError {
+            span: diagnostics.0.first().map(|d| d.span).unwrap_or_default(),
+            diagnostics,
+        }
+    }
+}
+
+/// Recovers the kind of failure carried along by `anyhow`, so that the errors of nested
+/// steps keep their variant.
+impl From<anyhow::Error> for DollyError {
+    fn from(error: anyhow::Error) -> Self {
+        match error.downcast::<Self>() {
+            Ok(error) => error,
+            Err(error) => match error.downcast::<Diagnostics>() {
+                Ok(diagnostics) => diagnostics.into(),
+                Err(error) => Self::Other(error),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_puppet_manifest;
+    use crate::parser::pp::Manifest;
+    use anyhow::{Result, anyhow};
+
+    fn compile(source: &str) -> Result<DollyError> {
+        let manifest = Manifest::from_str_lenient(source)?;
+        match parse_puppet_manifest(&manifest) {
+            Ok(_) => Err(anyhow!("{source} compiles")),
+            Err(e) => Ok(e),
+        }
+    }
+
+    #[test]
+    fn test_failures_can_be_matched() -> Result<()> {
+        let Err(DollyError::ParseError { span, .. }) = r#"file { "/a" }"#.parse::<Manifest>()
+        else {
+            return Err(anyhow!("A syntax error is a parse error"));
+        };
+        assert_eq!((span.line, span.col), (1, 11));
+
+        let DollyError::UnknownResource { references } =
+            compile("file { '/a': require => Service['x'] }")?
+        else {
+            return Err(anyhow!("Service[x] is not declared"));
+        };
+        assert_eq!(references[0].id(), "Service[x]");
+
+        let e = compile(
+            "file { '/a': }\nfile { '/b': }\nFile['/a'] -> File['/b']\nFile['/b'] -> File['/a']",
+        )?;
+        let DollyError::CycleDetected { path, closed_by } = &e else {
+            return Err(anyhow!("The relations form a cycle, got {e}"));
+        };
+        assert_eq!(path, &["File[/b]", "File[/a]", "File[/b]"]);
+        assert!(closed_by.is_some());
+
+        let nested = anyhow::Error::from(e).context("While compiling");
+        assert!(
+            matches!(DollyError::from(nested), DollyError::CycleDetected { .. }),
+            "The kind survives being carried by anyhow"
+        );
+        assert!(matches!(compile("foo { 'x': }")?, DollyError::Other(_)));
+        Ok(())
+    }
+}
@@ -0,0 +1,264 @@
+//! Custom facts from the files of a `facts.d` directory. Executables print facts, as
+//! Facter's external facts, either as a JSON object or as `name=value` lines; `.json` and
+//! `.txt` files hold them. Nested JSON objects become dotted facts, so that
+//! `{"inventory": {"rack": "B4"}}` is `$facts['inventory']['rack']`.
+//!
+//! Slow sources can be cached, for a number of seconds per file name:
+//!
+//! ```toml
+//! [facts]
+//! cache = "/var/cache/dolly/facts.json"
+//!
+//! [facts.ttls]
+//! "inventory.sh" = 3600
+//! ```
+
+use super::Facts;
+use anyhow::{Context, Result, anyhow};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Where cached custom facts are kept by default.
+pub const FACT_CACHE: &str = "/var/cache/dolly/facts.json";
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FactsConfig {
+    /// File holding the facts of the sources with a TTL.
+    pub cache: PathBuf,
+    /// Seconds the facts of a source are reused before it is run again, by file name.
+    /// Sources without one run on every compile.
+    pub ttls: IndexMap<String, u64>,
+}
+
+impl Default for FactsConfig {
+    fn default() -> Self {
+        Self {
+            cache: FACT_CACHE.into(),
+            ttls: IndexMap::new(),
+        }
+    }
+}
+
+/// The facts a source gave, and when.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct CachedFacts {
+    /// Seconds since the epoch.
+    gathered: u64,
+    facts: IndexMap<String, String>,
+}
+
+/// Cached facts by source file name.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct FactCache(IndexMap<String, CachedFacts>);
+
+impl FactCache {
+    fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json)
+                .with_context(|| format!("Invalid fact cache {}", path.display())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Cannot read {}", path.display())),
+        }
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut temporary = PathBuf::from(path).into_os_string();
+        temporary.push(".tmp");
+        fs::write(&temporary, serde_json::to_string_pretty(self)?)
+            .and_then(|()| fs::rename(&temporary, path))
+            .with_context(|| format!("Cannot write fact cache {}", path.display()))
+    }
+
+    /// The facts of `source` if they were gathered less than `ttl` before `now`.
+    fn fresh(&self, source: &str, ttl: Duration, now: SystemTime) -> Option<Facts> {
+        let cached = self.0.get(source)?;
+        let age = now
+            .duration_since(UNIX_EPOCH + Duration::from_secs(cached.gathered))
+            .ok()?;
+        (age < ttl).then(|| Facts(cached.facts.clone().into_iter().collect()))
+    }
+}
+
+impl Facts {
+    /// Facts from the files of `dir`, in name order, none of them cached. A script that
+    /// fails fails the gathering, since compiling without one of its facts could change
+    /// the plan silently.
+    pub fn custom(dir: &Path) -> Result<Self> {
+        Self::custom_with(dir, &FactsConfig::default(), SystemTime::now())
+    }
+
+    /// Facts from the files of `dir`, reusing the cached facts of sources whose TTL has
+    /// not expired at `now`.
+    pub fn custom_with(dir: &Path, config: &FactsConfig, now: SystemTime) -> Result<Self> {
+        let mut paths: Vec<_> = fs::read_dir(dir)
+            .with_context(|| format!("Cannot read {}", dir.display()))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<_>>()?;
+        paths.sort();
+        let mut cache = match config.ttls.is_empty() {
+            true => FactCache::default(),
+            false => FactCache::load(&config.cache)?,
+        };
+        let mut refreshed = false;
+        let mut facts = Self::new();
+        for path in paths.iter().filter(|path| path.is_file()) {
+            let source = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let ttl = config.ttls.get(&source).copied().map(Duration::from_secs);
+            if let Some(ttl) = ttl
+                && let Some(cached) = cache.fresh(&source, ttl, now)
+            {
+                facts.merge(cached);
+                continue;
+            }
+            let Some(gathered) = Self::from_source(path)? else {
+                continue;
+            };
+            if ttl.is_some() {
+                let gathered_at = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+                cache.0.insert(
+                    source,
+                    CachedFacts {
+                        gathered: gathered_at.as_secs(),
+                        facts: gathered.0.clone().into_iter().collect(),
+                    },
+                );
+                refreshed = true;
+            }
+            facts.merge(gathered);
+        }
+        if refreshed {
+            cache.save(&config.cache)?;
+        }
+        Ok(facts)
+    }
+
+    /// The facts of one file of a `facts.d` directory, `None` for files that are not fact
+    /// sources.
+    fn from_source(path: &Path) -> Result<Option<Self>> {
+        let executable = fs::metadata(path)?.permissions().mode() & 0o111 != 0;
+        let extension = path.extension().and_then(|e| e.to_str());
+        let text = match (executable, extension) {
+            (true, _) => {
+                let output = Command::new(path)
+                    .output()
+                    .with_context(|| format!("Cannot run fact script {}", path.display()))?;
+                if !output.status.success() {
+                    return Err(anyhow!(
+                        "Fact script {} failed: {}",
+                        path.display(),
+                        String::from_utf8_lossy(&output.stderr).trim_end()
+                    ));
+                }
+                String::from_utf8_lossy(&output.stdout).into_owned()
+            }
+            (false, Some("txt" | "json")) => fs::read_to_string(path)?,
+            (false, _) => return Ok(None),
+        };
+        Self::parse_custom(&text)
+            .map(Some)
+            .with_context(|| format!("Invalid facts from {}", path.display()))
+    }
+
+    /// A JSON object, or `name=value` lines.
+    fn parse_custom(text: &str) -> Result<Self> {
+        if !text.trim_start().starts_with('{') {
+            let lines = text
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'));
+            return Self::from_overrides(lines);
+        }
+        let json: Value = serde_json::from_str(text)?;
+        let mut facts = Self::new();
+        flatten("", &json, &mut facts);
+        Ok(facts)
+    }
+}
+
+/// Adds the facts of `value` named `name`: nested objects as dotted facts, strings as they
+/// are, other values as JSON. `null` is no fact.
+fn flatten(name: &str, value: &Value, facts: &mut Facts) {
+    match value {
+        Value::Object(entries) => {
+            for (key, value) in entries {
+                match name.is_empty() {
+                    true => flatten(key, value, facts),
+                    false => flatten(&format!("{name}.{key}"), value, facts),
+                }
+            }
+        }
+        Value::Null => {}
+        Value::String(text) => facts.insert(name, text.clone()),
+        value => facts.insert(name, value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_json_facts_are_cached_for_their_ttl() -> Result<()> {
+        let root = env::temp_dir().join(format!("dolly-custom-facts-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let dir = root.join("facts.d");
+        fs::create_dir_all(&dir)?;
+        let counter = root.join("runs");
+        fs::write(
+            dir.join("inventory.sh"),
+            format!(
+                "#!/bin/sh\necho run >> {}\n\
+                 echo '{{\"inventory\": {{\"rack\": \"B4\", \"disks\": 2, \"ssd\": true, \
+                 \"tags\": [\"db\"], \"note\": null}}}}'\n",
+                counter.display()
+            ),
+        )?;
+        fs::set_permissions(dir.join("inventory.sh"), fs::Permissions::from_mode(0o755))?;
+        fs::write(dir.join("site.json"), r#"{"site": {"name": "par1"}}"#)?;
+        fs::write(dir.join("role.txt"), "role=db\n")?;
+
+        let config = FactsConfig {
+            cache: root.join("cache/facts.json"),
+            ttls: IndexMap::from([("inventory.sh".to_string(), 60)]),
+        };
+        let runs = || fs::read_to_string(&counter).map(|runs| runs.lines().count());
+        let start = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let facts = Facts::custom_with(&dir, &config, start)?;
+        assert_eq!(facts.get("inventory.rack"), Some("B4"));
+        assert_eq!(facts.get("inventory.disks"), Some("2"));
+        assert_eq!(facts.get("inventory.ssd"), Some("true"));
+        assert_eq!(facts.get("inventory.tags"), Some(r#"["db"]"#));
+        assert_eq!(facts.get("inventory.note"), None);
+        assert_eq!(facts.get("site.name"), Some("par1"));
+        assert_eq!(facts.get("role"), Some("db"));
+        assert_eq!(runs()?, 1);
+
+        let cached = Facts::custom_with(&dir, &config, start + Duration::from_secs(59))?;
+        assert_eq!(cached, facts, "Cached facts are the gathered ones");
+        assert_eq!(runs()?, 1, "The script is not run within its TTL");
+        Facts::custom_with(&dir, &config, start + Duration::from_secs(60))?;
+        assert_eq!(runs()?, 2, "The script runs again once the TTL expired");
+        Facts::custom(&dir)?;
+        assert_eq!(runs()?, 3, "Without a TTL the script always runs");
+
+        fs::write(dir.join("broken.json"), "{\"site\": ")?;
+        assert!(Facts::custom_with(&dir, &config, start).is_err());
+        fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,350 @@
+//! Facts about the node: gathered from the system and from custom fact scripts, then
+//! overridden from the environment and the command line. Manifests read them as
+//! `$facts['os']['family']`, `"${facts.os.family}"` or with the legacy flat names, e.g.
+//! `$::osfamily`.
+
+mod custom;
+
+pub use custom::{FACT_CACHE, FactsConfig};
+
+use anyhow::{Result, anyhow};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Prefix of environment variables overriding facts, e.g. `DOLLY_FACT_OSFAMILY=Debian`.
+pub const ENV_PREFIX: &str = "DOLLY_FACT_";
+
+/// Where custom fact scripts are looked for by default.
+pub const FACTS_DIR: &str = "/etc/dolly/facts.d";
+
+/// Facts about the node a plan is compiled for, keyed by (dotted) fact name.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Facts(pub BTreeMap<String, String>);
+
+impl Facts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.0.insert(name.into(), value.into());
+    }
+
+    /// The facts named in `names` and the ones below them, e.g. `os.family` for `os`.
+    pub fn restricted_to<'a>(&self, names: impl IntoIterator<Item = &'a String>) -> Facts {
+        let mut facts = Self::new();
+        for name in names {
+            let prefix = format!("{name}.");
+            for (fact, value) in &self.0 {
+                if fact == name || fact.starts_with(&prefix) {
+                    facts.insert(fact.clone(), value.clone());
+                }
+            }
+        }
+        facts
+    }
+
+    /// Overlays `other`, whose facts win on conflicts.
+    pub fn merge(&mut self, other: Facts) {
+        self.0.extend(other.0);
+    }
+
+    /// Parses `key=value` overrides as given with `--fact`.
+    pub fn from_overrides<'a>(overrides: impl IntoIterator<Item = &'a str>) -> Result<Self> {
+        let mut facts = Self::new();
+        for fact in overrides {
+            let Some((name, value)) = fact.split_once('=') else {
+                return Err(anyhow!(
+                    "Invalid fact override '{fact}', expected key=value"
+                ));
+            };
+            if name.trim().is_empty() {
+                return Err(anyhow!("Invalid fact override '{fact}', the key is empty"));
+            }
+            facts.insert(name.trim(), value);
+        }
+        Ok(facts)
+    }
+
+    /// Collects `DOLLY_FACT_*` overrides from `vars`, with lowercased fact names.
+    pub fn from_env_vars(vars: impl IntoIterator<Item = (String, String)>) -> Self {
+        let mut facts = Self::new();
+        for (key, value) in vars {
+            if let Some(name) = key.strip_prefix(ENV_PREFIX)
+                && !name.is_empty()
+            {
+                facts.insert(name.to_lowercase(), value);
+            }
+        }
+        facts
+    }
+
+    pub fn from_env() -> Self {
+        Self::from_env_vars(env::vars())
+    }
+
+    /// The facts of this node: the system ones, overlaid by the custom facts of `custom_dir`
+    /// when it exists, cached as `config` says.
+    pub fn gather(custom_dir: &Path, config: &FactsConfig) -> Result<Self> {
+        let mut facts = Self::system(Path::new("/"));
+        if custom_dir.is_dir() {
+            facts.merge(Self::custom_with(custom_dir, config, SystemTime::now())?);
+        }
+        Ok(facts)
+    }
+
+    /// Hostname, kernel, operating system, memory and processors, read from `/proc` and
+    /// `/etc/os-release` under `root`. Facts that cannot be read are left out.
+    pub fn system(root: &Path) -> Self {
+        let read = |path: &str| {
+            fs::read_to_string(root.join(path))
+                .ok()
+                .map(|text| text.trim().to_string())
+                .filter(|text| !text.is_empty())
+        };
+        let mut facts = Self::new();
+        let mut set = |names: &[&str], value: &str| {
+            for name in names {
+                facts.insert(*name, value);
+            }
+        };
+
+        if let Some(hostname) = read("proc/sys/kernel/hostname").or_else(|| read("etc/hostname")) {
+            let fqdn = match read("proc/sys/kernel/domainname") {
+                Some(domain) if domain != "(none)" => format!("{hostname}.{domain}"),
+                _ => hostname.clone(),
+            };
+            set(&["hostname", "networking.hostname"], &hostname);
+            set(&["fqdn", "networking.fqdn"], &fqdn);
+        }
+        if let Some(kernel) = read("proc/sys/kernel/ostype") {
+            set(&["kernel"], &kernel);
+        }
+        if let Some(release) = read("proc/sys/kernel/osrelease") {
+            set(&["kernelrelease"], &release);
+            let major: Vec<_> = release.split('.').take(2).collect();
+            set(&["kernelmajversion"], &major.join("."));
+        }
+        let arch = env::consts::ARCH;
+        set(&["architecture", "hardwaremodel", "os.architecture"], arch);
+
+        if let Some(os_release) = read("etc/os-release") {
+            let release = parse_os_release(&os_release);
+            let get = |key: &str| release.get(key).map(String::as_str).unwrap_or_default();
+            let (name, family) = os_name_and_family(get("ID"), get("ID_LIKE"), get("NAME"));
+            set(&["os.name", "operatingsystem"], &name);
+            if let Some(family) = family {
+                set(&["os.family", "osfamily"], family);
+            }
+            if let Some(version) = release.get("VERSION_ID") {
+                let major = version.split('.').next().unwrap_or(version);
+                set(&["os.release.full", "operatingsystemrelease"], version);
+                set(&["os.release.major", "operatingsystemmajrelease"], major);
+            }
+        }
+
+        if let Some(meminfo) = read("proc/meminfo") {
+            for (key, names) in [
+                ("MemTotal", ["memory.system.total_bytes", "memorysize_mb"]),
+                (
+                    "MemAvailable",
+                    ["memory.system.available_bytes", "memoryfree_mb"],
+                ),
+            ] {
+                let kib = meminfo.lines().find_map(|line| {
+                    let rest = line.strip_prefix(key)?.strip_prefix(':')?;
+                    rest.split_whitespace().next()?.parse::<u64>().ok()
+                });
+                if let Some(kib) = kib {
+                    facts.insert(names[0], (kib * 1024).to_string());
+                    facts.insert(names[1], (kib / 1024).to_string());
+                }
+            }
+        }
+        let processors = read("proc/cpuinfo")
+            .map(|cpuinfo| {
+                cpuinfo
+                    .lines()
+                    .filter(|line| line.starts_with("processor"))
+                    .count()
+            })
+            .filter(|count| *count > 0);
+        if let Some(count) = processors {
+            facts.insert("processors.count", count.to_string());
+            facts.insert("processorcount", count.to_string());
+        }
+        facts
+    }
+
+    /// `gathered` facts overlaid by environment overrides, then by command line overrides.
+    pub fn with_overrides(gathered: Facts, cli: &[String]) -> Result<Self> {
+        let mut facts = gathered;
+        facts.merge(Self::from_env());
+        facts.merge(Self::from_overrides(cli.iter().map(String::as_str))?);
+        Ok(facts)
+    }
+}
+
+/// The `KEY=value` lines of `/etc/os-release`, unquoted.
+fn parse_os_release(text: &str) -> BTreeMap<String, String> {
+    text.lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| {
+            let value = value.trim().trim_matches('"').trim_matches('\'');
+            (key.trim().to_string(), value.to_string())
+        })
+        .collect()
+}
+
+/// The operating system name and family, as Facter names them, from the `ID`, `ID_LIKE`
+/// and `NAME` of `/etc/os-release`.
+fn os_name_and_family(id: &str, id_like: &str, name: &str) -> (String, Option<&'static str>) {
+    let known = [
+        ("debian", "Debian", "Debian"),
+        ("ubuntu", "Ubuntu", "Debian"),
+        ("rhel", "RedHat", "RedHat"),
+        ("centos", "CentOS", "RedHat"),
+        ("fedora", "Fedora", "RedHat"),
+        ("rocky", "Rocky", "RedHat"),
+        ("almalinux", "AlmaLinux", "RedHat"),
+        ("amzn", "Amazon", "RedHat"),
+        ("sles", "SLES", "Suse"),
+        ("opensuse", "OpenSuSE", "Suse"),
+        ("suse", "SLES", "Suse"),
+        ("arch", "Archlinux", "Archlinux"),
+        ("alpine", "Alpine", "Alpine"),
+        ("gentoo", "Gentoo", "Gentoo"),
+    ];
+    let lookup = |id: &str| {
+        known
+            .iter()
+            .find(|(known, _, _)| id == *known || id.starts_with(&format!("{known}-")))
+    };
+    let os = match lookup(id) {
+        Some((_, os, _)) => os.to_string(),
+        None if !name.is_empty() => name.to_string(),
+        None => id.to_string(),
+    };
+    let family = std::iter::once(id)
+        .chain(id_like.split_whitespace())
+        .find_map(lookup)
+        .map(|(_, _, family)| *family);
+    (os, family)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_override_precedence() -> Result<()> {
+        let mut facts = Facts::new();
+        facts.insert("osfamily", "RedHat");
+        facts.insert("hostname", "build-01");
+        facts.insert("kernel", "Linux");
+
+        facts.merge(Facts::from_env_vars([
+            ("DOLLY_FACT_OSFAMILY".to_string(), "Debian".to_string()),
+            ("DOLLY_FACT_HOSTNAME".to_string(), "web-01".to_string()),
+            ("HOME".to_string(), "/root".to_string()),
+        ]));
+        facts.merge(Facts::from_overrides(["hostname=web-02", "role=web=1"])?);
+
+        assert_eq!(facts.get("osfamily"), Some("Debian"));
+        assert_eq!(facts.get("hostname"), Some("web-02"), "CLI wins over env");
+        assert_eq!(facts.get("kernel"), Some("Linux"));
+        assert_eq!(facts.get("role"), Some("web=1"));
+        assert_eq!(facts.get("home"), None);
+        assert!(Facts::from_overrides(["novalue"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_gathered_facts_resolve_variables() -> Result<()> {
+        use crate::parse_puppet_manifest_with_options;
+        use crate::parser::pp::Manifest;
+        use crate::{CompileOptions, resources::Resource};
+
+        let root = env::temp_dir().join(format!("dolly-facts-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for (path, content) in [
+            ("proc/sys/kernel/hostname", "web-01\n"),
+            ("proc/sys/kernel/domainname", "example.com\n"),
+            ("proc/sys/kernel/ostype", "Linux\n"),
+            (
+                "proc/meminfo",
+                "MemTotal:        2048000 kB\nMemFree: 1 kB\n",
+            ),
+            (
+                "etc/os-release",
+                "ID=rocky\nID_LIKE=\"rhel centos fedora\"\nVERSION_ID=\"9.3\"\n",
+            ),
+            ("facts.d/role.txt", "role=web\n"),
+            ("facts.d/zone", "#!/bin/sh\necho zone=eu-west\n"),
+        ] {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap_or(&root))?;
+            fs::write(&path, content)?;
+        }
+        fs::set_permissions(root.join("facts.d/zone"), fs::Permissions::from_mode(0o755))?;
+
+        let mut facts = Facts::system(&root);
+        facts.merge(Facts::custom(&root.join("facts.d"))?);
+        assert_eq!(facts.get("fqdn"), Some("web-01.example.com"));
+        assert_eq!(facts.get("os.family"), Some("RedHat"));
+        assert_eq!(facts.get("operatingsystem"), Some("Rocky"));
+        assert_eq!(facts.get("os.release.major"), Some("9"));
+        assert_eq!(facts.get("memorysize_mb"), Some("2000"));
+        assert_eq!(facts.get("zone"), Some("eu-west"));
+
+        let manifest: Manifest = r#"
+            define motd($text) { file { "/etc/motd": content => "${text} on ${::hostname}" } }
+            motd { "main": text => "${facts.role} in ${facts.zone}" }
+            file { "/etc/${facts.os.family}.release": content => $::operatingsystemrelease }
+            service { "app": enable => $::missing }
+        "#
+        .parse()?;
+        let options = CompileOptions {
+            facts,
+            ..CompileOptions::default()
+        };
+        let plan = parse_puppet_manifest_with_options(&manifest, &options)?;
+        let attribute = |id: &str, name: &str| -> Result<Option<String>> {
+            let index = plan
+                .node(id)
+                .ok_or_else(|| anyhow!("{id} is in the plan"))?;
+            let resource: &dyn Resource = plan.plan().inner()[index].as_ref();
+            Ok(resource.attribute(name))
+        };
+        assert_eq!(
+            attribute("File[/etc/motd]", "content")?.as_deref(),
+            Some("web in eu-west on web-01")
+        );
+        assert_eq!(
+            attribute("File[/etc/RedHat.release]", "content")?.as_deref(),
+            Some("9.3")
+        );
+        assert_eq!(
+            attribute("Service[app]", "enable")?,
+            None,
+            "Missing facts are undef"
+        );
+
+        fs::write(root.join("facts.d/broken"), "#!/bin/sh\nexit 3\n")?;
+        fs::set_permissions(
+            root.join("facts.d/broken"),
+            fs::Permissions::from_mode(0o755),
+        )?;
+        assert!(Facts::custom(&root.join("facts.d")).is_err());
+        fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,351 @@
+//! Data kept out of manifests, looked up in a hierarchy of YAML files as Hiera does. The
+//! hierarchy is read from a `hiera.yaml`:
+//!
+//! ```yaml
+//! version: 5
+//! defaults:
+//!   datadir: data
+//! hierarchy:
+//!   - name: Per node
+//!     path: "nodes/%{facts.fqdn}.yaml"
+//!   - name: Per OS family
+//!     path: "os/%{facts.os.family}.yaml"
+//!   - name: Common
+//!     path: common.yaml
+//! ```
+//!
+//! Manifests read it with `lookup('ntp::servers')`, and class parameters without a
+//! declared value are looked up as `<class>::<parameter>`. The first level that has a key
+//! wins; levels whose path uses a fact the node does not have are skipped.
+
+use crate::parser::pp::{AttrValue, PuppetString};
+use anyhow::{Context, Result, anyhow};
+use indexmap::IndexMap;
+use serde::Deserialize;
+use serde_yaml::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    version: Option<u8>,
+    #[serde(default)]
+    defaults: Defaults,
+    #[serde(default)]
+    hierarchy: Vec<LevelConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Defaults {
+    datadir: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LevelConfig {
+    name: String,
+    path: Option<String>,
+    #[serde(default)]
+    paths: Vec<String>,
+    datadir: Option<PathBuf>,
+}
+
+/// One level of the hierarchy: data files whose paths interpolate facts, `%{facts.fqdn}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Level {
+    pub name: String,
+    pub datadir: PathBuf,
+    pub paths: Vec<String>,
+}
+
+/// A data hierarchy, empty when none is configured: every lookup then misses.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Hiera {
+    pub levels: Vec<Level>,
+}
+
+impl Hiera {
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let source =
+            fs::read_to_string(path).with_context(|| format!("Cannot read {}", path.display()))?;
+        Self::from_yaml(&source, path.parent().unwrap_or(Path::new(".")))
+            .with_context(|| format!("Cannot load {}", path.display()))
+    }
+
+    /// Reads a hierarchy whose relative data directories are under `base`.
+    pub fn from_yaml(source: &str, base: &Path) -> Result<Self> {
+        let config: Config = serde_yaml::from_str(source).context("Invalid hierarchy")?;
+        if let Some(version) = config.version
+            && version != 5
+        {
+            return Err(anyhow!(
+                "Unsupported hierarchy version {version}, expected 5"
+            ));
+        }
+        let default_datadir = config.defaults.datadir.unwrap_or_else(|| "data".into());
+        let levels = config
+            .hierarchy
+            .into_iter()
+            .map(|level| {
+                let mut paths = level.paths;
+                paths.extend(level.path);
+                if paths.is_empty() {
+                    return Err(anyhow!("Hierarchy level {} has no path", level.name));
+                }
+                let datadir = level.datadir.unwrap_or_else(|| default_datadir.clone());
+                Ok(Level {
+                    name: level.name,
+                    datadir: base.join(datadir),
+                    paths,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { levels })
+    }
+
+    /// The files data may come from, so that changes to them can be noticed.
+    pub fn sources(&self) -> Vec<PathBuf> {
+        fn walk(dir: &Path, files: &mut Vec<PathBuf>) {
+            let Ok(entries) = fs::read_dir(dir) else {
+                return;
+            };
+            for path in entries.flatten().map(|entry| entry.path()) {
+                match path.is_dir() {
+                    true => walk(&path, files),
+                    false => files.push(path),
+                }
+            }
+        }
+        let mut files = vec![];
+        for level in &self.levels {
+            walk(&level.datadir, &mut files);
+        }
+        files.sort();
+        files.dedup();
+        files
+    }
+
+    /// The value of `key` in the first data file of the hierarchy that has it. Keys are
+    /// whole, `ntp::servers`, or dig into hashes, `app.port`. `fact` gives the text of the
+    /// facts paths and values interpolate.
+    pub fn lookup(
+        &self,
+        key: &str,
+        fact: &dyn Fn(&str) -> Option<String>,
+    ) -> Result<Option<AttrValue>> {
+        for level in &self.levels {
+            for path in &level.paths {
+                let Some(path) = interpolate(path, fact, false) else {
+                    continue;
+                };
+                let file = level.datadir.join(path);
+                if !file.is_file() {
+                    continue;
+                }
+                let source = fs::read_to_string(&file)
+                    .with_context(|| format!("Cannot read {}", file.display()))?;
+                let data: Value = serde_yaml::from_str(&source)
+                    .with_context(|| format!("Invalid data in {}", file.display()))?;
+                if let Some(value) = dig(&data, key) {
+                    return to_attr_value(value, fact)
+                        .map(Some)
+                        .with_context(|| format!("Invalid {key} in {}", file.display()));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// The value of `key` in `data`, whole or dotted.
+fn dig<'v>(data: &'v Value, key: &str) -> Option<&'v Value> {
+    if let Some(value) = data.get(key) {
+        return Some(value);
+    }
+    key.split('.')
+        .try_fold(data, |value, segment| value.get(segment))
+}
+
+/// `text` with its `%{fact}` replaced. A missing fact makes the whole text `None` unless
+/// `lenient`, where it is empty.
+fn interpolate(text: &str, fact: &dyn Fn(&str) -> Option<String>, lenient: bool) -> Option<String> {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("%{") {
+        result.push_str(&rest[..start]);
+        let end = rest[start..].find('}')? + start;
+        let name = rest[start + 2..end].trim().trim_start_matches("::");
+        let name = name.strip_prefix("facts.").unwrap_or(name);
+        match fact(name) {
+            Some(value) => result.push_str(&value),
+            None if lenient => {}
+            None => return None,
+        }
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Some(result)
+}
+
+fn to_attr_value(value: &Value, fact: &dyn Fn(&str) -> Option<String>) -> Result<AttrValue> {
+    Ok(match value {
+        Value::Null => AttrValue::Undef,
+        Value::Bool(b) => AttrValue::Bool(*b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => AttrValue::Integer(i),
+            None => AttrValue::String(PuppetString::literal(&n.to_string())),
+        },
+        Value::String(s) => {
+            let text = interpolate(s, fact, true).unwrap_or_default();
+            AttrValue::String(PuppetString::literal(&text))
+        }
+        Value::Sequence(values) => AttrValue::Array(
+            values
+                .iter()
+                .map(|value| to_attr_value(value, fact))
+                .collect::<Result<_>>()?,
+        ),
+        Value::Mapping(mapping) => AttrValue::Hash(
+            mapping
+                .iter()
+                .map(|(key, value)| {
+                    let key = match key {
+                        Value::String(key) => key.clone(),
+                        Value::Bool(_) | Value::Number(_) => {
+                            serde_yaml::to_string(key)?.trim().to_string()
+                        }
+                        other => return Err(anyhow!("Unsupported hash key {other:?}")),
+                    };
+                    Ok((key, to_attr_value(value, fact)?))
+                })
+                .collect::<Result<IndexMap<_, _>>>()?,
+        ),
+        Value::Tagged(tagged) => to_attr_value(&tagged.value, fact)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::facts::Facts;
+    use crate::parser::pp::Manifest;
+    use crate::{CompileOptions, parse_puppet_manifest_with_options};
+    use std::env;
+
+    #[test]
+    fn test_hierarchy_lookups_and_class_parameters() -> Result<()> {
+        let dir = env::temp_dir().join(format!("dolly-hiera-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (path, content) in [
+            (
+                "hiera.yaml",
+                "version: 5\n\
+                 defaults:\n  datadir: data\n\
+                 hierarchy:\n\
+                 \x20 - name: Per node\n    path: \"nodes/%{facts.fqdn}.yaml\"\n\
+                 \x20 - name: Per OS family\n    path: \"os/%{facts.os.family}.yaml\"\n\
+                 \x20 - name: Common\n    path: common.yaml\n",
+            ),
+            (
+                "data/nodes/web-01.yaml",
+                "ntp::servers: [ntp.web.internal]\n",
+            ),
+            (
+                "data/os/Debian.yaml",
+                "motd: \"Debian %{facts.os.release.major} node\"\napp:\n  port: 8080\n",
+            ),
+            (
+                "data/common.yaml",
+                "ntp::servers: [pool.ntp.org]\nmotd: Generic\napp:\n  port: 80\n",
+            ),
+        ] {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap_or(&dir))?;
+            fs::write(path, content)?;
+        }
+        let hiera = Hiera::from_path(&dir.join("hiera.yaml"))?;
+        assert_eq!(hiera.sources().len(), 3);
+
+        let manifest: Manifest = r#"
+            class ntp($servers, $service = 'ntpd') {
+                file { "/etc/ntp.conf": content => "${servers}" }
+                service { "${service}": }
+            }
+            include ntp
+            file { "/etc/motd": content => lookup('motd') }
+            file { "/etc/app.port": content => lookup('app.port') }
+            file { "/etc/owner": content => lookup('owner', String, 'first', 'nobody') }
+        "#
+        .parse()?;
+        let compile = |facts: &[(&str, &str)]| {
+            let mut options = CompileOptions {
+                hiera: hiera.clone(),
+                ..CompileOptions::default()
+            };
+            for (name, value) in facts {
+                options.facts.insert(*name, *value);
+            }
+            parse_puppet_manifest_with_options(&manifest, &options)
+        };
+        let content = |plan: &crate::Plan, id: &str| {
+            plan.node(id).and_then(|index| {
+                let value = plan.plan().inner()[index].attributes().get("content")?;
+                Some(value.as_literal().unwrap_or_else(|| value.to_string()))
+            })
+        };
+
+        let debian = compile(&[
+            ("fqdn", "db-01"),
+            ("os.family", "Debian"),
+            ("os.release.major", "12"),
+        ])?;
+        assert_eq!(
+            content(&debian, "File[/etc/ntp.conf]").as_deref(),
+            Some("['pool.ntp.org']"),
+            "Class parameters are bound from the data"
+        );
+        assert!(
+            debian.node("Service[ntpd]").is_some(),
+            "Defaults still apply"
+        );
+        assert_eq!(
+            content(&debian, "File[/etc/motd]").as_deref(),
+            Some("Debian 12 node")
+        );
+        assert_eq!(
+            content(&debian, "File[/etc/app.port]").as_deref(),
+            Some("8080")
+        );
+        assert_eq!(
+            content(&debian, "File[/etc/owner]").as_deref(),
+            Some("nobody")
+        );
+        assert!(debian.facts_used().contains("fqdn"));
+
+        let web = compile(&[("fqdn", "web-01")])?;
+        assert_eq!(
+            content(&web, "File[/etc/ntp.conf]").as_deref(),
+            Some("['ntp.web.internal']"),
+            "The most specific level wins"
+        );
+        assert_eq!(content(&web, "File[/etc/motd]").as_deref(), Some("Generic"));
+
+        let mut facts = Facts::new();
+        facts.insert("fqdn", "web-01");
+        let missing: Manifest = r#"file { "/etc/x": content => lookup('nope') }"#.parse()?;
+        let options = CompileOptions {
+            hiera: hiera.clone(),
+            facts,
+            ..CompileOptions::default()
+        };
+        let Err(e) = parse_puppet_manifest_with_options(&missing, &options) else {
+            return Err(anyhow!("A missing key without default fails"));
+        };
+        assert!(
+            e.to_string().contains("Cannot find nope in the hierarchy"),
+            "{e}"
+        );
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -1,6 +1,12 @@
 use anyhow::{Result, anyhow};
+use config::DollyConfig;
+pub use error::DollyError;
+use facts::Facts;
+use hiera::Hiera;
 use indexmap::IndexMap;
+use parser::classes::Evaluated;
 use parser::pp::{Manifest, PuppetExpr, RelationOp, ResourceRef};
+use parser::value::FromValue;
 use petgraph::{
     acyclic::Acyclic,
     algo::toposort,
@@ -9,24 +15,67 @@ use petgraph::{
     prelude::StableDiGraph,
     visit::NodeRef,
 };
-use resources::{Relation, Resource};
-use std::collections::HashMap;
+use plan::{Budget, Deny, Origin, Provenance, policy_violations};
+use resources::{Ensure, Relation, Resource, ResourceRegistry};
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
 
+pub mod agent;
+pub mod apply;
+pub mod audit;
+pub mod cache;
+pub mod config;
+pub mod error;
+pub mod facts;
+pub mod hiera;
 pub mod parser;
+pub mod plan;
+pub mod repl;
 pub mod resources;
+pub mod stats;
+pub mod system;
+pub mod testing;
 
 type Unchecked = StableDiGraph<Box<dyn Resource>, Relation>;
 type Checked = Acyclic<Unchecked>;
 
-pub struct Plan(Checked);
+pub struct Plan {
+    graph: Checked,
+    index: HashMap<String, NodeIndex>,
+    concurrency_groups: HashMap<NodeIndex, String>,
+    ensures: HashMap<NodeIndex, Ensure>,
+    provenance: HashMap<NodeIndex, Provenance>,
+    facts_used: BTreeSet<String>,
+}
 
 impl Plan {
     pub fn plan(&self) -> &Checked {
-        &self.0
+        &self.graph
+    }
+
+    pub fn node(&self, id: &str) -> Option<NodeIndex> {
+        self.index.get(id).copied()
+    }
+
+    /// The named mutex a resource declared with `concurrency_group => '...'`.
+    pub fn concurrency_group(&self, index: NodeIndex) -> Option<&str> {
+        self.concurrency_groups.get(&index).map(String::as_str)
+    }
+
+    /// The state a resource is brought to, from its `ensure` attribute. `Present` when it
+    /// has none, or one its type gives a meaning of its own.
+    pub fn ensure(&self, index: NodeIndex) -> Ensure {
+        self.ensures.get(&index).cloned().unwrap_or_default()
+    }
+
+    /// The facts the manifest read while compiling: another node, or this one later, gets
+    /// the same plan as long as these facts are the same. Empty for imported plans.
+    pub fn facts_used(&self) -> &BTreeSet<String> {
+        &self.facts_used
     }
 
     pub fn dot(&self) -> petgraph::dot::Dot<'_, &Unchecked> {
-        let g = self.0.inner();
+        let g = self.graph.inner();
         Dot::with_attr_getters(
             g,
             &[Config::NodeNoLabel, Config::EdgeNoLabel],
@@ -36,14 +85,14 @@ impl Plan {
     }
 
     pub fn sorted(&self) -> Result<Vec<NodeIndex>> {
-        toposort(self.0.inner(), None).map_err(|_| anyhow!("Plan is not acyclic"))
+        toposort(self.graph.inner(), None)
+            .map_err(|cycle| plan::cycle::cycle_error(self.graph.inner(), cycle.node_id()))
     }
 
     pub fn sorted_weights(&self) -> Result<IndexMap<NodeIndex, &dyn Resource>> {
         let mut weights = IndexMap::new();
-        let indices = toposort(self.0.inner(), None).map_err(|_| anyhow!("Plan is not acyclic"))?;
-        for index in indices {
-            let Some(node) = self.0.inner().node_weight(index) else {
+        for index in self.sorted()? {
+            let Some(node) = self.graph.inner().node_weight(index) else {
                 return Err(anyhow!("Node without weight"));
             };
             weights.insert(index, node.as_ref());
@@ -52,24 +101,217 @@ impl Plan {
     }
 }
 
-pub fn parse_puppet_manifest(manifest: &Manifest) -> Result<Plan> {
+pub fn parse_puppet_manifest(manifest: &Manifest) -> Result<Plan, DollyError> {
+    parse_puppet_manifest_with_registry(manifest, &ResourceRegistry::default())
+}
+
+/// Compiles like [`parse_puppet_manifest`], building resources with the factories of
+/// `registry` so that manifests may declare types dolly does not know.
+pub fn parse_puppet_manifest_with_registry(
+    manifest: &Manifest,
+    registry: &ResourceRegistry,
+) -> Result<Plan, DollyError> {
+    Ok(compile(&manifest.evaluate_classes()?, registry)?)
+}
+
+fn compile(evaluated: &Evaluated, registry: &ResourceRegistry) -> Result<Plan> {
+    let manifest = &evaluated.manifest;
+    check_opaque(manifest)?;
+    let mut registry = registry.clone();
+    for rtype in &evaluated.defines {
+        registry.register_defined(rtype);
+    }
     let mut resource_nodes = HashMap::new();
+    let mut concurrency_groups = HashMap::new();
+    let mut ensures = HashMap::new();
+    let mut provenance = HashMap::new();
 
     let mut acyclic = StableDiGraph::<Box<dyn Resource>, Relation>::new();
 
     for resource in manifest.resources() {
-        let resource_node: Box<dyn Resource> = resource.try_into()?;
+        let resource_node = registry.build(resource)?;
         let id = resource_node.id();
-        resource_nodes.insert(id.clone(), acyclic.add_node(resource_node));
+        if let Some(existing) = resource_nodes.get(&id) {
+            let declared = provenance
+                .get(existing)
+                .map(|origin: &Provenance| origin.to_string())
+                .unwrap_or_default();
+            return Err(DollyError::DuplicateResource { id, declared }.into());
+        }
+        let ensure = desired_ensure(resource_node.as_ref());
+        let index = acyclic.add_node(resource_node);
+        stats::global().resource_compiled(acyclic[index].rtype());
+        resource_nodes.insert(id.clone(), index);
+        if let Some(ensure) = ensure {
+            ensures.insert(index, ensure);
+        }
+        if let PuppetExpr::Resource { span, .. } = resource {
+            let mut origins: Vec<_> = evaluated
+                .classes
+                .get(&id)
+                .into_iter()
+                .flatten()
+                .map(|(name, span)| Origin::Class {
+                    name: name.clone(),
+                    span: *span,
+                })
+                .collect();
+            origins.push(Origin::Declared { span: *span });
+            provenance.insert(index, Provenance(origins));
+        }
+        if let PuppetExpr::Resource { attributes, .. } = resource
+            && let Some(attr) = attributes.iter().find(|a| a.name == "concurrency_group")
+        {
+            concurrency_groups.insert(index, attr.value.to_string());
+        }
     }
 
+    let metaparameters = manifest.metaparameter_relations()?;
+    let captures = manifest.capture_relations()?;
+    let relations: Vec<_> = manifest
+        .relations()
+        .chain(&metaparameters)
+        .chain(&captures)
+        .collect();
+    check_relation_endpoints(&relations, &resource_nodes)?;
+    check_captures(&acyclic, &captures, &resource_nodes)?;
+
     let mut acyclic =
         Acyclic::try_from_graph(acyclic).map_err(|_| anyhow!("Error creating acyclic graph."))?;
 
-    for relations in manifest.relations() {
-        add_relations(&mut acyclic, &resource_nodes, relations)?;
+    for relation in relations {
+        add_relations(&mut acyclic, &resource_nodes, relation)?;
+    }
+    add_autorequires(&mut acyclic);
+    Ok(Plan {
+        graph: acyclic,
+        index: resource_nodes,
+        concurrency_groups,
+        ensures,
+        provenance,
+        facts_used: evaluated.facts_used.clone(),
+    })
+}
+
+/// The `ensure` of `resource`, unless it has none or one that is not an [`Ensure`], as a
+/// custom type may.
+pub(crate) fn desired_ensure(resource: &dyn Resource) -> Option<Ensure> {
+    resource
+        .attributes()
+        .get("ensure")
+        .and_then(|value| Ensure::from_value(value).ok())
+}
+
+/// Limits and restrictions checked while compiling.
+#[derive(Debug, Clone, Default)]
+pub struct CompileOptions {
+    pub budget: Budget,
+    pub deny: Vec<Deny>,
+    pub config: DollyConfig,
+    pub registry: ResourceRegistry,
+    /// The facts of the node, which iterations like `$facts['networking']['interfaces']`
+    /// generate resources from.
+    pub facts: Facts,
+    /// Where `lookup` and class parameters find their data.
+    pub hiera: Hiera,
+    /// Where `template` and `epp` read template files; relative to the working directory
+    /// when empty.
+    pub templates: PathBuf,
+}
+
+/// Compiles like [`parse_puppet_manifest`] after adding the configured defaults, failing on
+/// denied constructs or when the plan exceeds its budget. Both are checked before the graph
+/// is built where possible.
+pub fn parse_puppet_manifest_with_options(
+    manifest: &Manifest,
+    options: &CompileOptions,
+) -> Result<Plan, DollyError> {
+    let evaluated = manifest.evaluate_with(&options.facts, &options.hiera, &options.templates)?;
+    let evaluated = Evaluated {
+        manifest: options.config.with_defaults(&evaluated.manifest),
+        classes: evaluated.classes,
+        defines: evaluated.defines,
+        facts_used: evaluated.facts_used,
+    };
+    let manifest = &evaluated.manifest;
+    let violations = policy_violations(manifest, &options.deny);
+    if !violations.is_empty() {
+        let lines: Vec<_> = violations.iter().map(|v| format!("  {v}")).collect();
+        return Err(anyhow!("Policy violations:\n{}", lines.join("\n")).into());
+    }
+    options
+        .budget
+        .check_resources(manifest.resources().count())?;
+    let plan = compile(&evaluated, &options.registry)?;
+    options.budget.check(&plan)?;
+    Ok(plan)
+}
+
+/// Refuses manifests with statements lenient parsing kept but dolly cannot compile.
+fn check_opaque(manifest: &Manifest) -> Result<()> {
+    let unsupported: Vec<_> = manifest
+        .opaque()
+        .filter_map(|expr| match expr {
+            PuppetExpr::Opaque { text, span } => {
+                let first_line = text.lines().next().unwrap_or_default();
+                Some(format!("Unsupported construct at {span}: {first_line}"))
+            }
+            _ => None,
+        })
+        .collect();
+    if !unsupported.is_empty() {
+        return Err(anyhow!(unsupported.join("\n")));
+    }
+    Ok(())
+}
+
+/// Reports every relation endpoint that is not a declared resource, not just the first.
+fn check_relation_endpoints(
+    relations: &[&PuppetExpr],
+    resource_nodes: &HashMap<String, NodeIndex>,
+) -> Result<()> {
+    let mut unknown: Vec<ResourceRef> = Vec::new();
+    for relation in relations {
+        if let PuppetExpr::Relation { from, to, .. } = relation {
+            for endpoint in from.iter().chain(to.iter()) {
+                let reported = unknown
+                    .iter()
+                    .any(|r| r.id() == endpoint.id() && r.span == endpoint.span);
+                if !resource_nodes.contains_key(&endpoint.id()) && !reported {
+                    unknown.push(endpoint.clone());
+                }
+            }
+        }
+    }
+    if !unknown.is_empty() {
+        return Err(DollyError::UnknownResource {
+            references: unknown,
+        }
+        .into());
     }
-    Ok(Plan(acyclic))
+    Ok(())
+}
+
+/// Fails when a resource uses the output of an Exec that does not keep it.
+fn check_captures(
+    graph: &Unchecked,
+    captures: &[PuppetExpr],
+    resource_nodes: &HashMap<String, NodeIndex>,
+) -> Result<()> {
+    for capture in captures {
+        if let PuppetExpr::Relation { from, to, .. } = capture
+            && let (Some(exec), Some(user)) = (from.first(), to.first())
+            && let Some(index) = resource_nodes.get(&exec.id())
+            && graph[*index].attribute("capture_output").as_deref() != Some("true")
+        {
+            return Err(anyhow!(
+                "{} uses the output of {}, which needs capture_output => true",
+                user.id(),
+                exec.id()
+            ));
+        }
+    }
+    Ok(())
 }
 
 fn add_relations(
@@ -79,6 +321,19 @@ fn add_relations(
 ) -> Result<()> {
     match relations {
         PuppetExpr::Resource { .. } => Err(anyhow!("Got resource, when expecting relation.")),
+        PuppetExpr::Opaque { .. } => Err(anyhow!(
+            "Got unsupported construct, when expecting relation."
+        )),
+        PuppetExpr::Class { .. } | PuppetExpr::Include { .. } => {
+            Err(anyhow!("Got class, when expecting relation."))
+        }
+        PuppetExpr::Define { .. } => Err(anyhow!("Got define, when expecting relation.")),
+        PuppetExpr::Each { .. } => Err(anyhow!("Got iteration, when expecting relation.")),
+        PuppetExpr::Conditional { .. } => Err(anyhow!("Got conditional, when expecting relation.")),
+        PuppetExpr::Assert { .. } => Err(anyhow!("Got assertion, when expecting relation.")),
+        PuppetExpr::Defaults { .. } => {
+            Err(anyhow!("Got resource defaults, when expecting relation."))
+        }
         PuppetExpr::Relation { from, to, op } => match op {
             RelationOp::Provide => {
                 try_add_edges_from_relation(acyclic, resource_nodes, from, to, Relation::Provide)
@@ -96,6 +351,29 @@ fn add_relations(
     }
 }
 
+/// Orders resources after the resources of their [`Resource::autorequire_types`]. Declared
+/// relations win: an autorequire that would close a cycle with them is left out.
+fn add_autorequires(graph: &mut Checked) {
+    let mut edges = vec![];
+    for index in graph.inner().node_indices() {
+        let types = graph.inner()[index].autorequire_types();
+        if types.is_empty() {
+            continue;
+        }
+        for other in graph.inner().node_indices() {
+            if other != index
+                && types.contains(&graph.inner()[other].rtype())
+                && graph.inner().find_edge(other, index).is_none()
+            {
+                edges.push((other, index));
+            }
+        }
+    }
+    for (from, to) in edges {
+        let _ = graph.try_add_edge(from, to, Relation::Provide);
+    }
+}
+
 fn try_add_edges_from_relation(
     graph: &mut Acyclic<StableDiGraph<Box<dyn Resource>, Relation>>,
     resource_nodes: &HashMap<String, NodeIndex>,
@@ -105,17 +383,37 @@ fn try_add_edges_from_relation(
 ) -> Result<()> {
     for from in froms {
         for to in tos {
+            let unknown = |reference: &ResourceRef| DollyError::UnknownResource {
+                references: vec![reference.clone()],
+            };
             let Some(f) = resource_nodes.get(&from.id()) else {
-                return Err(anyhow!("Unknown resource: {}", from.id()));
+                return Err(unknown(from).into());
             };
             let Some(t) = resource_nodes.get(&to.id()) else {
-                return Err(anyhow!("Unknown resource: {}", to.id()));
+                return Err(unknown(to).into());
             };
+            // A resource is trivially ordered with itself; parsing warns about the
+            // relation, as it is likely a typo.
+            if f == t {
+                continue;
+            }
 
             graph
                 .try_add_edge(f.to_owned(), t.to_owned(), relation.clone())
                 .map_err(|_| {
-                    anyhow!("Error creating edge in acyclic graph: {from} {relation} {to}")
+                    // The edge is refused because `to` already leads back to `from`.
+                    let back = plan::cycle::path(graph.inner(), *t, *f).map_or_else(
+                        || vec![from.id()],
+                        |path| {
+                            path.iter()
+                                .map(|index| graph.inner()[*index].id())
+                                .collect()
+                        },
+                    );
+                    DollyError::CycleDetected {
+                        path: [vec![from.id()], back].concat(),
+                        closed_by: Some(format!("{from} {relation} {to}")),
+                    }
                 })?;
         }
     }
@@ -125,6 +423,9 @@ fn try_add_edges_from_relation(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use parser::pp::{AttrValue, PuppetString, self_relations};
+    use parser::value::IntoValue;
+    use resources::File;
     use std::str::FromStr;
 
     // 0. Tmp Cases
@@ -166,6 +467,7 @@ mod tests {
             rtype,
             title,
             attributes,
+            ..
         }) = manifest.0.first()
         {
             assert_eq!(rtype, "File", "Resource type should be 'File' (uc_first)");
@@ -201,6 +503,124 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_resource_attributes() -> Result<()> {
+        let input = r#"
+            file { "/etc/motd":
+                mode => "0644",
+                owner => "root",
+                content => Deferred("file", ["/etc/motd.in"]),
+            }
+        "#;
+        let plan = parse_puppet_manifest(&Manifest::from_str(input)?)?;
+        let weights = plan.sorted_weights()?;
+        let (_, node) = weights
+            .first()
+            .ok_or(anyhow!("Plan should have one node"))?;
+        assert_eq!(node.attribute("mode").as_deref(), Some("0644"));
+        assert_eq!(
+            node.attributes().keys().collect::<Vec<_>>(),
+            vec!["mode", "owner", "content"],
+            "Attributes keep their declaration order"
+        );
+        assert_eq!(
+            node.attribute("content"),
+            None,
+            "Deferred values are not literals"
+        );
+        assert!(node.attributes().contains_key("content"));
+        assert_eq!(node.attribute("group"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_resource_ref_from_str() -> Result<()> {
+        for (text, id) in [
+            ("File['/etc/motd']", "File[/etc/motd]"),
+            ("file[/etc/motd]", "File[/etc/motd]"),
+            (" ::foo::bar[\"x y\"] ", "Foo::Bar[x y]"),
+            (r"Exec['echo \'hi\'']", "Exec[echo 'hi']"),
+            ("Package[nginx[1]]", "Package[nginx[1]]"),
+        ] {
+            assert_eq!(ResourceRef::from_str(text)?.id(), id, "Parsing {text}");
+        }
+        for bad in [
+            "File", "File[]", "File['']", "[x]", "File[x", "Fo o[x]", "1File[x]",
+        ] {
+            assert!(ResourceRef::from_str(bad).is_err(), "{bad} is invalid");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_titles_are_normalized_per_type() -> Result<()> {
+        let input = r#"
+            file { "/etc//nginx/": ensure => directory }
+            service { "Nginx": }
+            File["/etc/nginx"] ~> Service["nginx"]
+        "#;
+        let plan = parse_puppet_manifest(&Manifest::from_str(input)?)?;
+        assert_eq!(
+            plan.to_canonical_text(),
+            "node File[/etc/nginx]\n\
+             node Service[nginx]\n\
+             edge File[/etc/nginx] ~> Service[nginx]\n"
+        );
+        assert_eq!(File::normalize_title("/"), "/");
+
+        let input = r#"
+            file { "/etc/motd": }
+            file { "/etc//motd/": }
+        "#;
+        let Err(e) = parse_puppet_manifest(&Manifest::from_str(input)?) else {
+            return Err(anyhow!(
+                "Two spellings of one file must not both be managed"
+            ));
+        };
+        assert_eq!(
+            e.to_string(),
+            "Duplicate declaration: File[/etc/motd] is already declared at 2:13"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_registered_resource_types() -> Result<()> {
+        let manifest = Manifest::from_str(
+            r#"
+            mycorp::widget { "gear": size => "large" }
+            file { "/etc/widget": }
+            Mycorp::Widget["gear"] -> File["/etc/widget"]
+            "#,
+        )?;
+        let Err(e) = parse_puppet_manifest(&manifest) else {
+            return Err(anyhow!("Unregistered types are unknown"));
+        };
+        assert_eq!(e.to_string(), "unknown rtype: Mycorp::Widget");
+
+        let mut registry = ResourceRegistry::default();
+        registry.register("mycorp::widget", |expr| {
+            let (title, attributes, _) = ResourceRegistry::declaration(expr)?;
+            Ok(Box::new(resources::Imported {
+                rtype: "Mycorp::Widget".to_string(),
+                title,
+                attributes,
+            }))
+        });
+        let plan = parse_puppet_manifest_with_registry(&manifest, &registry)?;
+        assert_eq!(
+            plan.to_canonical_text(),
+            "node File[/etc/widget]\n\
+             node Mycorp::Widget[gear]\n\
+             edge Mycorp::Widget[gear] -> File[/etc/widget]\n"
+        );
+        assert!(
+            parse_puppet_manifest_with_registry(&manifest, &ResourceRegistry::empty()).is_err(),
+            "An empty registry knows no type"
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_single_relation() -> Result<()> {
         let input = r#"
@@ -280,6 +700,7 @@ mod tests {
             rtype,
             title,
             attributes,
+            ..
         } = resources[0]
         {
             assert_eq!(rtype, "File");
@@ -294,6 +715,7 @@ mod tests {
             rtype,
             title,
             attributes,
+            ..
         } = resources[1]
         {
             assert_eq!(rtype, "Exec");
@@ -306,6 +728,7 @@ mod tests {
             rtype,
             title,
             attributes,
+            ..
         } = resources[2]
         {
             assert_eq!(rtype, "Service");
@@ -382,6 +805,55 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_chained_declarations() -> Result<()> {
+        let input = r#"
+            package { "nginx": } -> file { "/etc/nginx/nginx.conf": content => "x" }
+            ~> service { "nginx": }
+            class web {
+                file { "/srv/www": ensure => directory } -> [File["/srv/www/a"], File["/srv/www/b"]]
+                file { "/srv/www/a": }
+                file { "/srv/www/b": }
+            }
+            include web
+        "#;
+        let manifest = Manifest::from_str(input)?;
+        assert_eq!(
+            manifest.resources().count(),
+            3,
+            "The chain declares its resources"
+        );
+
+        let plan = parse_puppet_manifest(&manifest)?;
+        let mut edges: Vec<_> = plan
+            .plan()
+            .inner()
+            .edge_indices()
+            .filter_map(|edge| {
+                let (from, to) = plan.plan().inner().edge_endpoints(edge)?;
+                let graph = plan.plan().inner();
+                let relation = &graph[edge];
+                Some(format!(
+                    "{} {relation:?} {}",
+                    graph[from].id(),
+                    graph[to].id()
+                ))
+            })
+            .filter(|edge| !edge.contains("Class["))
+            .collect();
+        edges.sort();
+        assert_eq!(
+            edges,
+            vec![
+                "File[/etc/nginx/nginx.conf] Notify Service[nginx]",
+                "File[/srv/www] Provide File[/srv/www/a]",
+                "File[/srv/www] Provide File[/srv/www/b]",
+                "Package[nginx] Provide File[/etc/nginx/nginx.conf]",
+            ]
+        );
+        Ok(())
+    }
+
     // 3. Edge Cases
     #[test]
     fn test_undefined_resource_reference() -> Result<()> {
@@ -593,4 +1065,720 @@ mod tests {
         assert_eq!(plan.plan().inner().node_count(), 2);
         Ok(())
     }
+
+    #[test]
+    fn test_deeply_namespaced_rtypes() -> Result<()> {
+        let input = r#"
+            profile::web::nginx::config { "main": }
+            ::profile::web::nginx::config { "extra": }
+            service { "nginx": }
+            Profile::Web::Nginx::Config["main"] -> ::Profile::Web::Nginx::Config["extra"]
+            Profile::WEB::Nginx::Config["extra"] ~> Service["nginx"]
+        "#;
+        let manifest = Manifest::from_str(input)?;
+        let rtypes: Vec<_> = manifest
+            .resources()
+            .map(|r| match r {
+                PuppetExpr::Resource { rtype, .. } => rtype.as_str(),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(
+            rtypes,
+            vec![
+                "Profile::Web::Nginx::Config",
+                "Profile::Web::Nginx::Config",
+                "Service"
+            ],
+            "Leading :: is dropped and every segment is uc_first"
+        );
+
+        let relations: Vec<_> = manifest.relations().collect();
+        if let PuppetExpr::Relation { from, to, .. } = relations[1] {
+            assert_eq!(from[0].id(), "Profile::Web::Nginx::Config[extra]");
+            assert_eq!(to[0].id(), "Service[nginx]");
+        }
+
+        let Err(e) = parse_puppet_manifest(&manifest) else {
+            return Err(anyhow!("Unregistered rtype should not be planned"));
+        };
+        assert!(
+            e.to_string().contains("Profile::Web::Nginx::Config"),
+            "Error should name the full rtype"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_namespace_separator_is_not_spaced() -> Result<()> {
+        let input = r#"
+            foo :: bar { "x": }
+        "#;
+        assert!(Manifest::from_str(input).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_trailing_commas_and_multiline_chains() -> Result<()> {
+        let input = "
+            file { '/tmp/one':
+                mode   => '0644',
+                ensure => 'file',
+            }\r
+            file { '/tmp/two': }
+            service { 'nginx': }
+            [
+                File['/tmp/one'],
+                File['/tmp/two'],
+            ]
+                ->
+            Service['nginx']\r
+        ";
+        let manifest = Manifest::from_str(input)?;
+        assert_eq!(
+            manifest.0.len(),
+            4,
+            "Should have 3 resources and 1 relation"
+        );
+        if let Some(PuppetExpr::Resource { attributes, .. }) = manifest.0.first() {
+            assert_eq!(attributes.len(), 2, "Trailing comma adds no attribute");
+        }
+        let plan = parse_puppet_manifest(&manifest)?;
+        assert_eq!(plan.plan().inner().edge_count(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_comments_between_chained_operands() -> Result<()> {
+        let input = r#"
+            # Configuration first
+            file { '/etc/app.conf':
+                mode => '0644', # world readable
+                content => "port # 8080",
+            }
+            service { 'app': } /* started last */
+            File['/etc/app.conf'] ->
+            # wait for config
+            /* and also
+               for anything else */
+            Service['app']"#;
+        let manifest = Manifest::from_str(input)?;
+        assert_eq!(
+            manifest.0.len(),
+            3,
+            "Should have 2 resources and 1 relation"
+        );
+        if let Some(PuppetExpr::Resource { attributes, .. }) = manifest.0.first() {
+            assert_eq!(
+                attributes[1].value.to_string(),
+                "port # 8080",
+                "Comment markers inside strings are kept"
+            );
+        }
+        let plan = parse_puppet_manifest(&manifest)?;
+        assert_eq!(plan.plan().inner().edge_count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_resource_ref_attribute_values() -> Result<()> {
+        let input = r#"
+            file { "/etc/nginx.conf": }
+            service { "nginx":
+                ensure  => running,
+                require => File['/etc/nginx.conf'],
+            }
+        "#;
+        let manifest = Manifest::from_str(input)?;
+        let resources: Vec<_> = manifest.resources().collect();
+        let PuppetExpr::Resource { attributes, .. } = resources[1] else {
+            return Err(anyhow!("Expected a Resource variant"));
+        };
+        assert_eq!(
+            attributes[0].value,
+            AttrValue::String(PuppetString::literal("running")),
+            "Barewords are plain strings"
+        );
+        let AttrValue::ResourceRef(reference) = &attributes[1].value else {
+            return Err(anyhow!("Expected a ResourceRef value"));
+        };
+        assert_eq!(reference.id(), "File[/etc/nginx.conf]");
+        Ok(())
+    }
+
+    #[test]
+    fn test_typed_attribute_values() -> Result<()> {
+        let input = r#"
+            file { "/a": }
+            file { "/b": }
+            service { "nginx":
+                enable  => true,
+                timeout => 30,
+                mode    => 0755,
+                require => [File["/a"], File["/b"],],
+                env     => { "LANG" => "C", jobs => -0x10 },
+                before  => undef,
+                empty   => [],
+                falsy   => "false",
+            }
+        "#;
+        let manifest = Manifest::from_str(input)?;
+        let resources: Vec<_> = manifest.resources().collect();
+        let PuppetExpr::Resource { attributes, .. } = resources[2] else {
+            return Err(anyhow!("Expected a Resource variant"));
+        };
+        let values: Vec<_> = attributes.iter().map(|attr| &attr.value).collect();
+        assert_eq!(values[0], &AttrValue::Bool(true));
+        assert_eq!(values[1], &AttrValue::Integer(30));
+        assert_eq!(
+            values[2],
+            &AttrValue::Integer(0o755),
+            "A leading 0 is octal"
+        );
+        let ids: Vec<_> = values[3]
+            .as_array()
+            .iter()
+            .filter_map(|value| match value {
+                AttrValue::ResourceRef(reference) => Some(reference.id()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ids, vec!["File[/a]", "File[/b]"]);
+        assert_eq!(values[4].to_string(), "{ 'LANG' => 'C', 'jobs' => -16 }");
+        assert_eq!(values[5], &AttrValue::Undef);
+        assert_eq!(values[6], &AttrValue::Array(vec![]));
+        assert_eq!(
+            values[7],
+            &AttrValue::String(PuppetString::literal("false")),
+            "Quoted booleans stay strings"
+        );
+        assert_eq!(values[0].as_literal(), Some("true".to_string()));
+        assert!(Manifest::from_str(r#"file { "/a": mode => 0789 }"#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_metaparameters_add_edges() -> Result<()> {
+        let input = r#"
+            package { "nginx": }
+            file { "/etc/nginx/nginx.conf":
+                require => Package["nginx"],
+                notify  => Service["nginx"],
+            }
+            service { "nginx": }
+            exec { "reload":
+                subscribe => [File["/etc/nginx/nginx.conf"], Package["nginx"]],
+                before    => "Service[nginx]",
+            }
+        "#;
+        let plan = parse_puppet_manifest(&Manifest::from_str(input)?)?;
+        assert_eq!(
+            plan.to_canonical_text(),
+            "node Exec[reload]\n\
+             node File[/etc/nginx/nginx.conf]\n\
+             node Package[nginx]\n\
+             node Service[nginx]\n\
+             edge Exec[reload] -> Service[nginx]\n\
+             edge File[/etc/nginx/nginx.conf] ~> Exec[reload]\n\
+             edge File[/etc/nginx/nginx.conf] ~> Service[nginx]\n\
+             edge Package[nginx] -> File[/etc/nginx/nginx.conf]\n\
+             edge Package[nginx] ~> Exec[reload]\n"
+        );
+
+        let input = r#"file { "/a": require => File["/missing"] }"#;
+        let Err(e) = parse_puppet_manifest(&Manifest::from_str(input)?) else {
+            return Err(anyhow!("Metaparameters must reference declared resources"));
+        };
+        assert!(
+            e.to_string()
+                .starts_with("Unknown resource: File[/missing]")
+        );
+        let input = r#"file { "/a": before => true }"#;
+        assert!(parse_puppet_manifest(&Manifest::from_str(input)?).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_assertions_abort_before_applying() -> Result<()> {
+        let input = r#"
+            assert { 'disk space': condition => $facts['disk_free'] > 1024 }
+            define app($port) {
+                assert { "${title} port": condition => $port >= 1024,
+                  message => "${title} must not listen on ${port} as ${::user}" }
+                service { "${title}": }
+            }
+            app { "web": port => 80 }
+            if $::kernel == 'Linux' { assert { 'systemd': condition => $::init in ['systemd'] } }
+        "#;
+        let manifest = Manifest::from_str(input)?;
+        assert!(
+            manifest
+                .to_string()
+                .contains("assert { 'disk space': condition => $facts['disk_free'] > 1024 }")
+        );
+        let compile = |facts: &[(&str, &str)]| {
+            let mut options = CompileOptions::default();
+            for (name, value) in facts {
+                options.facts.insert(*name, *value);
+            }
+            parse_puppet_manifest_with_options(&manifest, &options)
+        };
+        let Err(e) = compile(&[("disk_free", "512"), ("kernel", "Linux"), ("user", "app")]) else {
+            return Err(anyhow!("Failed assertions abort the compile"));
+        };
+        assert_eq!(
+            e.to_string(),
+            "Preconditions not met, nothing applied:\n  \
+             Assert[disk space] at 2:13: $facts['disk_free'] > 1024 does not hold\n  \
+             Assert[web port] at 4:17: web must not listen on 80 as app\n  \
+             Assert[systemd] at 9:39: $init in ['systemd'] does not hold"
+        );
+
+        let app = Manifest::from_str(&input.replace("port => 80", "port => 8080"))?;
+        let mut options = CompileOptions::default();
+        for (name, value) in [
+            ("disk_free", "2048"),
+            ("kernel", "Linux"),
+            ("init", "systemd"),
+        ] {
+            options.facts.insert(name, value);
+        }
+        let plan = parse_puppet_manifest_with_options(&app, &options)?;
+        assert!(plan.node("Service[web]").is_some());
+        assert!(plan.facts_used().contains("disk_free"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_conditionals_select_resources() -> Result<()> {
+        let input = r#"
+            if $facts['os']['family'] == 'debian' and $::kernel != 'windows' {
+                package { "apt-transport-https": }
+            } elsif $osfamily in ['RedHat', 'CentOS'] {
+                package { "yum-utils": }
+            } else {
+                file { "/etc/unknown": }
+            }
+            unless $facts['virtual'] { file { "/etc/physical": } }
+            case $facts['os']['family'] {
+                'RedHat', 'Suse': { file { "/etc/rpm": } }
+                'Debian': { file { "/etc/dpkg": } }
+                default: { file { "/etc/other": } }
+            }
+            define svc($ensure = 'running') {
+                if $ensure == 'stopped' { file { "/etc/${title}.disabled": } }
+                else { service { "${title}": } }
+            }
+            svc { "ssh": }
+            svc { "cron": ensure => stopped }
+            if !($facts['processors']['count'] >= 4) { file { "/etc/small": } }
+        "#;
+        let manifest = Manifest::from_str(input)?;
+        let compile = |facts: &[(&str, &str)]| -> Result<Vec<String>> {
+            let mut options = CompileOptions::default();
+            for (name, value) in facts {
+                options.facts.insert(*name, *value);
+            }
+            let plan = parse_puppet_manifest_with_options(&manifest, &options)?;
+            let mut ids: Vec<_> = plan.index.keys().cloned().collect();
+            ids.sort();
+            Ok(ids)
+        };
+        assert_eq!(
+            compile(&[
+                ("os.family", "Debian"),
+                ("kernel", "Linux"),
+                ("processors.count", "2"),
+            ])?,
+            vec![
+                "File[/etc/cron.disabled]",
+                "File[/etc/dpkg]",
+                "File[/etc/physical]",
+                "File[/etc/small]",
+                "Package[apt-transport-https]",
+                "Service[ssh]",
+                "Svc[cron]",
+                "Svc[ssh]",
+            ]
+        );
+        assert_eq!(
+            compile(&[
+                ("os.family", "RedHat"),
+                ("osfamily", "RedHat"),
+                ("virtual", "kvm"),
+                ("processors.count", "16"),
+            ])?,
+            vec![
+                "File[/etc/cron.disabled]",
+                "File[/etc/rpm]",
+                "Package[yum-utils]",
+                "Service[ssh]",
+                "Svc[cron]",
+                "Svc[ssh]",
+            ]
+        );
+        assert!(
+            compile(&[("processors.count", "1")])?.contains(&"File[/etc/unknown]".to_string()),
+            "Without matching facts the else branch applies"
+        );
+        let Err(e) = compile(&[]) else {
+            return Err(anyhow!("undef cannot be ordered"));
+        };
+        assert_eq!(
+            e.to_string(),
+            "Cannot compare undef with 4 in the condition at 21:13"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_resource_defaults() -> Result<()> {
+        let input = r#"
+            File { mode => '0644', owner => 'root' }
+            file { "/etc/motd": }
+            file { "/etc/shadow": mode => '0600' }
+            class web {
+                file { "/srv/www": ensure => directory }
+                File { owner => 'www-data' }
+            }
+            define site() {
+                File { group => 'www-data' }
+                file { "/srv/www/${title}": }
+            }
+            include web
+            site { "blog": }
+            exec { "/bin/true": }
+        "#;
+        let plan = parse_puppet_manifest_with_options(
+            &Manifest::from_str(input)?,
+            &CompileOptions::default(),
+        )?;
+        let attribute = |id: &str, name: &str| {
+            let node = plan.node(id).expect("declared");
+            plan.plan()[node].attribute(name)
+        };
+        for (id, mode, owner, group) in [
+            ("File[/etc/motd]", "0644", "root", None),
+            ("File[/etc/shadow]", "0600", "root", None),
+            ("File[/srv/www]", "0644", "www-data", None),
+            ("File[/srv/www/blog]", "0644", "root", Some("www-data")),
+        ] {
+            assert_eq!(attribute(id, "mode").as_deref(), Some(mode), "{id}");
+            assert_eq!(attribute(id, "owner").as_deref(), Some(owner), "{id}");
+            assert_eq!(attribute(id, "group").as_deref(), group, "{id}");
+        }
+        assert_eq!(
+            attribute("Exec[/bin/true]", "mode"),
+            None,
+            "Defaults only apply to their type"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_class_containment() -> Result<()> {
+        let input = r#"
+            class nginx {
+                package { "nginx": }
+                contain nginx::service
+            }
+            class nginx::service {
+                service { "nginx": }
+            }
+            class unused { file { "/never": } }
+            include nginx
+            exec { "before": }
+            exec { "after": }
+            Exec["before"] -> Class["nginx"] ~> Exec["after"]
+        "#;
+        let plan = parse_puppet_manifest(&Manifest::from_str(input)?)?;
+        assert_eq!(
+            plan.to_canonical_text(),
+            "node Class[Nginx::Service]\n\
+             node Class[Nginx]\n\
+             node Exec[after]\n\
+             node Exec[before]\n\
+             node Package[nginx]\n\
+             node Service[nginx]\n\
+             edge Class[Nginx::Service] -> Class[Nginx]\n\
+             edge Class[Nginx::Service] ~> Exec[after]\n\
+             edge Class[Nginx] ~> Exec[after]\n\
+             edge Exec[before] -> Class[Nginx::Service]\n\
+             edge Exec[before] -> Class[Nginx]\n\
+             edge Exec[before] -> Package[nginx]\n\
+             edge Exec[before] -> Service[nginx]\n\
+             edge Package[nginx] -> Class[Nginx]\n\
+             edge Package[nginx] ~> Exec[after]\n\
+             edge Service[nginx] -> Class[Nginx::Service]\n\
+             edge Service[nginx] ~> Exec[after]\n",
+            "Everything in the class is ordered between its neighbours"
+        );
+        assert_eq!(
+            plan.provenance("Service[nginx]").map(ToString::to_string),
+            Some(
+                "in class nginx declared at 10:13 → in class nginx::service declared at 4:17 \
+                 → declared at 7:17"
+                    .to_string()
+            )
+        );
+
+        let input = r#"class { "nginx": } include ssh"#;
+        let Err(e) = parse_puppet_manifest(&Manifest::from_str(input)?) else {
+            return Err(anyhow!("Undefined classes cannot be declared"));
+        };
+        assert_eq!(e.to_string(), "Unknown class: nginx at 1:1");
+        let input = r#"class a {} class { "a": } class { "A": }"#;
+        assert!(parse_puppet_manifest(&Manifest::from_str(input)?).is_err());
+        let input = r#"class a {} include a include a class { "b": } class b {}"#;
+        assert_eq!(
+            parse_puppet_manifest(&Manifest::from_str(input)?)?.to_canonical_text(),
+            "node Class[A]\nnode Class[B]\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_defined_type_instances() -> Result<()> {
+        let input = r#"
+            define mymod::vhost(Integer $port, String $docroot = "/var/www/${title}") {
+                file { "/etc/nginx/${title}.conf": content => "listen ${port}", tag => $port }
+                file { "${docroot}": }
+                File["${docroot}"] -> File["/etc/nginx/${title}.conf"]
+            }
+            service { "nginx": }
+            mymod::vhost { "a": port => 80, notify => Service["nginx"] }
+            mymod::vhost { "b": port => 8080, docroot => "/srv/b" }
+            Mymod::Vhost["a"] -> Mymod::Vhost["b"]
+        "#;
+        let plan = parse_puppet_manifest(&Manifest::from_str(input)?)?;
+        assert_eq!(
+            plan.to_canonical_text(),
+            "node File[/etc/nginx/a.conf]\n\
+             node File[/etc/nginx/b.conf]\n\
+             node File[/srv/b]\n\
+             node File[/var/www/a]\n\
+             node Mymod::Vhost[a]\n\
+             node Mymod::Vhost[b]\n\
+             node Service[nginx]\n\
+             edge File[/etc/nginx/a.conf] -> Mymod::Vhost[a]\n\
+             edge File[/etc/nginx/a.conf] ~> Service[nginx]\n\
+             edge File[/etc/nginx/b.conf] -> Mymod::Vhost[b]\n\
+             edge File[/srv/b] -> File[/etc/nginx/b.conf]\n\
+             edge File[/srv/b] -> Mymod::Vhost[b]\n\
+             edge File[/var/www/a] -> File[/etc/nginx/a.conf]\n\
+             edge File[/var/www/a] -> Mymod::Vhost[a]\n\
+             edge File[/var/www/a] ~> Service[nginx]\n\
+             edge Mymod::Vhost[a] -> File[/etc/nginx/b.conf]\n\
+             edge Mymod::Vhost[a] -> File[/srv/b]\n\
+             edge Mymod::Vhost[a] -> Mymod::Vhost[b]\n\
+             edge Mymod::Vhost[a] ~> Service[nginx]\n",
+            "Each instance expands its body and contains it"
+        );
+        let node = plan.node("File[/etc/nginx/b.conf]").expect("expanded");
+        let conf = &plan.plan()[node];
+        assert_eq!(conf.attribute("content").as_deref(), Some("listen 8080"));
+        assert_eq!(
+            conf.attributes().get("tag"),
+            Some(&AttrValue::Integer(8080)),
+            "A bare parameter keeps its type"
+        );
+
+        for (input, error) in [
+            (
+                r#"define t($p) {} t { "x": }"#,
+                "Missing parameter p of T[x] at 1:17",
+            ),
+            (
+                r#"define t($p) {} t { "x": p => 1, q => 2 }"#,
+                "Unknown parameter q of T[x] at 1:17",
+            ),
+            (
+                r#"define t { file { "/${nope}": } } t { "x": }"#,
+                "Unknown variable $nope in T[x] at 1:35",
+            ),
+        ] {
+            let Err(e) = parse_puppet_manifest(&Manifest::from_str(input)?) else {
+                return Err(anyhow!("{input} should fail"));
+            };
+            assert_eq!(e.to_string(), error);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_fact_iteration_ids_are_stable() -> Result<()> {
+        let input = r#"
+            service { "networking": }
+            $facts['networking']['interfaces'].each |$name, $iface| {
+                file { "/etc/network/interfaces.d/${name}":
+                    content => "iface ${name}",
+                    notify => Service["networking"],
+                }
+            }
+            ["a", "b"].each |$user| { file { "/home/${user}": } }
+        "#;
+        let manifest = Manifest::from_str(input)?;
+        let compile = |facts: &[(&str, &str)]| -> Result<Plan> {
+            let mut options = CompileOptions::default();
+            for (name, value) in facts {
+                options.facts.insert(*name, *value);
+            }
+            Ok(parse_puppet_manifest_with_options(&manifest, &options)?)
+        };
+        let plan = compile(&[
+            ("networking.interfaces.lo.ip", "127.0.0.1"),
+            ("networking.interfaces.eth1.ip", "10.0.0.2"),
+            ("networking.interfaces.eth0.ip", "10.0.0.1"),
+            ("networking.interfaces.eth0.mtu", "1500"),
+        ])?;
+        assert_eq!(
+            plan.to_canonical_text(),
+            "node File[/etc/network/interfaces.d/eth0]\n\
+             node File[/etc/network/interfaces.d/eth1]\n\
+             node File[/etc/network/interfaces.d/lo]\n\
+             node File[/home/a]\n\
+             node File[/home/b]\n\
+             node Service[networking]\n\
+             edge File[/etc/network/interfaces.d/eth0] ~> Service[networking]\n\
+             edge File[/etc/network/interfaces.d/eth1] ~> Service[networking]\n\
+             edge File[/etc/network/interfaces.d/lo] ~> Service[networking]\n"
+        );
+        let ids = |plan: &Plan| -> Vec<String> {
+            let graph = plan.plan();
+            graph
+                .node_indices()
+                .map(|index| graph[index].id())
+                .collect()
+        };
+        let regathered = compile(&[
+            ("networking.interfaces.eth0.mtu", "9000"),
+            ("networking.interfaces.eth0.ip", "10.0.0.1"),
+            ("networking.interfaces.lo.ip", "127.0.0.1"),
+            ("networking.interfaces.eth1.ip", "10.0.0.2"),
+        ])?;
+        assert_eq!(
+            ids(&plan),
+            ids(&regathered),
+            "Regathered facts generate the same resources in the same order"
+        );
+        assert_eq!(
+            compile(&[])?.index.len(),
+            3,
+            "A missing fact generates nothing"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_all_undefined_references_reported() -> Result<()> {
+        let input = r#"
+            file { "/tmp/one": }
+            File["/tmp/missing"] -> File["/tmp/one"]
+            File["/tmp/one"] -> Service["nginx"] -> Service["ssh"]
+        "#;
+        let Err(e) = Manifest::from_str(input) else {
+            return Err(anyhow!("Undefined references should fail"));
+        };
+        let message = e.to_string();
+        assert!(message.contains("File[/tmp/missing] at 3:13"), "{message}");
+        assert!(message.contains("Service[nginx] at 4:33"), "{message}");
+        assert!(message.contains("Service[ssh] at 4:53"), "{message}");
+        assert_eq!(
+            message.matches("Service[nginx]").count(),
+            1,
+            "Chained operands are reported once"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_self_relation_through_different_spellings() -> Result<()> {
+        let input = r#"
+            file { "/tmp/${dir}": }
+            service { "nginx": }
+            File['/tmp/${dir}'] -> [Service["nginx"], ::File["/tmp/${dir}"]]
+        "#;
+        let plan = parse_puppet_manifest(&Manifest::from_str(input)?)?;
+        assert_eq!(
+            plan.graph.inner().edge_count(),
+            1,
+            "The self relation is ignored"
+        );
+
+        let warnings = self_relations(input)?;
+        assert_eq!(warnings.len(), 1);
+        let message = warnings[0].to_string();
+        assert!(
+            message.contains("Relation connects File[/tmp/${dir}] to itself and is ignored"),
+            "{message}"
+        );
+        assert!(
+            message.contains(r#"`::File["/tmp/${dir}"]` at 4:55"#),
+            "{message}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_lenient_parsing_keeps_unsupported_constructs() -> Result<()> {
+        let input = r#"
+            notice('starting')
+            class profile::web (String $root = '/srv') {
+              file { $root: ensure => 'directory' }
+            }
+            file { "/etc/motd": }
+            $greeting = "hello { world"
+            File["/etc/motd"] -> Service["nginx"]
+        "#;
+        assert!(Manifest::from_str(input).is_err());
+
+        let manifest = Manifest::from_str_lenient(input)?;
+        let opaque: Vec<_> = manifest
+            .opaque()
+            .map(|expr| match expr {
+                PuppetExpr::Opaque { text, span } => format!("{span} {}", text.lines().count()),
+                _ => String::new(),
+            })
+            .collect();
+        assert_eq!(opaque, vec!["2:13 1", "3:13 3", "7:13 1"]);
+        assert_eq!(manifest.resources().count(), 1);
+        assert_eq!(manifest.relations().count(), 1);
+
+        let Err(e) = parse_puppet_manifest(&manifest) else {
+            return Err(anyhow!("Opaque statements cannot be compiled"));
+        };
+        assert!(
+            e.to_string()
+                .contains("Unsupported construct at 2:13: notice('starting')"),
+            "{e}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_carries_ensure() -> Result<()> {
+        let input = r#"
+            service { "nginx": ensure => stopped }
+            file { "/srv/www": ensure => directory }
+            package { "nginx": ensure => '1.24.0' }
+            package { "apache2": ensure => purged }
+            exec { "reload": command => "/bin/true" }
+        "#;
+        let plan = parse_puppet_manifest(&Manifest::from_str(input)?)?;
+        let ensure = |id: &str| plan.node(id).map(|index| plan.ensure(index));
+        assert_eq!(ensure("Service[nginx]"), Some(Ensure::Stopped));
+        assert_eq!(ensure("File[/srv/www]"), Some(Ensure::Directory));
+        assert_eq!(
+            ensure("Package[nginx]"),
+            Some(Ensure::Version("1.24.0".to_string()))
+        );
+        assert_eq!(ensure("Package[apache2]"), Some(Ensure::Absent));
+        assert_eq!(ensure("Exec[reload]"), Some(Ensure::Present));
+
+        let ensure = |value: &str| Ensure::from_value(&value.into_value());
+        assert_eq!(ensure("running")?, Ensure::Running);
+        assert_eq!(ensure(">= 2.0")?.to_string(), ">= 2.0");
+        assert!(ensure("sideways").is_err());
+        Ok(())
+    }
 }
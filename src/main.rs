@@ -1,25 +1,633 @@
-use anyhow::Result;
-use dolly::{parse_puppet_manifest, parser::pp::Manifest};
+use anyhow::{Context, Result, anyhow};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use dolly::{
+    CompileOptions, Plan,
+    apply::{ApplyOptions, ApplyReport, OnFailure, SinkConfig, deliver_all},
+    audit::Audit,
+    cache::{PlanCache, StateCache},
+    config::DollyConfig,
+    facts::{self, Facts},
+    hiera::Hiera,
+    parse_puppet_manifest_with_options,
+    parser::deprecations::deprecations,
+    parser::pp::{Manifest, PuppetExpr, ResourceRef, self_relations},
+    parser::shrink::{error_of, shrink},
+    plan::Budget,
+    plan::Deny,
+    plan::GraphDiff,
+    plan::RefreshMode,
+    resources::{Capabilities, OutputLog},
+};
 use petgraph::visit::EdgeRef;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime};
+
+/// How many plans the agent keeps, so facts flipping between values do not recompile.
+const AGENT_PLANS: usize = 4;
+
+#[derive(Parser)]
+#[command(
+    name = "dolly",
+    version,
+    about = "Compile and inspect Puppet-style manifests"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Parse and validate a manifest, printing it back normalized.
+    Parse {
+        file: PathBuf,
+        /// Keep statements dolly does not support instead of failing, and list them.
+        #[arg(long)]
+        lenient: bool,
+    },
+    /// Print the resource graph.
+    Graph {
+        #[command(flatten)]
+        compile: CompileArgs,
+        #[arg(long, value_enum, default_value_t = GraphFormat::Dot)]
+        format: GraphFormat,
+    },
+    /// Print resources in the order they would be applied.
+    Plan {
+        #[command(flatten)]
+        compile: CompileArgs,
+    },
+    /// Apply every resource in dependency order.
+    Apply {
+        #[command(flatten)]
+        compile: CompileArgs,
+        /// Skip everything after the first failure, not only its dependents.
+        #[arg(long)]
+        stop_on_failure: bool,
+        /// Refresh notified resources only after everything is applied.
+        #[arg(long)]
+        deferred_refresh: bool,
+        /// Report what would change without changing anything.
+        #[arg(long)]
+        noop: bool,
+        /// Exit with 0 when nothing changed, 2 when something changed, 4 on failures
+        /// and 6 when both.
+        #[arg(long)]
+        detailed_exitcodes: bool,
+        /// Where progress is saved so an interrupted run can be resumed.
+        #[arg(long, value_name = "FILE", default_value = ".dolly-checkpoint.json")]
+        checkpoint: PathBuf,
+        /// Continue the interrupted run saved in the checkpoint.
+        #[arg(long, conflicts_with = "noop")]
+        resume: bool,
+        /// What earlier runs enforced, to tell corrective changes from intentional ones.
+        #[arg(long, value_name = "FILE", default_value = ".dolly-state.json")]
+        state: PathBuf,
+        /// Print a unified diff of every file content change.
+        #[arg(long)]
+        show_diff: bool,
+        /// How to print the report: for people, or for pipelines with per-resource
+        /// outcomes, durations and totals.
+        #[arg(long, value_enum, default_value_t = ReportFormat::Text)]
+        format: ReportFormat,
+        /// How many resources to enforce at once, as far as dependencies and
+        /// concurrency groups allow.
+        #[arg(long, default_value_t = 1)]
+        jobs: usize,
+    },
+    /// Apply the manifest every interval, changing the system only inside the configured
+    /// maintenance windows.
+    Agent {
+        #[command(flatten)]
+        compile: CompileArgs,
+        /// Where progress is saved so an interrupted run can be resumed.
+        #[arg(long, value_name = "FILE", default_value = ".dolly-checkpoint.json")]
+        checkpoint: PathBuf,
+        /// What earlier runs enforced, to tell corrective changes from intentional ones.
+        #[arg(long, value_name = "FILE", default_value = ".dolly-state.json")]
+        state: PathBuf,
+        /// Do a single run and exit.
+        #[arg(long)]
+        once: bool,
+    },
+    /// Score how much of the system complies with the manifest, without changing anything.
+    Compliance {
+        #[command(flatten)]
+        compile: CompileArgs,
+        /// Print the report as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show everything the plan knows about one resource.
+    Explain {
+        #[command(flatten)]
+        compile: CompileArgs,
+        /// Resource reference, e.g. `File[/etc/motd]` or `file['/etc/motd']`.
+        id: ResourceRef,
+        /// The state cache `apply` records the outcome of the last run in.
+        #[arg(long, value_name = "FILE", default_value = ".dolly-state.json")]
+        state: PathBuf,
+    },
+    /// Report which Puppet features the manifests under a directory use.
+    Audit { dir: PathBuf },
+    /// Render the changes between two manifests as a colored graph.
+    GraphDiff {
+        old: PathBuf,
+        new: PathBuf,
+        #[arg(long, value_enum, default_value_t = DiffFormat::Dot)]
+        format: DiffFormat,
+    },
+    /// List what compiling FILE instead of OLD changes: resources, attributes and
+    /// relations.
+    Diff {
+        /// The manifest or catalog to compare against, compiled with the same options.
+        old: PathBuf,
+        #[command(flatten)]
+        compile: CompileArgs,
+    },
+    /// Interactively build and query a plan, and evaluate expressions against facts.
+    Repl {
+        /// Override a fact, as `name=value`. May be repeated.
+        #[arg(long = "fact", value_name = "NAME=VALUE")]
+        facts: Vec<String>,
+        /// Custom fact scripts, as for `compile`.
+        #[arg(long, value_name = "DIR", default_value = facts::FACTS_DIR)]
+        facts_dir: PathBuf,
+    },
+    /// Print the fewest lines of a Puppet manifest that still fail to parse or compile
+    /// with an error containing the expected text, for bug reports.
+    Shrink {
+        file: PathBuf,
+        #[arg(long, value_name = "TEXT")]
+        expect_error: String,
+    },
+}
+
+#[derive(Args)]
+struct CompileArgs {
+    /// Manifest to compile: Puppet (.pp), YAML, JSON, TOML or Terraform (.tf), a directory
+    /// of Puppet manifests, or an already compiled catalog (.catalog.json).
+    file: PathBuf,
+    /// Override a fact, as `name=value`. May be repeated.
+    #[arg(long = "fact", value_name = "NAME=VALUE")]
+    facts: Vec<String>,
+    /// Custom fact scripts, `.json` and `.txt` files, printing or holding a JSON object or
+    /// `name=value` lines.
+    #[arg(long, value_name = "DIR", default_value = facts::FACTS_DIR)]
+    facts_dir: PathBuf,
+    #[arg(long)]
+    max_resources: Option<usize>,
+    #[arg(long)]
+    max_edges: Option<usize>,
+    #[arg(long)]
+    max_depth: Option<usize>,
+    /// Forbid a construct: `Exec`, `File.mode=*7` or `File outside /etc,/opt`.
+    #[arg(long, value_name = "RULE")]
+    deny: Vec<Deny>,
+    /// Settings such as per-type attribute defaults.
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Hierarchy of YAML data for `lookup` and class parameters, a `hiera.yaml`.
+    #[arg(long, value_name = "FILE")]
+    hiera: Option<PathBuf>,
+    /// Where `template` and `epp` read templates; `templates` next to the manifest by
+    /// default.
+    #[arg(long, value_name = "DIR")]
+    templates: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum GraphFormat {
+    Dot,
+    D2,
+    Mermaid,
+    Graphml,
+    /// The graph alone in the JSON Graph Format.
+    Json,
+    Canonical,
+    /// A JSON catalog that `apply` and the other commands read from a `.catalog.json`
+    /// file without the manifest.
+    Catalog,
+    /// The catalog as `puppet catalog compile` prints it, to diff against Puppet.
+    PuppetCatalog,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ReportFormat {
+    Text,
+    Json,
+    Yaml,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DiffFormat {
+    Dot,
+    Mermaid,
+}
 
 fn main() -> Result<()> {
-    let input = String::from_utf8_lossy(include_bytes!("../res/test.pp"));
-    let manifest = &input.parse::<Manifest>()?;
-    let plan = parse_puppet_manifest(manifest)?;
-
-    println!("{:?}", plan.dot());
-
-    print!("# Execution plan debug:");
-    for (index, node) in plan.sorted_weights()? {
-        let edges = plan.plan().edges(index);
-        print!("# {}", node.id());
-        for edge in edges {
-            print!(
-                " ({} {})",
-                edge.weight(),
-                plan.sorted_weights()?.get(&edge.target()).unwrap().id()
+    match Cli::parse().command {
+        Command::Parse { file, lenient } => {
+            let manifest = match lenient {
+                true => {
+                    let source = std::fs::read_to_string(&file)
+                        .with_context(|| format!("Cannot read {}", file.display()))?;
+                    warn_about(&file, &source);
+                    Manifest::from_str_lenient(&source)?
+                }
+                false => load(&file)?,
+            };
+            for expr in manifest.opaque() {
+                if let PuppetExpr::Opaque { text, span } = expr {
+                    let first_line = text.lines().next().unwrap_or_default();
+                    eprintln!("unsupported at {span}: {first_line}");
+                }
+            }
+            for warning in manifest.validate() {
+                eprintln!("{warning}");
+            }
+            print!("{manifest}");
+        }
+        Command::Graph { compile, format } => {
+            let (facts, plan) = compile.compile()?;
+            match format {
+                GraphFormat::Dot => println!("{:?}", plan.dot()),
+                GraphFormat::D2 => print!("{}", plan.to_d2()),
+                GraphFormat::Mermaid => print!("{}", plan.mermaid()),
+                GraphFormat::Graphml => print!("{}", plan.graphml()),
+                GraphFormat::Json => println!("{}", plan.json_graph()),
+                GraphFormat::Canonical => print!("{}", plan.to_canonical_text()),
+                GraphFormat::Catalog => println!("{}", plan.to_json()?),
+                GraphFormat::PuppetCatalog => {
+                    let node = ["fqdn", "networking.fqdn", "hostname"]
+                        .iter()
+                        .find_map(|fact| facts.get(fact))
+                        .unwrap_or("localhost");
+                    println!("{}", plan.to_puppet_json(node)?)
+                }
+            }
+        }
+        Command::Plan { compile } => {
+            let (facts, plan) = compile.compile()?;
+            for unsupported in plan.unsupported(Capabilities::cached()) {
+                eprintln!("warning: {unsupported}");
+            }
+            for not_applicable in plan.not_applicable(&facts) {
+                eprintln!("warning: {not_applicable}");
+            }
+            print_plan(&plan)?;
+        }
+        Command::Apply {
+            compile,
+            stop_on_failure,
+            deferred_refresh,
+            noop,
+            detailed_exitcodes,
+            checkpoint,
+            resume,
+            state,
+            show_diff,
+            format,
+            jobs,
+        } => {
+            let (_, plan) = compile.compile()?;
+            let config = compile.config()?;
+            let mut cache = StateCache::load(&state)?;
+            let options = ApplyOptions {
+                refresh: match deferred_refresh {
+                    true => RefreshMode::Deferred,
+                    false => RefreshMode::Interleaved,
+                },
+                on_failure: match stop_on_failure {
+                    true => OnFailure::Stop,
+                    false => OnFailure::SkipDependents,
+                },
+                noop,
+                watch_triggers: cache.watch_triggers(&plan),
+                exec_policy: config.exec_policy()?,
+                log: OutputLog::new(|line| eprintln!("{line}")),
+                jobs,
+                ..ApplyOptions::default()
+            };
+            let report = match noop {
+                true => plan.apply(options)?,
+                false => plan.apply_with_checkpoint(options, &checkpoint, resume)?,
+            };
+            let report = record_state(&plan, report, &mut cache, &state)?;
+            match format {
+                ReportFormat::Text => print!("{report}"),
+                ReportFormat::Json => println!("{}", report.structured().to_json()?),
+                ReportFormat::Yaml => print!("{}", report.structured().to_yaml()?),
+            }
+            if show_diff && matches!(format, ReportFormat::Text) {
+                for (id, diff) in report.diffs() {
+                    println!("{id}:\n{}", diff.trim_end());
+                }
+            }
+            send_reports(&config, &report);
+            if detailed_exitcodes {
+                std::process::exit(report.detailed_exit_code());
+            }
+            if report.failed() {
+                std::process::exit(1);
+            }
+        }
+        Command::Agent {
+            compile,
+            checkpoint,
+            state,
+            once,
+        } => {
+            let config = compile.config()?;
+            let agent = &config.agent;
+            let mut plans = PlanCache::with_capacity(AGENT_PLANS);
+            loop {
+                std::thread::sleep(agent.jitter());
+                let started = Instant::now();
+                let noop = !agent.allows_changes(SystemTime::now());
+                if noop {
+                    eprintln!("Outside the maintenance windows: running in noop mode");
+                }
+                let report = compile.compile_cached(&mut plans).and_then(|plan| {
+                    let mut cache = StateCache::load(&state)?;
+                    let options = ApplyOptions {
+                        noop,
+                        watch_triggers: cache.watch_triggers(plan),
+                        exec_policy: config.exec_policy()?,
+                        log: OutputLog::new(|line| eprintln!("{line}")),
+                        ..ApplyOptions::default()
+                    };
+                    let report = match noop {
+                        true => plan.apply(options)?,
+                        false => plan.apply_with_checkpoint(options, &checkpoint, false)?,
+                    };
+                    record_state(plan, report, &mut cache, &state)
+                });
+                match report {
+                    Ok(report) => {
+                        print!("{report}");
+                        send_reports(&config, &report);
+                    }
+                    Err(e) => eprintln!("Run failed: {e:#}"),
+                }
+                if once {
+                    break;
+                }
+                std::thread::sleep(agent.interval().saturating_sub(started.elapsed()));
+            }
+        }
+        Command::Compliance { compile, json } => {
+            let (facts, plan) = compile.compile()?;
+            let report = plan.compliance(&facts);
+            match json {
+                true => println!("{}", report.to_json()),
+                false => print!("{report}"),
+            }
+            if !report.compliant() {
+                std::process::exit(1);
+            }
+        }
+        Command::Explain { compile, id, state } => {
+            let (_, plan) = compile.compile()?;
+            let state = StateCache::load(&state)?;
+            print!("{}", plan.explain_with_state(&id.id(), &state)?);
+        }
+        Command::Audit { dir } => print!("{}", Audit::scan_dir(&dir)?),
+        Command::GraphDiff { old, new, format } => {
+            let diff = GraphDiff::between(&load(&old)?, &load(&new)?)?;
+            match format {
+                DiffFormat::Dot => print!("{}", diff.to_dot()),
+                DiffFormat::Mermaid => print!("{}", diff.to_mermaid()),
+            }
+        }
+        Command::Diff { old, compile } => {
+            let (_, new_plan) = compile.compile()?;
+            let (_, old_plan) = compile.compile_file(&old)?;
+            print!("{}", dolly::plan::diff(&old_plan, &new_plan));
+        }
+        Command::Repl { facts, facts_dir } => {
+            let facts = Facts::with_overrides(
+                Facts::gather(&facts_dir, &facts::FactsConfig::default())?,
+                &facts,
+            )?;
+            dolly::repl::run(facts, std::io::stdin().lock(), std::io::stdout())?
+        }
+        Command::Shrink { file, expect_error } => {
+            let source = std::fs::read_to_string(&file)
+                .with_context(|| format!("Cannot read {}", file.display()))?;
+            let fails = |source: &str| error_of(source).is_some_and(|e| e.contains(&expect_error));
+            if !fails(&source) {
+                return Err(anyhow!(
+                    "{} does not fail with {expect_error:?}: {}",
+                    file.display(),
+                    error_of(&source).unwrap_or_else(|| "it compiles".to_string())
+                ));
+            }
+            print!("{}", shrink(&source, fails));
+        }
+    }
+    Ok(())
+}
+
+impl CompileArgs {
+    fn config(&self) -> Result<DollyConfig> {
+        match &self.config {
+            Some(path) => DollyConfig::from_path(path),
+            None => Ok(DollyConfig::default()),
+        }
+    }
+
+    fn hiera(&self) -> Result<Hiera> {
+        match &self.hiera {
+            Some(path) => Hiera::from_path(path),
+            None => Ok(Hiera::default()),
+        }
+    }
+
+    fn templates(&self, file: &Path) -> PathBuf {
+        match &self.templates {
+            Some(dir) => dir.clone(),
+            None if file.is_dir() => file.join("templates"),
+            None => file.parent().unwrap_or(Path::new("")).join("templates"),
+        }
+    }
+
+    fn facts(&self) -> Result<Facts> {
+        Facts::with_overrides(
+            Facts::gather(&self.facts_dir, &self.config()?.facts)?,
+            &self.facts,
+        )
+    }
+
+    fn compile(&self) -> Result<(Facts, Plan)> {
+        self.compile_file(&self.file)
+    }
+
+    /// The plan in `plans` compiled from the same manifest and config with the same values
+    /// of the facts it used, or a new one, which is added to `plans`.
+    fn compile_cached<'a>(&self, plans: &'a mut PlanCache) -> Result<&'a Plan> {
+        let facts = self.facts()?;
+        let mut inputs = String::new();
+        let manifests = match self.file.is_dir() || self.file.extension().is_some_and(|e| e == "pp")
+        {
+            true => Manifest::sources(&self.file)?,
+            false => vec![self.file.clone()],
+        };
+        for manifest in manifests {
+            inputs.push_str(
+                &std::fs::read_to_string(&manifest)
+                    .with_context(|| format!("Cannot read {}", manifest.display()))?,
             );
         }
+        if let Some(config) = &self.config {
+            inputs.push_str(&std::fs::read_to_string(config).unwrap_or_default());
+        }
+        if let Some(hiera) = &self.hiera {
+            inputs.push_str(&std::fs::read_to_string(hiera).unwrap_or_default());
+            for source in self.hiera()?.sources() {
+                inputs.push_str(&std::fs::read_to_string(source).unwrap_or_default());
+            }
+        }
+        read_tree(&self.templates(&self.file), &mut inputs);
+        let changed = plans.changed_facts(&facts);
+        match plans.get(&inputs, &facts) {
+            Some(_) => eprintln!("Manifest and used facts unchanged: reusing the plan"),
+            None if !changed.is_empty() => {
+                eprintln!("Recompiling: changed facts {}", changed.join(", "));
+            }
+            None => {}
+        }
+        plans.get_or_compile(&inputs, &facts, || self.compile_with(&self.file, &facts))
+    }
+
+    /// Compiles `file` with these options.
+    fn compile_file(&self, file: &Path) -> Result<(Facts, Plan)> {
+        let facts = self.facts()?;
+        let plan = self.compile_with(file, &facts)?;
+        Ok((facts, plan))
+    }
+
+    fn compile_with(&self, file: &Path, facts: &Facts) -> Result<Plan> {
+        if file.extension().is_some_and(|e| e == "dot") {
+            let source = std::fs::read_to_string(file)
+                .with_context(|| format!("Cannot read {}", file.display()))?;
+            let plan = Plan::from_puppet_dot(&source)
+                .with_context(|| format!("Cannot import {}", file.display()))?;
+            return Ok(plan);
+        }
+        if file.to_string_lossy().ends_with(".catalog.json") {
+            let source = std::fs::read_to_string(file)
+                .with_context(|| format!("Cannot read {}", file.display()))?;
+            let plan = Plan::from_json(&source)
+                .with_context(|| format!("Cannot import {}", file.display()))?;
+            return Ok(plan);
+        }
+        let manifest = load(file)?;
+        for warning in manifest.validate() {
+            eprintln!("{warning}");
+        }
+        let options = CompileOptions {
+            budget: Budget {
+                max_resources: self.max_resources,
+                max_edges: self.max_edges,
+                max_depth: self.max_depth,
+            },
+            deny: self.deny.clone(),
+            config: self.config()?,
+            facts: facts.clone(),
+            hiera: self.hiera()?,
+            templates: self.templates(file),
+            ..CompileOptions::default()
+        };
+        Ok(parse_puppet_manifest_with_options(&manifest, &options)?)
+    }
+}
+
+/// Appends the files under `dir`, in name order, to `text`.
+fn read_tree(dir: &Path, text: &mut String) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    let mut paths: Vec<_> = entries.flatten().map(|entry| entry.path()).collect();
+    paths.sort();
+    for path in paths {
+        match path.is_dir() {
+            true => read_tree(&path, text),
+            false => text.push_str(&std::fs::read_to_string(&path).unwrap_or_default()),
+        }
+    }
+}
+
+/// Classifies the changes of `report` against the state cache loaded from `path`, then
+/// records what the run left in sync.
+fn record_state(
+    plan: &Plan,
+    mut report: ApplyReport,
+    state: &mut StateCache,
+    path: &Path,
+) -> Result<ApplyReport> {
+    report.classify(plan, state);
+    if !report.noop {
+        state.record(plan, &report);
+        state.save(path)?;
+    }
+    Ok(report)
+}
+
+/// Delivers `report` to the configured sinks, warning about the ones that fail.
+fn send_reports(config: &DollyConfig, report: &ApplyReport) {
+    let sinks: Vec<_> = config.reports.iter().map(SinkConfig::sink).collect();
+    for error in deliver_all(&sinks, report) {
+        eprintln!("warning: {error:#}");
+    }
+}
+
+/// Reads a manifest, choosing the front-end from the file extension. Puppet manifests are
+/// merged with the files they import, or with the other manifests of their directory.
+fn load(path: &Path) -> Result<Manifest> {
+    if path.is_dir() {
+        return Manifest::from_dir(path).with_context(|| format!("Cannot load {}", path.display()));
+    }
+    let source =
+        std::fs::read_to_string(path).with_context(|| format!("Cannot read {}", path.display()))?;
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("pp");
+    let manifest = match extension {
+        "json" => Manifest::from_json(&source),
+        "yaml" | "yml" => Manifest::from_yaml(&source),
+        "toml" => Manifest::from_toml(&source),
+        "tf" => dolly::parser::hcl::import(&source).map(|import| {
+            for skipped in import.skipped {
+                eprintln!("skipped: {skipped}");
+            }
+            import.manifest
+        }),
+        "pp" => {
+            warn_about(path, &source);
+            Manifest::from_path(path)
+        }
+        other => Err(anyhow!("Unknown manifest format: .{other}")),
+    };
+    manifest.with_context(|| format!("Cannot load {}", path.display()))
+}
+
+/// Warns about deprecated syntax and relations of a resource to itself in the Puppet
+/// manifest `source` read from `path`.
+fn warn_about(path: &Path, source: &str) {
+    for deprecation in deprecations(source).unwrap_or_default() {
+        eprintln!("{}: {deprecation}", path.display());
+    }
+    for relation in self_relations(source).unwrap_or_default() {
+        eprintln!("{}: warning: {relation}", path.display());
+    }
+}
+
+fn print_plan(plan: &Plan) -> Result<()> {
+    let weights = plan.sorted_weights()?;
+    for (index, node) in &weights {
+        print!("{}", node.id());
+        for edge in plan.plan().edges(*index) {
+            print!(" ({} {})", edge.weight(), weights[&edge.target()].id());
+        }
         println!();
     }
     Ok(())
@@ -0,0 +1,1034 @@
+//! Evaluation of class declarations and defined type instances: the body of every
+//! declared class joins the manifest, next to a `Class[Name]` resource that its resources
+//! are ordered before. Instances of defined types work the same way, with the body
+//! evaluated once per instance and its parameters substituted. Iterations evaluate their
+//! body once per entry.
+
+use super::conditions::Operand;
+use super::functions;
+use super::pp::{
+    AttrValue, Attribute, Iterable, Manifest, Parameter, PuppetExpr, PuppetString, RelationOp,
+    ResourceRef, Span, normalize_rtype,
+};
+use super::template::{Template, is_template_function};
+use crate::facts::Facts;
+use crate::hiera::Hiera;
+use anyhow::{Result, anyhow};
+use indexmap::IndexMap;
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Attributes every resource accepts, so instances of defined types take them besides
+/// their parameters.
+const METAPARAMETERS: &[&str] = &[
+    "require",
+    "before",
+    "notify",
+    "subscribe",
+    "tag",
+    "watch",
+    "concurrency_group",
+];
+
+/// A manifest whose declared classes have been evaluated.
+#[derive(Debug)]
+pub struct Evaluated {
+    /// Resources and relations without class definitions or declarations: every declared
+    /// class is a `Class` resource, contained resources have a relation to it and
+    /// relations with a class endpoint are extended to what it contains. Instances of
+    /// defined types stay as resources of their type, with their bodies expanded.
+    pub manifest: Manifest,
+    /// The classes whose declarations led to a resource, outermost first, with the span
+    /// of each declaration, by resource id.
+    pub classes: HashMap<String, Vec<(String, Span)>>,
+    /// The defined types, by normalized type name.
+    pub defines: Vec<String>,
+    /// The facts conditions and iterations read, whether the node has them or not: the
+    /// evaluation only changes when one of them does.
+    pub facts_used: BTreeSet<String>,
+}
+
+#[derive(Default)]
+struct Evaluation<'a> {
+    /// Parameters and body of every class, by name.
+    definitions: HashMap<String, (&'a [Parameter], &'a [PuppetExpr])>,
+    /// Parameters and body of every defined type, by normalized type name.
+    defines: HashMap<String, (&'a [Parameter], &'a [PuppetExpr])>,
+    expressions: Vec<PuppetExpr>,
+    classes: HashMap<String, Vec<(String, Span)>>,
+    /// What each declared class or defined type instance directly contains.
+    members: IndexMap<ResourceRef, Vec<ResourceRef>>,
+    facts: Facts,
+    facts_used: RefCell<BTreeSet<String>>,
+    hiera: Option<&'a Hiera>,
+    /// Where `template` and `epp` read template files.
+    templates: PathBuf,
+    /// The resource defaults of the scopes being evaluated, outermost first.
+    defaults: Vec<(String, Vec<Attribute>)>,
+    /// The assertions that do not hold, all reported at once.
+    failed_assertions: Vec<String>,
+}
+
+fn class_ref(name: &str, span: Span) -> ResourceRef {
+    ResourceRef {
+        rtype: "Class".to_string(),
+        title: PuppetString::literal(name),
+        span,
+    }
+}
+
+impl<'a> Evaluation<'a> {
+    fn evaluate(
+        &mut self,
+        body: &[PuppetExpr],
+        chain: &[(String, Span)],
+        container: Option<&ResourceRef>,
+    ) -> Result<()> {
+        // Defaults apply to the whole scope wherever they are, and to the scopes it
+        // declares, as in Puppet.
+        let outer = self.defaults.len();
+        for expr in body {
+            if let PuppetExpr::Defaults {
+                rtype, attributes, ..
+            } = expr
+            {
+                self.defaults.push((rtype.clone(), attributes.clone()));
+            }
+        }
+        for expr in body {
+            let resolved = self.resolve_facts(expr)?;
+            match resolved.as_ref().unwrap_or(expr) {
+                PuppetExpr::Resource {
+                    rtype,
+                    title,
+                    attributes,
+                    span,
+                } if rtype == "Class" => {
+                    let declaration = PuppetExpr::Resource {
+                        rtype: rtype.clone(),
+                        title: title.clone(),
+                        attributes: attributes.clone(),
+                        span: *span,
+                    };
+                    let name = title.to_string().trim_start_matches("::").to_lowercase();
+                    self.declare(&name, Some(declaration), *span, chain, None)?;
+                }
+                PuppetExpr::Resource {
+                    rtype,
+                    title,
+                    attributes,
+                    span,
+                } => {
+                    let expr = &PuppetExpr::Resource {
+                        rtype: rtype.clone(),
+                        title: title.clone(),
+                        attributes: self.with_defaults(rtype, attributes),
+                        span: *span,
+                    };
+                    let reference = ResourceRef {
+                        rtype: rtype.clone(),
+                        title: title.clone(),
+                        span: *span,
+                    };
+                    if !chain.is_empty() {
+                        self.classes.insert(reference.id(), chain.to_vec());
+                    }
+                    if let Some(container) = container {
+                        self.members
+                            .entry(container.clone())
+                            .or_default()
+                            .push(reference.clone());
+                    }
+                    self.expressions.push(expr.clone());
+                    if self.defines.contains_key(rtype) {
+                        self.instantiate(expr, reference, chain)?;
+                    }
+                }
+                PuppetExpr::Include {
+                    classes,
+                    contain,
+                    span,
+                } => {
+                    for name in classes {
+                        let container = container.filter(|_| *contain);
+                        self.declare(name, None, *span, chain, container)?;
+                    }
+                }
+                PuppetExpr::Each {
+                    iterable,
+                    params,
+                    body,
+                    span,
+                } => {
+                    for scope in self.iterations(iterable, params, *span)? {
+                        let body = body
+                            .iter()
+                            .map(|expr| substitute(expr, &scope))
+                            .collect::<Result<Vec<_>>>()
+                            .map_err(|e| anyhow!("{e} in the iteration at {span}"))?;
+                        self.evaluate(&body, chain, container)?;
+                    }
+                }
+                PuppetExpr::Conditional {
+                    branches,
+                    otherwise,
+                    span,
+                } => {
+                    let mut chosen = otherwise;
+                    for (condition, body) in branches {
+                        if condition
+                            .holds(&|operand| self.resolve(operand))
+                            .map_err(|e| anyhow!("{e} in the condition at {span}"))?
+                        {
+                            chosen = body;
+                            break;
+                        }
+                    }
+                    self.evaluate(chosen, chain, container)?;
+                }
+                PuppetExpr::Assert {
+                    title,
+                    condition,
+                    message,
+                    span,
+                } => {
+                    let interpolate = |s: &PuppetString| {
+                        s.substitute(|name| Ok(self.variable(name).map(|value| text(&value))))
+                    };
+                    if !condition
+                        .holds(&|operand| self.resolve(operand))
+                        .map_err(|e| anyhow!("{e} in Assert[{title}] at {span}"))?
+                    {
+                        let title = interpolate(title)?;
+                        self.failed_assertions.push(match message {
+                            Some(message) => {
+                                format!("Assert[{title}] at {span}: {}", interpolate(message)?)
+                            }
+                            None => format!("Assert[{title}] at {span}: {condition} does not hold"),
+                        });
+                    }
+                }
+                PuppetExpr::Class { .. } | PuppetExpr::Define { .. } => {}
+                PuppetExpr::Defaults { .. } => {}
+                expr @ (PuppetExpr::Relation { .. } | PuppetExpr::Opaque { .. }) => {
+                    self.expressions.push(expr.clone())
+                }
+            }
+        }
+        self.defaults.truncate(outer);
+        Ok(())
+    }
+
+    /// `attributes` completed with the defaults for `rtype`, the innermost scope's first.
+    fn with_defaults(&self, rtype: &str, attributes: &[Attribute]) -> Vec<Attribute> {
+        let mut attributes = attributes.to_vec();
+        for (_, defaults) in self.defaults.iter().rev().filter(|(r, _)| r == rtype) {
+            for default in defaults {
+                if !attributes.iter().any(|attr| attr.name == default.name) {
+                    attributes.push(default.clone());
+                }
+            }
+        }
+        attributes
+    }
+
+    /// Declares the class `name` once: `include` of a declared class only adds
+    /// containment, a second resource-like declaration is a duplicate.
+    fn declare(
+        &mut self,
+        name: &str,
+        declaration: Option<PuppetExpr>,
+        span: Span,
+        chain: &[(String, Span)],
+        container: Option<&ResourceRef>,
+    ) -> Result<()> {
+        let reference = class_ref(name, span);
+        if let Some(container) = container {
+            self.members
+                .entry(container.clone())
+                .or_default()
+                .push(reference.clone());
+        }
+        if self.members.contains_key(&reference) {
+            if declaration.is_some() {
+                return Err(anyhow!(
+                    "Duplicate declaration: {} at {span} is already declared",
+                    reference.id()
+                ));
+            }
+            return Ok(());
+        }
+        let (params, body) = self
+            .definitions
+            .get(name)
+            .copied()
+            .ok_or_else(|| anyhow!("Unknown class: {name} at {span}"))?;
+        let declared = match &declaration {
+            Some(PuppetExpr::Resource { attributes, .. }) => attributes.as_slice(),
+            _ => &[],
+        };
+        let (scope, mut attributes) = self.bind_class_parameters(name, params, declared, span)?;
+        attributes.extend(
+            declared
+                .iter()
+                .filter(|attribute| METAPARAMETERS.contains(&attribute.name.as_str()))
+                .cloned(),
+        );
+        self.members.insert(reference.clone(), vec![]);
+        if !chain.is_empty() {
+            self.classes.insert(reference.id(), chain.to_vec());
+        }
+        self.expressions.push(PuppetExpr::Resource {
+            rtype: "Class".to_string(),
+            title: PuppetString::literal(name),
+            attributes,
+            span: declaration.as_ref().map_or(span, PuppetExpr::span),
+        });
+        let mut chain = chain.to_vec();
+        chain.push((name.to_string(), span));
+        if params.is_empty() {
+            return self.evaluate(body, &chain, Some(&reference));
+        }
+        let body = body
+            .iter()
+            .map(|expr| substitute(expr, &scope))
+            .collect::<Result<Vec<_>>>()
+            .map_err(|e| anyhow!("{e} in {} at {span}", reference.id()))?;
+        self.evaluate(&body, &chain, Some(&reference))
+    }
+
+    /// The value of every parameter of class `name`: the one declared with
+    /// `class { 'name': param => ... }`, else the one the hierarchy has for `name::param`,
+    /// else the default. Returns the scope of the class body and the parameters as the
+    /// attributes of its `Class` resource.
+    fn bind_class_parameters(
+        &self,
+        name: &str,
+        params: &[Parameter],
+        declared: &[Attribute],
+        span: Span,
+    ) -> Result<(HashMap<String, AttrValue>, Vec<Attribute>)> {
+        let id = format!("Class[{name}]");
+        for attribute in declared {
+            if !params.iter().any(|param| param.name == attribute.name)
+                && !METAPARAMETERS.contains(&attribute.name.as_str())
+            {
+                return Err(anyhow!(
+                    "Unknown parameter {} of {id} at {span}",
+                    attribute.name
+                ));
+            }
+        }
+        let mut scope = HashMap::from([
+            (
+                "title".to_string(),
+                AttrValue::String(PuppetString::literal(name)),
+            ),
+            (
+                "name".to_string(),
+                AttrValue::String(PuppetString::literal(name)),
+            ),
+        ]);
+        let mut attributes = vec![];
+        for param in params {
+            let declared = declared
+                .iter()
+                .find(|attribute| attribute.name == param.name);
+            let value = match declared {
+                Some(attribute) => attribute.value.clone(),
+                None => match self.lookup_key(&format!("{name}::{}", param.name))? {
+                    Some(value) => value,
+                    None => match &param.default {
+                        Some(default) => substitute_value(default, &scope)
+                            .map_err(|e| anyhow!("{e} in the default of {} of {id}", param.name))?,
+                        None => {
+                            return Err(anyhow!(
+                                "Missing parameter {} of {id} at {span}",
+                                param.name
+                            ));
+                        }
+                    },
+                },
+            };
+            scope.insert(param.name.clone(), value.clone());
+            attributes.push(Attribute {
+                name: param.name.clone(),
+                value,
+            });
+        }
+        Ok((scope, attributes))
+    }
+
+    /// The value of `key` in the data hierarchy, if one is configured and has it.
+    fn lookup_key(&self, key: &str) -> Result<Option<AttrValue>> {
+        let Some(hiera) = self.hiera else {
+            return Ok(None);
+        };
+        hiera.lookup(key, &|name| self.fact(name).map(|value| text(&value)))
+    }
+
+    /// `template('nginx.conf.epp')`, `epp('nginx.conf.epp', { 'port' => 80 })` or
+    /// `inline_epp('...')`: the rendered text. Template files are under the templates
+    /// directory.
+    fn render(&self, function: &str, args: &[AttrValue]) -> Result<AttrValue> {
+        let (source, params) = match args {
+            [source] => (source, None),
+            [source, AttrValue::Hash(params)] => (source, Some(params)),
+            _ => {
+                return Err(anyhow!(
+                    "{function} takes a template and a hash of parameters"
+                ));
+            }
+        };
+        let source = source
+            .as_literal()
+            .ok_or_else(|| anyhow!("The {function} template must be a string, got {source}"))?;
+        let (name, text) = match function {
+            "inline_epp" | "inline_template" => ("the inline template".to_string(), source),
+            _ => {
+                let path = self.templates.join(&source);
+                let text = fs::read_to_string(&path)
+                    .map_err(|e| anyhow!("Cannot read template {}: {e}", path.display()))?;
+                (path.display().to_string(), text)
+            }
+        };
+        let template: Template = text
+            .parse()
+            .map_err(|e| anyhow!("{e} in template {name}"))?;
+        let variable = |variable: &str| {
+            params
+                .and_then(|params| params.get(variable).cloned())
+                .or_else(|| self.variable(variable))
+        };
+        let text = template
+            .render(&variable)
+            .map_err(|e| anyhow!("{e} in template {name}"))?;
+        Ok(AttrValue::String(PuppetString::literal(&text)))
+    }
+
+    /// `lookup(key)`, or `lookup(key, type, merge, default)` as in Puppet. Only the
+    /// `first` merge is supported, and the type is not checked.
+    fn lookup(&self, args: &[AttrValue]) -> Result<AttrValue> {
+        let (key, default) = match args {
+            [key] => (key, None),
+            [key, _, merge, default] => {
+                if !matches!(merge.as_literal().as_deref(), None | Some("first")) {
+                    return Err(anyhow!("Unsupported lookup merge {merge}, only 'first' is"));
+                }
+                (key, Some(default))
+            }
+            _ => {
+                return Err(anyhow!(
+                    "lookup takes a key, or a key, a type, a merge and a default, got {} \
+                     arguments",
+                    args.len()
+                ));
+            }
+        };
+        let key = key
+            .as_literal()
+            .ok_or_else(|| anyhow!("The lookup key must be a string, got {key}"))?;
+        match (self.lookup_key(&key)?, default) {
+            (Some(value), _) => Ok(value),
+            (None, Some(default)) => Ok(default.clone()),
+            (None, None) => Err(anyhow!("Cannot find {key} in the hierarchy")),
+        }
+    }
+
+    /// Expands the instance `expr` of a defined type, which `reference` names: the body
+    /// of the type is evaluated with `$title`, `$name` and every parameter bound to the
+    /// values the instance declares, or to the defaults of the parameters.
+    fn instantiate(
+        &mut self,
+        expr: &PuppetExpr,
+        reference: ResourceRef,
+        chain: &[(String, Span)],
+    ) -> Result<()> {
+        let PuppetExpr::Resource {
+            rtype,
+            title,
+            attributes,
+            span,
+        } = expr
+        else {
+            return Ok(());
+        };
+        let (params, body) = self.defines[rtype];
+        let id = reference.id();
+        let title = title
+            .as_literal()
+            .ok_or_else(|| anyhow!("The title of {id} at {span} must be known"))?;
+        let mut scope = HashMap::from([
+            (
+                "title".to_string(),
+                AttrValue::String(PuppetString::literal(&title)),
+            ),
+            (
+                "name".to_string(),
+                AttrValue::String(PuppetString::literal(&title)),
+            ),
+        ]);
+        for attribute in attributes {
+            if !params.iter().any(|param| param.name == attribute.name)
+                && !METAPARAMETERS.contains(&attribute.name.as_str())
+            {
+                return Err(anyhow!(
+                    "Unknown parameter {} of {id} at {span}",
+                    attribute.name
+                ));
+            }
+        }
+        for param in params {
+            let declared = attributes
+                .iter()
+                .find(|attribute| attribute.name == param.name);
+            let value = match (declared, &param.default) {
+                (Some(attribute), _) => attribute.value.clone(),
+                (None, Some(default)) => substitute_value(default, &scope)
+                    .map_err(|e| anyhow!("{e} in the default of {} of {id}", param.name))?,
+                (None, None) => {
+                    return Err(anyhow!(
+                        "Missing parameter {} of {id} at {span}",
+                        param.name
+                    ));
+                }
+            };
+            scope.insert(param.name.clone(), value);
+        }
+        let body = body
+            .iter()
+            .map(|expr| substitute(expr, &scope))
+            .collect::<Result<Vec<_>>>()
+            .map_err(|e| anyhow!("{e} in {id} at {span}"))?;
+        self.members.insert(reference.clone(), vec![]);
+        self.evaluate(&body, chain, Some(&reference))
+    }
+
+    /// The variables of every pass of an iteration, like Puppet's `each`: one parameter
+    /// takes an array element or a `[key, value]` pair of a hash, two take the index or key
+    /// and the value. Fact hashes are iterated in key order, so whatever order facts were
+    /// gathered in, generated resources keep their ids. A missing fact iterates nothing.
+    fn iterations(
+        &self,
+        iterable: &Iterable,
+        params: &[String],
+        span: Span,
+    ) -> Result<Vec<HashMap<String, AttrValue>>> {
+        let value = match iterable {
+            Iterable::Fact(path) => match self.fact(&path.join(".")) {
+                Some(value) => value,
+                None => return Ok(vec![]),
+            },
+            Iterable::Value(value) => value.clone(),
+        };
+        let entries: Vec<(AttrValue, AttrValue)> = match value {
+            AttrValue::Array(values) => values
+                .into_iter()
+                .enumerate()
+                .map(|(i, value)| (AttrValue::Integer(i as i64), value))
+                .collect(),
+            AttrValue::Hash(entries) => entries
+                .into_iter()
+                .map(|(key, value)| (AttrValue::String(PuppetString::literal(&key)), value))
+                .collect(),
+            value => {
+                return Err(anyhow!(
+                    "Cannot iterate over {iterable} at {span}: {value} is not an array or hash"
+                ));
+            }
+        };
+        Ok(entries
+            .into_iter()
+            .map(|(key, value)| match params {
+                [param] if matches!(key, AttrValue::Integer(_)) => {
+                    HashMap::from([(param.clone(), value)])
+                }
+                [param] => HashMap::from([(param.clone(), AttrValue::Array(vec![key, value]))]),
+                [key_param, value_param, ..] => {
+                    HashMap::from([(key_param.clone(), key), (value_param.clone(), value)])
+                }
+                [] => HashMap::new(),
+            })
+            .collect())
+    }
+
+    /// The value of an operand once parameters are substituted: variables are top-level
+    /// facts, and unknown facts are `undef`.
+    fn resolve(&self, operand: &Operand) -> Result<AttrValue> {
+        let fact = |name: &str| self.fact(name).unwrap_or(AttrValue::Undef);
+        Ok(match operand {
+            Operand::Fact(path) => fact(&path.join(".")),
+            Operand::Variable(name) => fact(name),
+            Operand::Value(AttrValue::String(s)) => AttrValue::String(s.interpolate(|name| {
+                self.fact(name)
+                    .map(|value| value.as_literal().unwrap_or_else(|| value.to_string()))
+            })?),
+            Operand::Value(value) => value.clone(),
+        })
+    }
+
+    /// The value of variable `name` outside of any defined type: `$::osfamily`,
+    /// `${facts.osfamily}` and `$osfamily` are all the fact `osfamily`. A fact the node does
+    /// not have is `undef`, but a plain `$name` is kept as it is.
+    fn variable(&self, name: &str) -> Option<AttrValue> {
+        if !is_fact_variable(name) {
+            return self.fact(name);
+        }
+        let name = name.trim_start_matches("::");
+        let name = name.strip_prefix("facts.").unwrap_or(name);
+        Some(self.fact(name).unwrap_or(AttrValue::Undef))
+    }
+
+    /// A resource or relation with the facts it uses substituted, `None` for other
+    /// expressions, whose facts are resolved as they are evaluated.
+    fn resolve_facts(&self, expr: &PuppetExpr) -> Result<Option<PuppetExpr>> {
+        let lookup = |name: &str| Ok(self.variable(name));
+        let call = |function: &str, args: &[AttrValue]| match function {
+            "lookup" => self.lookup(args).map(Some),
+            _ if is_template_function(function) => self.render(function, args).map(Some),
+            _ => functions::call(function, args).map(Some),
+        };
+        let title = |title: &PuppetString| {
+            title.substitute(|name| Ok(self.variable(name).map(|value| text(&value))))
+        };
+        let reference = |r: &ResourceRef| -> Result<ResourceRef> {
+            Ok(ResourceRef {
+                title: title(&r.title)?,
+                ..r.clone()
+            })
+        };
+        Ok(match expr {
+            PuppetExpr::Resource {
+                rtype,
+                title: name,
+                attributes,
+                span,
+            } => Some(PuppetExpr::Resource {
+                rtype: rtype.clone(),
+                title: title(name)?,
+                attributes: attributes
+                    .iter()
+                    .map(|attribute| {
+                        Ok(Attribute {
+                            name: attribute.name.clone(),
+                            value: resolve_value(&attribute.value, &lookup, &call)
+                                .map_err(|e| anyhow!("{e} in {rtype}[{name}] at {span}"))?,
+                        })
+                    })
+                    .collect::<Result<_>>()?,
+                span: *span,
+            }),
+            PuppetExpr::Relation { from, to, op } => Some(PuppetExpr::Relation {
+                from: from.iter().map(reference).collect::<Result<_>>()?,
+                to: to.iter().map(reference).collect::<Result<_>>()?,
+                op: *op,
+            }),
+            _ => None,
+        })
+    }
+
+    /// The fact `name`, recorded as used.
+    fn fact(&self, name: &str) -> Option<AttrValue> {
+        self.facts_used.borrow_mut().insert(name.to_string());
+        fact_value(&self.facts, name)
+    }
+
+    /// Everything `container` contains, including what the classes and instances it
+    /// contains contain.
+    fn contents(&self, container: &ResourceRef) -> Vec<ResourceRef> {
+        let mut contents = Vec::new();
+        for member in self.members.get(container).into_iter().flatten() {
+            contents.push(member.clone());
+            contents.extend(self.contents(member));
+        }
+        contents
+    }
+
+    /// The reference itself if it names a declared class or defined type instance.
+    fn container_of<'r>(&self, reference: &'r ResourceRef) -> Option<&'r ResourceRef> {
+        self.members.contains_key(reference).then_some(reference)
+    }
+
+    /// Containment relations, and relations with a class endpoint extended to what the
+    /// class contains: `X -> Class[a]` orders X before the contents of `a`, and the
+    /// contents of `a` notify for `Class[a] ~> X`.
+    fn relations<'r>(&self, declared: impl Iterator<Item = &'r PuppetExpr>) -> Vec<PuppetExpr> {
+        let mut relations = Vec::new();
+        for (container, members) in &self.members {
+            if !members.is_empty() {
+                relations.push(PuppetExpr::Relation {
+                    from: members.clone(),
+                    to: vec![ResourceRef {
+                        span: Span::default(),
+                        ..container.clone()
+                    }],
+                    op: RelationOp::Provide,
+                });
+            }
+        }
+        for expr in declared {
+            let PuppetExpr::Relation { from, to, op } = expr else {
+                continue;
+            };
+            let (sources, targets, notify) = match op {
+                RelationOp::Provide => (from, to, false),
+                RelationOp::Notify => (from, to, true),
+                RelationOp::Require => (to, from, false),
+                RelationOp::Subscribe => (to, from, true),
+            };
+            let op = if notify {
+                RelationOp::Notify
+            } else {
+                RelationOp::Provide
+            };
+            for container in targets
+                .iter()
+                .filter_map(|target| self.container_of(target))
+            {
+                let contents = self.contents(container);
+                if !contents.is_empty() {
+                    relations.push(PuppetExpr::Relation {
+                        from: sources.clone(),
+                        to: contents,
+                        op,
+                    });
+                }
+            }
+            if !notify {
+                continue;
+            }
+            for container in sources
+                .iter()
+                .filter_map(|source| self.container_of(source))
+            {
+                let contents = self.contents(container);
+                if !contents.is_empty() {
+                    relations.push(PuppetExpr::Relation {
+                        from: contents,
+                        to: targets.clone(),
+                        op: RelationOp::Notify,
+                    });
+                }
+            }
+        }
+        relations
+    }
+}
+
+impl Manifest {
+    /// Evaluates the declared classes and defined type instances, without facts to
+    /// iterate over.
+    pub fn evaluate_classes(&self) -> Result<Evaluated> {
+        self.evaluate(&Facts::new())
+    }
+
+    /// Evaluates the declared classes, defined type instances and iterations over
+    /// `facts`. Declaring an undefined class and defining a class or type twice are errors;
+    /// classes and types defined but never declared add nothing.
+    pub fn evaluate(&self, facts: &Facts) -> Result<Evaluated> {
+        self.evaluate_with(facts, &Hiera::default(), Path::new(""))
+    }
+
+    /// Evaluates as [`Manifest::evaluate`], looking data up in `hiera` and reading
+    /// templates under `templates`.
+    pub fn evaluate_with(
+        &self,
+        facts: &Facts,
+        hiera: &Hiera,
+        templates: &Path,
+    ) -> Result<Evaluated> {
+        let mut evaluation = Evaluation {
+            facts: facts.clone(),
+            hiera: Some(hiera),
+            templates: templates.to_path_buf(),
+            ..Evaluation::default()
+        };
+        for expr in &self.0 {
+            match expr {
+                PuppetExpr::Class {
+                    name,
+                    params,
+                    body,
+                    span,
+                } if evaluation
+                    .definitions
+                    .insert(name.clone(), (params.as_slice(), body.as_slice()))
+                    .is_some() =>
+                {
+                    return Err(anyhow!("Class {name} at {span} is already defined"));
+                }
+                PuppetExpr::Define {
+                    name,
+                    params,
+                    body,
+                    span,
+                } if evaluation
+                    .defines
+                    .insert(normalize_rtype(name), (params.as_slice(), body.as_slice()))
+                    .is_some() =>
+                {
+                    return Err(anyhow!("Type {name} at {span} is already defined"));
+                }
+                _ => {}
+            }
+        }
+        evaluation.evaluate(&self.0, &[], None)?;
+        if !evaluation.failed_assertions.is_empty() {
+            return Err(anyhow!(
+                "Preconditions not met, nothing applied:\n  {}",
+                evaluation.failed_assertions.join("\n  ")
+            ));
+        }
+        let mut manifest = Manifest(std::mem::take(&mut evaluation.expressions));
+        let metaparameters = manifest.metaparameter_relations()?;
+        let relations = evaluation.relations(manifest.relations().chain(&metaparameters));
+        manifest.0.extend(relations);
+        Ok(Evaluated {
+            manifest,
+            classes: evaluation.classes,
+            defines: evaluation.defines.into_keys().collect(),
+            facts_used: evaluation.facts_used.into_inner(),
+        })
+    }
+}
+
+/// The fact `name` as a value: the string of a fact, or the hash of the facts below a
+/// dotted name, e.g. `networking.interfaces` for `networking.interfaces.eth0.ip`.
+fn fact_value(facts: &Facts, name: &str) -> Option<AttrValue> {
+    if let Some(value) = facts.get(name) {
+        return Some(AttrValue::String(PuppetString::literal(value)));
+    }
+    let prefix = format!("{name}.");
+    let mut keys: Vec<_> = facts
+        .0
+        .keys()
+        .filter_map(|fact| fact.strip_prefix(&prefix))
+        .map(|rest| rest.split('.').next().unwrap_or(rest))
+        .collect();
+    keys.dedup();
+    let entries: IndexMap<_, _> = keys
+        .into_iter()
+        .filter_map(|key| {
+            Some((
+                key.to_string(),
+                fact_value(facts, &format!("{prefix}{key}"))?,
+            ))
+        })
+        .collect();
+    (!entries.is_empty()).then_some(AttrValue::Hash(entries))
+}
+
+/// `expr` with the variables of `scope` substituted, as in the body of a defined type.
+fn substitute(expr: &PuppetExpr, scope: &HashMap<String, AttrValue>) -> Result<PuppetExpr> {
+    let lookup = |name: &str| Ok(binding(scope, name)?.map(|value| text(&value)));
+    let reference = |r: &ResourceRef| -> Result<ResourceRef> {
+        Ok(ResourceRef {
+            title: r.title.substitute(lookup)?,
+            ..r.clone()
+        })
+    };
+    // Variables the scope does not bind in conditions are facts, resolved at evaluation.
+    let operand = |operand: &Operand| match operand {
+        Operand::Variable(name) if scope.contains_key(name) => Operand::Value(scope[name].clone()),
+        Operand::Value(AttrValue::String(s)) => s.substitute(lookup).map_or_else(
+            |_| operand.clone(),
+            |s| Operand::Value(AttrValue::String(s)),
+        ),
+        operand => operand.clone(),
+    };
+    Ok(match expr {
+        PuppetExpr::Resource {
+            rtype,
+            title,
+            attributes,
+            span,
+        } => PuppetExpr::Resource {
+            rtype: rtype.clone(),
+            title: title.substitute(lookup)?,
+            attributes: attributes
+                .iter()
+                .map(|attribute| {
+                    Ok(Attribute {
+                        name: attribute.name.clone(),
+                        value: substitute_value(&attribute.value, scope)?,
+                    })
+                })
+                .collect::<Result<_>>()?,
+            span: *span,
+        },
+        PuppetExpr::Defaults {
+            rtype,
+            attributes,
+            span,
+        } => PuppetExpr::Defaults {
+            rtype: rtype.clone(),
+            attributes: attributes
+                .iter()
+                .map(|attribute| {
+                    Ok(Attribute {
+                        name: attribute.name.clone(),
+                        value: substitute_value(&attribute.value, scope)?,
+                    })
+                })
+                .collect::<Result<_>>()?,
+            span: *span,
+        },
+        PuppetExpr::Relation { from, to, op } => PuppetExpr::Relation {
+            from: from.iter().map(reference).collect::<Result<_>>()?,
+            to: to.iter().map(reference).collect::<Result<_>>()?,
+            op: *op,
+        },
+        PuppetExpr::Conditional {
+            branches,
+            otherwise,
+            span,
+        } => {
+            let body = |body: &[PuppetExpr]| -> Result<Vec<PuppetExpr>> {
+                body.iter().map(|expr| substitute(expr, scope)).collect()
+            };
+            PuppetExpr::Conditional {
+                branches: branches
+                    .iter()
+                    .map(|(condition, branch)| {
+                        Ok((condition.map_operands(&operand), body(branch)?))
+                    })
+                    .collect::<Result<_>>()?,
+                otherwise: body(otherwise)?,
+                span: *span,
+            }
+        }
+        PuppetExpr::Assert {
+            title,
+            condition,
+            message,
+            span,
+        } => PuppetExpr::Assert {
+            title: title.substitute(lookup)?,
+            condition: condition.map_operands(&operand),
+            message: message
+                .as_ref()
+                .map(|message| message.substitute(lookup))
+                .transpose()?,
+            span: *span,
+        },
+        expr => expr.clone(),
+    })
+}
+
+/// Whether variable `name` is a fact, `$::osfamily` or `$facts['os']['family']`, which
+/// only the evaluation resolves.
+fn is_fact_variable(name: &str) -> bool {
+    name.starts_with("::") || name.starts_with("facts.")
+}
+
+/// The value `scope` binds to variable `name`, `None` for a fact left for the evaluation.
+fn binding(scope: &HashMap<String, AttrValue>, name: &str) -> Result<Option<AttrValue>> {
+    match scope.get(name) {
+        Some(value) => Ok(Some(value.clone())),
+        None if is_fact_variable(name) => Ok(None),
+        None => Err(anyhow!("Unknown variable ${name}")),
+    }
+}
+
+/// The text of a value when interpolated in a string, empty for `undef`.
+fn text(value: &AttrValue) -> String {
+    match value {
+        AttrValue::Undef => String::new(),
+        value => value.as_literal().unwrap_or_else(|| value.to_string()),
+    }
+}
+
+/// A bare `$param` takes the value of the parameter whatever its type, variables
+/// interpolated in strings take its text.
+fn substitute_value(value: &AttrValue, scope: &HashMap<String, AttrValue>) -> Result<AttrValue> {
+    let call = |function: &str, args: &[AttrValue]| match function {
+        _ if is_template_function(function) => Ok(Some(with_scope(function, args, scope))),
+        _ if functions::depends_on_node(function) => Ok(None),
+        _ => functions::call(function, args).map(Some),
+    };
+    resolve_value(value, &|name| binding(scope, name), &call)
+}
+
+/// A template call that also sees the variables of `scope` its parameters do not set, to
+/// be rendered once the facts are known.
+fn with_scope(function: &str, args: &[AttrValue], scope: &HashMap<String, AttrValue>) -> AttrValue {
+    let mut args = args.to_vec();
+    if args.len() == 1 {
+        args.push(AttrValue::Hash(IndexMap::new()));
+    }
+    if let Some(AttrValue::Hash(params)) = args.get_mut(1) {
+        let mut names: Vec<_> = scope.keys().collect();
+        names.sort();
+        for name in names {
+            params
+                .entry(name.clone())
+                .or_insert_with(|| scope[name].clone());
+        }
+    }
+    AttrValue::Call {
+        function: function.to_string(),
+        args,
+    }
+}
+
+/// Calls a function, or returns `None` to leave the call for later.
+type FunctionCall<'a> = dyn Fn(&str, &[AttrValue]) -> Result<Option<AttrValue>> + 'a;
+
+/// `value` with the variables `lookup` gives a value replaced, and functions called once
+/// their arguments are known, by `call` unless it leaves them to later.
+fn resolve_value(
+    value: &AttrValue,
+    lookup: &dyn Fn(&str) -> Result<Option<AttrValue>>,
+    call: &FunctionCall,
+) -> Result<AttrValue> {
+    let interpolate = |s: &PuppetString| s.substitute(|name| Ok(lookup(name)?.map(|v| text(&v))));
+    Ok(match value {
+        AttrValue::String(s) => match s.as_variable() {
+            Some(name) => lookup(name)?.unwrap_or_else(|| value.clone()),
+            None => AttrValue::String(interpolate(s)?),
+        },
+        AttrValue::Array(values) => AttrValue::Array(
+            values
+                .iter()
+                .map(|value| resolve_value(value, lookup, call))
+                .collect::<Result<_>>()?,
+        ),
+        AttrValue::Hash(entries) => AttrValue::Hash(
+            entries
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), resolve_value(value, lookup, call)?)))
+                .collect::<Result<_>>()?,
+        ),
+        AttrValue::ResourceRef(r) => AttrValue::ResourceRef(ResourceRef {
+            title: interpolate(&r.title)?,
+            ..r.clone()
+        }),
+        AttrValue::Deferred { function, args } => AttrValue::Deferred {
+            function: function.clone(),
+            args: args
+                .iter()
+                .map(|arg| resolve_value(arg, lookup, call))
+                .collect::<Result<_>>()?,
+        },
+        AttrValue::Sensitive(value) => {
+            AttrValue::Sensitive(Box::new(resolve_value(value, lookup, call)?))
+        }
+        AttrValue::Call { function, args } => {
+            let args: Vec<_> = args
+                .iter()
+                .map(|arg| resolve_value(arg, lookup, call))
+                .collect::<Result<_>>()?;
+            let called = match args.iter().any(AttrValue::has_variables) {
+                true => None,
+                false => call(function, &args)?,
+            };
+            called.unwrap_or_else(|| AttrValue::Call {
+                function: function.clone(),
+                args,
+            })
+        }
+        value => value.clone(),
+    })
+}
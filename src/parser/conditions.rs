@@ -0,0 +1,208 @@
+//! Conditions of `if`, `unless` and `case` statements, compared the way Puppet compares
+//! values: strings case-insensitively, and only `undef` and `false` are false.
+
+use super::pp::AttrValue;
+use anyhow::{Result, anyhow};
+use std::cmp::Ordering;
+use std::fmt;
+
+/// A value a condition reads.
+#[derive(Debug, Clone)]
+pub enum Operand {
+    Value(AttrValue),
+    /// A structured fact, `$facts['os']['family']`, by the keys of its path.
+    Fact(Vec<String>),
+    /// A variable, `$osfamily` or `$::osfamily`: a parameter inside a defined type or an
+    /// iteration, a top-level fact otherwise.
+    Variable(String),
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Value(AttrValue::String(s)) => write!(f, "'{s}'"),
+            Self::Value(value) => write!(f, "{value}"),
+            Self::Fact(path) => {
+                write!(f, "$facts")?;
+                for key in path {
+                    write!(f, "['{key}']")?;
+                }
+                Ok(())
+            }
+            Self::Variable(name) => write!(f, "${name}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    In,
+}
+
+impl fmt::Display for CompareOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            Self::Eq => "==",
+            Self::Ne => "!=",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+            Self::In => "in",
+        };
+        write!(f, "{op}")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Condition {
+    /// Holds unless the operand is `undef` or `false`.
+    Value(Operand),
+    Compare {
+        left: Operand,
+        op: CompareOp,
+        right: Operand,
+    },
+    Not(Box<Condition>),
+    And(Vec<Condition>),
+    Or(Vec<Condition>),
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |f: &mut fmt::Formatter<'_>, conditions: &[Condition], keyword: &str| {
+            for (i, condition) in conditions.iter().enumerate() {
+                if i > 0 {
+                    write!(f, " {keyword} ")?;
+                }
+                match condition {
+                    Self::And(_) | Self::Or(_) => write!(f, "({condition})")?,
+                    condition => write!(f, "{condition}")?,
+                }
+            }
+            Ok(())
+        };
+        match self {
+            Self::Value(operand) => write!(f, "{operand}"),
+            Self::Compare { left, op, right } => write!(f, "{left} {op} {right}"),
+            Self::Not(condition) => write!(f, "!({condition})"),
+            Self::And(conditions) => join(f, conditions, "and"),
+            Self::Or(conditions) => join(f, conditions, "or"),
+        }
+    }
+}
+
+impl Condition {
+    /// Whether the condition holds, with `resolve` giving the value of every operand.
+    pub fn holds(&self, resolve: &impl Fn(&Operand) -> Result<AttrValue>) -> Result<bool> {
+        Ok(match self {
+            Self::Value(operand) => truthy(&resolve(operand)?),
+            Self::Compare { left, op, right } => compare(&resolve(left)?, *op, &resolve(right)?)?,
+            Self::Not(condition) => !condition.holds(resolve)?,
+            Self::And(conditions) => {
+                for condition in conditions {
+                    if !condition.holds(resolve)? {
+                        return Ok(false);
+                    }
+                }
+                true
+            }
+            Self::Or(conditions) => {
+                for condition in conditions {
+                    if condition.holds(resolve)? {
+                        return Ok(true);
+                    }
+                }
+                false
+            }
+        })
+    }
+
+    /// The condition with every operand replaced by `substitute`.
+    pub fn map_operands(&self, substitute: &impl Fn(&Operand) -> Operand) -> Self {
+        match self {
+            Self::Value(operand) => Self::Value(substitute(operand)),
+            Self::Compare { left, op, right } => Self::Compare {
+                left: substitute(left),
+                op: *op,
+                right: substitute(right),
+            },
+            Self::Not(condition) => Self::Not(Box::new(condition.map_operands(substitute))),
+            Self::And(conditions) => Self::And(
+                conditions
+                    .iter()
+                    .map(|condition| condition.map_operands(substitute))
+                    .collect(),
+            ),
+            Self::Or(conditions) => Self::Or(
+                conditions
+                    .iter()
+                    .map(|condition| condition.map_operands(substitute))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+fn truthy(value: &AttrValue) -> bool {
+    !matches!(value, AttrValue::Undef | AttrValue::Bool(false))
+}
+
+/// Scalars are equal as integers when both sides are numbers, facts being strings, and
+/// as strings ignoring case otherwise.
+fn equals(left: &AttrValue, right: &AttrValue) -> bool {
+    match (left, right) {
+        (AttrValue::Array(left), AttrValue::Array(right)) => {
+            left.len() == right.len() && left.iter().zip(right).all(|(l, r)| equals(l, r))
+        }
+        (AttrValue::Hash(left), AttrValue::Hash(right)) => {
+            left.len() == right.len()
+                && left
+                    .iter()
+                    .all(|(key, l)| right.get(key).is_some_and(|r| equals(l, r)))
+        }
+        (AttrValue::Undef, AttrValue::Undef) => true,
+        (left, right) => order(left, right) == Some(Ordering::Equal),
+    }
+}
+
+fn order(left: &AttrValue, right: &AttrValue) -> Option<Ordering> {
+    let (left, right) = (left.as_literal()?, right.as_literal()?);
+    match (left.parse::<i64>(), right.parse::<i64>()) {
+        (Ok(left), Ok(right)) => Some(left.cmp(&right)),
+        _ => Some(left.to_lowercase().cmp(&right.to_lowercase())),
+    }
+}
+
+fn compare(left: &AttrValue, op: CompareOp, right: &AttrValue) -> Result<bool> {
+    let ordering =
+        || order(left, right).ok_or_else(|| anyhow!("Cannot compare {left} with {right}"));
+    Ok(match op {
+        CompareOp::Eq => equals(left, right),
+        CompareOp::Ne => !equals(left, right),
+        CompareOp::Lt => ordering()?.is_lt(),
+        CompareOp::Le => ordering()?.is_le(),
+        CompareOp::Gt => ordering()?.is_gt(),
+        CompareOp::Ge => ordering()?.is_ge(),
+        CompareOp::In => match right {
+            AttrValue::Array(values) => values.iter().any(|value| equals(left, value)),
+            AttrValue::Hash(entries) => entries.keys().any(|key| {
+                left.as_literal()
+                    .is_some_and(|left| left.eq_ignore_ascii_case(key))
+            }),
+            AttrValue::Undef => false,
+            right => match (left.as_literal(), right.as_literal()) {
+                (Some(needle), Some(haystack)) => {
+                    haystack.to_lowercase().contains(&needle.to_lowercase())
+                }
+                _ => return Err(anyhow!("Cannot look for {left} in {right}")),
+            },
+        },
+    })
+}
@@ -0,0 +1,265 @@
+//! Front-end for resources and relations described as YAML or JSON data instead of the
+//! Puppet DSL:
+//!
+//! ```yaml
+//! resources:
+//!   - type: file
+//!     title: /etc/nginx/nginx.conf
+//!     attributes:
+//!       mode: "0644"
+//!   - type: service
+//!     title: nginx
+//! relations:
+//!   - from: File[/etc/nginx/nginx.conf]
+//!     op: "~>"
+//!     to: ["Service[nginx]"]
+//! ```
+//!
+//! or as TOML, with one table per resource and its dependencies listed in `requires`:
+//!
+//! ```toml
+//! ["File[/etc/nginx/nginx.conf]"]
+//! mode = "0644"
+//!
+//! ["Service[nginx]"]
+//! requires = ["File[/etc/nginx/nginx.conf]"]
+//! ```
+
+use super::pp::{
+    AttrValue, Attribute, Manifest, PuppetExpr, PuppetString, RelationOp, ResourceRef, Span,
+    normalize_rtype,
+};
+use anyhow::{Context, Result};
+use indexmap::IndexMap;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Document {
+    #[serde(default)]
+    resources: Vec<ResourceDecl>,
+    #[serde(default)]
+    relations: Vec<RelationDecl>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ResourceDecl {
+    #[serde(rename = "type")]
+    rtype: String,
+    title: String,
+    #[serde(default)]
+    attributes: IndexMap<String, Scalar>,
+}
+
+/// A TOML resource table: `requires` plus the remaining keys as attributes.
+#[derive(Debug, Deserialize)]
+struct TomlResource {
+    #[serde(default)]
+    requires: Vec<String>,
+    #[serde(flatten)]
+    attributes: IndexMap<String, Scalar>,
+}
+
+/// An attribute value written as data.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Scalar {
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+}
+
+impl Scalar {
+    /// The attribute value, typed like the same value written in a manifest.
+    pub fn to_attr_value(&self) -> AttrValue {
+        match self {
+            Self::Bool(b) => AttrValue::Bool(*b),
+            Self::Integer(i) => AttrValue::Integer(*i),
+            _ => AttrValue::String(PuppetString::literal(&self.to_text())),
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        match self {
+            Self::Bool(b) => b.to_string(),
+            Self::Integer(i) => i.to_string(),
+            Self::Float(f) => f.to_string(),
+            Self::String(s) => s.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RelationDecl {
+    from: OneOrMany,
+    to: OneOrMany,
+    #[serde(default = "default_op")]
+    op: String,
+}
+
+fn default_op() -> String {
+    "->".to_string()
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl OneOrMany {
+    fn refs(&self) -> Result<Vec<ResourceRef>> {
+        match self {
+            Self::One(r) => Ok(vec![r.parse()?]),
+            Self::Many(refs) => refs.iter().map(|r| r.parse()).collect(),
+        }
+    }
+}
+
+fn resource_expr(rtype: &str, title: &str, attributes: &IndexMap<String, Scalar>) -> PuppetExpr {
+    PuppetExpr::Resource {
+        rtype: normalize_rtype(rtype),
+        title: PuppetString::literal(title),
+        attributes: attributes
+            .iter()
+            .map(|(name, value)| Attribute {
+                name: name.clone(),
+                value: value.to_attr_value(),
+            })
+            .collect(),
+        span: Span::default(),
+    }
+}
+
+impl Document {
+    fn into_manifest(self) -> Result<Manifest> {
+        let mut expressions = Vec::new();
+        for resource in self.resources {
+            expressions.push(resource_expr(
+                &resource.rtype,
+                &resource.title,
+                &resource.attributes,
+            ));
+        }
+        for relation in self.relations {
+            expressions.push(PuppetExpr::Relation {
+                from: relation.from.refs()?,
+                to: relation.to.refs()?,
+                op: relation.op.parse()?,
+            });
+        }
+        Manifest::from_exprs(expressions)
+    }
+}
+
+impl Manifest {
+    pub fn from_json(input: &str) -> Result<Self> {
+        let document: Document = serde_json::from_str(input).context("Invalid JSON manifest")?;
+        document.into_manifest()
+    }
+
+    pub fn from_yaml(input: &str) -> Result<Self> {
+        let document: Document = serde_yaml::from_str(input).context("Invalid YAML manifest")?;
+        document.into_manifest()
+    }
+
+    pub fn from_toml(input: &str) -> Result<Self> {
+        let tables: IndexMap<String, TomlResource> =
+            toml::from_str(input).context("Invalid TOML manifest")?;
+        let mut expressions = Vec::new();
+        let mut relations = Vec::new();
+        for (key, table) in tables {
+            let target = key.parse::<ResourceRef>()?;
+            let title = target.title.as_literal().unwrap_or_default();
+            expressions.push(resource_expr(&target.rtype, &title, &table.attributes));
+            if !table.requires.is_empty() {
+                relations.push(PuppetExpr::Relation {
+                    from: table
+                        .requires
+                        .iter()
+                        .map(|r| r.parse())
+                        .collect::<Result<_>>()?,
+                    to: vec![target],
+                    op: RelationOp::Provide,
+                });
+            }
+        }
+        expressions.extend(relations);
+        Manifest::from_exprs(expressions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_puppet_manifest;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_yaml_and_json_match_puppet() -> Result<()> {
+        let puppet = r#"
+            file { "/etc/nginx/nginx.conf": mode => "0644" }
+            service { "nginx": }
+            File["/etc/nginx/nginx.conf"] ~> Service["nginx"]
+        "#;
+        let yaml = r#"
+resources:
+  - type: file
+    title: /etc/nginx/nginx.conf
+    attributes:
+      mode: "0644"
+  - type: service
+    title: nginx
+relations:
+  - from: File['/etc/nginx/nginx.conf']
+    op: "~>"
+    to: ["Service[nginx]"]
+"#;
+        let json = r#"{
+            "resources": [
+                {"type": "file", "title": "/etc/nginx/nginx.conf", "attributes": {"mode": "0644"}},
+                {"type": "service", "title": "nginx"}
+            ],
+            "relations": [{"from": ["File[/etc/nginx/nginx.conf]"], "op": "~>", "to": "Service[nginx]"}]
+        }"#;
+
+        let expected = parse_puppet_manifest(&Manifest::from_str(puppet)?)?.to_canonical_text();
+        let from_yaml = parse_puppet_manifest(&Manifest::from_yaml(yaml)?)?;
+        let from_json = parse_puppet_manifest(&Manifest::from_json(json)?)?;
+        assert_eq!(from_yaml.to_canonical_text(), expected);
+        assert_eq!(from_json.to_canonical_text(), expected);
+
+        let toml = r#"
+            ["File[/etc/nginx/nginx.conf]"]
+            mode = "0644"
+
+            ["Service[nginx]"]
+            requires = ["File['/etc/nginx/nginx.conf']"]
+        "#;
+        let requires = puppet.replace("~>", "->");
+        let expected = parse_puppet_manifest(&Manifest::from_str(&requires)?)?.to_canonical_text();
+        let from_toml = parse_puppet_manifest(&Manifest::from_toml(toml)?)?;
+        assert_eq!(from_toml.to_canonical_text(), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_data_front_end_errors() {
+        let undefined = r#"{"relations": [{"from": "File[/a]", "to": "File[/b]"}]}"#;
+        let Err(e) = Manifest::from_json(undefined) else {
+            panic!("Undefined references should fail");
+        };
+        assert!(
+            e.to_string()
+                .contains("Undefined resource reference: File[/a]")
+        );
+        assert!(Manifest::from_yaml("resources: [{type: file}]").is_err());
+        assert!(Manifest::from_json(r#"{"relations": [{"from": "File", "to": "x"}]}"#).is_err());
+        assert!(Manifest::from_toml("[\"File[/a]\"]\nrequires = [\"File[/b]\"]").is_err());
+        assert!(Manifest::from_toml("[nginx]\nensure = \"running\"").is_err());
+    }
+}
@@ -0,0 +1,165 @@
+//! Syntax dolly still accepts and normalizes but manifests should stop using. Every
+//! deprecated construct is an entry of [`DEPRECATED`]; checking a manifest walks its parse
+//! tree and reports each use with the spelling that replaces it, so the language can
+//! evolve without silently changing what existing manifests mean.
+
+use super::pp::{Rule, Span, parse_tree};
+use anyhow::Result;
+use pest::iterators::Pair;
+use std::fmt;
+
+/// A deprecated grammar construct and how to rewrite a use of it.
+pub struct Deprecated {
+    /// Stable name of the construct, for tools that filter warnings.
+    pub construct: &'static str,
+    /// The grammar rule whose pairs may use the construct.
+    pub rule: Rule,
+    pub message: &'static str,
+    /// The text replacing a pair of `rule`, `None` when the pair does not use the construct.
+    pub replacement: fn(&Pair<Rule>) -> Option<String>,
+}
+
+pub const DEPRECATED: &[Deprecated] = &[
+    Deprecated {
+        construct: "top-scope-prefix",
+        rule: Rule::rtype,
+        message: "the top-scope prefix `::` on a type",
+        replacement: without_top_scope,
+    },
+    Deprecated {
+        construct: "top-scope-prefix",
+        rule: Rule::ref_rtype,
+        message: "the top-scope prefix `::` on a type",
+        replacement: without_top_scope,
+    },
+    Deprecated {
+        construct: "top-scope-prefix",
+        rule: Rule::class_name,
+        message: "the top-scope prefix `::` on a class name",
+        replacement: without_top_scope,
+    },
+    Deprecated {
+        construct: "capitalized-class-name",
+        rule: Rule::class_name,
+        message: "a class name with capitals",
+        replacement: lowercase,
+    },
+    Deprecated {
+        construct: "capitalized-resource-type",
+        rule: Rule::rtype,
+        message: "a resource declaration with a capitalized type",
+        replacement: lowercase,
+    },
+    Deprecated {
+        construct: "numeric-file-mode",
+        rule: Rule::attribute,
+        message: "a numeric file mode",
+        replacement: quoted_mode,
+    },
+];
+
+fn without_top_scope(pair: &Pair<Rule>) -> Option<String> {
+    pair.as_str().strip_prefix("::").map(str::to_string)
+}
+
+fn lowercase(pair: &Pair<Rule>) -> Option<String> {
+    let text = pair.as_str();
+    let lowercase = text.to_lowercase();
+    (lowercase != text).then_some(lowercase)
+}
+
+/// `mode => 0644` becomes `mode => '0644'`, which does not depend on how integers are read.
+fn quoted_mode(pair: &Pair<Rule>) -> Option<String> {
+    let mut inner = pair.clone().into_inner();
+    let (name, value) = (inner.next()?, inner.next()?.into_inner().next()?);
+    (name.as_str() == "mode" && value.as_rule() == Rule::integer)
+        .then(|| format!("mode => '{}'", value.as_str()))
+}
+
+/// One use of a deprecated construct.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deprecation {
+    pub construct: &'static str,
+    pub message: &'static str,
+    pub span: Span,
+    /// The source text using the construct.
+    pub found: String,
+    pub replacement: String,
+}
+
+impl fmt::Display for Deprecation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "warning: deprecated {} at {}: use `{}` instead of `{}`",
+            self.message, self.span, self.replacement, self.found
+        )
+    }
+}
+
+/// Every use of a deprecated construct in `source`, in source order.
+pub fn deprecations(source: &str) -> Result<Vec<Deprecation>> {
+    let mut found = Vec::new();
+    for pair in parse_tree(source)? {
+        check(pair, &mut found);
+    }
+    Ok(found)
+}
+
+fn check(pair: Pair<Rule>, found: &mut Vec<Deprecation>) {
+    for deprecated in DEPRECATED.iter().filter(|d| d.rule == pair.as_rule()) {
+        if let Some(replacement) = (deprecated.replacement)(&pair) {
+            found.push(Deprecation {
+                construct: deprecated.construct,
+                message: deprecated.message,
+                span: pair.as_span().into(),
+                found: pair.as_str().to_string(),
+                replacement,
+            });
+        }
+    }
+    for inner in pair.into_inner() {
+        check(inner, found);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_legacy_syntax_is_deprecated() -> Result<()> {
+        let input = r#"
+            class Base { file { "/etc/motd": mode => 0644 } }
+            include ::base
+            File { "/tmp/a": mode => "0600" }
+            ::foo::bar { "x": }
+            Foo::Bar["x"] -> ::File["/tmp/a"]
+        "#;
+        let found: Vec<_> = deprecations(input)?
+            .iter()
+            .map(|d| {
+                format!(
+                    "{} {} {} -> {}",
+                    d.span, d.construct, d.found, d.replacement
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                "2:19 capitalized-class-name Base -> base",
+                "2:46 numeric-file-mode mode => 0644 -> mode => '0644'",
+                "3:21 top-scope-prefix ::base -> base",
+                "4:13 capitalized-resource-type File -> file",
+                "5:13 top-scope-prefix ::foo::bar -> foo::bar",
+                "6:30 top-scope-prefix ::File -> File",
+            ]
+        );
+        assert!(
+            deprecations(r#"file { "/tmp/a": mode => "0644" }"#)?.is_empty(),
+            "Current syntax is not deprecated"
+        );
+        Ok(())
+    }
+}
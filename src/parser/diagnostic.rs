@@ -0,0 +1,156 @@
+//! Errors located in a manifest, rendered with the line they are on:
+//!
+//! ```text
+//! Undefined resource reference: Service[nginx] at 3:33
+//!   |
+//! 3 |             File["/tmp/one"] -> Service["nginx"]
+//!   |                                 ^^^^^^^^^^^^^^^^
+//! ```
+
+use super::pp::{Rule, Span};
+use pest::error::{InputLocation, LineColLocation};
+use std::error::Error;
+use std::fmt;
+
+/// How the message of a syntax error starts.
+pub const SYNTAX_ERROR: &str = "Syntax error";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+    /// The source line the span starts on, without its line break.
+    pub source_line: String,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>, span: Span, source: &str) -> Self {
+        let start = span.start.min(source.len());
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |i| start + i);
+        Self {
+            message: message.into(),
+            span,
+            source_line: source[line_start..line_end]
+                .trim_end_matches('\r')
+                .to_string(),
+        }
+    }
+
+    /// The syntax error `error` in `source`.
+    pub fn from_pest(error: &pest::error::Error<Rule>, source: &str) -> Self {
+        let (start, end) = match error.location {
+            InputLocation::Pos(pos) => (pos, pos),
+            InputLocation::Span(span) => span,
+        };
+        let ((line, col), _) = match error.line_col {
+            LineColLocation::Pos(pos) => (pos, pos),
+            LineColLocation::Span(start, end) => (start, end),
+        };
+        let span = Span {
+            start,
+            end,
+            line,
+            col,
+        };
+        Self::new(
+            format!("{SYNTAX_ERROR}, {}", error.variant.message()),
+            span,
+            source,
+        )
+    }
+}
+
+/// The message and its location, then the source line with the span underlined, up to
+/// the end of the line. Expressions built without source have no location to show.
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.span.line == 0 {
+            return write!(f, "{}", self.message);
+        }
+        let line = self.span.line.to_string();
+        let pad = " ".repeat(line.len());
+        let indent: String = self
+            .source_line
+            .chars()
+            .take(self.span.col.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let rest = self.source_line.chars().count() + 1;
+        let width =
+            (self.span.end - self.span.start).clamp(1, rest.saturating_sub(self.span.col).max(1));
+        writeln!(f, "{} at {}", self.message, self.span)?;
+        writeln!(f, "{pad} |")?;
+        writeln!(f, "{line} | {}", self.source_line)?;
+        write!(f, "{pad} | {indent}{}", "^".repeat(width))
+    }
+}
+
+impl Error for Diagnostic {}
+
+/// Every problem found in a manifest, for consumers to render as they see fit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, diagnostic) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{diagnostic}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for Diagnostics {}
+
+impl From<Diagnostic> for Diagnostics {
+    fn from(diagnostic: Diagnostic) -> Self {
+        Self(vec![diagnostic])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::pp::Manifest;
+    use anyhow::{Result, anyhow};
+
+    #[test]
+    fn test_diagnostics_show_the_source_line() -> Result<()> {
+        let source = "file { \"/tmp/one\": }\nFile[\"/tmp/one\"] -> Service[\"nginx\"]\n";
+        let Err(e) = source.parse::<Manifest>() else {
+            return Err(anyhow!("The reference is undefined"));
+        };
+        let diagnostics = e
+            .diagnostics()
+            .ok_or_else(|| anyhow!("Expected diagnostics, got {e}"))?;
+        assert_eq!(diagnostics.0[0].span.line, 2);
+        assert_eq!(
+            e.to_string(),
+            "Undefined resource reference: Service[nginx] at 2:21\n\
+             \x20 |\n\
+             2 | File[\"/tmp/one\"] -> Service[\"nginx\"]\n\
+             \x20 |                     ^^^^^^^^^^^^^^^^"
+        );
+
+        let Err(e) = "file { \"/tmp/one\" }".parse::<Manifest>() else {
+            return Err(anyhow!("The colon is missing"));
+        };
+        let diagnostic = &e
+            .diagnostics()
+            .ok_or_else(|| anyhow!("Expected diagnostics, got {e}"))?
+            .0[0];
+        assert_eq!((diagnostic.span.line, diagnostic.span.col), (1, 17));
+        assert!(
+            diagnostic
+                .to_string()
+                .ends_with("1 | file { \"/tmp/one\" }\n  |                 ^"),
+            "{diagnostic}"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,131 @@
+//! Functions evaluated when a manifest is compiled, as opposed to `Deferred` ones, which
+//! run on the target when it is applied.
+
+use super::pp::{AttrValue, PuppetString};
+use super::template::is_template_function;
+use anyhow::{Context, Result, anyhow};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Whether `function` needs the node it is compiled for, like `lookup` and templates its
+/// facts, so that it is only called when the manifest is evaluated.
+pub fn depends_on_node(function: &str) -> bool {
+    function == "lookup" || is_template_function(function)
+}
+
+/// Evaluates `function` with arguments free of variables.
+pub fn call(function: &str, args: &[AttrValue]) -> Result<AttrValue> {
+    match function {
+        "pw_hash" => pw_hash(args),
+        other => Err(anyhow!("Unknown function {other}")),
+    }
+}
+
+/// `pw_hash(password, 'SHA-512', salt)`: the password hashed in crypt(3) format for
+/// `/etc/shadow`, with `openssl passwd`. The hash is sensitive when the password is.
+fn pw_hash(args: &[AttrValue]) -> Result<AttrValue> {
+    let [password, algorithm, salt] = args else {
+        return Err(anyhow!(
+            "pw_hash takes a password, a hash type and a salt, got {} arguments",
+            args.len()
+        ));
+    };
+    let text = |value: &AttrValue, what: &str| {
+        value
+            .as_literal()
+            .ok_or_else(|| anyhow!("pw_hash {what} must be a string, got {value}"))
+    };
+    let flag = match text(algorithm, "hash type")?.as_str() {
+        "MD5" => "-1",
+        "SHA-256" => "-5",
+        "SHA-512" => "-6",
+        other => {
+            return Err(anyhow!(
+                "Unsupported pw_hash type {other}, expected MD5, SHA-256 or SHA-512"
+            ));
+        }
+    };
+    let salt = text(salt, "salt")?;
+    let valid_salt = !salt.is_empty()
+        && salt.len() <= 16
+        && salt
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "./".contains(c));
+    if !valid_salt {
+        return Err(anyhow!(
+            "pw_hash salt must be 1 to 16 letters, digits, . or /"
+        ));
+    }
+
+    // The password goes through stdin, so that it never shows in the process list.
+    let mut child = Command::new("openssl")
+        .args(["passwd", flag, "-salt", &salt, "-stdin"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Cannot run openssl to hash a password")?;
+    child
+        .stdin
+        .take()
+        .ok_or_else(|| anyhow!("openssl stdin is not piped"))?
+        .write_all(text(password, "password")?.as_bytes())?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "openssl passwd failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        ));
+    }
+    let hash = AttrValue::String(PuppetString::literal(
+        String::from_utf8_lossy(&output.stdout).trim_end(),
+    ));
+    Ok(match password {
+        AttrValue::Sensitive(_) => AttrValue::Sensitive(Box::new(hash)),
+        _ => hash,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse_puppet_manifest;
+    use crate::parser::pp::{AttrValue, Manifest};
+    use anyhow::{Result, anyhow};
+
+    #[test]
+    fn test_pw_hash_at_compile_time() -> Result<()> {
+        let manifest: Manifest = r#"
+            define account($password) {
+                user { "${title}": password => pw_hash($password, 'SHA-512', 'saltsalt') }
+            }
+            account { "alice": password => Sensitive('secret') }
+            user { "bob": password => pw_hash('secret', 'SHA-512', 'saltsalt') }
+        "#
+        .parse()?;
+        let plan = parse_puppet_manifest(&manifest)?;
+        let passwords: Vec<_> = ["User[alice]", "User[bob]"]
+            .into_iter()
+            .filter_map(|id| plan.node(id))
+            .map(|index| plan.plan().inner()[index].attributes()["password"].clone())
+            .collect();
+        let hash = "$6$saltsalt$TVLlQcbpFVof5W3Yz4DTP6gRstiNuHwwTt6GLc1E5n0U0aDehy0S5knV8wiOQSpT0Y77vwPZN.Pq.H91p5hVO1";
+        assert_eq!(passwords.len(), 2, "{passwords:?}");
+        assert_eq!(
+            passwords[0].to_string(),
+            "Sensitive [value redacted]",
+            "Hashes of sensitive passwords stay sensitive"
+        );
+        for password in &passwords {
+            assert_eq!(password.as_literal().as_deref(), Some(hash));
+        }
+
+        let Err(e) = super::call("pw_hash", &[AttrValue::Integer(1)]) else {
+            return Err(anyhow!("pw_hash needs three arguments"));
+        };
+        assert_eq!(
+            e.to_string(),
+            "pw_hash takes a password, a hash type and a salt, got 1 arguments"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,581 @@
+//! Importer for a small subset of Terraform HCL, to ease trying dolly on existing
+//! configurations. `local_file` resources become `File`s and each `local-exec`
+//! provisioner of a `null_resource` becomes an `Exec`. Dependencies come from
+//! `depends_on` and from references to other resources inside attribute values.
+//! Everything else is reported in [`HclImport::skipped`] instead of failing the import.
+
+use super::pp::{
+    AttrValue, Attribute, Manifest, PuppetExpr, PuppetString, RelationOp, ResourceRef, Span,
+};
+use anyhow::{Context, Result, anyhow};
+use indexmap::{IndexMap, IndexSet};
+use pest::Parser;
+use pest::iterators::Pair;
+use pest_derive::Parser;
+
+#[derive(Parser)]
+#[grammar = "../res/hcl.pest"]
+struct HclParser;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    String(String),
+    Number(String),
+    Bool(bool),
+    Null,
+    List(Vec<Expr>),
+    Object(Vec<(String, Expr)>),
+    Call(String, Vec<Expr>),
+    Traversal(String),
+}
+
+#[derive(Debug, Default)]
+struct Body {
+    attributes: IndexMap<String, Expr>,
+    blocks: Vec<Block>,
+}
+
+#[derive(Debug)]
+struct Block {
+    kind: String,
+    labels: Vec<String>,
+    body: Body,
+}
+
+#[derive(Debug)]
+pub struct HclImport {
+    pub manifest: Manifest,
+    /// Blocks and attributes that have no dolly equivalent, one message each.
+    pub skipped: Vec<String>,
+}
+
+pub fn import(source: &str) -> Result<HclImport> {
+    let file = HclParser::parse(Rule::file, source)
+        .context("Invalid HCL")?
+        .next()
+        .ok_or_else(|| anyhow!("Empty HCL parse"))?;
+    let body = parse_body(
+        file.into_inner()
+            .next()
+            .ok_or_else(|| anyhow!("Missing HCL body"))?,
+    )?;
+
+    let mut skipped = Vec::new();
+    let mut expressions = Vec::new();
+    // Terraform address (`type.name`) -> the dolly resources it became, in order.
+    let mut imported: IndexMap<String, Vec<ResourceRef>> = IndexMap::new();
+    let mut dependencies: IndexMap<String, IndexSet<String>> = IndexMap::new();
+
+    for block in &body.blocks {
+        let [rtype, name] = block.labels.as_slice() else {
+            skipped.push(format!("{} blocks are not imported", block.kind));
+            continue;
+        };
+        if block.kind != "resource" {
+            skipped.push(format!("{} blocks are not imported", block.kind));
+            continue;
+        }
+        let address = format!("{rtype}.{name}");
+        if ["count", "for_each"]
+            .iter()
+            .any(|meta| block.body.attributes.contains_key(*meta))
+        {
+            skipped.push(format!("{address}: count and for_each are not supported"));
+            continue;
+        }
+
+        let mut references = IndexSet::new();
+        collect_body_references(&block.body, &mut references);
+        references.shift_remove(&address);
+        dependencies.insert(address.clone(), references);
+
+        let resources = match rtype.as_str() {
+            "local_file" => local_file(&address, &block.body, &mut skipped)?,
+            "null_resource" => null_resource(&address, &block.body, &mut skipped)?,
+            _ => {
+                skipped.push(format!("{address}: unsupported resource type"));
+                Vec::new()
+            }
+        };
+        let mut refs = Vec::new();
+        for resource in resources {
+            if let PuppetExpr::Resource { rtype, title, .. } = &resource {
+                refs.push(ResourceRef {
+                    rtype: rtype.clone(),
+                    title: title.clone(),
+                    span: Span::default(),
+                });
+            }
+            expressions.push(resource);
+        }
+        // Provisioners run in the order they are declared.
+        for pair in refs.windows(2) {
+            expressions.push(relation(vec![pair[0].clone()], pair[1].clone()));
+        }
+        imported.insert(address, refs);
+    }
+
+    for (address, references) in &dependencies {
+        let Some(first) = imported[address].first() else {
+            continue;
+        };
+        let mut from = IndexSet::new();
+        for reference in references {
+            exits(
+                reference,
+                &imported,
+                &dependencies,
+                &mut IndexSet::new(),
+                &mut from,
+            );
+        }
+        if !from.is_empty() {
+            expressions.push(relation(from.into_iter().collect(), first.clone()));
+        }
+    }
+
+    Ok(HclImport {
+        manifest: Manifest::from_exprs(expressions)?,
+        skipped,
+    })
+}
+
+/// The resources that must be done before anything depending on `address` may start.
+/// Addresses that imported to nothing pass their own dependencies through, so ordering
+/// survives skipped resources.
+fn exits(
+    address: &str,
+    imported: &IndexMap<String, Vec<ResourceRef>>,
+    dependencies: &IndexMap<String, IndexSet<String>>,
+    visited: &mut IndexSet<String>,
+    out: &mut IndexSet<ResourceRef>,
+) {
+    if !visited.insert(address.to_string()) {
+        return;
+    }
+    match imported.get(address).and_then(|refs| refs.last()) {
+        Some(last) => {
+            out.insert(last.clone());
+        }
+        None => {
+            for dependency in dependencies.get(address).into_iter().flatten() {
+                exits(dependency, imported, dependencies, visited, out);
+            }
+        }
+    }
+}
+
+fn relation(from: Vec<ResourceRef>, to: ResourceRef) -> PuppetExpr {
+    PuppetExpr::Relation {
+        from,
+        to: vec![to],
+        op: RelationOp::Provide,
+    }
+}
+
+fn resource(rtype: &str, title: &str, attributes: Vec<Attribute>) -> PuppetExpr {
+    PuppetExpr::Resource {
+        rtype: rtype.to_string(),
+        title: PuppetString::literal(title),
+        attributes,
+        span: Span::default(),
+    }
+}
+
+fn literal_attribute(name: &str, value: &str) -> Attribute {
+    Attribute {
+        name: name.to_string(),
+        value: AttrValue::String(PuppetString::literal(value)),
+    }
+}
+
+/// Attributes handled by the importer itself rather than mapped onto dolly attributes.
+const META_ATTRIBUTES: &[&str] = &["depends_on", "triggers", "lifecycle"];
+
+fn local_file(address: &str, body: &Body, skipped: &mut Vec<String>) -> Result<Vec<PuppetExpr>> {
+    let filename = body
+        .attributes
+        .get("filename")
+        .and_then(Expr::as_text)
+        .ok_or_else(|| anyhow!("{address}: filename must be a string"))?;
+    let mut attributes = vec![literal_attribute("ensure", "file")];
+    for (name, value) in &body.attributes {
+        let mapped = match name.as_str() {
+            "filename" => continue,
+            "content" | "sensitive_content" => "content",
+            "source" => "source",
+            "file_permission" => "mode",
+            meta if META_ATTRIBUTES.contains(&meta) => continue,
+            _ => {
+                skipped.push(format!("{address}.{name}: unsupported attribute"));
+                continue;
+            }
+        };
+        match value.to_attr_value() {
+            Some(value) => attributes.push(Attribute {
+                name: mapped.to_string(),
+                value,
+            }),
+            None => skipped.push(format!("{address}.{name}: unsupported expression")),
+        }
+    }
+    Ok(vec![resource("File", &filename, attributes)])
+}
+
+fn null_resource(address: &str, body: &Body, skipped: &mut Vec<String>) -> Result<Vec<PuppetExpr>> {
+    let mut execs = Vec::new();
+    for block in &body.blocks {
+        if block.kind != "provisioner"
+            || block.labels.first().map(String::as_str) != Some("local-exec")
+        {
+            let label = block.labels.first().map(String::as_str).unwrap_or_default();
+            skipped.push(format!("{address}: {} {label} is not imported", block.kind));
+            continue;
+        }
+        let command = block
+            .body
+            .attributes
+            .get("command")
+            .and_then(Expr::as_text)
+            .ok_or_else(|| anyhow!("{address}: local-exec command must be a string"))?;
+        let mut attributes = Vec::new();
+        for (name, value) in &block.body.attributes {
+            match (name.as_str(), value.as_text()) {
+                ("command", _) => {}
+                ("working_dir", Some(dir)) => attributes.push(literal_attribute("cwd", &dir)),
+                _ => skipped.push(format!("{address}: local-exec {name} is not imported")),
+            }
+        }
+        execs.push(resource("Exec", &command, attributes));
+    }
+    Ok(execs)
+}
+
+impl Expr {
+    fn as_text(&self) -> Option<String> {
+        match self {
+            Self::String(s) | Self::Number(s) => Some(s.clone()),
+            Self::Bool(b) => Some(b.to_string()),
+            _ => None,
+        }
+    }
+
+    fn to_attr_value(&self) -> Option<AttrValue> {
+        match self {
+            Self::Call(function, args) if function == "file" => Some(AttrValue::Deferred {
+                function: function.clone(),
+                args: args
+                    .iter()
+                    .map(Expr::to_attr_value)
+                    .collect::<Option<_>>()?,
+            }),
+            Self::Bool(b) => Some(AttrValue::Bool(*b)),
+            _ => self
+                .as_text()
+                .map(|text| AttrValue::String(PuppetString::literal(&text))),
+        }
+    }
+}
+
+/// Terraform addresses (`type.name`) referenced anywhere in `body`.
+fn collect_body_references(body: &Body, out: &mut IndexSet<String>) {
+    for expr in body.attributes.values() {
+        collect_references(expr, out);
+    }
+    for block in &body.blocks {
+        collect_body_references(&block.body, out);
+    }
+}
+
+fn collect_references(expr: &Expr, out: &mut IndexSet<String>) {
+    match expr {
+        Expr::Traversal(path) => out.extend(address_of(path)),
+        Expr::String(s) => {
+            let mut rest = s.as_str();
+            while let Some(start) = rest.find("${") {
+                let Some(end) = rest[start..].find('}') else {
+                    break;
+                };
+                out.extend(address_of(rest[start + 2..start + end].trim()));
+                rest = &rest[start + end..];
+            }
+        }
+        Expr::List(items) | Expr::Call(_, items) => {
+            items.iter().for_each(|item| collect_references(item, out))
+        }
+        Expr::Object(items) => items
+            .iter()
+            .for_each(|(_, item)| collect_references(item, out)),
+        Expr::Number(_) | Expr::Bool(_) | Expr::Null => {}
+    }
+}
+
+/// `local_file.motd.filename` -> `local_file.motd`. Variables, locals and other
+/// non-resource roots are not dependencies.
+fn address_of(path: &str) -> Option<String> {
+    let mut segments = path.split(['.', '[']);
+    let rtype = segments.next()?;
+    let name = segments.next()?;
+    let reserved = [
+        "var",
+        "local",
+        "module",
+        "data",
+        "path",
+        "terraform",
+        "count",
+        "each",
+        "self",
+    ];
+    (!reserved.contains(&rtype) && !name.is_empty()).then(|| format!("{rtype}.{name}"))
+}
+
+fn parse_body(pair: Pair<Rule>) -> Result<Body> {
+    let mut body = Body::default();
+    for item in pair.into_inner() {
+        match item.as_rule() {
+            Rule::attribute => {
+                let mut inner = item.into_inner();
+                let (Some(name), Some(value)) = (inner.next(), inner.next()) else {
+                    return Err(anyhow!("Malformed HCL attribute"));
+                };
+                body.attributes
+                    .insert(name.as_str().to_string(), parse_expr(value)?);
+            }
+            Rule::block => {
+                let mut kind = String::new();
+                let mut labels = Vec::new();
+                let mut block_body = Body::default();
+                for part in item.into_inner() {
+                    match part.as_rule() {
+                        Rule::ident => kind = part.as_str().to_string(),
+                        Rule::block_label => {
+                            let label = part
+                                .into_inner()
+                                .next()
+                                .ok_or_else(|| anyhow!("Empty HCL block label"))?;
+                            labels.push(match label.as_rule() {
+                                Rule::string => parse_string(label),
+                                _ => label.as_str().to_string(),
+                            });
+                        }
+                        Rule::body => block_body = parse_body(part)?,
+                        rule => return Err(anyhow!("Unexpected {rule:?} in HCL block")),
+                    }
+                }
+                body.blocks.push(Block {
+                    kind,
+                    labels,
+                    body: block_body,
+                });
+            }
+            rule => return Err(anyhow!("Unexpected {rule:?} in HCL body")),
+        }
+    }
+    Ok(body)
+}
+
+fn parse_expr(pair: Pair<Rule>) -> Result<Expr> {
+    let pair = match pair.as_rule() {
+        Rule::expr => pair
+            .into_inner()
+            .next()
+            .ok_or_else(|| anyhow!("Empty HCL expression"))?,
+        _ => pair,
+    };
+    Ok(match pair.as_rule() {
+        Rule::string => Expr::String(parse_string(pair)),
+        Rule::heredoc => parse_heredoc(pair),
+        Rule::number => Expr::Number(pair.as_str().to_string()),
+        Rule::bool => Expr::Bool(pair.as_str() == "true"),
+        Rule::null => Expr::Null,
+        Rule::traversal => Expr::Traversal(pair.as_str().to_string()),
+        Rule::list => Expr::List(pair.into_inner().map(parse_expr).collect::<Result<_>>()?),
+        Rule::object => Expr::Object(
+            pair.into_inner()
+                .map(|item| {
+                    let mut inner = item.into_inner();
+                    let (Some(key), Some(value)) = (inner.next(), inner.next()) else {
+                        return Err(anyhow!("Malformed HCL object item"));
+                    };
+                    let key = match key.as_rule() {
+                        Rule::string => parse_string(key),
+                        _ => key.as_str().to_string(),
+                    };
+                    Ok((key, parse_expr(value)?))
+                })
+                .collect::<Result<_>>()?,
+        ),
+        Rule::call => {
+            let mut inner = pair.into_inner();
+            let function = inner
+                .next()
+                .ok_or_else(|| anyhow!("Missing HCL function name"))?;
+            Expr::Call(
+                function.as_str().to_string(),
+                inner.map(parse_expr).collect::<Result<_>>()?,
+            )
+        }
+        rule => return Err(anyhow!("Unexpected {rule:?} in HCL expression")),
+    })
+}
+
+fn parse_string(pair: Pair<Rule>) -> String {
+    let raw = pair
+        .into_inner()
+        .next()
+        .map(|content| content.as_str())
+        .unwrap_or_default();
+    let mut unescaped = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some('r') => unescaped.push('\r'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+fn parse_heredoc(pair: Pair<Rule>) -> Expr {
+    let mut indented = false;
+    let mut body = "";
+    for part in pair.into_inner() {
+        match part.as_rule() {
+            Rule::heredoc_indent => indented = true,
+            Rule::heredoc_body => body = part.as_str(),
+            _ => {}
+        }
+    }
+    let lines: Vec<&str> = body.lines().collect();
+    let indent = match indented {
+        true => lines
+            .iter()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.len() - line.trim_start().len())
+            .min()
+            .unwrap_or(0),
+        false => 0,
+    };
+    let mut text = String::new();
+    for line in lines {
+        text.push_str(line.get(indent..).unwrap_or_else(|| line.trim_start()));
+        text.push('\n');
+    }
+    Expr::String(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_puppet_manifest;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_import_local_file_and_null_resource() -> Result<()> {
+        let source = r#"
+            terraform {
+              required_version = ">= 1.0"
+            }
+
+            variable "greeting" {
+              default = "hello"
+            }
+
+            resource "local_file" "motd" {
+              filename        = "/etc/motd"
+              file_permission = "0644"
+              content         = <<-EOT
+                Welcome
+                  to dolly
+              EOT
+            }
+
+            // Only runs once the file exists.
+            resource "null_resource" "announce" {
+              triggers = {
+                content = local_file.motd.content
+              }
+
+              provisioner "local-exec" {
+                command     = "wall < ${local_file.motd.filename}"
+                working_dir = "/tmp"
+              }
+
+              provisioner "local-exec" {
+                command = "logger motd updated"
+              }
+            }
+
+            resource "null_resource" "gate" {
+              depends_on = [null_resource.announce]
+            }
+
+            resource "local_file" "done" {
+              filename   = "/tmp/done"
+              content    = file("/etc/motd")
+              depends_on = [null_resource.gate]
+            }
+
+            resource "aws_instance" "web" {
+              ami = var.ami
+            }
+        "#;
+        let import = import(source)?;
+        assert_eq!(
+            import.skipped,
+            vec![
+                "terraform blocks are not imported",
+                "variable blocks are not imported",
+                "aws_instance.web: unsupported resource type",
+            ]
+        );
+
+        let expected = parse_puppet_manifest(&Manifest::from_str(
+            r#"
+            file { "/etc/motd": }
+            exec { 'wall < ${local_file.motd.filename}': }
+            exec { "logger motd updated": }
+            file { "/tmp/done": }
+            File["/etc/motd"] -> Exec['wall < ${local_file.motd.filename}']
+                -> Exec["logger motd updated"] -> File["/tmp/done"]
+            "#,
+        )?)?;
+        let plan = parse_puppet_manifest(&import.manifest)?;
+        assert_eq!(plan.to_canonical_text(), expected.to_canonical_text());
+
+        let content = import.manifest.0.iter().find_map(|expr| match expr {
+            PuppetExpr::Resource {
+                title, attributes, ..
+            } if title.as_literal().as_deref() == Some("/etc/motd") => attributes
+                .iter()
+                .find(|a| a.name == "content")
+                .and_then(|a| a.value.as_literal()),
+            _ => None,
+        });
+        assert_eq!(content.as_deref(), Some("Welcome\n  to dolly\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_rejects_what_it_cannot_map() {
+        assert!(import("resource \"local_file\" \"x\" { content = \"no filename\" }").is_err());
+        assert!(import("resource {").is_err());
+        let Ok(import) =
+            import("resource \"local_file\" \"x\" {\n count = 2\n filename = \"/x\"\n}")
+        else {
+            panic!("count is skipped, not an error");
+        };
+        assert_eq!(
+            import.skipped,
+            vec!["local_file.x: count and for_each are not supported"]
+        );
+    }
+}
@@ -0,0 +1,261 @@
+//! Manifests spread over several files: a directory of `.pp` files, as Puppet's
+//! `manifests` directory, or a file that pulls others in with `import`:
+//!
+//! ```puppet
+//! import 'nodes/*.pp'
+//! import 'common.pp'
+//! ```
+//!
+//! Import paths are relative to the importing file and may use `*` and `?` in their file
+//! name. A file is read once however often it is imported. References are checked once
+//! every file is merged, so one file may require what another declares.
+
+use super::pp::{Manifest, PuppetExpr, declared, flatten, parse_with_imports, validate_references};
+use anyhow::{Context, Result, anyhow};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// The files of a manifest, each with its source and expressions, in the order they are
+/// merged.
+#[derive(Default)]
+struct Sources {
+    files: Vec<(PathBuf, String, Vec<PuppetExpr>)>,
+    seen: HashSet<PathBuf>,
+}
+
+impl Sources {
+    /// Reads `path`, then the files it imports.
+    fn add(&mut self, path: &Path) -> Result<()> {
+        let canonical =
+            fs::canonicalize(path).with_context(|| format!("Cannot read {}", path.display()))?;
+        if !self.seen.insert(canonical) {
+            return Ok(());
+        }
+        let source =
+            fs::read_to_string(path).with_context(|| format!("Cannot read {}", path.display()))?;
+        let (expressions, imports) = parse_with_imports(&source)
+            .with_context(|| format!("Cannot parse {}", path.display()))?;
+        self.files.push((path.to_path_buf(), source, expressions));
+        let base = path.parent().unwrap_or(Path::new(""));
+        for (pattern, span) in imports {
+            let files = glob(&base.join(&pattern))?;
+            if files.is_empty() {
+                return Err(anyhow!(
+                    "The import of {pattern} at {span} in {} matches no file",
+                    path.display()
+                ));
+            }
+            for file in files {
+                self.add(&lexically_normal(&file))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the `.pp` files under `dir`, in path order.
+    fn add_dir(&mut self, dir: &Path) -> Result<()> {
+        let mut paths: Vec<_> = fs::read_dir(dir)
+            .with_context(|| format!("Cannot read {}", dir.display()))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<_>>()?;
+        paths.sort();
+        for path in paths {
+            match path.is_dir() {
+                true => self.add_dir(&path)?,
+                false if path.extension().is_some_and(|e| e == "pp") => self.add(&path)?,
+                false => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// One manifest of every file, whose references are checked against what all of them
+    /// declare. Errors point into the file the reference is in.
+    fn merge(self) -> Result<Manifest> {
+        let everything: Vec<_> = self
+            .files
+            .iter()
+            .flat_map(|(_, _, expressions)| flatten(expressions))
+            .collect();
+        let resources = declared(&everything);
+        for (path, source, expressions) in &self.files {
+            let flattened = flatten(expressions);
+            validate_references(&flattened, &resources, source)
+                .with_context(|| format!("Invalid references in {}", path.display()))?;
+        }
+        Ok(Manifest(
+            self.files
+                .into_iter()
+                .flat_map(|(_, _, expressions)| expressions)
+                .collect(),
+        ))
+    }
+
+    fn paths(self) -> Vec<PathBuf> {
+        self.files.into_iter().map(|(path, _, _)| path).collect()
+    }
+}
+
+/// `path` without the `..` that can be resolved against its own components, so that
+/// `nodes/../common.pp` is reported as `common.pp`.
+fn lexically_normal(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir
+                if matches!(normal.components().next_back(), Some(Component::Normal(_))) =>
+            {
+                normal.pop();
+            }
+            Component::CurDir => {}
+            component => normal.push(component),
+        }
+    }
+    normal
+}
+
+/// The files `pattern` names, sorted. Only its file name may hold wildcards.
+fn glob(pattern: &Path) -> Result<Vec<PathBuf>> {
+    let name = pattern
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    if !name.contains(['*', '?']) {
+        return Ok(pattern
+            .is_file()
+            .then(|| pattern.to_path_buf())
+            .into_iter()
+            .collect());
+    }
+    let dir = pattern.parent().unwrap_or(Path::new(""));
+    let mut files: Vec<_> = fs::read_dir(dir)
+        .with_context(|| format!("Cannot read {}", dir.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?
+        .into_iter()
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            path.file_name()
+                .is_some_and(|file| matches(name.as_bytes(), file.as_encoded_bytes()))
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Whether `name` matches `pattern`, where `*` is any run of characters and `?` any one.
+fn matches(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, _) => name.is_empty(),
+        (Some((b'*', rest)), _) => {
+            matches(rest, name) || (!name.is_empty() && matches(pattern, &name[1..]))
+        }
+        (Some((b'?', rest)), Some((_, name))) => matches(rest, name),
+        (Some((expected, rest)), Some((found, name))) => expected == found && matches(rest, name),
+        (Some(_), None) => false,
+    }
+}
+
+impl Manifest {
+    /// The manifest in the file at `path`, merged with the files it imports.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let mut sources = Sources::default();
+        sources.add(path)?;
+        sources.merge()
+    }
+
+    /// The manifest of every `.pp` file under `dir`, merged in path order with the files
+    /// they import.
+    pub fn from_dir(dir: &Path) -> Result<Self> {
+        let mut sources = Sources::default();
+        sources.add_dir(dir)?;
+        if sources.files.is_empty() {
+            return Err(anyhow!("There is no manifest in {}", dir.display()));
+        }
+        sources.merge()
+    }
+
+    /// The files [`Manifest::from_path`] or, for a directory, [`Manifest::from_dir`] reads,
+    /// so that changes to them can be noticed.
+    pub fn sources(path: &Path) -> Result<Vec<PathBuf>> {
+        let mut sources = Sources::default();
+        match path.is_dir() {
+            true => sources.add_dir(path)?,
+            false => sources.add(path)?,
+        }
+        Ok(sources.paths())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_puppet_manifest;
+    use std::env;
+
+    #[test]
+    fn test_manifests_merge_across_files() -> Result<()> {
+        let dir = env::temp_dir().join(format!("dolly-import-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (path, content) in [
+            (
+                "site.pp",
+                "import 'nodes/*.pp'\nimport 'common.pp'\n\
+                 service { 'nginx': require => Package['nginx'] }\n",
+            ),
+            ("common.pp", "package { 'nginx': }\n"),
+            (
+                "nodes/web.pp",
+                "file { '/etc/web': }\nFile['/etc/web'] -> Service['nginx']\n",
+            ),
+            (
+                "nodes/db.pp",
+                "import '../common.pp'\nfile { '/etc/db': }\n",
+            ),
+            ("nodes/README", "not a manifest\n"),
+        ] {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap_or(&dir))?;
+            fs::write(path, content)?;
+        }
+
+        let site = dir.join("site.pp");
+        assert!(
+            fs::read_to_string(&site)?.parse::<Manifest>().is_err(),
+            "Imports need the path of the manifest"
+        );
+        let plan = parse_puppet_manifest(&Manifest::from_path(&site)?)?;
+        for id in [
+            "Service[nginx]",
+            "Package[nginx]",
+            "File[/etc/web]",
+            "File[/etc/db]",
+        ] {
+            assert!(plan.node(id).is_some(), "{id} is merged in");
+        }
+        assert_eq!(
+            Manifest::sources(&site)?,
+            ["site.pp", "nodes/db.pp", "common.pp", "nodes/web.pp"].map(|path| dir.join(path)),
+            "Each file is read once"
+        );
+
+        let from_dir = parse_puppet_manifest(&Manifest::from_dir(&dir)?)?;
+        assert_eq!(from_dir.plan().inner().node_count(), 4);
+
+        fs::write(
+            dir.join("nodes/web.pp"),
+            "file { '/etc/web': }\nFile['/etc/web'] -> Service['nginz']\n",
+        )?;
+        let Err(e) = Manifest::from_path(&site) else {
+            return Err(anyhow!("A reference to nothing in any file fails"));
+        };
+        assert!(format!("{e:#}").contains("nodes/web.pp"), "{e:#}");
+        assert!(format!("{e:#}").contains("Service[nginz]"), "{e:#}");
+
+        fs::write(dir.join("site.pp"), "import 'missing/*.pp'\n")?;
+        assert!(Manifest::from_path(&site).is_err());
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
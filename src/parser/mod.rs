@@ -1 +1,14 @@
+pub mod classes;
+pub mod conditions;
+pub mod data;
+pub mod deprecations;
+pub mod diagnostic;
+pub mod functions;
+pub mod hcl;
+pub mod import;
 pub mod pp;
+pub mod shrink;
+pub mod template;
+pub mod units;
+pub mod validate;
+pub mod value;
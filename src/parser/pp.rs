@@ -1,6 +1,14 @@
+use super::conditions::{CompareOp, Condition, Operand};
+use super::diagnostic::{Diagnostic, Diagnostics};
+use super::functions;
+use crate::DollyError;
+use crate::resources::normalize_title;
+use crate::stats;
 use anyhow::{Result, anyhow};
+use indexmap::IndexMap;
 use pest::Parser;
 use pest_derive::Parser;
+use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::error::Error;
@@ -12,22 +20,79 @@ use std::str::FromStr;
 #[grammar = "../res/puppet.pest"]
 struct PuppetParser;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum PuppetExpr {
     Resource {
         rtype: String,
         title: PuppetString,
         attributes: Vec<Attribute>,
+        span: Span,
     },
     Relation {
         from: Vec<ResourceRef>,
         to: Vec<ResourceRef>,
         op: RelationOp,
     },
+    /// Resource defaults, `File { mode => '0644' }`: attributes for every resource of the
+    /// type in the same scope and the scopes it declares that does not set them itself.
+    Defaults {
+        rtype: String,
+        attributes: Vec<Attribute>,
+        span: Span,
+    },
+    /// A class definition, `class nginx($port = 80) { ... }`. Its body is only evaluated
+    /// once the class is declared, with the parameters substituted.
+    Class {
+        name: String,
+        params: Vec<Parameter>,
+        body: Vec<PuppetExpr>,
+        span: Span,
+    },
+    /// A defined resource type, `define mymod::thing($param) { ... }`. Its body is
+    /// evaluated once for every declared instance, with the parameters substituted.
+    Define {
+        name: String,
+        params: Vec<Parameter>,
+        body: Vec<PuppetExpr>,
+        span: Span,
+    },
+    /// `$facts['networking']['interfaces'].each |$name, $iface| { ... }` evaluates the
+    /// body once per entry of a fact, array or hash.
+    Each {
+        iterable: Iterable,
+        params: Vec<String>,
+        body: Vec<PuppetExpr>,
+        span: Span,
+    },
+    /// `if`, `unless` and `case`: the body of the first branch whose condition holds is
+    /// evaluated, `otherwise` when none does.
+    Conditional {
+        branches: Vec<(Condition, Vec<PuppetExpr>)>,
+        otherwise: Vec<PuppetExpr>,
+        span: Span,
+    },
+    /// `include nginx, ssh` declares classes; `contain nginx` also makes the enclosing
+    /// class contain them.
+    Include {
+        classes: Vec<String>,
+        contain: bool,
+        span: Span,
+    },
+    /// `assert { 'disk space': condition => $facts['disk_free'] > 1024 }`: a precondition
+    /// of the node, checked when the manifest is evaluated so that nothing is applied
+    /// when it does not hold.
+    Assert {
+        title: PuppetString,
+        condition: Condition,
+        message: Option<PuppetString>,
+        span: Span,
+    },
+    /// A statement lenient parsing could not make sense of, kept verbatim.
+    Opaque { text: String, span: Span },
 }
 
 // "->", "<-", "~>", "<~"
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum RelationOp {
     Provide,
     Require,
@@ -66,6 +131,66 @@ impl PuppetString {
     pub fn new() -> Self {
         Self(vec![])
     }
+
+    pub fn literal(s: &str) -> Self {
+        Self(vec![StringContent::Literal(s.to_string().into())])
+    }
+
+    /// The string value if it contains no interpolated variables.
+    pub fn as_literal(&self) -> Option<String> {
+        self.0
+            .iter()
+            .all(|content| matches!(content, StringContent::Literal(_)))
+            .then(|| self.to_string())
+    }
+
+    /// A string made of one variable, `$name` or `"${name}"`.
+    pub fn variable(name: &str) -> Self {
+        Self(vec![StringContent::Variable(name.to_string())])
+    }
+
+    /// The name of the variable this string consists of, if it is nothing else.
+    pub fn as_variable(&self) -> Option<&str> {
+        match self.0.as_slice() {
+            [StringContent::Variable(name)] => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Replaces every interpolated variable with its value from `lookup`, failing on the
+    /// first one it does not know.
+    pub fn interpolate(&self, lookup: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        self.substitute(|name| {
+            lookup(name)
+                .map(Some)
+                .ok_or_else(|| anyhow!("Unknown variable ${name}"))
+        })
+    }
+
+    /// Replaces the interpolated variables `lookup` gives a value, keeping the ones it
+    /// gives `None` for, e.g. facts left for later in the body of a defined type.
+    pub fn substitute(&self, lookup: impl Fn(&str) -> Result<Option<String>>) -> Result<Self> {
+        let mut contents: Vec<StringContent> = vec![];
+        let mut text = String::new();
+        for content in &self.0 {
+            match content {
+                StringContent::Literal(s) => text.push_str(s),
+                StringContent::Variable(name) => match lookup(name)? {
+                    Some(value) => text.push_str(&value),
+                    None => {
+                        if !text.is_empty() {
+                            contents.push(StringContent::Literal(std::mem::take(&mut text).into()));
+                        }
+                        contents.push(content.clone());
+                    }
+                },
+            }
+        }
+        if !text.is_empty() || contents.is_empty() {
+            contents.push(StringContent::Literal(text.into()));
+        }
+        Ok(Self(contents))
+    }
 }
 
 impl fmt::Display for PuppetString {
@@ -95,15 +220,89 @@ impl fmt::Display for StringContent {
     }
 }
 
+/// Location of a construct in the manifest source.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl From<pest::Span<'_>> for Span {
+    fn from(span: pest::Span<'_>) -> Self {
+        let (line, col) = span.start_pos().line_col();
+        Self {
+            start: span.start(),
+            end: span.end(),
+            line,
+            col,
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ResourceRef {
     pub rtype: String,
     pub title: PuppetString,
+    pub span: Span,
 }
 
 impl ResourceRef {
+    /// The id of the referenced resource, with the title normalized for its type.
     pub fn id(&self) -> String {
-        format!("{}[{}]", self.rtype, self.title)
+        let title = normalize_title(&self.rtype, &self.title.to_string());
+        format!("{}[{}]", self.rtype, title)
+    }
+}
+
+/// Parses the reference syntax of manifests, e.g. `File['/etc/motd']`, `file[/etc/motd]` or
+/// `::Foo::Bar["x"]`. The type is normalized like in a manifest and quotes around the title
+/// are removed, so the result matches plan ids.
+impl FromStr for ResourceRef {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = |reason: &str| anyhow!("Invalid resource reference '{s}': {reason}");
+        let (rtype, rest) = s
+            .trim()
+            .split_once('[')
+            .ok_or_else(|| invalid("expected Type[title]"))?;
+        let rtype = rtype.trim();
+        let valid_type = rtype.trim_start_matches("::").split("::").all(|part| {
+            part.starts_with(|c: char| c.is_alphabetic())
+                && part.chars().all(|c| c.is_alphanumeric() || c == '_')
+        });
+        if !valid_type {
+            return Err(invalid("the type must be a name like File or Foo::Bar"));
+        }
+        let title = rest
+            .strip_suffix(']')
+            .ok_or_else(|| invalid("missing closing ]"))?
+            .trim();
+        let title = match ['\'', '"']
+            .into_iter()
+            .find(|q| title.len() >= 2 && title.starts_with(*q) && title.ends_with(*q))
+        {
+            Some(quote) => title[1..title.len() - 1]
+                .replace(&format!("\\{quote}"), &quote.to_string())
+                .replace("\\\\", "\\"),
+            None => title.to_string(),
+        };
+        if title.is_empty() {
+            return Err(invalid("the title is empty"));
+        }
+        Ok(Self {
+            rtype: normalize_rtype(rtype),
+            title: PuppetString::literal(&title),
+            span: Span::default(),
+        })
     }
 }
 
@@ -127,10 +326,182 @@ impl PartialEq for ResourceRef {
 
 impl Eq for ResourceRef {}
 
-#[derive(Debug)]
+/// What an `each` iterates over.
+#[derive(Debug, Clone)]
+pub enum Iterable {
+    /// A structured fact, `$facts['networking']['interfaces']`, by the keys of its path.
+    Fact(Vec<String>),
+    /// A literal array or hash.
+    Value(AttrValue),
+}
+
+impl fmt::Display for Iterable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Fact(path) => {
+                write!(f, "$facts")?;
+                for key in path {
+                    write!(f, "['{key}']")?;
+                }
+                Ok(())
+            }
+            Self::Value(value) => write!(f, "{value}"),
+        }
+    }
+}
+
+/// A parameter of a defined type, with the value used when a declaration omits it.
+#[derive(Debug, Clone)]
+pub struct Parameter {
+    pub name: String,
+    pub default: Option<AttrValue>,
+}
+
+#[derive(Debug, Clone)]
 pub struct Attribute {
     pub name: String,
-    pub value: PuppetString,
+    pub value: AttrValue,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttrValue {
+    String(PuppetString),
+    Bool(bool),
+    /// Written in decimal, octal with a leading `0` or hexadecimal with `0x`, as in Puppet.
+    Integer(i64),
+    Array(Vec<AttrValue>),
+    Hash(IndexMap<String, AttrValue>),
+    /// `undef`, the absence of a value.
+    Undef,
+    ResourceRef(ResourceRef),
+    /// A value computed at apply time on the target, e.g. `Deferred('file', ['/etc/secret'])`.
+    Deferred {
+        function: String,
+        args: Vec<AttrValue>,
+    },
+    /// A value kept out of output, `Sensitive('hunter2')`. It is used as the value itself.
+    Sensitive(Box<AttrValue>),
+    /// A function evaluated when the manifest is compiled, `pw_hash($password, 'SHA-512',
+    /// 'salt')`, kept only until the variables of its arguments are known.
+    Call {
+        function: String,
+        args: Vec<AttrValue>,
+    },
+}
+
+impl AttrValue {
+    /// The text of a scalar value: a string without interpolated variables, a boolean or
+    /// an integer (in decimal).
+    pub fn as_literal(&self) -> Option<String> {
+        match self {
+            Self::String(s) => s.as_literal(),
+            Self::Bool(b) => Some(b.to_string()),
+            Self::Integer(i) => Some(i.to_string()),
+            Self::Sensitive(value) => value.as_literal(),
+            Self::Array(_)
+            | Self::Hash(_)
+            | Self::Undef
+            | Self::ResourceRef(_)
+            | Self::Deferred { .. }
+            | Self::Call { .. } => None,
+        }
+    }
+
+    /// Whether the value interpolates variables anywhere, so that it is only known once
+    /// they are substituted.
+    pub fn has_variables(&self) -> bool {
+        match self {
+            Self::String(s) => s.as_literal().is_none(),
+            Self::Array(values) => values.iter().any(Self::has_variables),
+            Self::Hash(hash) => hash.values().any(Self::has_variables),
+            Self::ResourceRef(r) => r.title.as_literal().is_none(),
+            Self::Deferred { args, .. } | Self::Call { args, .. } => {
+                args.iter().any(Self::has_variables)
+            }
+            Self::Sensitive(value) => value.has_variables(),
+            Self::Bool(_) | Self::Integer(_) | Self::Undef => false,
+        }
+    }
+
+    /// The elements of an array, or the value itself as the only element.
+    pub fn as_array(&self) -> Vec<&AttrValue> {
+        match self {
+            Self::Array(values) => values.iter().collect(),
+            Self::Undef => vec![],
+            value => vec![value],
+        }
+    }
+
+    /// Writes the value as it appears inside an array or hash, with strings quoted.
+    fn fmt_nested(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::String(s) => write!(f, "'{s}'"),
+            value => write!(f, "{value}"),
+        }
+    }
+}
+
+impl Default for AttrValue {
+    fn default() -> Self {
+        Self::String(PuppetString::new())
+    }
+}
+
+impl fmt::Display for AttrValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::String(s) => write!(f, "{s}"),
+            Self::Bool(b) => write!(f, "{b}"),
+            Self::Integer(i) => write!(f, "{i}"),
+            Self::Undef => write!(f, "undef"),
+            Self::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    value.fmt_nested(f)?;
+                }
+                write!(f, "]")
+            }
+            Self::Hash(entries) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, " '{key}' => ")?;
+                    value.fmt_nested(f)?;
+                }
+                if !entries.is_empty() {
+                    write!(f, " ")?;
+                }
+                write!(f, "}}")
+            }
+            Self::ResourceRef(r) => write!(f, "{}['{}']", r.rtype, r.title),
+            Self::Deferred { function, args } => {
+                write!(f, "Deferred('{function}', [")?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "'{arg}'")?;
+                }
+                write!(f, "])")
+            }
+            Self::Sensitive(_) => write!(f, "Sensitive [value redacted]"),
+            Self::Call { function, args } => {
+                write!(f, "{function}(")?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    arg.fmt_nested(f)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -161,6 +532,183 @@ impl Manifest {
             .iter()
             .filter(|s| matches!(s, PuppetExpr::Relation { .. }))
     }
+
+    /// Statements skipped by [`Manifest::from_str_lenient`].
+    pub fn opaque(&self) -> impl Iterator<Item = &PuppetExpr> {
+        self.0
+            .iter()
+            .filter(|s| matches!(s, PuppetExpr::Opaque { .. }))
+    }
+
+    /// The relations declared with the `require`, `before`, `notify` and `subscribe`
+    /// metaparameters inside resource bodies, as the equivalent chaining expressions:
+    /// `require => File['/a']` in `Service['x']` is `Service['x'] <- File['/a']`.
+    pub fn metaparameter_relations(&self) -> Result<Vec<PuppetExpr>> {
+        let mut relations = Vec::new();
+        for expr in self.resources() {
+            let PuppetExpr::Resource {
+                rtype,
+                title,
+                attributes,
+                span,
+            } = expr
+            else {
+                continue;
+            };
+            for attr in attributes {
+                let op = match attr.name.as_str() {
+                    "require" => RelationOp::Require,
+                    "before" => RelationOp::Provide,
+                    "notify" => RelationOp::Notify,
+                    "subscribe" => RelationOp::Subscribe,
+                    _ => continue,
+                };
+                let to = attr
+                    .value
+                    .as_array()
+                    .into_iter()
+                    .map(|value| match value {
+                        AttrValue::ResourceRef(reference) => Ok(reference.clone()),
+                        value => value.as_literal().map_or_else(
+                            || Err(anyhow!("{} is not a resource reference", value)),
+                            |text| text.parse(),
+                        ),
+                    })
+                    .collect::<Result<Vec<_>>>()
+                    .map_err(|e| anyhow!("Invalid {} of {rtype}[{title}]: {e}", attr.name))?;
+                if to.is_empty() {
+                    continue;
+                }
+                let resource = ResourceRef {
+                    rtype: rtype.clone(),
+                    title: title.clone(),
+                    span: *span,
+                };
+                relations.push(PuppetExpr::Relation {
+                    from: vec![resource],
+                    to,
+                    op,
+                });
+            }
+        }
+        Ok(relations)
+    }
+
+    /// Relations from the Execs whose output resources use through
+    /// `Deferred('exec_output', [title])` to those resources, since the value only exists
+    /// once the Exec has run.
+    pub fn capture_relations(&self) -> Result<Vec<PuppetExpr>> {
+        fn captured<'a>(value: &'a AttrValue, titles: &mut Vec<&'a AttrValue>) {
+            match value {
+                AttrValue::Deferred { function, args } => {
+                    if function == "exec_output" {
+                        titles.extend(args.first());
+                    }
+                    args.iter().for_each(|arg| captured(arg, titles));
+                }
+                AttrValue::Array(values) => values.iter().for_each(|v| captured(v, titles)),
+                AttrValue::Hash(hash) => hash.values().for_each(|v| captured(v, titles)),
+                _ => {}
+            }
+        }
+        let mut relations = Vec::new();
+        for expr in self.resources() {
+            let PuppetExpr::Resource {
+                rtype,
+                title,
+                attributes,
+                span,
+            } = expr
+            else {
+                continue;
+            };
+            let mut titles = vec![];
+            attributes
+                .iter()
+                .for_each(|attr| captured(&attr.value, &mut titles));
+            for exec in titles {
+                let exec = exec.as_literal().ok_or_else(|| {
+                    anyhow!("The exec_output in {rtype}[{title}] must name an Exec, got {exec}")
+                })?;
+                relations.push(PuppetExpr::Relation {
+                    from: vec![ResourceRef {
+                        rtype: "Exec".to_string(),
+                        title: PuppetString::literal(&exec),
+                        span: *span,
+                    }],
+                    to: vec![ResourceRef {
+                        rtype: rtype.clone(),
+                        title: title.clone(),
+                        span: *span,
+                    }],
+                    op: RelationOp::Provide,
+                });
+            }
+        }
+        Ok(relations)
+    }
+
+    pub fn validate(&self) -> Vec<super::validate::Warning> {
+        super::validate::validate(self)
+    }
+}
+
+impl Manifest {
+    /// Builds a manifest from expressions produced by another front-end, with the same
+    /// reference checks as parsed Puppet source.
+    pub fn from_exprs(expressions: Vec<PuppetExpr>) -> Result<Self> {
+        Self::validated(expressions, "")
+    }
+
+    fn validated(expressions: Vec<PuppetExpr>, source: &str) -> Result<Self> {
+        let flattened = flatten(&expressions);
+        validate_references(&flattened, &declared(&flattened), source)?;
+        Ok(Manifest(expressions))
+    }
+}
+
+/// The resources and classes `expressions` declare, for references to be checked against.
+pub(super) fn declared(expressions: &[&PuppetExpr]) -> HashMap<ResourceRef, ()> {
+    let mut resources = HashMap::new();
+    for expr in expressions {
+        let (rtype, title) = match expr {
+            PuppetExpr::Resource { rtype, title, .. } => (rtype.to_string(), title.clone()),
+            PuppetExpr::Class { name, .. } => ("Class".to_string(), PuppetString::literal(name)),
+            _ => continue,
+        };
+        let resource_ref = ResourceRef {
+            rtype,
+            title,
+            span: Span::default(),
+        };
+        resources.insert(resource_ref, ());
+    }
+    resources
+}
+
+/// `expressions` followed by the bodies of the classes, defined types, iterations and
+/// conditionals among them, every branch included.
+pub(super) fn flatten(expressions: &[PuppetExpr]) -> Vec<&PuppetExpr> {
+    let mut flattened: Vec<_> = expressions.iter().collect();
+    for expr in expressions {
+        match expr {
+            PuppetExpr::Class { body, .. }
+            | PuppetExpr::Define { body, .. }
+            | PuppetExpr::Each { body, .. } => flattened.extend(flatten(body)),
+            PuppetExpr::Conditional {
+                branches,
+                otherwise,
+                ..
+            } => {
+                for (_, body) in branches {
+                    flattened.extend(flatten(body));
+                }
+                flattened.extend(flatten(otherwise));
+            }
+            _ => {}
+        }
+    }
+    flattened
 }
 
 impl Display for Manifest {
@@ -172,6 +720,40 @@ impl Display for Manifest {
     }
 }
 
+impl PuppetExpr {
+    /// Where the expression is in the manifest; a relation is where its first operand is.
+    pub fn span(&self) -> Span {
+        match self {
+            Self::Resource { span, .. }
+            | Self::Defaults { span, .. }
+            | Self::Class { span, .. }
+            | Self::Define { span, .. }
+            | Self::Each { span, .. }
+            | Self::Conditional { span, .. }
+            | Self::Include { span, .. }
+            | Self::Assert { span, .. }
+            | Self::Opaque { span, .. } => *span,
+            Self::Relation { from, .. } => from.first().map(|r| r.span).unwrap_or_default(),
+        }
+    }
+}
+
+/// `($name, $port = 80)`, as classes and defined types declare their parameters.
+fn fmt_parameters(f: &mut fmt::Formatter<'_>, params: &[Parameter]) -> fmt::Result {
+    write!(f, "(")?;
+    for (i, param) in params.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "${}", param.name)?;
+        if let Some(default) = &param.default {
+            write!(f, " = ")?;
+            default.fmt_nested(f)?;
+        }
+    }
+    write!(f, ")")
+}
+
 impl fmt::Display for PuppetExpr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -179,6 +761,7 @@ impl fmt::Display for PuppetExpr {
                 rtype,
                 title,
                 attributes,
+                ..
             } => {
                 write!(f, "{} {{\n  '", rtype)?;
                 write!(f, "{title}")?;
@@ -190,6 +773,102 @@ impl fmt::Display for PuppetExpr {
                 }
                 write!(f, "}}")
             }
+            PuppetExpr::Defaults {
+                rtype, attributes, ..
+            } => {
+                writeln!(f, "{rtype} {{")?;
+                for attr in attributes {
+                    writeln!(f, "  {} => {},", attr.name, attr.value)?;
+                }
+                write!(f, "}}")
+            }
+            PuppetExpr::Opaque { text, .. } => write!(f, "{text}"),
+            PuppetExpr::Class {
+                name, params, body, ..
+            } => {
+                write!(f, "class {name}")?;
+                if !params.is_empty() {
+                    fmt_parameters(f, params)?;
+                }
+                writeln!(f, " {{")?;
+                for expr in body {
+                    for line in expr.to_string().lines() {
+                        writeln!(f, "  {line}")?;
+                    }
+                }
+                write!(f, "}}")
+            }
+            PuppetExpr::Define {
+                name, params, body, ..
+            } => {
+                write!(f, "define {name}")?;
+                fmt_parameters(f, params)?;
+                writeln!(f, " {{")?;
+                for expr in body {
+                    for line in expr.to_string().lines() {
+                        writeln!(f, "  {line}")?;
+                    }
+                }
+                write!(f, "}}")
+            }
+            PuppetExpr::Each {
+                iterable,
+                params,
+                body,
+                ..
+            } => {
+                let params: Vec<_> = params.iter().map(|param| format!("${param}")).collect();
+                writeln!(f, "{iterable}.each |{}| {{", params.join(", "))?;
+                for expr in body {
+                    for line in expr.to_string().lines() {
+                        writeln!(f, "  {line}")?;
+                    }
+                }
+                write!(f, "}}")
+            }
+            PuppetExpr::Conditional {
+                branches,
+                otherwise,
+                ..
+            } => {
+                let block = |f: &mut fmt::Formatter<'_>, body: &[PuppetExpr]| {
+                    for expr in body {
+                        for line in expr.to_string().lines() {
+                            writeln!(f, "  {line}")?;
+                        }
+                    }
+                    write!(f, "}}")
+                };
+                for (i, (condition, body)) in branches.iter().enumerate() {
+                    let keyword = if i == 0 { "if" } else { " elsif" };
+                    writeln!(f, "{keyword} {condition} {{")?;
+                    block(f, body)?;
+                }
+                match branches.is_empty() {
+                    true => writeln!(f, "if true {{")?,
+                    false if otherwise.is_empty() => return Ok(()),
+                    false => writeln!(f, " else {{")?,
+                }
+                block(f, otherwise)
+            }
+            PuppetExpr::Assert {
+                title,
+                condition,
+                message,
+                ..
+            } => {
+                write!(f, "assert {{ '{title}': condition => {condition}")?;
+                if let Some(message) = message {
+                    write!(f, ", message => \"{message}\"")?;
+                }
+                write!(f, " }}")
+            }
+            PuppetExpr::Include {
+                classes, contain, ..
+            } => {
+                let keyword = if *contain { "contain" } else { "include" };
+                write!(f, "{keyword} {}", classes.join(", "))
+            }
             PuppetExpr::Relation { from, to, op } => {
                 write!(f, "[")?;
                 for (i, r) in from.iter().enumerate() {
@@ -215,46 +894,135 @@ impl fmt::Display for PuppetExpr {
     }
 }
 
-impl FromStr for Manifest {
-    type Err = anyhow::Error;
+/// The parse tree of `s` as lenient parsing sees it, for checks of how constructs are
+/// spelled before parsing normalizes them.
+pub(super) fn parse_tree(s: &str) -> Result<pest::iterators::Pairs<'_, Rule>> {
+    parse_program(Rule::lenient_program, s)
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut pairs = PuppetParser::parse(Rule::program, s)?;
-        let mut expressions = Vec::new();
-        let mut resources = HashMap::new();
+/// Parses `s` from `rule`, reporting syntax errors as [`Diagnostics`].
+fn parse_program(rule: Rule, s: &str) -> Result<pest::iterators::Pairs<'_, Rule>> {
+    let pairs = PuppetParser::parse(rule, s)
+        .map_err(|e| anyhow!(Diagnostics::from(Diagnostic::from_pest(&e, s))))?;
+    stats::global().manifest_parsed();
+    Ok(pairs)
+}
 
+impl Manifest {
+    /// Parses what dolly supports and keeps every other statement as
+    /// [`PuppetExpr::Opaque`], so a manifest using unsupported features can still be
+    /// inspected. References into skipped statements are left for compilation to report.
+    pub fn from_str_lenient(s: &str) -> Result<Self> {
+        let mut pairs = parse_program(Rule::lenient_program, s)?;
         let Some(program) = pairs.next() else {
             return Err(anyhow!(PuppetError {
                 message: "No program pair".to_owned()
             }));
         };
 
+        let mut expressions = Vec::new();
         for pair in program.into_inner() {
             match pair.as_rule() {
-                Rule::resource => {
-                    let expr = parse_resource(pair)?;
-                    if let PuppetExpr::Resource { rtype, title, .. } = &expr {
-                        let resource_ref = ResourceRef {
-                            rtype: rtype.to_string(),
-                            title: PuppetString(title.0.clone()),
-                        };
-                        resources.insert(resource_ref, ());
-                    }
-                    expressions.push(expr);
-                }
-                Rule::relation => {
-                    expressions.extend(parse_relation(pair)?);
-                }
-                _ => {} // Silently ignore unknown rules (e.g., EOI)
+                Rule::resource => expressions.push(parse_resource(pair)?),
+                Rule::resource_defaults => expressions.push(parse_resource_defaults(pair)?),
+                Rule::relation => expressions.extend(parse_relation(pair)?),
+                Rule::class_definition => expressions.push(parse_class(pair)?),
+                Rule::define_definition => expressions.push(parse_define(pair)?),
+                Rule::iteration => expressions.push(parse_iteration(pair)?),
+                Rule::conditional => expressions.push(parse_conditional(pair)?),
+                Rule::include => expressions.push(parse_include(pair)),
+                Rule::assertion => expressions.push(parse_assertion(pair)?),
+                Rule::opaque => expressions.push(PuppetExpr::Opaque {
+                    text: pair.as_str().trim_end().to_string(),
+                    span: pair.as_span().into(),
+                }),
+                _ => {}
             }
         }
-
-        validate_references(&expressions, &resources)?;
         Ok(Manifest(expressions))
     }
 }
 
+impl FromStr for Manifest {
+    type Err = DollyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (expressions, imports) = parse_with_imports(s)?;
+        if let Some((path, span)) = imports.first() {
+            return Err(anyhow!(
+                "The import of {path} at {span} needs the path of the manifest; load it with \
+                 Manifest::from_path"
+            )
+            .into());
+        }
+        Ok(Manifest::validated(expressions, s)?)
+    }
+}
+
+/// An `import` statement: the files it names, and where it is.
+pub(super) type Import = (String, Span);
+
+/// The expressions of `s`, and its `import` statements.
+pub(super) fn parse_with_imports(s: &str) -> Result<(Vec<PuppetExpr>, Vec<Import>)> {
+    let mut pairs = parse_program(Rule::program, s)?;
+    let mut expressions = Vec::new();
+    let mut imports = Vec::new();
+
+    let Some(program) = pairs.next() else {
+        return Err(anyhow!(PuppetError {
+            message: "No program pair".to_owned()
+        }));
+    };
+
+    for pair in program.into_inner() {
+        match pair.as_rule() {
+            Rule::resource => {
+                expressions.push(parse_resource(pair)?);
+            }
+            Rule::resource_defaults => {
+                expressions.push(parse_resource_defaults(pair)?);
+            }
+            Rule::relation => {
+                expressions.extend(parse_relation(pair)?);
+            }
+            Rule::class_definition => {
+                expressions.push(parse_class(pair)?);
+            }
+            Rule::define_definition => {
+                expressions.push(parse_define(pair)?);
+            }
+            Rule::iteration => {
+                expressions.push(parse_iteration(pair)?);
+            }
+            Rule::conditional => {
+                expressions.push(parse_conditional(pair)?);
+            }
+            Rule::include => {
+                expressions.push(parse_include(pair));
+            }
+            Rule::assertion => {
+                expressions.push(parse_assertion(pair)?);
+            }
+            Rule::import => {
+                let span = pair.as_span().into();
+                let path = pair
+                    .into_inner()
+                    .find(|inner| inner.as_rule() == Rule::quoted_string)
+                    .map(parse_quoted_string)
+                    .transpose()?
+                    .and_then(|path| path.as_literal())
+                    .ok_or_else(|| anyhow!("The import at {span} must name a literal path"))?;
+                imports.push((path, span));
+            }
+            _ => {} // Silently ignore unknown rules (e.g., EOI)
+        }
+    }
+
+    Ok((expressions, imports))
+}
+
 fn parse_resource(pair: pest::iterators::Pair<Rule>) -> Result<PuppetExpr> {
+    let span = pair.as_span().into();
     let mut rtype = String::new();
     let mut title = PuppetString::new();
     let mut attributes = Vec::new();
@@ -282,27 +1050,397 @@ fn parse_resource(pair: pest::iterators::Pair<Rule>) -> Result<PuppetExpr> {
         rtype,
         title,
         attributes,
+        span,
     })
 }
 
+fn parse_resource_defaults(pair: pest::iterators::Pair<Rule>) -> Result<PuppetExpr> {
+    let span = pair.as_span().into();
+    let mut rtype = String::new();
+    let mut attributes = Vec::new();
+    for inner in pair.into_inner() {
+        match inner.as_rule() {
+            Rule::ref_rtype => rtype = parse_rtype(inner)?,
+            Rule::attributes => attributes = parse_attributes(inner)?,
+            _ => {}
+        }
+    }
+    Ok(PuppetExpr::Defaults {
+        rtype,
+        attributes,
+        span,
+    })
+}
+
+/// Class names are lowercase and never start with `::`.
+fn class_name(name: &str) -> String {
+    name.trim_start_matches("::").to_lowercase()
+}
+
+fn parse_class(pair: pest::iterators::Pair<Rule>) -> Result<PuppetExpr> {
+    let span = pair.as_span().into();
+    let mut name = String::new();
+    let mut params = Vec::new();
+    let mut body = Vec::new();
+    for inner in pair.into_inner() {
+        match inner.as_rule() {
+            Rule::class_name => name = class_name(inner.as_str()),
+            Rule::parameters => params = parse_parameters(inner)?,
+            Rule::resource => body.push(parse_resource(inner)?),
+            Rule::resource_defaults => body.push(parse_resource_defaults(inner)?),
+            Rule::relation => body.extend(parse_relation(inner)?),
+            Rule::include => body.push(parse_include(inner)),
+            Rule::conditional => body.push(parse_conditional(inner)?),
+            Rule::iteration => body.push(parse_iteration(inner)?),
+            Rule::assertion => body.push(parse_assertion(inner)?),
+            _ => {}
+        }
+    }
+    Ok(PuppetExpr::Class {
+        name,
+        params,
+        body,
+        span,
+    })
+}
+
+fn parse_iteration(pair: pest::iterators::Pair<Rule>) -> Result<PuppetExpr> {
+    let span = pair.as_span().into();
+    let mut iterable = Iterable::Fact(vec![]);
+    let mut params = Vec::new();
+    let mut body = Vec::new();
+    for inner in pair.into_inner() {
+        match inner.as_rule() {
+            Rule::iterable => {
+                iterable = match inner.clone().into_inner().next() {
+                    Some(lookup) if lookup.as_rule() == Rule::fact_lookup => Iterable::Fact(
+                        lookup
+                            .into_inner()
+                            .map(|key| Ok(parse_quoted_string(key)?.to_string()))
+                            .collect::<Result<_>>()?,
+                    ),
+                    // An array or hash, parsed like the attribute value it would be.
+                    _ => Iterable::Value(parse_attr_value(inner)?),
+                };
+            }
+            Rule::variable_ref => params.push(inner.as_str().trim_start_matches('$').to_string()),
+            Rule::resource => body.push(parse_resource(inner)?),
+            Rule::resource_defaults => body.push(parse_resource_defaults(inner)?),
+            Rule::relation => body.extend(parse_relation(inner)?),
+            Rule::include => body.push(parse_include(inner)),
+            Rule::conditional => body.push(parse_conditional(inner)?),
+            Rule::assertion => body.push(parse_assertion(inner)?),
+            _ => {}
+        }
+    }
+    Ok(PuppetExpr::Each {
+        iterable,
+        params,
+        body,
+        span,
+    })
+}
+
+fn parse_conditional(pair: pest::iterators::Pair<Rule>) -> Result<PuppetExpr> {
+    let span = pair.as_span().into();
+    let statement = pair
+        .into_inner()
+        .next()
+        .ok_or_else(|| anyhow!("Missing conditional statement"))?;
+    let rule = statement.as_rule();
+    let mut branches = Vec::new();
+    let mut otherwise = Vec::new();
+    let mut control = None;
+    let mut condition = None;
+    for inner in statement.into_inner() {
+        match inner.as_rule() {
+            Rule::condition => condition = Some(parse_condition(inner)?),
+            Rule::block => {
+                let condition = condition
+                    .take()
+                    .ok_or_else(|| anyhow!("Missing condition"))?;
+                let condition = match rule {
+                    Rule::unless_statement => Condition::Not(Box::new(condition)),
+                    _ => condition,
+                };
+                branches.push((condition, parse_block(inner)?));
+            }
+            Rule::elsif_branch => {
+                let mut parts = inner.into_inner().skip(1);
+                let (Some(condition), Some(block)) = (parts.next(), parts.next()) else {
+                    return Err(anyhow!("Incomplete elsif"));
+                };
+                branches.push((parse_condition(condition)?, parse_block(block)?));
+            }
+            Rule::else_branch => {
+                if let Some(block) = inner.into_inner().find(|p| p.as_rule() == Rule::block) {
+                    otherwise = parse_block(block)?;
+                }
+            }
+            Rule::operand => control = Some(parse_operand(inner)?),
+            Rule::case_branch => {
+                let control = control
+                    .clone()
+                    .ok_or_else(|| anyhow!("Missing case control expression"))?;
+                let mut matchers = Vec::new();
+                let mut default = false;
+                for part in inner.into_inner() {
+                    match part.as_rule() {
+                        Rule::case_matcher => match part.into_inner().next() {
+                            Some(operand) if operand.as_rule() == Rule::operand => {
+                                matchers.push(Condition::Compare {
+                                    left: control.clone(),
+                                    op: CompareOp::Eq,
+                                    right: parse_operand(operand)?,
+                                });
+                            }
+                            _ => default = true,
+                        },
+                        Rule::block if default => otherwise = parse_block(part)?,
+                        Rule::block => {
+                            branches.push((Condition::Or(matchers.clone()), parse_block(part)?))
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(PuppetExpr::Conditional {
+        branches,
+        otherwise,
+        span,
+    })
+}
+
+fn parse_assertion(pair: pest::iterators::Pair<Rule>) -> Result<PuppetExpr> {
+    let span = pair.as_span().into();
+    let mut title = PuppetString::new();
+    let mut condition = None;
+    let mut message = None;
+    for inner in pair.into_inner() {
+        match inner.as_rule() {
+            Rule::title => {
+                if let Some(quoted) = inner.into_inner().next() {
+                    title = parse_quoted_string(quoted)?;
+                }
+            }
+            Rule::condition => condition = Some(parse_condition(inner)?),
+            Rule::quoted_string => message = Some(parse_quoted_string(inner)?),
+            _ => {}
+        }
+    }
+    Ok(PuppetExpr::Assert {
+        title,
+        condition: condition.ok_or_else(|| anyhow!("Missing assertion condition"))?,
+        message,
+        span,
+    })
+}
+
+fn parse_block(pair: pest::iterators::Pair<Rule>) -> Result<Vec<PuppetExpr>> {
+    let mut body = Vec::new();
+    for inner in pair.into_inner() {
+        match inner.as_rule() {
+            Rule::resource => body.push(parse_resource(inner)?),
+            Rule::resource_defaults => body.push(parse_resource_defaults(inner)?),
+            Rule::relation => body.extend(parse_relation(inner)?),
+            Rule::include => body.push(parse_include(inner)),
+            Rule::iteration => body.push(parse_iteration(inner)?),
+            Rule::conditional => body.push(parse_conditional(inner)?),
+            Rule::assertion => body.push(parse_assertion(inner)?),
+            _ => {}
+        }
+    }
+    Ok(body)
+}
+
+/// `a or b and !c`, with `and` binding tighter than `or` as in Puppet.
+fn parse_condition(pair: pest::iterators::Pair<Rule>) -> Result<Condition> {
+    let mut disjuncts = Vec::new();
+    for conjunction in pair.into_inner() {
+        if conjunction.as_rule() != Rule::conjunction {
+            continue;
+        }
+        let mut conjuncts = Vec::new();
+        for negation in conjunction.into_inner() {
+            if negation.as_rule() != Rule::negation {
+                continue;
+            }
+            let mut negated = false;
+            let mut condition = None;
+            for inner in negation.into_inner() {
+                match inner.as_rule() {
+                    Rule::not_op => negated = !negated,
+                    Rule::condition => condition = Some(parse_condition(inner)?),
+                    Rule::comparison => {
+                        let mut parts = inner.into_inner();
+                        let left =
+                            parse_operand(parts.next().ok_or_else(|| anyhow!("Missing operand"))?)?;
+                        condition = Some(match (parts.next(), parts.next()) {
+                            (Some(op), Some(right)) => Condition::Compare {
+                                left,
+                                op: match op.as_str() {
+                                    "==" => CompareOp::Eq,
+                                    "!=" => CompareOp::Ne,
+                                    "<" => CompareOp::Lt,
+                                    "<=" => CompareOp::Le,
+                                    ">" => CompareOp::Gt,
+                                    ">=" => CompareOp::Ge,
+                                    _ => CompareOp::In,
+                                },
+                                right: parse_operand(right)?,
+                            },
+                            _ => Condition::Value(left),
+                        });
+                    }
+                    _ => {}
+                }
+            }
+            let condition = condition.ok_or_else(|| anyhow!("Missing condition"))?;
+            conjuncts.push(match negated {
+                true => Condition::Not(Box::new(condition)),
+                false => condition,
+            });
+        }
+        disjuncts.push(match conjuncts.len() {
+            1 => conjuncts.remove(0),
+            _ => Condition::And(conjuncts),
+        });
+    }
+    Ok(match disjuncts.len() {
+        1 => disjuncts.remove(0),
+        _ => Condition::Or(disjuncts),
+    })
+}
+
+/// Parses all of `s` as `rule`, for the expressions templates embed.
+fn parse_fragment(rule: Rule, s: &str) -> Result<pest::iterators::Pair<'_, Rule>> {
+    let s = s.trim();
+    let pair = parse_program(rule, s)?
+        .next()
+        .ok_or_else(|| anyhow!("Cannot parse {s}"))?;
+    match pair.as_str().len() == s.len() {
+        true => Ok(pair),
+        false => Err(anyhow!("Unexpected {} in {s}", &s[pair.as_str().len()..])),
+    }
+}
+
+/// `$facts['os']['family'] == 'Debian' and $port > 1024`, as in an `if`.
+pub fn parse_condition_str(s: &str) -> Result<Condition> {
+    parse_condition(parse_fragment(Rule::condition, s)?)
+}
+
+/// A value as conditions read it: `$port`, `$facts['os']['family']`, `'text'`, `[1, 2]`.
+pub fn parse_operand_str(s: &str) -> Result<Operand> {
+    parse_operand(parse_fragment(Rule::operand, s)?)
+}
+
+fn parse_operand(pair: pest::iterators::Pair<Rule>) -> Result<Operand> {
+    let value = pair
+        .clone()
+        .into_inner()
+        .next()
+        .ok_or_else(|| anyhow!("Missing operand"))?;
+    Ok(match value.as_rule() {
+        Rule::fact_lookup => Operand::Fact(
+            value
+                .into_inner()
+                .map(|key| Ok(parse_quoted_string(key)?.to_string()))
+                .collect::<Result<_>>()?,
+        ),
+        Rule::top_variable => Operand::Variable(
+            value
+                .as_str()
+                .trim_start_matches('$')
+                .trim_start_matches("::")
+                .to_string(),
+        ),
+        Rule::bare_word => Operand::Value(AttrValue::String(PuppetString::literal(value.as_str()))),
+        // Any other operand is parsed like the attribute value it would be.
+        _ => Operand::Value(parse_attr_value(pair)?),
+    })
+}
+
+fn parse_parameters(pair: pest::iterators::Pair<Rule>) -> Result<Vec<Parameter>> {
+    let mut params = Vec::new();
+    for parameter in pair.into_inner() {
+        let mut param = Parameter {
+            name: String::new(),
+            default: None,
+        };
+        for part in parameter.into_inner() {
+            match part.as_rule() {
+                Rule::variable_ref => {
+                    param.name = part.as_str().trim_start_matches('$').to_string()
+                }
+                Rule::attr_value => param.default = Some(parse_attr_value(part)?),
+                _ => {}
+            }
+        }
+        params.push(param);
+    }
+    Ok(params)
+}
+
+fn parse_define(pair: pest::iterators::Pair<Rule>) -> Result<PuppetExpr> {
+    let span = pair.as_span().into();
+    let mut name = String::new();
+    let mut params = Vec::new();
+    let mut body = Vec::new();
+    for inner in pair.into_inner() {
+        match inner.as_rule() {
+            Rule::class_name => name = class_name(inner.as_str()),
+            Rule::parameters => params = parse_parameters(inner)?,
+            Rule::resource => body.push(parse_resource(inner)?),
+            Rule::resource_defaults => body.push(parse_resource_defaults(inner)?),
+            Rule::relation => body.extend(parse_relation(inner)?),
+            Rule::include => body.push(parse_include(inner)),
+            Rule::conditional => body.push(parse_conditional(inner)?),
+            Rule::assertion => body.push(parse_assertion(inner)?),
+            _ => {}
+        }
+    }
+    Ok(PuppetExpr::Define {
+        name,
+        params,
+        body,
+        span,
+    })
+}
+
+fn parse_include(pair: pest::iterators::Pair<Rule>) -> PuppetExpr {
+    let span = pair.as_span().into();
+    let mut classes = Vec::new();
+    let mut contain = false;
+    for inner in pair.into_inner() {
+        match inner.as_rule() {
+            Rule::include_keyword => contain = inner.as_str() == "contain",
+            Rule::class_name => classes.push(class_name(inner.as_str())),
+            _ => {}
+        }
+    }
+    PuppetExpr::Include {
+        classes,
+        contain,
+        span,
+    }
+}
+
 fn parse_attributes(pair: pest::iterators::Pair<Rule>) -> Result<Vec<Attribute>> {
     let mut attributes = Vec::new();
     for attr_pair in pair.into_inner() {
         if attr_pair.as_rule() == Rule::attribute {
             let mut attr_name = String::new();
-            let mut attr_value = PuppetString::new();
+            let mut attr_value = AttrValue::default();
             for ap in attr_pair.into_inner() {
                 match ap.as_rule() {
                     Rule::attr_name => {
                         attr_name = ap.as_str().to_string();
                     }
                     Rule::attr_value => {
-                        attr_value =
-                            parse_quoted_string(ap.into_inner().next().ok_or_else(|| {
-                                anyhow!(PuppetError {
-                                    message: "Missing attribute value".to_string()
-                                })
-                            })?)?;
+                        attr_value = parse_attr_value(ap)?;
                     }
                     _ => {}
                 }
@@ -316,20 +1454,131 @@ fn parse_attributes(pair: pest::iterators::Pair<Rule>) -> Result<Vec<Attribute>>
     Ok(attributes)
 }
 
+fn parse_attr_value(pair: pest::iterators::Pair<Rule>) -> Result<AttrValue> {
+    let value = pair.into_inner().next().ok_or_else(|| {
+        anyhow!(PuppetError {
+            message: "Missing attribute value".to_string()
+        })
+    })?;
+    match value.as_rule() {
+        Rule::deferred => {
+            let mut function = String::new();
+            let mut args = Vec::new();
+            for inner in value.into_inner() {
+                match inner.as_rule() {
+                    Rule::quoted_string => function = parse_quoted_string(inner)?.to_string(),
+                    Rule::deferred_args => {
+                        args = inner
+                            .into_inner()
+                            .map(parse_attr_value)
+                            .collect::<Result<_>>()?;
+                    }
+                    _ => {}
+                }
+            }
+            Ok(AttrValue::Deferred { function, args })
+        }
+        Rule::sensitive => {
+            let inner = value
+                .into_inner()
+                .next()
+                .ok_or_else(|| anyhow!("Sensitive needs a value"))?;
+            Ok(AttrValue::Sensitive(Box::new(parse_attr_value(inner)?)))
+        }
+        Rule::function_call => {
+            let mut inner = value.into_inner();
+            let function = inner
+                .next()
+                .map(|name| name.as_str().to_string())
+                .unwrap_or_default();
+            let args: Vec<_> = inner.map(parse_attr_value).collect::<Result<_>>()?;
+            match args.iter().any(AttrValue::has_variables) || functions::depends_on_node(&function)
+            {
+                true => Ok(AttrValue::Call { function, args }),
+                false => functions::call(&function, &args),
+            }
+        }
+        Rule::resource_ref => Ok(AttrValue::ResourceRef(parse_resource_ref(value)?)),
+        Rule::array => Ok(AttrValue::Array(
+            value
+                .into_inner()
+                .map(parse_attr_value)
+                .collect::<Result<_>>()?,
+        )),
+        Rule::hash => {
+            let mut entries = IndexMap::new();
+            for entry in value.into_inner() {
+                let mut inner = entry.into_inner();
+                let (Some(key), Some(value)) = (inner.next(), inner.next()) else {
+                    return Err(anyhow!("Incomplete hash entry"));
+                };
+                let key = match key.into_inner().next() {
+                    Some(quoted) if quoted.as_rule() == Rule::quoted_string => {
+                        parse_quoted_string(quoted)?.to_string()
+                    }
+                    Some(ident) => ident.as_str().to_string(),
+                    None => return Err(anyhow!("Missing hash key")),
+                };
+                entries.insert(key, parse_attr_value(value)?);
+            }
+            Ok(AttrValue::Hash(entries))
+        }
+        Rule::boolean => Ok(AttrValue::Bool(value.as_str() == "true")),
+        Rule::undef => Ok(AttrValue::Undef),
+        Rule::integer => Ok(AttrValue::Integer(parse_integer(value.as_str())?)),
+        Rule::ident => Ok(AttrValue::String(PuppetString::literal(value.as_str()))),
+        Rule::top_variable => Ok(AttrValue::String(PuppetString::variable(
+            value.as_str().trim_start_matches('$'),
+        ))),
+        Rule::fact_lookup => {
+            let keys = value
+                .into_inner()
+                .map(|key| Ok(parse_quoted_string(key)?.to_string()))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(AttrValue::String(PuppetString::variable(&format!(
+                "facts.{}",
+                keys.join(".")
+            ))))
+        }
+        _ => Ok(AttrValue::String(parse_quoted_string(value)?)),
+    }
+}
+
+/// Parses an integer like Puppet: `0x1F` is hexadecimal and `0755` octal.
+fn parse_integer(text: &str) -> Result<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let magnitude = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        i64::from_str_radix(hex, 16)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        i64::from_str_radix(&digits[1..], 8)
+    } else {
+        digits.parse()
+    }
+    .map_err(|e| anyhow!("Invalid integer {text}: {e}"))?;
+    Ok(if negative { -magnitude } else { magnitude })
+}
+
+/// The relations of a chain, after the resources it declares, as in
+/// `file { "/tmp/a": } -> service { "nginx": }`.
 fn parse_relation(pair: pest::iterators::Pair<Rule>) -> Result<Vec<PuppetExpr>> {
     let mut relation_parts = Vec::new();
     let mut current_refs = Vec::new();
+    let mut expressions = Vec::new();
 
     for inner in pair.into_inner() {
         match inner.as_rule() {
             Rule::ref_arg => {
-                current_refs = parse_ref_arg(inner)?;
+                current_refs = parse_ref_arg(inner, &mut expressions)?;
             }
-            Rule::rel_op => {
-                if !current_refs.is_empty() {
-                    relation_parts.push((current_refs.clone(), inner.as_str().to_string()));
-                    current_refs = Vec::new();
-                }
+            Rule::rel_op if !current_refs.is_empty() => {
+                relation_parts.push((current_refs.clone(), inner.as_str().to_string()));
+                current_refs = Vec::new();
             }
             _ => {}
         }
@@ -339,7 +1588,6 @@ fn parse_relation(pair: pest::iterators::Pair<Rule>) -> Result<Vec<PuppetExpr>>
         relation_parts.push((current_refs, "".to_string()));
     }
 
-    let mut expressions = Vec::new();
     for i in 0..relation_parts.len().saturating_sub(1) {
         let (from, op_str) = &relation_parts[i];
         let (to, _) = &relation_parts[i + 1];
@@ -355,10 +1603,29 @@ fn parse_relation(pair: pest::iterators::Pair<Rule>) -> Result<Vec<PuppetExpr>>
     Ok(expressions)
 }
 
-fn parse_ref_arg(pair: pest::iterators::Pair<Rule>) -> Result<Vec<ResourceRef>> {
+/// The references of a chain operand, adding the resource it declares, if any, to
+/// `declarations`.
+fn parse_ref_arg(
+    pair: pest::iterators::Pair<Rule>,
+    declarations: &mut Vec<PuppetExpr>,
+) -> Result<Vec<ResourceRef>> {
     let mut refs = Vec::new();
     for inner in pair.into_inner() {
         match inner.as_rule() {
+            Rule::resource => {
+                let resource = parse_resource(inner)?;
+                if let PuppetExpr::Resource {
+                    rtype, title, span, ..
+                } = &resource
+                {
+                    refs.push(ResourceRef {
+                        rtype: rtype.clone(),
+                        title: title.clone(),
+                        span: *span,
+                    });
+                }
+                declarations.push(resource);
+            }
             Rule::resource_ref => {
                 refs.push(parse_resource_ref(inner)?);
             }
@@ -376,6 +1643,7 @@ fn parse_ref_arg(pair: pest::iterators::Pair<Rule>) -> Result<Vec<ResourceRef>>
 }
 
 fn parse_resource_ref(pair: pest::iterators::Pair<Rule>) -> Result<ResourceRef> {
+    let span = pair.as_span().into();
     let mut rtype = String::new();
     let mut title = PuppetString::new();
     for inner in pair.into_inner() {
@@ -389,43 +1657,81 @@ fn parse_resource_ref(pair: pest::iterators::Pair<Rule>) -> Result<ResourceRef>
             _ => {}
         }
     }
-    Ok(ResourceRef { rtype, title })
+    Ok(ResourceRef { rtype, title, span })
 }
 
 fn parse_rtype(pair: pest::iterators::Pair<Rule>) -> Result<String> {
-    let mut rtype = String::new();
-    for inner in pair.into_inner() {
-        match inner.as_rule() {
-            Rule::ident | Rule::uc_ident => {
-                return Ok(to_uc_first(inner.as_str()));
-            }
-            Rule::namespaced_ident | Rule::uc_namespaced_ident => {
-                rtype = to_uc_first_namespaced(inner);
-            }
-            no_match => return Err(anyhow!("unknown rtype: {no_match:?}")),
-        };
+    let Some(inner) = pair.into_inner().next() else {
+        return Err(anyhow!("Missing rtype"));
+    };
+    match inner.as_rule() {
+        Rule::ident | Rule::uc_ident | Rule::namespaced_ident | Rule::uc_namespaced_ident => {
+            Ok(normalize_rtype(inner.as_str()))
+        }
+        no_match => Err(anyhow!("unknown rtype: {no_match:?}")),
     }
-    Ok(to_uc_first(&rtype))
 }
 
-fn validate_references(
-    expressions: &[PuppetExpr],
+pub(super) fn validate_references(
+    expressions: &[&PuppetExpr],
     resources: &HashMap<ResourceRef, ()>,
+    source: &str,
 ) -> Result<()> {
+    let mut undefined: Vec<Diagnostic> = Vec::new();
     for expr in expressions {
         if let PuppetExpr::Relation { from, to, .. } = expr {
             for r in from.iter().chain(to.iter()) {
-                if !resources.contains_key(r) {
-                    return Err(anyhow!(PuppetError {
-                        message: format!("Undefined resource reference: {}", r.id()),
-                    }));
+                let message = format!("Undefined resource reference: {}", r.id());
+                let reported = undefined
+                    .iter()
+                    .any(|d| d.message == message && d.span == r.span);
+                if !resources.contains_key(r) && !reported {
+                    undefined.push(Diagnostic::new(message, r.span, source));
                 }
             }
         }
     }
+    if !undefined.is_empty() {
+        return Err(anyhow!(Diagnostics(undefined)));
+    }
     Ok(())
 }
 
+/// Warnings for the relations of the Puppet manifest `source` whose endpoints normalize to
+/// the same resource, quoting the spellings used so that e.g. `File['/a'] -> File["/a"]`
+/// is easy to spot. Such relations order nothing and compiling ignores them.
+pub fn self_relations(source: &str) -> Result<Vec<Diagnostic>> {
+    let manifest = Manifest::from_str_lenient(source)?;
+    Ok(find_self_relations(&flatten(&manifest.0), source))
+}
+
+fn find_self_relations(expressions: &[&PuppetExpr], source: &str) -> Vec<Diagnostic> {
+    let spelling = |r: &ResourceRef| {
+        source
+            .get(r.span.start..r.span.end)
+            .filter(|spelling| !spelling.is_empty())
+            .map_or_else(|| r.to_string(), str::to_string)
+    };
+    let mut diagnostics = Vec::new();
+    for expr in expressions {
+        if let PuppetExpr::Relation { from, to, op } = expr {
+            for f in from {
+                for t in to.iter().filter(|t| *t == f) {
+                    let message = format!(
+                        "Relation connects {} to itself and is ignored: `{}` at {} {op} `{}`",
+                        f.id(),
+                        spelling(f),
+                        f.span,
+                        spelling(t),
+                    );
+                    diagnostics.push(Diagnostic::new(message, t.span, source));
+                }
+            }
+        }
+    }
+    diagnostics
+}
+
 fn parse_quoted_string(pair: pest::iterators::Pair<Rule>) -> Result<PuppetString> {
     let mut content = Vec::new();
     for inner in pair.into_inner() {
@@ -464,9 +1770,14 @@ fn parse_quoted_string(pair: pest::iterators::Pair<Rule>) -> Result<PuppetString
     Ok(PuppetString(content))
 }
 
-fn to_uc_first(s: &str) -> String {
-    s.split("::")
+/// Canonical spelling of a (possibly namespaced) type name, as Puppet displays it:
+/// `::profile::WEB::nginx` becomes `Profile::Web::Nginx`.
+pub fn normalize_rtype(rtype: &str) -> String {
+    rtype
+        .trim_start_matches("::")
+        .split("::")
         .map(|part| {
+            let part = part.to_lowercase();
             let mut chars = part.chars();
             match chars.next() {
                 None => String::new(),
@@ -476,16 +1787,3 @@ fn to_uc_first(s: &str) -> String {
         .collect::<Vec<String>>()
         .join("::")
 }
-
-fn to_uc_first_namespaced(pair: pest::iterators::Pair<Rule>) -> String {
-    pair.into_inner()
-        .map(|part| {
-            let mut chars = part.as_str().chars();
-            match chars.next() {
-                None => String::new(),
-                Some(c) => c.to_uppercase().collect::<String>() + chars.as_str(),
-            }
-        })
-        .collect::<Vec<String>>()
-        .join("::")
-}
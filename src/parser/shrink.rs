@@ -0,0 +1,120 @@
+//! Minimal reproducers for bug reports: `dolly shrink` drops lines of a manifest for as
+//! long as it still fails to parse or compile with the same error.
+
+use super::pp::Manifest;
+use crate::parse_puppet_manifest;
+
+/// The error parsing then compiling `source` fails with, if any.
+pub fn error_of(source: &str) -> Option<String> {
+    let manifest = match source.parse::<Manifest>() {
+        Ok(manifest) => manifest,
+        Err(e) => return Some(e.to_string()),
+    };
+    parse_puppet_manifest(&manifest)
+        .err()
+        .map(|e| e.to_string())
+}
+
+/// What is left of `source` once no statement, then no line, can be dropped without
+/// `fails` turning false. `fails(source)` itself should hold.
+pub fn shrink(source: &str, mut fails: impl FnMut(&str) -> bool) -> String {
+    let statements = minimize(statements(source), &mut fails);
+    let lines = statements
+        .concat()
+        .lines()
+        .map(|line| format!("{line}\n"))
+        .collect();
+    minimize(lines, &mut fails).concat()
+}
+
+/// The `units` left once no chunk of them can be dropped, by delta debugging: chunks
+/// start as halves and shrink to single units.
+fn minimize(mut units: Vec<String>, fails: &mut impl FnMut(&str) -> bool) -> Vec<String> {
+    let mut chunks = 2;
+    while units.len() > 1 {
+        let size = units.len().div_ceil(chunks);
+        let smaller = (0..units.len()).step_by(size).find_map(|start| {
+            let end = (start + size).min(units.len());
+            let candidate = [&units[..start], &units[end..]].concat();
+            fails(&candidate.concat()).then_some(candidate)
+        });
+        match smaller {
+            Some(candidate) => {
+                units = candidate;
+                chunks = (chunks - 1).max(2);
+            }
+            None if size == 1 => break,
+            None => chunks = (chunks * 2).min(units.len()),
+        }
+    }
+    units
+}
+
+/// The lines of `source` grouped so that brackets opened on a line close in its group,
+/// which makes whole resources and blocks the first thing dropped.
+fn statements(source: &str) -> Vec<String> {
+    let mut statements = vec![];
+    let mut current = String::new();
+    let mut depth = 0i32;
+    for line in source.lines() {
+        current.push_str(line);
+        current.push('\n');
+        let mut quote = None;
+        for c in line.chars() {
+            match (quote, c) {
+                (None, '"' | '\'') => quote = Some(c),
+                (Some(open), c) if c == open => quote = None,
+                (None, '{' | '[' | '(') => depth += 1,
+                (None, '}' | ']' | ')') => depth -= 1,
+                _ => {}
+            }
+        }
+        if depth <= 0 {
+            statements.push(std::mem::take(&mut current));
+            depth = 0;
+        }
+    }
+    if !current.is_empty() {
+        statements.push(current);
+    }
+    statements
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_shrink_keeps_the_error() -> Result<()> {
+        let source = r#"
+package { "nginx": }
+file { "/etc/nginx/nginx.conf":
+  content => "worker_processes 4;\n",
+  require => Package["nginx"],
+}
+service { "nginx":
+  ensure => running,
+}
+file { "/a": }
+file { "/b": }
+File["/a"] -> File["/b"]
+File["/b"] -> File["/a"]
+"#;
+        let fails = |source: &str| error_of(source).is_some_and(|e| e.contains("cycle"));
+        assert!(fails(source));
+        assert_eq!(
+            shrink(source, fails),
+            "file { \"/a\": }\nfile { \"/b\": }\nFile[\"/a\"] -> File[\"/b\"]\n\
+             File[\"/b\"] -> File[\"/a\"]\n"
+        );
+
+        let broken = "file { \"/a\": }\nfile { \"/b\" }\nfile { \"/c\": }\n";
+        assert_eq!(
+            shrink(broken, |source| error_of(source)
+                .is_some_and(|e| e.contains("expected"))),
+            "file { \"/b\" }\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,438 @@
+//! Templates for file content, a subset of Puppet's EPP that also reads ERB-style `@name`
+//! variables:
+//!
+//! ```text
+//! <%# Managed by dolly -%>
+//! worker_processes <%= $workers %>;
+//! <% if $facts['os']['family'] == 'Debian' { -%>
+//! user www-data;
+//! <% } else { -%>
+//! user nginx;
+//! <% } -%>
+//! <% $upstreams.each |$upstream| { -%>
+//! server <%= $upstream %>;
+//! <% } -%>
+//! ```
+//!
+//! `<%-` removes the blanks before the tag on its line and `-%>` the line break after it.
+//! Variables are the parameters of the template, then those of the enclosing class or
+//! defined type, then facts.
+
+use super::conditions::{Condition, Operand};
+use super::pp::{AttrValue, PuppetString, parse_condition_str, parse_operand_str};
+use anyhow::{Result, anyhow};
+use indexmap::IndexMap;
+use std::str::FromStr;
+
+/// The functions rendering a template: `template` and `epp` read a file,
+/// `inline_template` and `inline_epp` take the template itself.
+pub fn is_template_function(function: &str) -> bool {
+    matches!(
+        function,
+        "template" | "epp" | "inline_template" | "inline_epp"
+    )
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Text(String),
+    Value(Operand),
+    If {
+        branches: Vec<(Condition, Vec<Node>)>,
+        otherwise: Vec<Node>,
+    },
+    Each {
+        iterable: Operand,
+        params: Vec<String>,
+        body: Vec<Node>,
+    },
+}
+
+/// A parsed template.
+#[derive(Debug, Clone)]
+pub struct Template(Vec<Node>);
+
+/// A block being parsed: what opened it, and the nodes of its current branch.
+enum Block {
+    If {
+        branches: Vec<(Condition, Vec<Node>)>,
+        /// The condition of the current branch, `None` in the `else` one.
+        condition: Option<Condition>,
+    },
+    Each {
+        iterable: Operand,
+        params: Vec<String>,
+    },
+}
+
+/// The template parsed so far: the top-level nodes and the blocks still open.
+#[derive(Default)]
+struct Builder {
+    nodes: Vec<Node>,
+    open: Vec<(Block, Vec<Node>)>,
+}
+
+impl FromStr for Template {
+    type Err = anyhow::Error;
+
+    fn from_str(source: &str) -> Result<Self> {
+        let mut builder = Builder::default();
+        let mut rest = source;
+        let mut trim_next_newline = false;
+        while !rest.is_empty() {
+            let start = rest.find("<%");
+            let mut text = &rest[..start.unwrap_or(rest.len())];
+            if trim_next_newline {
+                text = text
+                    .strip_prefix("\r\n")
+                    .or_else(|| text.strip_prefix('\n'))
+                    .unwrap_or(text);
+            }
+            let Some(start) = start else {
+                builder.text(text);
+                break;
+            };
+            let after = &rest[start + 2..];
+            let end = after
+                .find("%>")
+                .ok_or_else(|| anyhow!("Unclosed tag at {}", &rest[start..]))?;
+            let mut tag = &after[..end];
+            rest = &after[end + 2..];
+            if let Some(trimmed) = tag.strip_prefix('-') {
+                tag = trimmed;
+                text = text.trim_end_matches([' ', '\t']);
+            }
+            builder.text(text);
+            trim_next_newline = tag.ends_with('-');
+            let tag = tag.strip_suffix('-').unwrap_or(tag);
+            if tag.starts_with('#') || tag.trim().is_empty() {
+                continue;
+            }
+            match tag.strip_prefix('=') {
+                Some(expression) => {
+                    let operand = parse_operand(expression)?;
+                    builder.current().push(Node::Value(operand));
+                }
+                None => builder.code(tag.trim())?,
+            }
+        }
+        match builder.open.is_empty() {
+            true => Ok(Self(builder.nodes)),
+            false => Err(anyhow!("Unclosed block at the end of the template")),
+        }
+    }
+}
+
+/// `$name`, `@name` as in ERB, `$facts['os']['family']`, `$::fact` or a literal.
+fn parse_operand(expression: &str) -> Result<Operand> {
+    let expression = expression.trim();
+    match expression.strip_prefix('@') {
+        Some(name) => Ok(Operand::Variable(name.to_string())),
+        None => parse_operand_str(&top_scope_as_facts(expression)),
+    }
+}
+
+fn parse_condition(condition: &str) -> Result<Condition> {
+    parse_condition_str(&top_scope_as_facts(&condition.replace('@', "$")))
+}
+
+/// `expression` with top-scope variables, `$::osfamily`, read as the facts they are, so
+/// that a missing one is `undef` rather than an unknown variable.
+fn top_scope_as_facts(expression: &str) -> String {
+    let mut result = String::new();
+    let mut rest = expression;
+    while let Some(start) = rest.find("$::") {
+        result.push_str(&rest[..start]);
+        rest = &rest[start + 3..];
+        let end = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(rest.len());
+        result.push_str(&format!("$facts['{}']", &rest[..end]));
+        rest = &rest[end..];
+    }
+    result.push_str(rest);
+    result
+}
+
+impl Builder {
+    fn current(&mut self) -> &mut Vec<Node> {
+        match self.open.last_mut() {
+            Some((_, nodes)) => nodes,
+            None => &mut self.nodes,
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        if !text.is_empty() {
+            self.current().push(Node::Text(text.to_string()));
+        }
+    }
+
+    /// Opens, continues or closes a block with the code of a `<% ... %>` tag.
+    fn code(&mut self, code: &str) -> Result<()> {
+        fn opens(code: &str) -> Option<&str> {
+            code.strip_suffix('{').map(str::trim_end)
+        }
+        if let Some(rest) = code.strip_prefix('}') {
+            let rest = rest.trim_start();
+            if rest.is_empty() {
+                return self.close();
+            }
+            let next = match rest.strip_prefix("elsif") {
+                Some(elsif) => {
+                    Some(parse_condition(opens(elsif).ok_or_else(|| {
+                        anyhow!("Expected {{ at the end of {code}")
+                    })?)?)
+                }
+                None if opens(rest) == Some("else") => None,
+                None => return Err(anyhow!("Unexpected {code}")),
+            };
+            let Some((
+                Block::If {
+                    branches,
+                    condition,
+                },
+                nodes,
+            )) = self.open.last_mut()
+            else {
+                return Err(anyhow!("Unexpected {code} outside of an if"));
+            };
+            let finished = condition
+                .take()
+                .ok_or_else(|| anyhow!("Unexpected {code} after else"))?;
+            branches.push((finished, std::mem::take(nodes)));
+            *condition = next;
+            return Ok(());
+        }
+        let head = opens(code).ok_or_else(|| anyhow!("Unsupported code {code}"))?;
+        let block = if let Some(condition) = head.strip_prefix("if ") {
+            Block::If {
+                branches: vec![],
+                condition: Some(parse_condition(condition)?),
+            }
+        } else if let Some(condition) = head.strip_prefix("unless ") {
+            Block::If {
+                branches: vec![],
+                condition: Some(Condition::Not(Box::new(parse_condition(condition)?))),
+            }
+        } else if let Some((iterable, params)) = head.split_once(".each") {
+            let params = params
+                .trim()
+                .strip_prefix('|')
+                .and_then(|params| params.strip_suffix('|'))
+                .ok_or_else(|| anyhow!("Expected |$name| after each in {code}"))?;
+            Block::Each {
+                iterable: parse_operand(iterable)?,
+                params: params
+                    .split(',')
+                    .map(|param| param.trim().trim_start_matches(['$', '@']).to_string())
+                    .collect(),
+            }
+        } else {
+            return Err(anyhow!("Unsupported code {code}"));
+        };
+        self.open.push((block, vec![]));
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        let node = match self.open.pop() {
+            Some((
+                Block::If {
+                    mut branches,
+                    condition,
+                },
+                nodes,
+            )) => match condition {
+                Some(condition) => {
+                    branches.push((condition, nodes));
+                    Node::If {
+                        branches,
+                        otherwise: vec![],
+                    }
+                }
+                None => Node::If {
+                    branches,
+                    otherwise: nodes,
+                },
+            },
+            Some((Block::Each { iterable, params }, body)) => Node::Each {
+                iterable,
+                params,
+                body,
+            },
+            None => return Err(anyhow!("Unexpected }} closing nothing")),
+        };
+        self.current().push(node);
+        Ok(())
+    }
+}
+
+impl Template {
+    /// The text of the template, with `lookup` giving the value of its variables, `None`
+    /// for unknown ones. Facts are looked up as `facts.os.family`.
+    pub fn render(&self, lookup: &dyn Fn(&str) -> Option<AttrValue>) -> Result<String> {
+        let mut output = String::new();
+        render(&self.0, lookup, &mut output)?;
+        Ok(output)
+    }
+}
+
+fn render(
+    nodes: &[Node],
+    lookup: &dyn Fn(&str) -> Option<AttrValue>,
+    output: &mut String,
+) -> Result<()> {
+    let resolve = |operand: &Operand| -> Result<AttrValue> {
+        Ok(match operand {
+            Operand::Fact(path) => {
+                lookup(&format!("facts.{}", path.join("."))).unwrap_or(AttrValue::Undef)
+            }
+            Operand::Variable(name) => {
+                lookup(name).ok_or_else(|| anyhow!("Unknown variable ${name}"))?
+            }
+            Operand::Value(AttrValue::String(s)) => {
+                AttrValue::String(s.interpolate(|name| lookup(name).map(|value| text(&value)))?)
+            }
+            Operand::Value(value) => value.clone(),
+        })
+    };
+    for node in nodes {
+        match node {
+            Node::Text(text) => output.push_str(text),
+            Node::Value(operand) => output.push_str(&text(&resolve(operand)?)),
+            Node::If {
+                branches,
+                otherwise,
+            } => {
+                let mut chosen = otherwise;
+                for (condition, body) in branches {
+                    if condition.holds(&resolve)? {
+                        chosen = body;
+                        break;
+                    }
+                }
+                render(chosen, lookup, output)?;
+            }
+            Node::Each {
+                iterable,
+                params,
+                body,
+            } => {
+                let entries: Vec<(AttrValue, AttrValue)> = match resolve(iterable)? {
+                    AttrValue::Array(values) => values
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, value)| (AttrValue::Integer(i as i64), value))
+                        .collect(),
+                    AttrValue::Hash(entries) => entries
+                        .into_iter()
+                        .map(|(key, value)| (AttrValue::String(PuppetString::literal(&key)), value))
+                        .collect(),
+                    AttrValue::Undef => vec![],
+                    value => return Err(anyhow!("Cannot iterate over {value}")),
+                };
+                for (key, value) in entries {
+                    let bound: IndexMap<&str, AttrValue> = match params.as_slice() {
+                        [param] if matches!(key, AttrValue::Integer(_)) => {
+                            IndexMap::from([(param.as_str(), value)])
+                        }
+                        [param] => {
+                            IndexMap::from([(param.as_str(), AttrValue::Array(vec![key, value]))])
+                        }
+                        [key_param, value_param, ..] => IndexMap::from([
+                            (key_param.as_str(), key),
+                            (value_param.as_str(), value),
+                        ]),
+                        [] => IndexMap::new(),
+                    };
+                    let scoped = |name: &str| bound.get(name).cloned().or_else(|| lookup(name));
+                    render(body, &scoped, output)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// How a value appears in the rendered text: strings as they are, `undef` as nothing.
+fn text(value: &AttrValue) -> String {
+    match value {
+        AttrValue::Undef => String::new(),
+        value => value.as_literal().unwrap_or_else(|| value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::pp::Manifest;
+    use crate::{CompileOptions, parse_puppet_manifest_with_options};
+    use std::{env, fs};
+
+    #[test]
+    fn test_templates_render_with_scope_and_facts() -> Result<()> {
+        let dir = env::temp_dir().join(format!("dolly-templates-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        fs::write(
+            dir.join("nginx.conf.epp"),
+            "<%# Managed by dolly -%>\n\
+             worker_processes <%= $workers %>;\n\
+             <% if $facts['os']['family'] == 'Debian' { -%>\n\
+             user www-data;\n\
+             <% } elsif @osfamily == 'RedHat' { -%>\n\
+             user nginx;\n\
+             <% } else { -%>\n\
+             user nobody;\n\
+             <% } -%>\n\
+             <% $upstreams.each |$upstream| { -%>\n\
+             \x20   <%- -%>server <%= $upstream %>; # <%= $title %>\n\
+             <% } -%>\n\
+             <% unless $::missing { %>done<% } %>\n",
+        )?;
+        let manifest: Manifest = r#"
+            define site($workers = 2, $upstreams = []) {
+                file { "/etc/nginx/${title}.conf": content => template('nginx.conf.epp') }
+            }
+            site { "shop": upstreams => ['10.0.0.1', '10.0.0.2'] }
+            file { "/etc/motd": content => inline_epp('Hello <%= $name %> on <%= $::hostname %>', { 'name' => 'ops' }) }
+        "#
+        .parse()?;
+        let mut options = CompileOptions {
+            templates: dir.clone(),
+            ..CompileOptions::default()
+        };
+        options.facts.insert("os.family", "Debian");
+        options.facts.insert("hostname", "web-01");
+        let plan = parse_puppet_manifest_with_options(&manifest, &options)?;
+        let content = |id: &str| {
+            plan.node(id)
+                .and_then(|index| plan.plan().inner()[index].attribute("content"))
+        };
+        assert_eq!(
+            content("File[/etc/nginx/shop.conf]").as_deref(),
+            Some(
+                "worker_processes 2;\n\
+                 user www-data;\n\
+                 server 10.0.0.1; # shop\n\
+                 server 10.0.0.2; # shop\n\
+                 done\n"
+            )
+        );
+        assert_eq!(
+            content("File[/etc/motd]").as_deref(),
+            Some("Hello ops on web-01")
+        );
+        assert!(plan.facts_used().contains("os.family"));
+
+        assert!("<% if $a { %>x".parse::<Template>().is_err());
+        assert!("<% } %>".parse::<Template>().is_err());
+        let template: Template = "<%= $nope %>".parse()?;
+        assert!(
+            template.render(&|_| None).is_err(),
+            "Unknown variables are errors"
+        );
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+use anyhow::{Result, anyhow};
+use std::time::Duration;
+
+fn split_number(value: &str) -> Result<(u64, String)> {
+    let value = value.trim();
+    let digits = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let number = value[..digits]
+        .parse()
+        .map_err(|_| anyhow!("'{value}' does not start with a number"))?;
+    Ok((number, value[digits..].trim().to_lowercase()))
+}
+
+/// Parses durations like `30`, `30s`, `5m`, `2h`, `1d` or `250ms`. Bare numbers are seconds.
+pub fn parse_duration(value: &str) -> Result<Duration> {
+    let (number, unit) = split_number(value)?;
+    let seconds = match unit.as_str() {
+        "ms" => return Ok(Duration::from_millis(number)),
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        other => {
+            return Err(anyhow!(
+                "invalid duration unit '{other}' in '{value}', expected one of: ms, s, m, h, d, w"
+            ));
+        }
+    };
+    number
+        .checked_mul(seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(|| anyhow!("duration '{value}' is too long"))
+}
+
+/// Parses sizes like `512`, `100k`, `100M` or `2GiB` into bytes, using powers of 1024.
+pub fn parse_size(value: &str) -> Result<u64> {
+    let (number, unit) = split_number(value)?;
+    let exponent = match unit.trim_end_matches("ib").trim_end_matches('b') {
+        "" => 0,
+        "k" => 1,
+        "m" => 2,
+        "g" => 3,
+        "t" => 4,
+        _ => {
+            return Err(anyhow!(
+                "invalid size unit '{unit}' in '{value}', expected one of: b, k, m, g, t"
+            ));
+        }
+    };
+    number
+        .checked_mul(1024u64.pow(exponent))
+        .ok_or_else(|| anyhow!("size '{value}' is too large"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() -> Result<()> {
+        assert_eq!(parse_duration("30")?, Duration::from_secs(30));
+        assert_eq!(parse_duration("5m")?, Duration::from_secs(300));
+        assert_eq!(parse_duration("2H")?, Duration::from_secs(7200));
+        assert_eq!(parse_duration("250ms")?, Duration::from_millis(250));
+        assert!(parse_duration("5 minutes").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("99999999999999999w").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_size() -> Result<()> {
+        assert_eq!(parse_size("512")?, 512);
+        assert_eq!(parse_size("100k")?, 100 * 1024);
+        assert_eq!(parse_size("100M")?, 100 * 1024 * 1024);
+        assert_eq!(parse_size("2GiB")?, 2 * 1024 * 1024 * 1024);
+        assert!(parse_size("10x").is_err());
+        Ok(())
+    }
+}
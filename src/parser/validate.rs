@@ -0,0 +1,173 @@
+use super::pp::{Manifest, PuppetExpr};
+use super::value::FromValue;
+use crate::resources::FileMode;
+use std::fmt;
+
+/// A non-fatal problem with a well-known attribute, found before apply.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub resource: String,
+    pub attribute: String,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "warning: {} {}: {}",
+            self.resource, self.attribute, self.message
+        )
+    }
+}
+
+fn ensure_values(rtype: &str) -> Option<&'static [&'static str]> {
+    match rtype {
+        "File" => Some(&["present", "absent", "file", "directory", "link"]),
+        "Service" => Some(&["running", "stopped", "true", "false"]),
+        "Exec" => Some(&[]),
+        _ => None,
+    }
+}
+
+pub fn validate(manifest: &Manifest) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+    for expr in manifest.resources() {
+        let PuppetExpr::Resource {
+            rtype,
+            title,
+            attributes,
+            ..
+        } = expr
+        else {
+            continue;
+        };
+        let resource = format!("{rtype}[{title}]");
+        for attr in attributes {
+            // Interpolated values are only known once variables are resolved.
+            let Some(value) = attr.value.as_literal() else {
+                continue;
+            };
+            let message = match attr.name.as_str() {
+                "ensure" => match ensure_values(rtype) {
+                    Some([]) => Some(format!("{rtype} does not support ensure")),
+                    Some(valid) if !valid.contains(&value.as_str()) => {
+                        Some(invalid_value_message(&value, valid))
+                    }
+                    _ => None,
+                },
+                "mode" if rtype == "File" => FileMode::from_value(&attr.value)
+                    .err()
+                    .map(|e| e.to_string()),
+                _ => None,
+            };
+            if let Some(message) = message {
+                warnings.push(Warning {
+                    resource: resource.clone(),
+                    attribute: attr.name.clone(),
+                    message,
+                });
+            }
+        }
+    }
+    warnings
+}
+
+fn invalid_value_message(value: &str, valid: &[&str]) -> String {
+    let suggestion = valid
+        .iter()
+        .map(|candidate| (edit_distance(value, candidate), candidate))
+        .filter(|(distance, _)| *distance <= 2)
+        .min_by_key(|(distance, _)| *distance);
+    match suggestion {
+        Some((_, candidate)) => format!("invalid value '{value}', did you mean '{candidate}'?"),
+        None => format!(
+            "invalid value '{value}', expected one of: {}",
+            valid.join(", ")
+        ),
+    }
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                previous
+            } else {
+                1 + previous.min(row[j]).min(row[j + 1])
+            };
+            previous = current;
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_validate_well_known_attributes() -> Result<()> {
+        let input = r#"
+            file { "/tmp/one":
+                ensure => presnet,
+                mode   => "0644",
+            }
+            file { "/tmp/two":
+                ensure => "directory",
+                mode   => "rw-r--r--",
+            }
+            file { "/tmp/three":
+                mode => "u=rwX,go=rX",
+            }
+            file { "/tmp/${dir}":
+                ensure => "${state}",
+            }
+            service { "nginx":
+                ensure => "runing",
+            }
+            exec { "/bin/true":
+                ensure => present,
+            }
+            foo::bar { "x":
+                ensure => whatever,
+            }
+        "#;
+        let warnings = validate(&Manifest::from_str(input)?);
+        assert_eq!(warnings.len(), 4, "Unexpected warnings: {warnings:?}");
+        assert_eq!(warnings[0].resource, "File[/tmp/one]");
+        assert!(warnings[0].message.contains("did you mean 'present'"));
+        assert_eq!(warnings[1].attribute, "mode");
+        assert_eq!(
+            warnings[1].message, "must be an octal or symbolic mode, got rw-r--r--",
+            "Symbolic modes are fine, ls-style ones are not"
+        );
+        assert!(warnings[2].message.contains("did you mean 'running'"));
+        assert!(warnings[3].message.contains("does not support ensure"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_unquoted_octal_modes_are_valid() -> Result<()> {
+        let input = r#"
+            file { "/tmp/one": mode => 0755 }
+            file { "/tmp/two": mode => 0644 }
+            file { "/tmp/three": mode => 017777 }
+        "#;
+        let warnings = validate(&Manifest::from_str(input)?);
+        assert_eq!(warnings.len(), 1, "Unexpected warnings: {warnings:?}");
+        assert_eq!(warnings[0].resource, "File[/tmp/three]");
+        assert_eq!(
+            warnings[0].message,
+            "must be an octal or symbolic mode, got 17777"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,347 @@
+//! Conversions between attribute values and the Rust types resources keep them as.
+
+use super::pp::{AttrValue, Attribute, PuppetString};
+use super::units::parse_duration;
+use anyhow::{Result, anyhow};
+use indexmap::IndexMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// A type an attribute value converts to. Errors complete "{rtype} {attribute} ...", e.g.
+/// "must be an integer from 0 to 65535, got 70000".
+pub trait FromValue: Sized {
+    fn from_value(value: &AttrValue) -> Result<Self>;
+}
+
+/// A type that converts to an attribute value.
+pub trait IntoValue {
+    fn into_value(self) -> AttrValue;
+}
+
+impl Attribute {
+    /// The value as `T`, with errors naming the resource type and the attribute.
+    pub fn parse_as<T: FromValue>(&self, rtype: &str) -> Result<T> {
+        T::from_value(&self.value).map_err(|e| anyhow!("{rtype} {} {e}", self.name))
+    }
+}
+
+fn literal(value: &AttrValue) -> Result<String> {
+    value
+        .as_literal()
+        .ok_or_else(|| anyhow!("must be a literal, got {value}"))
+}
+
+impl FromValue for String {
+    fn from_value(value: &AttrValue) -> Result<Self> {
+        literal(value)
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &AttrValue) -> Result<Self> {
+        match value {
+            AttrValue::Bool(b) => Ok(*b),
+            value => match literal(value)?.as_str() {
+                "true" => Ok(true),
+                "false" => Ok(false),
+                other => Err(anyhow!("must be true or false, got {other}")),
+            },
+        }
+    }
+}
+
+macro_rules! integer_value {
+    ($($int:ty),*) => {$(
+        impl FromValue for $int {
+            fn from_value(value: &AttrValue) -> Result<Self> {
+                let text = literal(value)?;
+                text.trim().parse().map_err(|_| {
+                    anyhow!(
+                        "must be an integer from {} to {}, got {text}",
+                        <$int>::MIN,
+                        <$int>::MAX
+                    )
+                })
+            }
+        }
+
+        impl IntoValue for $int {
+            fn into_value(self) -> AttrValue {
+                AttrValue::Integer(self as i64)
+            }
+        }
+    )*};
+}
+
+integer_value!(u8, u16, u32, i32, i64);
+
+impl FromValue for u64 {
+    fn from_value(value: &AttrValue) -> Result<Self> {
+        let text = literal(value)?;
+        text.trim()
+            .parse()
+            .map_err(|_| anyhow!("must be a non-negative integer, got {text}"))
+    }
+}
+
+impl FromValue for usize {
+    fn from_value(value: &AttrValue) -> Result<Self> {
+        let text = literal(value)?;
+        text.trim()
+            .parse()
+            .map_err(|_| anyhow!("must be a non-negative integer, got {text}"))
+    }
+}
+
+impl IntoValue for usize {
+    fn into_value(self) -> AttrValue {
+        AttrValue::Integer(self as i64)
+    }
+}
+
+impl FromValue for PathBuf {
+    fn from_value(value: &AttrValue) -> Result<Self> {
+        literal(value).map(PathBuf::from)
+    }
+}
+
+/// Durations are written as in `timeout => '5m'`, bare numbers being seconds.
+impl FromValue for Duration {
+    fn from_value(value: &AttrValue) -> Result<Self> {
+        parse_duration(&literal(value)?).map_err(|e| anyhow!("must be a duration: {e}"))
+    }
+}
+
+/// `undef` is `None`.
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &AttrValue) -> Result<Self> {
+        match value {
+            AttrValue::Undef => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+/// A single value is an array of one element, as in Puppet.
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: &AttrValue) -> Result<Self> {
+        value
+            .as_array()
+            .into_iter()
+            .enumerate()
+            .map(|(i, value)| T::from_value(value).map_err(|e| anyhow!("[{i}] {e}")))
+            .collect()
+    }
+}
+
+impl<T: FromValue> FromValue for IndexMap<String, T> {
+    fn from_value(value: &AttrValue) -> Result<Self> {
+        match value {
+            AttrValue::Hash(entries) => entries
+                .iter()
+                .map(|(key, value)| {
+                    T::from_value(value)
+                        .map(|value| (key.clone(), value))
+                        .map_err(|e| anyhow!("['{key}'] {e}"))
+                })
+                .collect(),
+            AttrValue::Undef => Ok(IndexMap::new()),
+            value => Err(anyhow!("must be a hash, got {value}")),
+        }
+    }
+}
+
+impl FromValue for AttrValue {
+    fn from_value(value: &AttrValue) -> Result<Self> {
+        Ok(value.clone())
+    }
+}
+
+impl IntoValue for AttrValue {
+    fn into_value(self) -> AttrValue {
+        self
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> AttrValue {
+        AttrValue::String(PuppetString::literal(self))
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> AttrValue {
+        self.as_str().into_value()
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> AttrValue {
+        AttrValue::Bool(self)
+    }
+}
+
+impl IntoValue for PathBuf {
+    fn into_value(self) -> AttrValue {
+        self.to_string_lossy().as_ref().into_value()
+    }
+}
+
+impl IntoValue for Duration {
+    fn into_value(self) -> AttrValue {
+        match self.subsec_millis() {
+            0 => format!("{}s", self.as_secs()).into_value(),
+            _ => format!("{}ms", self.as_millis()).into_value(),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> AttrValue {
+        self.map_or(AttrValue::Undef, T::into_value)
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> AttrValue {
+        AttrValue::Array(self.into_iter().map(T::into_value).collect())
+    }
+}
+
+impl<T: IntoValue> IntoValue for IndexMap<String, T> {
+    fn into_value(self) -> AttrValue {
+        AttrValue::Hash(
+            self.into_iter()
+                .map(|(key, value)| (key, value.into_value()))
+                .collect(),
+        )
+    }
+}
+
+/// Derives `from_attributes` for a spec whose fields are named after the attributes they
+/// hold and whose types implement [`FromValue`]. Attributes without a field are ignored,
+/// omitted ones keep the field's default:
+///
+/// ```
+/// # use dolly::parser::pp::Manifest;
+/// # use dolly::resources::ResourceRegistry;
+/// #[derive(Debug, Default)]
+/// struct WidgetSpec {
+///     port: u16,
+///     hosts: Vec<String>,
+///     enabled: Option<bool>,
+/// }
+/// dolly::from_attributes!(WidgetSpec, "Widget" { port, hosts, enabled });
+///
+/// let spec = |source: &str| {
+///     let manifest: Manifest = source.parse()?;
+///     let expr = manifest.resources().next().unwrap();
+///     WidgetSpec::from_attributes(ResourceRegistry::declaration(expr)?.2)
+/// };
+/// let widget = spec("widget { 'w': port => 8080, hosts => 'a' }")?;
+/// assert_eq!((widget.port, widget.hosts, widget.enabled), (8080, vec!["a".into()], None));
+/// let e = spec("widget { 'w': port => 70000 }").unwrap_err();
+/// assert_eq!(e.to_string(), "Widget port must be an integer from 0 to 65535, got 70000");
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[macro_export]
+macro_rules! from_attributes {
+    ($spec:ty, $rtype:literal { $($field:ident),* $(,)? }) => {
+        impl $spec {
+            pub fn from_attributes(
+                attributes: &[$crate::parser::pp::Attribute],
+            ) -> ::anyhow::Result<Self> {
+                let mut spec = <Self as ::std::default::Default>::default();
+                for attr in attributes {
+                    $(
+                        if attr.name == stringify!($field) {
+                            spec.$field = attr.parse_as($rtype)?;
+                        }
+                    )*
+                }
+                Ok(spec)
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::pp::Manifest;
+
+    #[derive(Debug, Default)]
+    struct ProxySpec {
+        port: u16,
+        backends: Vec<String>,
+        timeout: Option<Duration>,
+        headers: IndexMap<String, String>,
+        tls: bool,
+    }
+
+    crate::from_attributes!(ProxySpec, "Proxy" { port, backends, timeout, headers, tls });
+
+    fn spec(source: &str) -> Result<ProxySpec> {
+        let manifest: Manifest = source.parse()?;
+        let Some(crate::parser::pp::PuppetExpr::Resource { attributes, .. }) =
+            manifest.resources().next()
+        else {
+            return Err(anyhow!("Expected a resource"));
+        };
+        ProxySpec::from_attributes(attributes)
+    }
+
+    #[test]
+    fn test_typed_attributes() -> Result<()> {
+        let proxy = spec(
+            "proxy { 'web': port => 8080, backends => ['a', 'b'], timeout => '5m', \
+             headers => { 'X-Forwarded-Proto' => 'https' }, tls => true }",
+        )?;
+        assert_eq!(proxy.port, 8080);
+        assert_eq!(proxy.backends, ["a", "b"]);
+        assert_eq!(proxy.timeout, Some(Duration::from_secs(300)));
+        assert_eq!(proxy.headers["X-Forwarded-Proto"], "https");
+        assert!(proxy.tls);
+
+        let proxy = spec("proxy { 'web': backends => 'a', tls => 'false' }")?;
+        assert_eq!(proxy.port, 0, "Omitted attributes keep their default");
+        assert_eq!(proxy.backends, ["a"], "A single value is an array of one");
+        assert!(!proxy.tls);
+
+        for (source, message) in [
+            (
+                "proxy { 'web': port => 70000 }",
+                "Proxy port must be an integer from 0 to 65535, got 70000",
+            ),
+            (
+                "proxy { 'web': tls => 'yes' }",
+                "Proxy tls must be true or false, got yes",
+            ),
+            (
+                "proxy { 'web': backends => ['a', ['b']] }",
+                "Proxy backends [1] must be a literal, got ['b']",
+            ),
+            (
+                "proxy { 'web': headers => ['a'] }",
+                "Proxy headers must be a hash, got ['a']",
+            ),
+        ] {
+            let Err(e) = spec(source) else {
+                return Err(anyhow!("{source} should fail"));
+            };
+            assert_eq!(e.to_string(), message, "{source}");
+        }
+
+        assert_eq!(
+            vec![Some(8080u16), None].into_value().to_string(),
+            "[8080, undef]"
+        );
+        assert_eq!(Duration::from_millis(250).into_value().to_string(), "250ms");
+        let round_trip = IndexMap::from([("port".to_string(), 443u16)]).into_value();
+        assert_eq!(
+            IndexMap::<String, u16>::from_value(&round_trip)?["port"],
+            443
+        );
+        Ok(())
+    }
+}
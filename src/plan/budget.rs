@@ -0,0 +1,144 @@
+use crate::Plan;
+use anyhow::{Result, anyhow};
+use petgraph::Direction;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Upper limits on plan size, so a runaway manifest fails compilation instead of
+/// generating an unmanageable graph. `None` leaves a measure unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Budget {
+    pub max_resources: Option<usize>,
+    pub max_edges: Option<usize>,
+    /// Resources on the longest dependency chain.
+    pub max_depth: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Overrun {
+    pub measure: &'static str,
+    pub actual: usize,
+    pub max: usize,
+}
+
+impl fmt::Display for Overrun {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} > {}", self.measure, self.actual, self.max)
+    }
+}
+
+impl Budget {
+    /// Only the resource count, which is known before any graph is built.
+    pub fn check_resources(&self, count: usize) -> Result<()> {
+        report(
+            over("resources", count, self.max_resources)
+                .into_iter()
+                .collect(),
+        )
+    }
+
+    pub fn overruns(&self, plan: &Plan) -> Vec<Overrun> {
+        let graph = plan.plan().inner();
+        [
+            over("resources", graph.node_count(), self.max_resources),
+            over("edges", graph.edge_count(), self.max_edges),
+            over("depth", plan.depth(), self.max_depth),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    pub fn check(&self, plan: &Plan) -> Result<()> {
+        report(self.overruns(plan))
+    }
+}
+
+fn over(measure: &'static str, actual: usize, max: Option<usize>) -> Option<Overrun> {
+    max.filter(|max| actual > *max).map(|max| Overrun {
+        measure,
+        actual,
+        max,
+    })
+}
+
+fn report(overruns: Vec<Overrun>) -> Result<()> {
+    if overruns.is_empty() {
+        return Ok(());
+    }
+    let lines: Vec<_> = overruns.iter().map(|o| format!("  {o}")).collect();
+    Err(anyhow!(
+        "Plan exceeds its complexity budget:\n{}",
+        lines.join("\n")
+    ))
+}
+
+impl Plan {
+    /// The number of resources on the longest dependency chain, 0 for an empty plan.
+    pub fn depth(&self) -> usize {
+        let graph = self.graph.inner();
+        let mut depths = HashMap::new();
+        for index in self.sorted().unwrap_or_default() {
+            let depth = graph
+                .neighbors_directed(index, Direction::Incoming)
+                .filter_map(|parent| depths.get(&parent))
+                .max()
+                .map_or(1, |parent| parent + 1);
+            depths.insert(index, depth);
+        }
+        depths.into_values().max().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::pp::Manifest;
+    use crate::{CompileOptions, parse_puppet_manifest, parse_puppet_manifest_with_options};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_budget_reports_every_overrun() -> Result<()> {
+        let manifest = Manifest::from_str(
+            r#"
+            file { "/a": }
+            file { "/b": }
+            file { "/c": }
+            File["/a"] -> File["/b"] -> File["/c"]
+            File["/a"] -> File["/c"]
+            "#,
+        )?;
+        let plan = parse_puppet_manifest(&manifest)?;
+        assert_eq!(plan.depth(), 3);
+
+        let budget = Budget {
+            max_resources: Some(3),
+            max_edges: Some(2),
+            max_depth: Some(2),
+        };
+        let with_budget = |budget| CompileOptions {
+            budget,
+            ..CompileOptions::default()
+        };
+        let Err(e) = parse_puppet_manifest_with_options(&manifest, &with_budget(budget)) else {
+            panic!("The plan is over budget");
+        };
+        assert_eq!(
+            e.to_string(),
+            "Plan exceeds its complexity budget:\n  edges: 3 > 2\n  depth: 3 > 2"
+        );
+
+        let tight = Budget {
+            max_resources: Some(1),
+            ..Budget::default()
+        };
+        let Err(e) = parse_puppet_manifest_with_options(&manifest, &with_budget(tight)) else {
+            panic!("The plan has too many resources");
+        };
+        assert!(e.to_string().contains("resources: 3 > 1"));
+        assert!(
+            parse_puppet_manifest_with_options(&manifest, &with_budget(Budget::default())).is_ok()
+        );
+        Ok(())
+    }
+}
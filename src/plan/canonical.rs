@@ -0,0 +1,73 @@
+use crate::Plan;
+use petgraph::visit::{EdgeRef, IntoEdgeReferences};
+
+impl Plan {
+    /// A sorted, line-oriented description of the plan, stable across runs, meant for
+    /// snapshot tests and reviewing plan changes as text diffs.
+    pub fn to_canonical_text(&self) -> String {
+        let graph = self.graph.inner();
+
+        let mut nodes: Vec<_> = graph
+            .node_weights()
+            .map(|node| format!("node {}", node.id()))
+            .collect();
+        nodes.sort();
+
+        let mut edges: Vec<_> = graph
+            .edge_references()
+            .map(|edge| {
+                format!(
+                    "edge {} {} {}",
+                    graph[edge.source()].id(),
+                    edge.weight(),
+                    graph[edge.target()].id()
+                )
+            })
+            .collect();
+        edges.sort();
+
+        let mut text = String::new();
+        for line in nodes.into_iter().chain(edges) {
+            text.push_str(&line);
+            text.push('\n');
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse_puppet_manifest;
+    use crate::parser::pp::Manifest;
+    use anyhow::Result;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_canonical_text_ignores_declaration_order() -> Result<()> {
+        let first = r#"
+            service { "nginx": }
+            file { "/tmp/b": }
+            file { "/tmp/a": }
+            [File["/tmp/b"], File["/tmp/a"]] ~> Service["nginx"]
+        "#;
+        let second = r#"
+            file { "/tmp/a": }
+            file { "/tmp/b": }
+            service { "nginx": }
+            Service["nginx"] <~ File["/tmp/a"]
+            File["/tmp/b"] ~> Service["nginx"]
+        "#;
+        let first = parse_puppet_manifest(&Manifest::from_str(first)?)?;
+        let second = parse_puppet_manifest(&Manifest::from_str(second)?)?;
+        let expected = "\
+node File[/tmp/a]
+node File[/tmp/b]
+node Service[nginx]
+edge File[/tmp/a] ~> Service[nginx]
+edge File[/tmp/b] ~> Service[nginx]
+";
+        assert_eq!(first.to_canonical_text(), expected);
+        assert_eq!(second.to_canonical_text(), expected);
+        Ok(())
+    }
+}
@@ -0,0 +1,320 @@
+use super::{Provenance, cycle};
+use crate::parser::pp::{AttrValue, Attribute, PuppetExpr, PuppetString, ResourceRef, Span};
+use crate::resources::{Relation, Resource, ResourceRegistry};
+use crate::{Plan, desired_ensure};
+use anyhow::{Context, Result, anyhow};
+use indexmap::IndexMap;
+use petgraph::{
+    acyclic::Acyclic,
+    prelude::StableDiGraph,
+    visit::{EdgeRef, IntoEdgeReferences},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeSet, HashMap};
+
+/// The catalog format written by [`Plan::to_json`]; catalogs of other versions are
+/// refused rather than misread.
+const CATALOG_VERSION: u32 = 1;
+
+/// A compiled plan as data: what an agent needs to apply it without the manifest.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Catalog {
+    version: u32,
+    resources: Vec<CatalogResource>,
+    edges: Vec<CatalogEdge>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CatalogResource {
+    #[serde(rename = "type")]
+    rtype: String,
+    title: String,
+    #[serde(default)]
+    attributes: IndexMap<String, Value>,
+    #[serde(default)]
+    provenance: Provenance,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CatalogEdge {
+    source: String,
+    target: String,
+    relation: Relation,
+}
+
+impl Plan {
+    /// The plan as a JSON catalog: resources in declaration order, with their attributes
+    /// and provenance, and the edges between them by resource id.
+    pub fn to_json(&self) -> Result<String> {
+        let graph = self.graph.inner();
+        let resources = graph
+            .node_indices()
+            .map(|index| {
+                let resource = &graph[index];
+                CatalogResource {
+                    rtype: resource.rtype().to_string(),
+                    title: resource.title(),
+                    attributes: resource
+                        .attributes()
+                        .iter()
+                        .map(|(name, value)| (name.clone(), value_to_json(value)))
+                        .collect(),
+                    provenance: self.provenance.get(&index).cloned().unwrap_or_default(),
+                }
+            })
+            .collect();
+        let edges = graph
+            .edge_references()
+            .map(|edge| CatalogEdge {
+                source: graph[edge.source()].id(),
+                target: graph[edge.target()].id(),
+                relation: edge.weight().clone(),
+            })
+            .collect();
+        let catalog = Catalog {
+            version: CATALOG_VERSION,
+            resources,
+            edges,
+        };
+        Ok(serde_json::to_string_pretty(&catalog)?)
+    }
+
+    /// Rebuilds a plan from a catalog written by [`Plan::to_json`], with the built-in
+    /// resource types.
+    pub fn from_json(json: &str) -> Result<Self> {
+        Self::from_json_with_registry(json, &ResourceRegistry::default())
+    }
+
+    /// Rebuilds a plan from a catalog with the factories of `registry`. Types it does not
+    /// know are taken for defined types, which are all a compiled plan may hold besides
+    /// registered ones.
+    pub fn from_json_with_registry(json: &str, registry: &ResourceRegistry) -> Result<Self> {
+        let catalog: Catalog = serde_json::from_str(json).context("Invalid catalog")?;
+        if catalog.version != CATALOG_VERSION {
+            return Err(anyhow!(
+                "Unsupported catalog version {}, expected {CATALOG_VERSION}",
+                catalog.version
+            ));
+        }
+        let mut registry = registry.clone();
+        let mut graph = StableDiGraph::<Box<dyn Resource>, Relation>::new();
+        let mut index = HashMap::new();
+        let mut concurrency_groups = HashMap::new();
+        let mut ensures = HashMap::new();
+        let mut provenance = HashMap::new();
+        for resource in catalog.resources {
+            if !registry.supports(&resource.rtype) {
+                registry.register_defined(&resource.rtype);
+            }
+            let attributes = resource
+                .attributes
+                .into_iter()
+                .map(|(name, value)| {
+                    Ok(Attribute {
+                        value: value_from_json(value)
+                            .with_context(|| format!("Invalid {name} of {}", resource.title))?,
+                        name,
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let expr = PuppetExpr::Resource {
+                rtype: resource.rtype,
+                title: PuppetString::literal(&resource.title),
+                attributes,
+                span: Span::default(),
+            };
+            let node = registry.build(&expr)?;
+            let id = node.id();
+            if index.contains_key(&id) {
+                return Err(anyhow!(
+                    "Duplicate declaration: {id} is in the catalog twice"
+                ));
+            }
+            let node = graph.add_node(node);
+            if let Some(group) = graph[node].attributes().get("concurrency_group") {
+                concurrency_groups.insert(node, group.to_string());
+            }
+            if let Some(ensure) = desired_ensure(graph[node].as_ref()) {
+                ensures.insert(node, ensure);
+            }
+            index.insert(id, node);
+            provenance.insert(node, resource.provenance);
+        }
+        let mut graph =
+            Acyclic::try_from_graph(graph).map_err(|_| anyhow!("Error creating acyclic graph."))?;
+        for edge in catalog.edges {
+            let endpoint = |id: &str| {
+                index.get(id).copied().ok_or_else(|| {
+                    anyhow!("Edge {} -> {} to unknown {id}", edge.source, edge.target)
+                })
+            };
+            let (source, target) = (endpoint(&edge.source)?, endpoint(&edge.target)?);
+            graph
+                .try_add_edge(source, target, edge.relation.clone())
+                .map_err(|_| {
+                    let back = cycle::path(graph.inner(), target, source).map_or_else(
+                        || edge.source.clone(),
+                        |path| cycle::describe(graph.inner(), &path),
+                    );
+                    anyhow!("Dependency cycle: {} -> {back}", edge.source)
+                })?;
+        }
+        Ok(Plan {
+            graph,
+            index,
+            concurrency_groups,
+            ensures,
+            provenance,
+            facts_used: BTreeSet::new(),
+        })
+    }
+}
+
+/// Attribute values as plain JSON where it has an equivalent: `undef` is `null`, a
+/// resource reference `{"$ref": "File[/a]"}`, a deferred value
+/// `{"$deferred": "file", "args": [...]}` and a sensitive one `{"$sensitive": ...}`.
+fn value_to_json(value: &AttrValue) -> Value {
+    match value {
+        AttrValue::String(s) => Value::String(s.to_string()),
+        AttrValue::Bool(b) => Value::Bool(*b),
+        AttrValue::Integer(i) => Value::from(*i),
+        AttrValue::Array(values) => Value::Array(values.iter().map(value_to_json).collect()),
+        AttrValue::Hash(hash) => Value::Object(
+            hash.iter()
+                .map(|(key, value)| (key.clone(), value_to_json(value)))
+                .collect(),
+        ),
+        AttrValue::Undef => Value::Null,
+        AttrValue::ResourceRef(reference) => Value::Object(Map::from_iter([(
+            "$ref".to_string(),
+            reference.id().into(),
+        )])),
+        AttrValue::Deferred { function, args } => Value::Object(Map::from_iter([
+            ("$deferred".to_string(), function.clone().into()),
+            (
+                "args".to_string(),
+                Value::Array(args.iter().map(value_to_json).collect()),
+            ),
+        ])),
+        AttrValue::Sensitive(value) => Value::Object(Map::from_iter([(
+            "$sensitive".to_string(),
+            value_to_json(value),
+        )])),
+        // Calls are evaluated when the manifest is compiled, so plans have none.
+        AttrValue::Call { .. } => Value::String(value.to_string()),
+    }
+}
+
+fn value_from_json(value: Value) -> Result<AttrValue> {
+    Ok(match value {
+        Value::Null => AttrValue::Undef,
+        Value::Bool(b) => AttrValue::Bool(b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => AttrValue::Integer(i),
+            None => AttrValue::String(PuppetString::literal(&n.to_string())),
+        },
+        Value::String(s) => AttrValue::String(PuppetString::literal(&s)),
+        Value::Array(values) => AttrValue::Array(
+            values
+                .into_iter()
+                .map(value_from_json)
+                .collect::<Result<_>>()?,
+        ),
+        Value::Object(mut object) => {
+            if let Some(Value::String(id)) = object.get("$ref")
+                && object.len() == 1
+            {
+                return Ok(AttrValue::ResourceRef(id.parse::<ResourceRef>()?));
+            }
+            if object.len() == 1
+                && let Some(value) = object.remove("$sensitive")
+            {
+                return Ok(AttrValue::Sensitive(Box::new(value_from_json(value)?)));
+            }
+            if object.len() == 2 && object.contains_key("$deferred") {
+                let (Some(Value::String(function)), Some(Value::Array(args))) =
+                    (object.remove("$deferred"), object.remove("args"))
+                else {
+                    return Err(anyhow!("$deferred needs a function name and args"));
+                };
+                return Ok(AttrValue::Deferred {
+                    function,
+                    args: args
+                        .into_iter()
+                        .map(value_from_json)
+                        .collect::<Result<_>>()?,
+                });
+            }
+            AttrValue::Hash(
+                object
+                    .into_iter()
+                    .map(|(key, value)| Ok((key, value_from_json(value)?)))
+                    .collect::<Result<_>>()?,
+            )
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_puppet_manifest;
+    use crate::parser::pp::Manifest;
+
+    #[test]
+    fn test_catalog_round_trip() -> Result<()> {
+        let input = r#"
+            define app::vhost($port, $options) { }
+            class web {
+                file { "/etc/nginx/nginx.conf":
+                    content => Deferred('file', ['/run/secrets/nginx']),
+                    mode => '0640',
+                    owner => undef,
+                }
+            }
+            include web
+            app::vhost { "site": port => 8080, options => { "tls" => ["1.3", true] } }
+            service { "nginx": concurrency_group => "web", require => App::Vhost["site"] }
+            exec { "reload": command => "/bin/true" }
+            File["/etc/nginx/nginx.conf"] ~> Service["nginx"] -> Exec["reload"]
+        "#;
+        let plan = parse_puppet_manifest(&input.parse::<Manifest>()?)?;
+        let json = plan.to_json()?;
+        let imported = Plan::from_json(&json)?;
+
+        assert_eq!(imported.to_canonical_text(), plan.to_canonical_text());
+        assert_eq!(
+            imported.to_json()?,
+            json,
+            "The catalog survives the round trip"
+        );
+        let nginx = imported
+            .node("Service[nginx]")
+            .ok_or_else(|| anyhow!("The service is in the catalog"))?;
+        assert_eq!(imported.concurrency_group(nginx), Some("web"));
+        let conf = plan
+            .node("File[/etc/nginx/nginx.conf]")
+            .ok_or_else(|| anyhow!("The file is in the plan"))?;
+        let imported_conf = imported
+            .node("File[/etc/nginx/nginx.conf]")
+            .ok_or_else(|| anyhow!("The file is in the catalog"))?;
+        assert_eq!(
+            imported.plan().inner()[imported_conf].attributes(),
+            plan.plan().inner()[conf].attributes()
+        );
+        assert_eq!(
+            imported.provenance.get(&imported_conf),
+            plan.provenance.get(&conf)
+        );
+
+        let Err(e) = Plan::from_json(&json.replacen("\"version\": 1", "\"version\": 2", 1)) else {
+            return Err(anyhow!("Later catalog versions are refused"));
+        };
+        assert_eq!(e.to_string(), "Unsupported catalog version 2, expected 1");
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use crate::Plan;
+use petgraph::{
+    graph::NodeIndex,
+    unionfind::UnionFind,
+    visit::{EdgeRef, IntoEdgeReferences, NodeIndexable},
+};
+use std::collections::BTreeMap;
+
+impl Plan {
+    /// Splits the plan into weakly-connected components: sets of resources with no
+    /// relation to any resource outside the set, which can be applied as independent
+    /// units with separate failure domains. Components and their members are ordered by
+    /// node index.
+    pub fn components(&self) -> Vec<Vec<NodeIndex>> {
+        let graph = self.graph.inner();
+        let mut sets = UnionFind::new(graph.node_bound());
+        for edge in graph.edge_references() {
+            sets.union(graph.to_index(edge.source()), graph.to_index(edge.target()));
+        }
+
+        let mut components: BTreeMap<usize, Vec<NodeIndex>> = BTreeMap::new();
+        for index in graph.node_indices() {
+            components
+                .entry(sets.find(graph.to_index(index)))
+                .or_default()
+                .push(index);
+        }
+        let mut components: Vec<_> = components.into_values().collect();
+        components.sort();
+        components
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse_puppet_manifest;
+    use crate::parser::pp::Manifest;
+    use anyhow::Result;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_components() -> Result<()> {
+        let input = include_str!("../../res/test.pp");
+        let plan = parse_puppet_manifest(&Manifest::from_str(input)?)?;
+        let components: Vec<Vec<String>> = plan
+            .components()
+            .iter()
+            .map(|component| component.iter().map(|i| plan.plan()[*i].id()).collect())
+            .collect();
+        assert_eq!(
+            components,
+            vec![
+                vec![
+                    "Foo::Bar[yo]",
+                    "File[/tmp/one]",
+                    "Service[ssh]",
+                    "Exec[/root/${scripts}/yo.sh]"
+                ],
+                vec![
+                    "File[/tmp/two]",
+                    "File[/tmp/two/three]",
+                    "File[/tmp/two/four]",
+                    "Service[nginx]"
+                ],
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+use crate::{DollyError, Unchecked};
+use petgraph::{Direction, graph::NodeIndex};
+use std::collections::{HashMap, VecDeque};
+
+/// The shortest chain of relations from `from` to `to`, both included.
+pub fn path(graph: &Unchecked, from: NodeIndex, to: NodeIndex) -> Option<Vec<NodeIndex>> {
+    let mut previous = HashMap::from([(from, from)]);
+    let mut queue = VecDeque::from([from]);
+    while let Some(index) = queue.pop_front() {
+        for next in graph.neighbors_directed(index, Direction::Outgoing) {
+            if previous.contains_key(&next) && next != to {
+                continue;
+            }
+            previous.insert(next, index);
+            if next == to {
+                let mut path = vec![to];
+                let mut node = index;
+                while node != from {
+                    path.push(node);
+                    node = previous[&node];
+                }
+                path.push(from);
+                path.reverse();
+                return Some(path);
+            }
+            queue.push_back(next);
+        }
+    }
+    None
+}
+
+/// The shortest cycle through `index`, which starts and ends with it.
+pub fn cycle_through(graph: &Unchecked, index: NodeIndex) -> Option<Vec<NodeIndex>> {
+    path(graph, index, index)
+}
+
+/// `File[/a] -> Service[b] -> File[/a]`
+pub fn describe(graph: &Unchecked, cycle: &[NodeIndex]) -> String {
+    cycle
+        .iter()
+        .map(|index| graph[*index].id())
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
+/// The error for a graph with a cycle through `index`, naming the resources on it.
+pub fn cycle_error(graph: &Unchecked, index: NodeIndex) -> anyhow::Error {
+    let path = match cycle_through(graph, index) {
+        Some(cycle) => cycle.iter().map(|index| graph[*index].id()).collect(),
+        None => vec![graph[index].id()],
+    };
+    DollyError::CycleDetected {
+        path,
+        closed_by: None,
+    }
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse_puppet_manifest;
+    use crate::parser::pp::Manifest;
+    use anyhow::{Result, anyhow};
+
+    #[test]
+    fn test_cycles_are_reported_with_their_path() -> Result<()> {
+        let input = r#"
+            file { "/a": }
+            file { "/b": }
+            service { "nginx": }
+            File["/a"] -> File["/b"] ~> Service["nginx"]
+            Service["nginx"] -> File["/a"]
+        "#;
+        let Err(e) = parse_puppet_manifest(&input.parse::<Manifest>()?) else {
+            return Err(anyhow!("The relations form a cycle"));
+        };
+        assert_eq!(
+            e.to_string(),
+            "Dependency cycle: Service[nginx] -> File[/a] -> File[/b] -> Service[nginx] \
+             (closed by Service[nginx] -> File[/a])"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+use crate::Plan;
+use crate::resources::Relation;
+use petgraph::visit::{EdgeRef, IntoEdgeReferences};
+use std::collections::BTreeMap;
+
+impl Plan {
+    /// Renders the plan in the D2 diagram language. Resources of namespaced types are
+    /// grouped into a container per module (`Foo::Bar[x]` goes in `foo`), and notify
+    /// edges are drawn dashed so refresh paths stand out from plain ordering.
+    pub fn to_d2(&self) -> String {
+        let graph = self.graph.inner();
+
+        let mut modules: BTreeMap<Option<String>, Vec<String>> = BTreeMap::new();
+        for node in graph.node_weights() {
+            modules
+                .entry(module_of(node.rtype()))
+                .or_default()
+                .push(node.id());
+        }
+
+        let mut text = String::from("direction: down\n");
+        for (module, mut ids) in modules {
+            ids.sort();
+            match module {
+                None => {
+                    for id in ids {
+                        text.push_str(&format!("{}\n", quote(&id)));
+                    }
+                }
+                Some(module) => {
+                    text.push_str(&format!("{}: {{\n", quote(&module)));
+                    for id in ids {
+                        text.push_str(&format!("  {}\n", quote(&id)));
+                    }
+                    text.push_str("}\n");
+                }
+            }
+        }
+
+        let mut edges: Vec<_> = graph
+            .edge_references()
+            .map(|edge| {
+                let from = key(graph[edge.source()].rtype(), &graph[edge.source()].id());
+                let to = key(graph[edge.target()].rtype(), &graph[edge.target()].id());
+                match edge.weight() {
+                    Relation::Provide => format!("{from} -> {to}\n"),
+                    Relation::Notify => format!(
+                        "{from} -> {to}: notify {{\n  style.stroke-dash: 3\n  style.stroke: \"#d9480f\"\n}}\n"
+                    ),
+                }
+            })
+            .collect();
+        edges.sort();
+        text.extend(edges);
+        text
+    }
+}
+
+fn module_of(rtype: &str) -> Option<String> {
+    rtype
+        .split_once("::")
+        .map(|(module, _)| module.to_lowercase())
+}
+
+/// The D2 path of a resource, including its module container.
+fn key(rtype: &str, id: &str) -> String {
+    match module_of(rtype) {
+        Some(module) => format!("{}.{}", quote(&module), quote(id)),
+        None => quote(id),
+    }
+}
+
+fn quote(key: &str) -> String {
+    format!("\"{}\"", key.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse_puppet_manifest;
+    use crate::parser::pp::Manifest;
+    use anyhow::Result;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_d2_containers_and_styles() -> Result<()> {
+        let manifest = Manifest::from_str(
+            r#"
+            file { "/etc/foo.conf": }
+            foo::bar { "baz": }
+            service { "foo": }
+            File["/etc/foo.conf"] -> Foo::Bar["baz"] ~> Service["foo"]
+            "#,
+        )?;
+        let plan = parse_puppet_manifest(&manifest)?;
+        let expected = r##"direction: down
+"File[/etc/foo.conf]"
+"Service[foo]"
+"foo": {
+  "Foo::Bar[baz]"
+}
+"File[/etc/foo.conf]" -> "foo"."Foo::Bar[baz]"
+"foo"."Foo::Bar[baz]" -> "Service[foo]": notify {
+  style.stroke-dash: 3
+  style.stroke: "#d9480f"
+}
+"##;
+        assert_eq!(plan.to_d2(), expected);
+        Ok(())
+    }
+}
@@ -0,0 +1,279 @@
+use crate::parser::pp::{Attribute, Manifest, PuppetExpr, Span, normalize_rtype};
+use crate::parser::value::FromValue;
+use crate::resources::FileMode;
+use anyhow::{Result, anyhow};
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+/// A construct forbidden at compile time. Parsed from:
+///
+/// - `Exec`: no resources of that type at all
+/// - `File.mode=*7`: no attribute value matching the glob (`*` and `?`)
+/// - `File outside /etc,/opt`: no titles outside the listed path prefixes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Deny {
+    Type(String),
+    Attribute {
+        rtype: String,
+        attribute: String,
+        pattern: String,
+    },
+    TitleOutside {
+        rtype: String,
+        prefixes: Vec<String>,
+    },
+}
+
+impl FromStr for Deny {
+    type Err = anyhow::Error;
+    fn from_str(rule: &str) -> Result<Self> {
+        let rule = rule.trim();
+        if let Some((rtype, prefixes)) = rule.split_once(" outside ") {
+            return Ok(Self::TitleOutside {
+                rtype: normalize_rtype(rtype.trim()),
+                prefixes: prefixes.split(',').map(|p| p.trim().to_string()).collect(),
+            });
+        }
+        if let Some((target, pattern)) = rule.split_once('=') {
+            let Some((rtype, attribute)) = target.rsplit_once('.') else {
+                return Err(anyhow!(
+                    "Invalid deny rule '{rule}', expected Type.attribute=pattern"
+                ));
+            };
+            return Ok(Self::Attribute {
+                rtype: normalize_rtype(rtype.trim()),
+                attribute: attribute.trim().to_string(),
+                pattern: pattern.trim().to_string(),
+            });
+        }
+        if rule.is_empty() || rule.contains(char::is_whitespace) {
+            return Err(anyhow!("Invalid deny rule '{rule}'"));
+        }
+        Ok(Self::Type(normalize_rtype(rule)))
+    }
+}
+
+impl fmt::Display for Deny {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Type(rtype) => write!(f, "{rtype}"),
+            Self::Attribute {
+                rtype,
+                attribute,
+                pattern,
+            } => write!(f, "{rtype}.{attribute}={pattern}"),
+            Self::TitleOutside { rtype, prefixes } => {
+                write!(f, "{rtype} outside {}", prefixes.join(","))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyViolation {
+    pub id: String,
+    pub span: Span,
+    pub rule: Deny,
+}
+
+impl fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at {} is denied by '{}'",
+            self.id, self.span, self.rule
+        )
+    }
+}
+
+/// Every resource in `manifest` that one of `rules` forbids, in manifest order.
+pub fn policy_violations(manifest: &Manifest, rules: &[Deny]) -> Vec<PolicyViolation> {
+    let mut violations = Vec::new();
+    for expr in manifest.resources() {
+        let PuppetExpr::Resource {
+            rtype,
+            title,
+            attributes,
+            span,
+        } = expr
+        else {
+            continue;
+        };
+        let title = title.as_literal().unwrap_or_else(|| title.to_string());
+        for rule in rules {
+            let denied = match rule {
+                Deny::Type(denied) => denied == rtype,
+                Deny::Attribute {
+                    rtype: denied,
+                    attribute,
+                    pattern,
+                } => {
+                    denied == rtype
+                        && attributes
+                            .iter()
+                            .filter(|attr| &attr.name == attribute)
+                            .any(|attr| spellings(attr).iter().any(|text| glob(pattern, text)))
+                }
+                Deny::TitleOutside {
+                    rtype: denied,
+                    prefixes,
+                } => {
+                    denied == rtype
+                        && !prefixes
+                            .iter()
+                            .any(|prefix| lexical(&title).starts_with(lexical(prefix)))
+                }
+            };
+            if denied {
+                violations.push(PolicyViolation {
+                    id: format!("{rtype}[{title}]"),
+                    span: *span,
+                    rule: rule.clone(),
+                });
+            }
+        }
+    }
+    violations
+}
+
+/// The texts an attribute rule matches the value of `attr` against: as written and, for a
+/// mode, in octal, since an unquoted `0777` is the integer 511.
+fn spellings(attr: &Attribute) -> Vec<String> {
+    let mut spellings = vec![attr.value.to_string()];
+    if attr.name == "mode"
+        && let Ok(mode) = FileMode::from_value(&attr.value)
+    {
+        spellings.push(mode.to_string());
+    }
+    spellings
+}
+
+/// `path` with `.` and `..` resolved without reading the filesystem, so that a title like
+/// `/etc/../root` is not inside `/etc`.
+fn lexical(path: &str) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Matches `*` (any run of characters) and `?` (one character).
+pub(crate) fn glob(pattern: &str, text: &str) -> bool {
+    let (pattern, text): (Vec<char>, Vec<char>) =
+        (pattern.chars().collect(), text.chars().collect());
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CompileOptions, parse_puppet_manifest_with_options};
+
+    #[test]
+    fn test_deny_rules() -> Result<()> {
+        let manifest = Manifest::from_str(
+            r#"
+            file { "/etc/app.conf": mode => "0644" }
+            file { "/etcetera": mode => "0777" }
+            file { "/opt/app": }
+            exec { "curl | sh": }
+            "#,
+        )?;
+        let rules: Vec<Deny> = ["exec", "File.mode=*7", "File outside /etc, /opt"]
+            .iter()
+            .map(|rule| rule.parse())
+            .collect::<Result<_>>()?;
+        let violations: Vec<_> = policy_violations(&manifest, &rules)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            violations,
+            vec![
+                "File[/etcetera] at 3:13 is denied by 'File.mode=*7'",
+                "File[/etcetera] at 3:13 is denied by 'File outside /etc,/opt'",
+                "Exec[curl | sh] at 5:13 is denied by 'Exec'",
+            ]
+        );
+
+        let options = CompileOptions {
+            deny: rules,
+            ..CompileOptions::default()
+        };
+        let Err(e) = parse_puppet_manifest_with_options(&manifest, &options) else {
+            panic!("Denied constructs fail compilation");
+        };
+        assert!(
+            e.to_string()
+                .starts_with("Policy violations:\n  File[/etcetera]")
+        );
+        assert!("two words".parse::<Deny>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_deny_rules_see_through_spellings() -> Result<()> {
+        let manifest = Manifest::from_str(
+            r#"
+            file { "/etc/app.conf": mode => 0777 }
+            file { "/etc/../root/.ssh/authorized_keys": }
+            file { "/etc/./nginx/../motd": }
+            "#,
+        )?;
+        let rules: Vec<Deny> = ["File.mode=*7", "File outside /etc"]
+            .iter()
+            .map(|rule| rule.parse())
+            .collect::<Result<_>>()?;
+        let violations: Vec<_> = policy_violations(&manifest, &rules)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            violations,
+            vec![
+                "File[/etc/app.conf] at 2:13 is denied by 'File.mode=*7'",
+                "File[/etc/../root/.ssh/authorized_keys] at 3:13 is denied by 'File outside /etc'",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_glob() {
+        assert!(glob("*7", "0777"));
+        assert!(glob("0?44", "0644"));
+        assert!(glob("*/.ssh/*", "/root/.ssh/authorized_keys"));
+        assert!(!glob("*7", "0644"));
+        assert!(!glob("0?4", "0644"));
+    }
+}
@@ -0,0 +1,288 @@
+use super::{Origin, Provenance, cycle};
+use crate::Plan;
+use crate::resources::{Attributes, Imported, Relation, Resource};
+use anyhow::{Result, anyhow};
+use indexmap::{IndexMap, IndexSet};
+use petgraph::{acyclic::Acyclic, prelude::StableDiGraph};
+use std::collections::{BTreeSet, HashMap};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Id(String),
+    Arrow,
+    Punct(char),
+}
+
+impl Plan {
+    /// Best-effort import of a graph written by `puppet agent --graph`, e.g.
+    /// `relationships.dot`. Every `Type[title]` node becomes an [`Imported`] resource;
+    /// Puppet's internal `Whit` sentinels are dropped and their edges bridged. Edges whose
+    /// label mentions a refresh become notify relations.
+    pub fn from_puppet_dot(dot: &str) -> Result<Self> {
+        let tokens = tokenize(dot)?;
+        let mut name = String::from("graph");
+        let mut nodes: IndexSet<String> = IndexSet::new();
+        let mut edges: IndexMap<(String, String), Relation> = IndexMap::new();
+
+        let mut i = tokens
+            .iter()
+            .position(|t| *t == Token::Punct('{'))
+            .map_or(0, |p| p + 1);
+        if let Some(Token::Id(graph_name)) = i.checked_sub(2).and_then(|p| tokens.get(p)) {
+            name = graph_name.clone();
+        }
+        while i < tokens.len() {
+            let Token::Id(id) = &tokens[i] else {
+                i += 1;
+                continue;
+            };
+            i += 1;
+            if tokens.get(i) == Some(&Token::Punct('=')) {
+                if id == "label"
+                    && let Some(Token::Id(label)) = tokens.get(i + 1)
+                {
+                    name = label.clone();
+                }
+                i += 2;
+                continue;
+            }
+            if ["node", "edge", "graph", "subgraph", "digraph", "strict"].contains(&id.as_str()) {
+                i = skip_attributes(&tokens, i).1;
+                continue;
+            }
+
+            let mut chain = vec![id.clone()];
+            while tokens.get(i) == Some(&Token::Arrow) {
+                let Some(Token::Id(next)) = tokens.get(i + 1) else {
+                    return Err(anyhow!(
+                        "Edge without a target after {}",
+                        chain.join(" -> ")
+                    ));
+                };
+                chain.push(next.clone());
+                i += 2;
+            }
+            let (attributes, next) = skip_attributes(&tokens, i);
+            i = next;
+            let relation = match attributes.iter().any(|(key, value)| {
+                ["label", "callback"].contains(&key.as_str())
+                    && value.to_lowercase().contains("refresh")
+            }) {
+                true => Relation::Notify,
+                false => Relation::Provide,
+            };
+            nodes.extend(chain.iter().cloned());
+            for pair in chain.windows(2) {
+                edges.insert((pair[0].clone(), pair[1].clone()), relation.clone());
+            }
+        }
+
+        bridge_whits(&mut nodes, &mut edges);
+        build(&name, &nodes, &edges)
+    }
+}
+
+fn is_whit(id: &str) -> bool {
+    ["Whit[", "Admissible_", "Completed_"]
+        .iter()
+        .any(|prefix| id.starts_with(prefix))
+}
+
+/// Removes whit nodes, connecting each of their predecessors to each of their successors.
+fn bridge_whits(nodes: &mut IndexSet<String>, edges: &mut IndexMap<(String, String), Relation>) {
+    let whits: Vec<String> = nodes.iter().filter(|id| is_whit(id)).cloned().collect();
+    for whit in whits {
+        let incoming: Vec<String> = edges
+            .keys()
+            .filter(|(_, to)| *to == whit)
+            .map(|(from, _)| from.clone())
+            .collect();
+        let outgoing: Vec<String> = edges
+            .keys()
+            .filter(|(from, _)| *from == whit)
+            .map(|(_, to)| to.clone())
+            .collect();
+        edges.retain(|(from, to), _| *from != whit && *to != whit);
+        for from in &incoming {
+            for to in &outgoing {
+                if from != to {
+                    edges
+                        .entry((from.clone(), to.clone()))
+                        .or_insert(Relation::Provide);
+                }
+            }
+        }
+        nodes.shift_remove(&whit);
+    }
+}
+
+fn build(
+    name: &str,
+    nodes: &IndexSet<String>,
+    edges: &IndexMap<(String, String), Relation>,
+) -> Result<Plan> {
+    let mut graph = StableDiGraph::<Box<dyn Resource>, Relation>::new();
+    let mut index = HashMap::new();
+    let mut provenance = HashMap::new();
+    for id in nodes {
+        let (rtype, title) = id
+            .strip_suffix(']')
+            .and_then(|id| id.split_once('['))
+            .ok_or_else(|| anyhow!("Node {id} is not a Type[title] resource"))?;
+        let resource = Imported {
+            rtype: rtype.to_string(),
+            title: title.to_string(),
+            attributes: Attributes::new(),
+        };
+        let node = graph.add_node(Box::new(resource));
+        index.insert(id.clone(), node);
+        provenance.insert(
+            node,
+            Provenance(vec![Origin::Imported {
+                graph: name.to_string(),
+            }]),
+        );
+    }
+    let mut graph =
+        Acyclic::try_from_graph(graph).map_err(|_| anyhow!("Error creating acyclic graph."))?;
+    for ((from, to), relation) in edges {
+        graph
+            .try_add_edge(index[from], index[to], relation.clone())
+            .map_err(|_| {
+                let back = cycle::path(graph.inner(), index[to], index[from]).map_or_else(
+                    || from.clone(),
+                    |path| cycle::describe(graph.inner(), &path),
+                );
+                anyhow!("Imported graph has a cycle: {from} -> {back}")
+            })?;
+    }
+    Ok(Plan {
+        graph,
+        index,
+        concurrency_groups: HashMap::new(),
+        ensures: HashMap::new(),
+        provenance,
+        facts_used: BTreeSet::new(),
+    })
+}
+
+/// Reads an optional `[key = value, ...]` list at `i`, returning it and the index after it.
+fn skip_attributes(tokens: &[Token], mut i: usize) -> (Vec<(String, String)>, usize) {
+    let mut attributes = Vec::new();
+    if tokens.get(i) != Some(&Token::Punct('[')) {
+        return (attributes, i);
+    }
+    i += 1;
+    while i < tokens.len() && tokens[i] != Token::Punct(']') {
+        if let (Token::Id(key), Some(Token::Punct('=')), Some(Token::Id(value))) =
+            (&tokens[i], tokens.get(i + 1), tokens.get(i + 2))
+        {
+            attributes.push((key.clone(), value.clone()));
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+    (attributes, i + 1)
+}
+
+fn tokenize(dot: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = dot.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '#' || (c == '/' && next == Some('/')) {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && next == Some('*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                i += 1;
+            }
+            i += 2;
+        } else if c == '"' {
+            let mut value = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err(anyhow!("Unterminated string in DOT input")),
+                    Some('"') => break,
+                    Some('\\') if chars.get(i + 1) == Some(&'"') => {
+                        value.push('"');
+                        i += 1;
+                    }
+                    Some(c) => value.push(*c),
+                }
+                i += 1;
+            }
+            i += 1;
+            tokens.push(Token::Id(value));
+        } else if c == '-' && (next == Some('>') || next == Some('-')) {
+            tokens.push(Token::Arrow);
+            i += 2;
+        } else if c.is_alphanumeric() || c == '_' || c == '.' || c == '-' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || "_.-".contains(chars[i])) {
+                i += 1;
+            }
+            tokens.push(Token::Id(chars[start..i].iter().collect()));
+        } else {
+            tokens.push(Token::Punct(c));
+            i += 1;
+        }
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_import_puppet_relationships_dot() -> Result<()> {
+        let dot = r#"
+digraph Relationships {
+    label = "Relationships"
+    "Stage[main]" [
+        fontsize = 8,
+        label = "Stage[main]"
+    ]
+    "Whit[Admissible_class[Main]]" [ fontsize = 8, label = "Whit[Admissible_class[Main]]" ]
+    "Package[nginx]" [ fontsize = 8, label = "Package[nginx]" ]
+    "File[/etc/nginx/nginx.conf]" [ fontsize = 8, label = "File[/etc/nginx/nginx.conf]" ]
+    "Service[nginx]" [ fontsize = 8, label = "Service[nginx]" ]
+    "Stage[main]" -> "Whit[Admissible_class[Main]]" [ fontsize = 8, label = "{}" ]
+    "Whit[Admissible_class[Main]]" -> "Package[nginx]" [ fontsize = 8 ]
+    "Package[nginx]" -> "File[/etc/nginx/nginx.conf]" [ fontsize = 8 ]
+    "File[/etc/nginx/nginx.conf]" -> "Service[nginx]" [
+        fontsize = 8,
+        label = "{callback => refresh, event => ALL_EVENTS}"
+    ]
+}
+"#;
+        let plan = Plan::from_puppet_dot(dot)?;
+        assert_eq!(
+            plan.to_canonical_text(),
+            "node File[/etc/nginx/nginx.conf]\n\
+             node Package[nginx]\n\
+             node Service[nginx]\n\
+             node Stage[main]\n\
+             edge File[/etc/nginx/nginx.conf] ~> Service[nginx]\n\
+             edge Package[nginx] -> File[/etc/nginx/nginx.conf]\n\
+             edge Stage[main] -> Package[nginx]\n"
+        );
+        assert_eq!(
+            plan.provenance("Package[nginx]").map(ToString::to_string),
+            Some("imported from graph Relationships".to_string())
+        );
+
+        let cyclic = r#"digraph { "File[/a]" -> "File[/b]" -> "File[/a]" }"#;
+        assert!(Plan::from_puppet_dot(cyclic).is_err());
+        Ok(())
+    }
+}
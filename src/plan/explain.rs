@@ -0,0 +1,181 @@
+use super::Provenance;
+use crate::Plan;
+use crate::cache::{LastApply, StateCache};
+use crate::resources::{Attributes, Relation};
+use anyhow::{Result, anyhow};
+use petgraph::{
+    Direction,
+    graph::NodeIndex,
+    visit::{Bfs, EdgeRef, Reversed, Walker},
+};
+use std::fmt;
+
+/// Everything the plan knows about a single resource.
+#[derive(Debug)]
+pub struct Explanation {
+    pub id: String,
+    pub rtype: String,
+    pub title: String,
+    pub attributes: Attributes,
+    pub provenance: Provenance,
+    /// As Puppet tags it in a catalog.
+    pub tags: Vec<String>,
+    /// How it fared in the last run recorded in the state cache.
+    pub last_applied: Option<LastApply>,
+    /// Resources applied directly before this one, with the relation kind.
+    pub dependencies: Vec<(String, Relation)>,
+    /// Resources applied directly after this one, with the relation kind.
+    pub dependents: Vec<(String, Relation)>,
+    pub transitive_dependencies: Vec<String>,
+    pub transitive_dependents: Vec<String>,
+}
+
+impl Plan {
+    pub fn explain(&self, id: &str) -> Result<Explanation> {
+        self.explain_with_state(id, &StateCache::default())
+    }
+
+    /// Explains `id` along with its outcome in the last run recorded in `state`.
+    pub fn explain_with_state(&self, id: &str, state: &StateCache) -> Result<Explanation> {
+        let Some(index) = self.node(id) else {
+            return Err(anyhow!("Unknown resource: {id}"));
+        };
+        let graph = self.graph.inner();
+        let Some(node) = graph.node_weight(index) else {
+            return Err(anyhow!("Node without weight"));
+        };
+
+        let dependencies = graph
+            .edges_directed(index, Direction::Incoming)
+            .map(|edge| (self.id_of(edge.source()), edge.weight().clone()))
+            .collect();
+        let dependents = graph
+            .edges_directed(index, Direction::Outgoing)
+            .map(|edge| (self.id_of(edge.target()), edge.weight().clone()))
+            .collect();
+
+        let transitive_dependencies = Bfs::new(Reversed(graph), index)
+            .iter(Reversed(graph))
+            .filter(|other| *other != index)
+            .map(|other| self.id_of(other))
+            .collect();
+        let transitive_dependents = Bfs::new(graph, index)
+            .iter(graph)
+            .filter(|other| *other != index)
+            .map(|other| self.id_of(other))
+            .collect();
+
+        Ok(Explanation {
+            id: node.id(),
+            rtype: node.rtype().to_string(),
+            title: node.title(),
+            attributes: node.attributes().clone(),
+            provenance: self.provenance(id).cloned().unwrap_or_default(),
+            tags: self.tags(index),
+            last_applied: state.last_applied.get(id).cloned(),
+            dependencies,
+            dependents,
+            transitive_dependencies,
+            transitive_dependents,
+        })
+    }
+
+    fn id_of(&self, index: NodeIndex) -> String {
+        self.graph
+            .inner()
+            .node_weight(index)
+            .map(|node| node.id())
+            .unwrap_or_default()
+    }
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.id)?;
+        writeln!(f, "  type:  {}", self.rtype)?;
+        writeln!(f, "  title: {}", self.title)?;
+        writeln!(f, "  origin: {}", self.provenance)?;
+        writeln!(f, "  tags:  {}", self.tags.join(", "))?;
+        match &self.last_applied {
+            Some(last) => writeln!(f, "  last applied: {last}")?,
+            None => writeln!(f, "  last applied: never")?,
+        }
+        writeln!(f, "  attributes:")?;
+        for (name, value) in &self.attributes {
+            writeln!(f, "    {name} => {value}")?;
+        }
+        writeln!(f, "  dependencies:")?;
+        for (id, relation) in &self.dependencies {
+            writeln!(f, "    {id} {relation}")?;
+        }
+        writeln!(f, "  dependents:")?;
+        for (id, relation) in &self.dependents {
+            writeln!(f, "    {relation} {id}")?;
+        }
+        writeln!(f, "  transitive dependencies:")?;
+        for id in &self.transitive_dependencies {
+            writeln!(f, "    {id}")?;
+        }
+        writeln!(f, "  transitive dependents:")?;
+        for id in &self.transitive_dependents {
+            writeln!(f, "    {id}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::apply::{ApplyOptions, Outcome};
+    use crate::cache::StateCache;
+    use crate::parse_puppet_manifest;
+    use crate::parser::pp::Manifest;
+    use crate::testing::FakeSystem;
+    use anyhow::Result;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_explain_chain() -> Result<()> {
+        let input = r#"
+            file { "/tmp/one": }
+            file { "/tmp/two": mode => "0644" }
+            service { "nginx": }
+            File["/tmp/one"] -> File["/tmp/two"] ~> Service["nginx"]
+        "#;
+        let plan = parse_puppet_manifest(&Manifest::from_str(input)?)?;
+        let explanation = plan.explain("File[/tmp/two]")?;
+        assert_eq!(explanation.rtype, "File");
+        assert_eq!(explanation.title, "/tmp/two");
+        assert!(explanation.to_string().contains("    mode => 0644\n"));
+        assert_eq!(explanation.dependencies.len(), 1);
+        assert_eq!(explanation.dependencies[0].0, "File[/tmp/one]");
+        assert_eq!(explanation.dependents[0].0, "Service[nginx]");
+
+        let explanation = plan.explain("Service[nginx]")?;
+        assert_eq!(
+            explanation.transitive_dependencies.len(),
+            2,
+            "Both files are applied before nginx"
+        );
+        assert!(explanation.transitive_dependents.is_empty());
+        assert_eq!(explanation.tags, ["service", "nginx"]);
+        assert!(explanation.to_string().contains("  last applied: never\n"));
+
+        let plan = parse_puppet_manifest(&Manifest::from_str(r#"exec { "/bin/false": }"#)?)?;
+        let fake = FakeSystem::default();
+        let report = plan.apply(ApplyOptions {
+            system: fake.system(),
+            ..ApplyOptions::default()
+        })?;
+        let mut state = StateCache::default();
+        state.record(&plan, &report);
+        let explanation = plan.explain_with_state("Exec[/bin/false]", &state)?;
+        assert_eq!(
+            explanation.last_applied.map(|last| last.outcome),
+            Some(Outcome::Failed)
+        );
+
+        assert!(plan.explain("Service[missing]").is_err());
+        Ok(())
+    }
+}
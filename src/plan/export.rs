@@ -0,0 +1,156 @@
+use crate::Plan;
+use crate::resources::Relation;
+use petgraph::visit::{EdgeRef, IntoEdgeReferences};
+use serde_json::{Map, Value, json};
+
+impl Plan {
+    /// Renders the plan as a Mermaid flowchart, for embedding in Markdown. Nodes are named
+    /// `n<index>` and labelled with the resource id; notify edges are dotted.
+    pub fn mermaid(&self) -> String {
+        let graph = self.graph.inner();
+        let mut text = String::from("flowchart TD\n");
+        for index in graph.node_indices() {
+            text.push_str(&format!(
+                "    n{}[\"{}\"]\n",
+                index.index(),
+                graph[index].id().replace('"', "#quot;")
+            ));
+        }
+        for edge in graph.edge_references() {
+            let arrow = match edge.weight() {
+                Relation::Provide => "-->",
+                Relation::Notify => "-.->",
+            };
+            text.push_str(&format!(
+                "    n{} {arrow} n{}\n",
+                edge.source().index(),
+                edge.target().index()
+            ));
+        }
+        text
+    }
+
+    /// Renders the plan as GraphML, which yEd, Gephi and networkx read. Nodes have the
+    /// resource type and title as data, edges their relation.
+    pub fn graphml(&self) -> String {
+        let graph = self.graph.inner();
+        let mut text = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n\
+             \x20 <key id=\"type\" for=\"node\" attr.name=\"type\" attr.type=\"string\"/>\n\
+             \x20 <key id=\"title\" for=\"node\" attr.name=\"title\" attr.type=\"string\"/>\n\
+             \x20 <key id=\"relation\" for=\"edge\" attr.name=\"relation\" attr.type=\"string\"/>\n\
+             \x20 <graph id=\"plan\" edgedefault=\"directed\">\n",
+        );
+        for node in graph.node_weights() {
+            text.push_str(&format!(
+                "    <node id=\"{}\">\n      <data key=\"type\">{}</data>\n      \
+                 <data key=\"title\">{}</data>\n    </node>\n",
+                escape(&node.id()),
+                escape(node.rtype()),
+                escape(&node.title())
+            ));
+        }
+        for edge in graph.edge_references() {
+            text.push_str(&format!(
+                "    <edge source=\"{}\" target=\"{}\">\n      \
+                 <data key=\"relation\">{}</data>\n    </edge>\n",
+                escape(&graph[edge.source()].id()),
+                escape(&graph[edge.target()].id()),
+                relation_name(edge.weight())
+            ));
+        }
+        text.push_str("  </graph>\n</graphml>\n");
+        text
+    }
+
+    /// The plan in the JSON Graph Format: nodes keyed by resource id with their type and
+    /// title as metadata, and edges labelled with their relation. Unlike
+    /// [`Plan::to_json`], it only describes the graph and cannot be applied.
+    pub fn json_graph(&self) -> String {
+        let graph = self.graph.inner();
+        let nodes: Map<String, Value> = graph
+            .node_weights()
+            .map(|node| {
+                let metadata = json!({"type": node.rtype(), "title": node.title()});
+                (node.id(), json!({"label": node.id(), "metadata": metadata}))
+            })
+            .collect();
+        let edges: Vec<_> = graph
+            .edge_references()
+            .map(|edge| {
+                json!({
+                    "source": graph[edge.source()].id(),
+                    "target": graph[edge.target()].id(),
+                    "relation": relation_name(edge.weight()),
+                })
+            })
+            .collect();
+        let document = json!({"graph": {"directed": true, "nodes": nodes, "edges": edges}});
+        serde_json::to_string_pretty(&document).unwrap_or_default()
+    }
+}
+
+fn relation_name(relation: &Relation) -> &'static str {
+    match relation {
+        Relation::Provide => "provide",
+        Relation::Notify => "notify",
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse_puppet_manifest;
+    use crate::parser::pp::Manifest;
+    use anyhow::Result;
+    use serde_json::{Value, json};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_graph_exports() -> Result<()> {
+        let manifest = Manifest::from_str(
+            r#"
+            file { "/etc/foo.conf": }
+            service { "foo": }
+            exec { 'echo "<done>"': }
+            File["/etc/foo.conf"] ~> Service["foo"] -> Exec['echo "<done>"']
+            "#,
+        )?;
+        let plan = parse_puppet_manifest(&manifest)?;
+
+        assert_eq!(
+            plan.mermaid(),
+            "flowchart TD\n    \
+             n0[\"File[/etc/foo.conf]\"]\n    \
+             n1[\"Service[foo]\"]\n    \
+             n2[\"Exec[echo #quot;<done>#quot;]\"]\n    \
+             n0 -.-> n1\n    \
+             n1 --> n2\n"
+        );
+
+        let graphml = plan.graphml();
+        assert!(graphml.contains("<node id=\"Exec[echo &quot;&lt;done&gt;&quot;]\">"));
+        assert!(graphml.contains(
+            "<edge source=\"File[/etc/foo.conf]\" target=\"Service[foo]\">\n      \
+             <data key=\"relation\">notify</data>"
+        ));
+
+        let graph: Value = serde_json::from_str(&plan.json_graph())?;
+        assert_eq!(
+            graph["graph"]["nodes"]["Service[foo]"]["metadata"],
+            json!({"type": "Service", "title": "foo"})
+        );
+        assert_eq!(
+            graph["graph"]["edges"][0],
+            json!({"source": "File[/etc/foo.conf]", "target": "Service[foo]", "relation": "notify"})
+        );
+        Ok(())
+    }
+}
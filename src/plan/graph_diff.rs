@@ -0,0 +1,244 @@
+use crate::parser::pp::{Manifest, PuppetExpr};
+use crate::resources::Resource;
+use crate::{Plan, parse_puppet_manifest};
+use anyhow::Result;
+use petgraph::visit::{EdgeRef, IntoEdgeReferences};
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Added,
+    Removed,
+    /// Present in both plans with different attributes. Only nodes can change.
+    Changed,
+    Unchanged,
+}
+
+impl Change {
+    fn dot_node_style(self) -> &'static str {
+        match self {
+            Self::Added => r#" style = "filled" fillcolor = "palegreen""#,
+            Self::Removed => r#" style = "filled" fillcolor = "lightcoral""#,
+            Self::Changed => r#" style = "filled" fillcolor = "yellow""#,
+            Self::Unchanged => "",
+        }
+    }
+
+    fn dot_edge_style(self) -> &'static str {
+        match self {
+            Self::Added => r#" color = "green""#,
+            Self::Removed => r#" color = "red""#,
+            Self::Changed | Self::Unchanged => "",
+        }
+    }
+
+    fn mermaid_class(self) -> Option<&'static str> {
+        match self {
+            Self::Added => Some("added"),
+            Self::Removed => Some("removed"),
+            Self::Changed => Some("changed"),
+            Self::Unchanged => None,
+        }
+    }
+}
+
+/// The union of two plans' graphs, with every node and edge marked by how it changed,
+/// for reviewing infrastructure changes visually.
+#[derive(Debug)]
+pub struct GraphDiff {
+    /// Resource ids in sorted order.
+    pub nodes: Vec<(String, Change)>,
+    /// `(from, relation, to)` by resource id, in sorted order.
+    pub edges: Vec<(String, String, String, Change)>,
+}
+
+impl GraphDiff {
+    pub fn between(old: &Manifest, new: &Manifest) -> Result<Self> {
+        let (old_plan, new_plan) = (parse_puppet_manifest(old)?, parse_puppet_manifest(new)?);
+        let (old_attrs, new_attrs) = (attributes(old)?, attributes(new)?);
+
+        let nodes = old_attrs
+            .keys()
+            .chain(new_attrs.keys())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .map(|id| {
+                let change = match (old_attrs.get(id), new_attrs.get(id)) {
+                    (None, _) => Change::Added,
+                    (_, None) => Change::Removed,
+                    (Some(before), Some(after)) if before != after => Change::Changed,
+                    _ => Change::Unchanged,
+                };
+                (id.clone(), change)
+            })
+            .collect();
+
+        let (old_edges, new_edges) = (edges(&old_plan), edges(&new_plan));
+        let edges = old_edges
+            .union(&new_edges)
+            .map(|edge| {
+                let change = match (old_edges.contains(edge), new_edges.contains(edge)) {
+                    (false, _) => Change::Added,
+                    (_, false) => Change::Removed,
+                    _ => Change::Unchanged,
+                };
+                (edge.0.clone(), edge.1.clone(), edge.2.clone(), change)
+            })
+            .collect();
+
+        Ok(Self { nodes, edges })
+    }
+
+    pub fn to_dot(&self) -> String {
+        let index = self.index();
+        let mut text = String::from("digraph {\n");
+        for (i, (id, change)) in self.nodes.iter().enumerate() {
+            text.push_str(&format!(
+                "    {i} [ label = \"{}\"{} ]\n",
+                id.replace('\\', "\\\\").replace('"', "\\\""),
+                change.dot_node_style()
+            ));
+        }
+        for (from, relation, to, change) in &self.edges {
+            text.push_str(&format!(
+                "    {} -> {} [ label = \"{relation}\"{} ]\n",
+                index[from.as_str()],
+                index[to.as_str()],
+                change.dot_edge_style()
+            ));
+        }
+        text.push_str("}\n");
+        text
+    }
+
+    pub fn to_mermaid(&self) -> String {
+        let index = self.index();
+        let mut text = String::from("flowchart TD\n");
+        for (i, (id, change)) in self.nodes.iter().enumerate() {
+            let class = change
+                .mermaid_class()
+                .map(|class| format!(":::{class}"))
+                .unwrap_or_default();
+            text.push_str(&format!(
+                "    n{i}[\"{}\"]{class}\n",
+                id.replace('"', "#quot;")
+            ));
+        }
+        let mut styles = Vec::new();
+        for (i, (from, relation, to, change)) in self.edges.iter().enumerate() {
+            let arrow = if relation == "~>" { "-.->" } else { "-->" };
+            text.push_str(&format!(
+                "    n{} {arrow} n{}\n",
+                index[from.as_str()],
+                index[to.as_str()]
+            ));
+            match change {
+                Change::Added => styles.push(format!("    linkStyle {i} stroke:green\n")),
+                Change::Removed => styles.push(format!("    linkStyle {i} stroke:red\n")),
+                Change::Changed | Change::Unchanged => {}
+            }
+        }
+        text.extend(styles);
+        text.push_str("    classDef added fill:#b2f2bb\n");
+        text.push_str("    classDef removed fill:#ffc9c9\n");
+        text.push_str("    classDef changed fill:#ffec99\n");
+        text
+    }
+
+    fn index(&self) -> BTreeMap<&str, usize> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .map(|(i, (id, _))| (id.as_str(), i))
+            .collect()
+    }
+}
+
+/// Each resource's attributes as sorted `name => value` lines, keyed by resource id.
+fn attributes(manifest: &Manifest) -> Result<BTreeMap<String, Vec<String>>> {
+    let mut attributes = BTreeMap::new();
+    for expr in manifest.resources() {
+        let resource = Box::<dyn Resource>::try_from(expr)?;
+        if let PuppetExpr::Resource {
+            attributes: attrs, ..
+        } = expr
+        {
+            let mut lines: Vec<_> = attrs
+                .iter()
+                .map(|attr| format!("{} => {}", attr.name, attr.value))
+                .collect();
+            lines.sort();
+            attributes.insert(resource.id(), lines);
+        }
+    }
+    Ok(attributes)
+}
+
+fn edges(plan: &Plan) -> BTreeSet<(String, String, String)> {
+    let graph = plan.plan().inner();
+    graph
+        .edge_references()
+        .map(|edge| {
+            (
+                graph[edge.source()].id(),
+                edge.weight().to_string(),
+                graph[edge.target()].id(),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_graph_diff_marks_changes() -> Result<()> {
+        let old = Manifest::from_str(
+            r#"
+            file { "/etc/app.conf": mode => "0644" }
+            service { "app": }
+            exec { "migrate": }
+            File["/etc/app.conf"] ~> Service["app"]
+            Exec["migrate"] -> Service["app"]
+            "#,
+        )?;
+        let new = Manifest::from_str(
+            r#"
+            file { "/etc/app.conf": mode => "0600" }
+            service { "app": }
+            file { "/opt/app": }
+            File["/etc/app.conf"] ~> Service["app"]
+            File["/opt/app"] -> File["/etc/app.conf"]
+            "#,
+        )?;
+
+        let diff = GraphDiff::between(&old, &new)?;
+        assert_eq!(
+            diff.nodes,
+            vec![
+                ("Exec[migrate]".to_string(), Change::Removed),
+                ("File[/etc/app.conf]".to_string(), Change::Changed),
+                ("File[/opt/app]".to_string(), Change::Added),
+                ("Service[app]".to_string(), Change::Unchanged),
+            ]
+        );
+        let expected_dot = r#"digraph {
+    0 [ label = "Exec[migrate]" style = "filled" fillcolor = "lightcoral" ]
+    1 [ label = "File[/etc/app.conf]" style = "filled" fillcolor = "yellow" ]
+    2 [ label = "File[/opt/app]" style = "filled" fillcolor = "palegreen" ]
+    3 [ label = "Service[app]" ]
+    0 -> 3 [ label = "->" color = "red" ]
+    1 -> 3 [ label = "~>" ]
+    2 -> 1 [ label = "->" color = "green" ]
+}
+"#;
+        assert_eq!(diff.to_dot(), expected_dot);
+        let mermaid = diff.to_mermaid();
+        assert!(mermaid.contains("    n0[\"Exec[migrate]\"]:::removed\n"));
+        assert!(mermaid.contains("    n1 -.-> n3\n"));
+        assert!(mermaid.contains("    linkStyle 2 stroke:green\n"));
+        Ok(())
+    }
+}
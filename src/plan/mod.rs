@@ -0,0 +1,28 @@
+pub mod budget;
+pub mod canonical;
+pub mod catalog;
+pub mod components;
+pub mod cycle;
+pub mod d2;
+pub mod deny;
+pub mod dot_import;
+pub mod explain;
+pub mod export;
+pub mod graph_diff;
+pub mod plan_diff;
+pub mod preview;
+pub mod provenance;
+pub mod providers;
+pub mod puppet_catalog;
+pub mod query;
+pub mod schedule;
+pub mod verify;
+
+pub use budget::{Budget, Overrun};
+pub use deny::{Deny, PolicyViolation, policy_violations};
+pub use explain::Explanation;
+pub use graph_diff::{Change, GraphDiff};
+pub use plan_diff::{AttributeChange, PlanDiff, diff};
+pub use provenance::{Origin, Provenance};
+pub use schedule::{RefreshMode, Step};
+pub use verify::Violation;
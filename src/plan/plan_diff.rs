@@ -0,0 +1,207 @@
+use crate::Plan;
+use crate::parser::pp::AttrValue;
+use crate::resources::{Attributes, Relation};
+use petgraph::visit::{EdgeRef, IntoEdgeReferences};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// An attribute set, unset or given another value between two plans.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttributeChange {
+    pub name: String,
+    pub old: Option<AttrValue>,
+    pub new: Option<AttrValue>,
+}
+
+impl fmt::Display for AttributeChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |value: &Option<AttrValue>| match value {
+            Some(value) => value.to_string(),
+            None => "(unset)".to_string(),
+        };
+        write!(
+            f,
+            "{}: {} -> {}",
+            self.name,
+            show(&self.old),
+            show(&self.new)
+        )
+    }
+}
+
+/// `(from, relation, to)` by resource id.
+pub type Edge = (String, Relation, String);
+
+/// What applying a new plan instead of an old one changes, by resource id and in sorted
+/// order. Unlike [`super::GraphDiff`], it compares compiled plans, so changes coming from
+/// facts, defaults or defined types show too.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PlanDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: BTreeMap<String, Vec<AttributeChange>>,
+    pub added_edges: Vec<Edge>,
+    pub removed_edges: Vec<Edge>,
+}
+
+impl PlanDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+            && self.added_edges.is_empty()
+            && self.removed_edges.is_empty()
+    }
+}
+
+/// Lines of `+ added`, `- removed` and `~ changed` resources, each change indented
+/// below its resource, then the edges.
+impl fmt::Display for PlanDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for id in &self.added {
+            writeln!(f, "+ {id}")?;
+        }
+        for id in &self.removed {
+            writeln!(f, "- {id}")?;
+        }
+        for (id, changes) in &self.changed {
+            writeln!(f, "~ {id}")?;
+            for change in changes {
+                writeln!(f, "    {change}")?;
+            }
+        }
+        for (from, relation, to) in &self.added_edges {
+            writeln!(f, "+ {from} {relation} {to}")?;
+        }
+        for (from, relation, to) in &self.removed_edges {
+            writeln!(f, "- {from} {relation} {to}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares two compiled plans. An `undef` attribute counts as unset.
+pub fn diff(old: &Plan, new: &Plan) -> PlanDiff {
+    let (old_resources, new_resources) = (resources(old), resources(new));
+    let mut diff = PlanDiff::default();
+    for (id, attributes) in &new_resources {
+        let Some(before) = old_resources.get(id) else {
+            diff.added.push(id.clone());
+            continue;
+        };
+        let names: BTreeSet<_> = before.keys().chain(attributes.keys()).collect();
+        let changes: Vec<_> = names
+            .into_iter()
+            .filter_map(|name| {
+                let (old, new) = (set(before, name), set(attributes, name));
+                (old != new).then(|| AttributeChange {
+                    name: name.clone(),
+                    old: old.cloned(),
+                    new: new.cloned(),
+                })
+            })
+            .collect();
+        if !changes.is_empty() {
+            diff.changed.insert(id.clone(), changes);
+        }
+    }
+    diff.removed = old_resources
+        .keys()
+        .filter(|id| !new_resources.contains_key(*id))
+        .cloned()
+        .collect();
+
+    let (old_edges, new_edges) = (edges(old), edges(new));
+    diff.added_edges = new_edges.difference(&old_edges).map(edge).collect();
+    diff.removed_edges = old_edges.difference(&new_edges).map(edge).collect();
+    diff
+}
+
+fn set<'a>(attributes: &'a Attributes, name: &str) -> Option<&'a AttrValue> {
+    attributes
+        .get(name)
+        .filter(|value| **value != AttrValue::Undef)
+}
+
+fn resources(plan: &Plan) -> BTreeMap<String, &Attributes> {
+    plan.graph
+        .inner()
+        .node_weights()
+        .map(|resource| (resource.id(), resource.attributes()))
+        .collect()
+}
+
+/// Edges with their relation as text, which orders them.
+fn edges(plan: &Plan) -> BTreeSet<(String, String, String)> {
+    let graph = plan.graph.inner();
+    graph
+        .edge_references()
+        .map(|edge| {
+            (
+                graph[edge.source()].id(),
+                edge.weight().to_string(),
+                graph[edge.target()].id(),
+            )
+        })
+        .collect()
+}
+
+fn edge((from, relation, to): &(String, String, String)) -> Edge {
+    let relation = match relation.as_str() {
+        "~>" => Relation::Notify,
+        _ => Relation::Provide,
+    };
+    (from.clone(), relation, to.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_puppet_manifest;
+    use crate::parser::pp::Manifest;
+    use anyhow::Result;
+
+    #[test]
+    fn test_plan_diff() -> Result<()> {
+        let old = parse_puppet_manifest(
+            &r#"
+            define app::site($port) { file { "/etc/app/${title}.conf": content => "${port}" } }
+            app::site { "web": port => 8080 }
+            service { "app": enable => true }
+            exec { "migrate": }
+            App::Site["web"] ~> Service["app"]
+            Exec["migrate"] -> Service["app"]
+        "#
+            .parse::<Manifest>()?,
+        )?;
+        let new = parse_puppet_manifest(
+            &r#"
+            define app::site($port) { file { "/etc/app/${title}.conf": content => "${port}" } }
+            app::site { "web": port => 8081 }
+            service { "app": enable => undef, ensure => running }
+            file { "/opt/app": }
+            App::Site["web"] ~> Service["app"]
+            File["/opt/app"] -> Service["app"]
+        "#
+            .parse::<Manifest>()?,
+        )?;
+
+        let diff = diff(&old, &new);
+        assert_eq!(
+            diff.to_string(),
+            "+ File[/opt/app]\n\
+             - Exec[migrate]\n\
+             ~ App::Site[web]\n    \
+             port: 8080 -> 8081\n\
+             ~ File[/etc/app/web.conf]\n    \
+             content: 8080 -> 8081\n\
+             ~ Service[app]\n    \
+             enable: true -> (unset)\n    \
+             ensure: (unset) -> running\n\
+             + File[/opt/app] -> Service[app]\n\
+             - Exec[migrate] -> Service[app]\n"
+        );
+        assert!(super::diff(&new, &new).is_empty());
+        Ok(())
+    }
+}
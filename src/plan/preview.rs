@@ -0,0 +1,45 @@
+use crate::Plan;
+use anyhow::Result;
+
+impl Plan {
+    /// What each resource's provider would do, in apply order, without touching the system.
+    pub fn preview(&self) -> Result<Vec<(String, Vec<String>)>> {
+        Ok(self
+            .sorted_weights()?
+            .into_iter()
+            .map(|(index, node)| (node.id(), node.preview(self.ensure(index))))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse_puppet_manifest;
+    use crate::parser::pp::Manifest;
+    use anyhow::Result;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_preview_in_apply_order() -> Result<()> {
+        let input = r#"
+            service { "nginx": }
+            file { "/etc/nginx.conf": }
+            File["/etc/nginx.conf"] ~> Service["nginx"]
+        "#;
+        let plan = parse_puppet_manifest(&Manifest::from_str(input)?)?;
+        assert_eq!(
+            plan.preview()?,
+            vec![
+                (
+                    "File[/etc/nginx.conf]".to_string(),
+                    vec!["would write: /etc/nginx.conf".to_string()]
+                ),
+                (
+                    "Service[nginx]".to_string(),
+                    vec!["would run: systemctl start nginx".to_string()]
+                ),
+            ]
+        );
+        Ok(())
+    }
+}
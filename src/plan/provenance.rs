@@ -0,0 +1,72 @@
+use crate::Plan;
+use crate::parser::pp::Span;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// One construct in the chain that produced a resource.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Origin {
+    /// A resource declaration in the manifest.
+    Declared { span: Span },
+    /// A node of a graph produced by another tool, named by the graph.
+    Imported { graph: String },
+    /// The declaration of the class whose body declared the resource.
+    Class { name: String, span: Span },
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Declared { span } => write!(f, "declared at {span}"),
+            Self::Imported { graph } => write!(f, "imported from graph {graph}"),
+            Self::Class { name, span } => write!(f, "in class {name} declared at {span}"),
+        }
+    }
+}
+
+/// The chain of constructs that produced a resource, outermost first.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Provenance(pub Vec<Origin>);
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, origin) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, " → ")?;
+            }
+            write!(f, "{origin}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Plan {
+    /// Why the resource `id` is in the plan.
+    pub fn provenance(&self, id: &str) -> Option<&Provenance> {
+        self.provenance.get(&self.node(id)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse_puppet_manifest;
+    use crate::parser::pp::Manifest;
+    use anyhow::{Result, anyhow};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_provenance_of_declared_resource() -> Result<()> {
+        let input = r#"
+            file { "/tmp/one": }
+            service { "nginx": }
+        "#;
+        let plan = parse_puppet_manifest(&Manifest::from_str(input)?)?;
+        let Some(provenance) = plan.provenance("Service[nginx]") else {
+            return Err(anyhow!("Missing provenance"));
+        };
+        assert_eq!(provenance.to_string(), "declared at 3:13");
+        assert!(plan.provenance("Service[other]").is_none());
+        Ok(())
+    }
+}
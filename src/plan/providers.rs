@@ -0,0 +1,127 @@
+use crate::Plan;
+use crate::facts::Facts;
+use crate::resources::{Capabilities, Confine};
+use std::fmt;
+
+/// A resource that cannot be applied because no provider works on this system.
+#[derive(Debug)]
+pub struct Unsupported {
+    pub id: String,
+    pub reason: anyhow::Error,
+}
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no suitable provider for {}: {}", self.id, self.reason)
+    }
+}
+
+/// A resource whose confinement does not match the node's facts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotApplicable {
+    pub id: String,
+    pub confine: Confine,
+}
+
+impl fmt::Display for NotApplicable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} is not applicable: requires {}",
+            self.id, self.confine
+        )
+    }
+}
+
+impl Plan {
+    /// Resources confined away from a node with `facts`.
+    pub fn not_applicable(&self, facts: &Facts) -> Vec<NotApplicable> {
+        let mut not_applicable: Vec<_> = self
+            .graph
+            .inner()
+            .node_weights()
+            .filter_map(|node| {
+                node.confines()
+                    .into_iter()
+                    .find(|confine| !confine.matches(facts))
+                    .map(|confine| NotApplicable {
+                        id: node.id(),
+                        confine,
+                    })
+            })
+            .collect();
+        not_applicable.sort_by(|a, b| a.id.cmp(&b.id));
+        not_applicable
+    }
+
+    /// Checks every resource against the system capabilities before anything is applied.
+    pub fn unsupported(&self, capabilities: &Capabilities) -> Vec<Unsupported> {
+        let mut unsupported: Vec<_> = self
+            .graph
+            .inner()
+            .node_weights()
+            .filter_map(|node| {
+                node.check_provider(capabilities)
+                    .err()
+                    .map(|reason| Unsupported {
+                        id: node.id(),
+                        reason,
+                    })
+            })
+            .collect();
+        unsupported.sort_by(|a, b| a.id.cmp(&b.id));
+        unsupported
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_puppet_manifest;
+    use crate::parser::pp::Manifest;
+    use anyhow::Result;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_unsupported_without_systemd() -> Result<()> {
+        let input = include_str!("../../res/test.pp");
+        let plan = parse_puppet_manifest(&Manifest::from_str(input)?)?;
+        let without_systemd = Capabilities::default();
+        let unsupported = plan.unsupported(&without_systemd);
+        let ids: Vec<_> = unsupported.iter().map(|u| u.id.as_str()).collect();
+        assert_eq!(ids, vec!["Service[nginx]", "Service[ssh]"]);
+        assert_eq!(
+            unsupported[0].to_string(),
+            "no suitable provider for Service[nginx]: systemd is not running on this system"
+        );
+
+        let with_systemd = Capabilities {
+            systemd: true,
+            ..Capabilities::default()
+        };
+        assert!(plan.unsupported(&with_systemd).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_not_applicable_by_facts() -> Result<()> {
+        let input = r#"
+            file { "/etc/motd": }
+            service { "nginx": }
+        "#;
+        let plan = parse_puppet_manifest(&Manifest::from_str(input)?)?;
+
+        let mut facts = Facts::new();
+        facts.insert("kernel", "linux");
+        assert!(plan.not_applicable(&facts).is_empty());
+
+        facts.insert("kernel", "Darwin");
+        let not_applicable = plan.not_applicable(&facts);
+        assert_eq!(not_applicable.len(), 1);
+        assert_eq!(
+            not_applicable[0].to_string(),
+            "Service[nginx] is not applicable: requires kernel in [Linux]"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,360 @@
+use super::Origin;
+use crate::Plan;
+use crate::parser::pp::{AttrValue, ResourceRef, normalize_rtype};
+use crate::resources::Relation;
+use anyhow::Result;
+use indexmap::{IndexMap, IndexSet};
+use petgraph::{
+    graph::NodeIndex,
+    visit::{EdgeRef, IntoEdgeReferences},
+};
+use serde_json::{Map, Value, json};
+use std::collections::HashSet;
+
+impl Plan {
+    /// The plan in the JSON wire format of Puppet catalogs, as `puppet catalog compile`
+    /// prints it, for diffing dolly against Puppet. As in Puppet, `edges` are containment
+    /// (`Stage[main]`, then classes, then resources) and dependencies are parameters:
+    /// declared metaparameters as written and chaining arrows as `before` or `notify` on
+    /// their source. The version is 0 rather than a timestamp, so catalogs of the same
+    /// manifest are identical.
+    pub fn to_puppet_json(&self, node: &str) -> Result<String> {
+        let graph = self.graph.inner();
+        let mut parameters: IndexMap<NodeIndex, Map<String, Value>> = graph
+            .node_indices()
+            .map(|index| {
+                let parameters = graph[index]
+                    .attributes()
+                    .iter()
+                    .filter(|(_, value)| **value != AttrValue::Undef)
+                    .map(|(name, value)| (name.clone(), puppet_value(value)))
+                    .collect();
+                (index, parameters)
+            })
+            .collect();
+
+        let declared = self.declared_dependencies();
+        for edge in graph.edge_references() {
+            let (source, target) = (edge.source(), edge.target());
+            let autorequired = graph[target]
+                .autorequire_types()
+                .contains(&graph[source].rtype());
+            if autorequired
+                || declared.contains(&(source, target))
+                || self.expanded_from_class(source, target, edge.weight())
+            {
+                continue;
+            }
+            let metaparameter = match edge.weight() {
+                Relation::Provide => "before",
+                Relation::Notify => "notify",
+            };
+            if let Some(parameters) = parameters.get_mut(&source) {
+                add_reference(parameters, metaparameter, graph[target].id());
+            }
+        }
+
+        let mut resources = vec![
+            json!({"type": "Stage", "title": "main", "tags": ["stage"], "exported": false,
+                   "parameters": {"name": "main"}}),
+            json!({"type": "Class", "title": "Settings", "tags": ["class", "settings"],
+                   "exported": false}),
+            json!({"type": "Class", "title": "Main", "tags": ["class"], "exported": false,
+                   "parameters": {"name": "main"}}),
+        ];
+        let mut edges = vec![
+            json!({"source": "Stage[main]", "target": "Class[Settings]"}),
+            json!({"source": "Stage[main]", "target": "Class[Main]"}),
+        ];
+        for (index, parameters) in parameters {
+            let resource = &graph[index];
+            let container = match (resource.rtype(), self.containers(index).pop()) {
+                ("Class", Some(class)) if self.edge(index, &class).is_some() => class,
+                ("Class", _) => "Stage[main]".to_string(),
+                (_, class) => class.unwrap_or_else(|| "Class[Main]".to_string()),
+            };
+            edges.push(json!({"source": container, "target": resource.id()}));
+            let mut entry = json!({
+                "type": resource.rtype(),
+                "title": resource.title(),
+                "tags": self.tags(index),
+            });
+            if let Some(line) = self.declared_line(index) {
+                entry["line"] = line.into();
+            }
+            entry["exported"] = false.into();
+            let sensitive: Vec<_> = resource
+                .attributes()
+                .iter()
+                .filter(|(_, value)| matches!(value, AttrValue::Sensitive(_)))
+                .map(|(name, _)| name.as_str())
+                .collect();
+            if !sensitive.is_empty() {
+                entry["sensitive_parameters"] = sensitive.into();
+            }
+            if !parameters.is_empty() {
+                entry["parameters"] = Value::Object(parameters);
+            }
+            resources.push(entry);
+        }
+
+        let catalog = json!({
+            "tags": ["settings"],
+            "name": node,
+            "version": 0,
+            "code_id": null,
+            "catalog_format": 1,
+            "environment": "production",
+            "resources": resources,
+            "edges": edges,
+            "classes": self.classes(),
+        });
+        Ok(serde_json::to_string_pretty(&catalog)?)
+    }
+
+    /// The edges the metaparameters of resources declare, which Puppet keeps as they are
+    /// written.
+    fn declared_dependencies(&self) -> HashSet<(NodeIndex, NodeIndex)> {
+        let graph = self.graph.inner();
+        let mut declared = HashSet::new();
+        for index in graph.node_indices() {
+            for (name, value) in graph[index].attributes() {
+                let references = value
+                    .as_array()
+                    .into_iter()
+                    .filter_map(|value| match value {
+                        AttrValue::ResourceRef(reference) => self.node(&reference.id()),
+                        value => self.node(&value.as_literal()?.parse::<ResourceRef>().ok()?.id()),
+                    });
+                for other in references {
+                    match name.as_str() {
+                        "require" | "subscribe" => declared.insert((other, index)),
+                        "before" | "notify" => declared.insert((index, other)),
+                        _ => false,
+                    };
+                }
+            }
+        }
+        declared
+    }
+
+    /// The classes a resource is declared in, outermost first, as `Class[Name]`.
+    fn containers(&self, index: NodeIndex) -> Vec<String> {
+        let Some(provenance) = self.provenance.get(&index) else {
+            return vec![];
+        };
+        provenance
+            .0
+            .iter()
+            .filter_map(|origin| match origin {
+                Origin::Class { name, .. } => Some(format!("Class[{}]", normalize_rtype(name))),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn edge(&self, source: NodeIndex, target: &str) -> Option<&Relation> {
+        let graph = self.graph.inner();
+        graph
+            .find_edge(source, self.node(target)?)
+            .map(|edge| &graph[edge])
+    }
+
+    /// Whether an edge only exists because classes are expanded: from a resource to the
+    /// class containing it, or one of the edges a relation to or from a class gives each
+    /// resource in it. Puppet keeps the relation on the class instead.
+    fn expanded_from_class(
+        &self,
+        source: NodeIndex,
+        target: NodeIndex,
+        relation: &Relation,
+    ) -> bool {
+        let graph = self.graph.inner();
+        let (source_classes, target_classes) = (self.containers(source), self.containers(target));
+        source_classes.contains(&graph[target].id())
+            || target_classes
+                .iter()
+                .any(|class| self.edge(source, class) == Some(relation))
+            || source_classes.iter().any(|class| {
+                self.node(class)
+                    .and_then(|class| graph.find_edge(class, target))
+                    .is_some_and(|edge| &graph[edge] == relation)
+            })
+    }
+
+    fn declared_line(&self, index: NodeIndex) -> Option<usize> {
+        self.provenance
+            .get(&index)?
+            .0
+            .iter()
+            .find_map(|origin| match origin {
+                Origin::Declared { span } if span.line > 0 => Some(span.line),
+                _ => None,
+            })
+    }
+
+    /// As Puppet tags resources: with their type and the segments of a namespaced one,
+    /// their title when it is a valid tag, and the classes they are declared in.
+    pub(crate) fn tags(&self, index: NodeIndex) -> Vec<String> {
+        let resource = &self.graph.inner()[index];
+        let mut tags = IndexSet::new();
+        add_tag(&mut tags, resource.rtype());
+        add_tag(&mut tags, &resource.title());
+        if let Some(provenance) = self.provenance.get(&index) {
+            for origin in &provenance.0 {
+                if let Origin::Class { name, .. } = origin {
+                    add_tag(&mut tags, name);
+                }
+            }
+        }
+        tags.into_iter().collect()
+    }
+
+    /// The names of the declared classes, as Puppet lists them.
+    fn classes(&self) -> Vec<String> {
+        let graph = self.graph.inner();
+        let mut classes = vec!["settings".to_string()];
+        classes.extend(
+            graph
+                .node_weights()
+                .filter(|resource| resource.rtype() == "Class")
+                .map(|resource| resource.title().to_lowercase()),
+        );
+        classes
+    }
+}
+
+/// Adds `name` and, when namespaced, each of its segments, if it is a valid tag.
+fn add_tag(tags: &mut IndexSet<String>, name: &str) {
+    let tag = name.to_lowercase();
+    let valid = tag.starts_with(|c: char| c.is_alphanumeric() || c == '_')
+        && tag
+            .chars()
+            .all(|c| c.is_alphanumeric() || "_:.-".contains(c));
+    if !valid {
+        return;
+    }
+    tags.insert(tag.clone());
+    if tag.contains("::") {
+        tags.extend(tag.split("::").map(str::to_string));
+    }
+}
+
+/// Appends `reference` to the `metaparameter` of a resource, which holds one reference or
+/// an array of them.
+fn add_reference(parameters: &mut Map<String, Value>, metaparameter: &str, reference: String) {
+    match parameters.remove(metaparameter) {
+        None => parameters.insert(metaparameter.to_string(), reference.into()),
+        Some(Value::Array(mut references)) => {
+            references.push(reference.into());
+            parameters.insert(metaparameter.to_string(), references.into())
+        }
+        Some(existing) => parameters.insert(
+            metaparameter.to_string(),
+            Value::Array(vec![existing, reference.into()]),
+        ),
+    };
+}
+
+/// A parameter value as Puppet serializes it: references as `Type[title]` strings,
+/// deferred values as rich data and sensitive ones as they are, listed in the resource's
+/// `sensitive_parameters`.
+fn puppet_value(value: &AttrValue) -> Value {
+    match value {
+        AttrValue::String(s) => Value::String(s.to_string()),
+        AttrValue::Bool(b) => Value::Bool(*b),
+        AttrValue::Integer(i) => Value::from(*i),
+        AttrValue::Array(values) => Value::Array(values.iter().map(puppet_value).collect()),
+        AttrValue::Hash(hash) => Value::Object(
+            hash.iter()
+                .map(|(key, value)| (key.clone(), puppet_value(value)))
+                .collect(),
+        ),
+        AttrValue::Undef => Value::Null,
+        AttrValue::ResourceRef(reference) => Value::String(reference.id()),
+        AttrValue::Deferred { function, args } => json!({
+            "__ptype": "Deferred",
+            "name": function,
+            "arguments": args.iter().map(puppet_value).collect::<Vec<_>>(),
+        }),
+        AttrValue::Sensitive(value) => puppet_value(value),
+        AttrValue::Call { .. } => Value::String(value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse_puppet_manifest;
+    use crate::parser::pp::Manifest;
+    use anyhow::Result;
+    use serde_json::{Value, json};
+
+    #[test]
+    fn test_puppet_catalog_wire_format() -> Result<()> {
+        let input = r#"
+            class nginx::config {
+                file { "/etc/nginx/nginx.conf": mode => '0640', owner => undef }
+            }
+            include nginx::config
+            service { "nginx": require => File["/etc/nginx/nginx.conf"] }
+            exec { "reload": command => "/bin/true" }
+            exec { "setup": command => "/bin/true" }
+            Service["nginx"] ~> Exec["reload"]
+            Exec["setup"] -> Class["nginx::config"]
+        "#;
+        let plan = parse_puppet_manifest(&input.parse::<Manifest>()?)?;
+        let catalog: Value = serde_json::from_str(&plan.to_puppet_json("web01")?)?;
+
+        assert_eq!(catalog["name"], "web01");
+        assert_eq!(catalog["version"], 0, "Catalogs are deterministic");
+        let resource = |id: &str| {
+            catalog["resources"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .find(|r| {
+                    format!(
+                        "{}[{}]",
+                        r["type"].as_str().unwrap_or(""),
+                        r["title"].as_str().unwrap_or("")
+                    ) == id
+                })
+                .cloned()
+                .unwrap_or(Value::Null)
+        };
+        let conf = resource("File[/etc/nginx/nginx.conf]");
+        assert_eq!(
+            conf["parameters"],
+            json!({"mode": "0640"}),
+            "undef is left out"
+        );
+        assert_eq!(
+            conf["tags"],
+            json!(["file", "nginx::config", "nginx", "config"])
+        );
+        assert_eq!(
+            resource("Service[nginx]")["parameters"],
+            json!({"require": "File[/etc/nginx/nginx.conf]", "notify": "Exec[reload]"}),
+            "Chaining arrows become metaparameters of their source"
+        );
+        assert_eq!(
+            resource("Exec[setup]")["parameters"]["before"],
+            "Class[Nginx::Config]",
+            "Relations to classes stay on the class"
+        );
+        assert_eq!(resource("Stage[main]")["type"], "Stage");
+
+        let edges = catalog["edges"].as_array().cloned().unwrap_or_default();
+        assert!(edges.contains(
+            &json!({"source": "Class[Nginx::Config]", "target": "File[/etc/nginx/nginx.conf]"})
+        ));
+        assert!(edges.contains(&json!({"source": "Class[Main]", "target": "Exec[reload]"})));
+        assert!(
+            edges
+                .iter()
+                .all(|edge| edge["target"] != "Service[nginx]" || edge["source"] == "Class[Main]"),
+            "Edges are containment only: {edges:?}"
+        );
+        Ok(())
+    }
+}
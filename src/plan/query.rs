@@ -0,0 +1,219 @@
+use super::deny::glob;
+use crate::Plan;
+use crate::parser::pp::{Attribute, PuppetExpr, PuppetString, Span};
+use crate::resources::{Relation, Resource, ResourceRegistry};
+use anyhow::{Result, anyhow};
+use petgraph::{
+    Direction,
+    acyclic::Acyclic,
+    graph::NodeIndex,
+    prelude::StableDiGraph,
+    visit::{Dfs, EdgeRef, Reversed},
+};
+use std::collections::{HashMap, HashSet};
+
+impl Plan {
+    /// The resource `id` and everything applied before it, with the relations between
+    /// them: what must succeed for `id` to be applied, or refreshed.
+    pub fn dependencies_of(&self, id: &str) -> Result<Plan> {
+        let start = self.existing(id)?;
+        let mut dfs = Dfs::new(Reversed(self.graph.inner()), start);
+        let mut keep = HashSet::new();
+        while let Some(index) = dfs.next(Reversed(self.graph.inner())) {
+            keep.insert(index);
+        }
+        self.restricted_to(&keep)
+    }
+
+    /// The resource `id` and everything applied after it: what a failure of `id` skips.
+    pub fn dependents_of(&self, id: &str) -> Result<Plan> {
+        let start = self.existing(id)?;
+        let mut dfs = Dfs::new(self.graph.inner(), start);
+        let mut keep = HashSet::new();
+        while let Some(index) = dfs.next(self.graph.inner()) {
+            keep.insert(index);
+        }
+        self.restricted_to(&keep)
+    }
+
+    /// The resources whose type and title match the globs (`*` and `?`), types
+    /// case-insensitively: `subgraph_matching("nginx::*", "*")`. Resources ordered through
+    /// ones left out stay ordered, by a plain relation.
+    pub fn subgraph_matching(&self, rtype_glob: &str, title_glob: &str) -> Result<Plan> {
+        let graph = self.graph.inner();
+        let keep = graph
+            .node_indices()
+            .filter(|index| {
+                let resource = &graph[*index];
+                glob(&rtype_glob.to_lowercase(), &resource.rtype().to_lowercase())
+                    && glob(title_glob, &resource.title())
+            })
+            .collect();
+        self.restricted_to(&keep)
+    }
+
+    fn existing(&self, id: &str) -> Result<NodeIndex> {
+        self.node(id)
+            .ok_or_else(|| anyhow!("No resource {id} in the plan"))
+    }
+
+    /// A new plan of the `keep` resources, rebuilt from their attributes, in which one is
+    /// ordered before another when it was here, directly or through resources left out.
+    fn restricted_to(&self, keep: &HashSet<NodeIndex>) -> Result<Plan> {
+        let graph = self.graph.inner();
+        let mut registry = ResourceRegistry::default();
+        let mut subgraph = StableDiGraph::<Box<dyn Resource>, Relation>::new();
+        let mut nodes = HashMap::new();
+        let mut plan_index = HashMap::new();
+        let mut concurrency_groups = HashMap::new();
+        let mut ensures = HashMap::new();
+        let mut provenance = HashMap::new();
+        for index in graph.node_indices().filter(|index| keep.contains(index)) {
+            let node = subgraph.add_node(rebuild(&mut registry, graph[index].as_ref())?);
+            nodes.insert(index, node);
+            plan_index.insert(subgraph[node].id(), node);
+            if let Some(group) = self.concurrency_groups.get(&index) {
+                concurrency_groups.insert(node, group.clone());
+            }
+            if let Some(ensure) = self.ensures.get(&index) {
+                ensures.insert(node, ensure.clone());
+            }
+            if let Some(origins) = self.provenance.get(&index) {
+                provenance.insert(node, origins.clone());
+            }
+        }
+        for (&index, &node) in &nodes {
+            for edge in graph.edges_directed(index, Direction::Outgoing) {
+                if let Some(&target) = nodes.get(&edge.target()) {
+                    subgraph.add_edge(node, target, edge.weight().clone());
+                }
+            }
+            for target in self.reachable_through_dropped(index, keep) {
+                if subgraph.find_edge(node, nodes[&target]).is_none() {
+                    subgraph.add_edge(node, nodes[&target], Relation::Provide);
+                }
+            }
+        }
+        Ok(Plan {
+            graph: Acyclic::try_from_graph(subgraph)
+                .map_err(|_| anyhow!("Error creating acyclic graph."))?,
+            index: plan_index,
+            concurrency_groups,
+            ensures,
+            provenance,
+            facts_used: self.facts_used.clone(),
+        })
+    }
+
+    /// The kept resources reachable from `index` only through resources left out.
+    fn reachable_through_dropped(
+        &self,
+        index: NodeIndex,
+        keep: &HashSet<NodeIndex>,
+    ) -> HashSet<NodeIndex> {
+        let graph = self.graph.inner();
+        let mut stack: Vec<_> = graph
+            .neighbors_directed(index, Direction::Outgoing)
+            .filter(|next| !keep.contains(next))
+            .collect();
+        let (mut seen, mut reached) = (HashSet::new(), HashSet::new());
+        while let Some(dropped) = stack.pop() {
+            if !seen.insert(dropped) {
+                continue;
+            }
+            for next in graph.neighbors_directed(dropped, Direction::Outgoing) {
+                match keep.contains(&next) {
+                    true => {
+                        reached.insert(next);
+                    }
+                    false => stack.push(next),
+                }
+            }
+        }
+        reached
+    }
+}
+
+/// A copy of `resource` built from its attributes, as the catalog import does.
+fn rebuild(registry: &mut ResourceRegistry, resource: &dyn Resource) -> Result<Box<dyn Resource>> {
+    if !registry.supports(resource.rtype()) {
+        registry.register_defined(resource.rtype());
+    }
+    registry.build(&PuppetExpr::Resource {
+        rtype: resource.rtype().to_string(),
+        title: PuppetString::literal(&resource.title()),
+        attributes: resource
+            .attributes()
+            .iter()
+            .map(|(name, value)| Attribute {
+                name: name.clone(),
+                value: value.clone(),
+            })
+            .collect(),
+        span: Span::default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse_puppet_manifest;
+    use crate::parser::pp::Manifest;
+    use anyhow::Result;
+
+    #[test]
+    fn test_graph_queries() -> Result<()> {
+        let plan = parse_puppet_manifest(
+            &r#"
+            package { "nginx": }
+            file { "/etc/nginx/nginx.conf": content => "worker_processes 4;" }
+            service { "nginx": }
+            exec { "warm-cache": }
+            file { "/var/log/app": }
+            Package["nginx"] -> File["/etc/nginx/nginx.conf"] ~> Service["nginx"]
+            Service["nginx"] -> Exec["warm-cache"]
+            "#
+            .parse::<Manifest>()?,
+        )?;
+
+        assert_eq!(
+            plan.dependencies_of("Service[nginx]")?.to_canonical_text(),
+            "node File[/etc/nginx/nginx.conf]\n\
+             node Package[nginx]\n\
+             node Service[nginx]\n\
+             edge File[/etc/nginx/nginx.conf] ~> Service[nginx]\n\
+             edge Package[nginx] -> File[/etc/nginx/nginx.conf]\n"
+        );
+        assert_eq!(
+            plan.dependents_of("File[/etc/nginx/nginx.conf]")?
+                .to_canonical_text(),
+            "node Exec[warm-cache]\n\
+             node File[/etc/nginx/nginx.conf]\n\
+             node Service[nginx]\n\
+             edge File[/etc/nginx/nginx.conf] ~> Service[nginx]\n\
+             edge Service[nginx] -> Exec[warm-cache]\n"
+        );
+        let subgraph = plan.subgraph_matching("*", "nginx")?;
+        assert_eq!(
+            subgraph.to_canonical_text(),
+            "node Package[nginx]\n\
+             node Service[nginx]\n\
+             edge Package[nginx] -> Service[nginx]\n",
+            "Ordering through the config file is kept"
+        );
+        assert_eq!(
+            plan.subgraph_matching("f?le", "/etc/*")?
+                .to_canonical_text(),
+            "node File[/etc/nginx/nginx.conf]\n"
+        );
+        let config = plan.dependencies_of("Service[nginx]")?;
+        let index = config
+            .node("File[/etc/nginx/nginx.conf]")
+            .ok_or_else(|| anyhow::anyhow!("The file is a dependency"))?;
+        assert_eq!(
+            config.plan().inner()[index].attributes()["content"].to_string(),
+            "worker_processes 4;"
+        );
+        assert!(plan.dependents_of("Service[apache]").is_err());
+        Ok(())
+    }
+}
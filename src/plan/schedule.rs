@@ -0,0 +1,237 @@
+use super::cycle;
+use crate::Plan;
+use crate::cache::watched_paths;
+use crate::resources::Relation;
+use anyhow::{Result, anyhow};
+use petgraph::{Direction, graph::NodeIndex, visit::EdgeRef};
+use std::collections::{HashMap, HashSet};
+
+/// When Notify-driven refreshes happen relative to applying resources.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RefreshMode {
+    /// Refresh a resource right after it is applied.
+    #[default]
+    Interleaved,
+    /// Apply every resource first, then perform all refreshes in a second phase.
+    Deferred,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    Apply(NodeIndex),
+    /// Apply resources that neither depend on each other nor share a concurrency group,
+    /// in parallel.
+    ApplyBatch(Vec<NodeIndex>),
+    /// Refresh `target` once, on behalf of every resource notifying it.
+    Refresh {
+        target: NodeIndex,
+        triggered_by: Vec<NodeIndex>,
+    },
+}
+
+impl Plan {
+    /// The apply order with refreshes placed according to `mode`. A resource notified by
+    /// several others gets a single refresh step, and so does a resource with a `watch`
+    /// metaparameter, even when nothing in the plan notifies it.
+    pub fn schedule(&self, mode: RefreshMode) -> Result<Vec<Step>> {
+        let batches = self.sorted()?.into_iter().map(|index| vec![index]);
+        Ok(self.schedule_batches(batches, mode, |mut batch| Step::Apply(batch.remove(0))))
+    }
+
+    /// Like [`Plan::schedule`], applying the [`Plan::batches`] one after the other. The
+    /// refreshes of a batch's resources follow the batch when interleaved.
+    pub fn parallel_schedule(&self, mode: RefreshMode) -> Result<Vec<Step>> {
+        Ok(self.schedule_batches(self.batches()?, mode, Step::ApplyBatch))
+    }
+
+    fn schedule_batches(
+        &self,
+        batches: impl IntoIterator<Item = Vec<NodeIndex>>,
+        mode: RefreshMode,
+        apply: impl Fn(Vec<NodeIndex>) -> Step,
+    ) -> Vec<Step> {
+        let mut steps = Vec::new();
+        let mut refreshes = Vec::new();
+        for batch in batches {
+            let batch_refreshes = batch.iter().filter_map(|index| self.refresh_step(*index));
+            match mode {
+                RefreshMode::Interleaved => {
+                    let batch_refreshes: Vec<_> = batch_refreshes.collect();
+                    steps.push(apply(batch));
+                    steps.extend(batch_refreshes);
+                }
+                RefreshMode::Deferred => {
+                    refreshes.extend(batch_refreshes);
+                    steps.push(apply(batch));
+                }
+            }
+        }
+        steps.extend(refreshes);
+        steps
+    }
+
+    /// The refresh of `index`, when something notifies or it watches paths.
+    fn refresh_step(&self, index: NodeIndex) -> Option<Step> {
+        let graph = self.graph.inner();
+        let mut triggered_by: Vec<_> = graph
+            .edges_directed(index, Direction::Incoming)
+            .filter(|edge| matches!(edge.weight(), Relation::Notify))
+            .map(|edge| edge.source())
+            .collect();
+        if triggered_by.is_empty() && watched_paths(graph[index].as_ref()).is_empty() {
+            return None;
+        }
+        triggered_by.sort();
+        triggered_by.dedup();
+        Some(Step::Refresh {
+            target: index,
+            triggered_by,
+        })
+    }
+
+    /// Groups resources into batches that may be applied in parallel: every resource only
+    /// depends on resources in earlier batches, and no batch holds two members of the same
+    /// concurrency group, even when the graph would allow it.
+    pub fn batches(&self) -> Result<Vec<Vec<NodeIndex>>> {
+        let graph = self.graph.inner();
+        let mut in_degree: HashMap<NodeIndex, usize> = graph
+            .node_indices()
+            .map(|index| {
+                let degree = graph.neighbors_directed(index, Direction::Incoming).count();
+                (index, degree)
+            })
+            .collect();
+
+        let mut batches = Vec::new();
+        while !in_degree.is_empty() {
+            let mut ready: Vec<_> = in_degree
+                .iter()
+                .filter(|(_, degree)| **degree == 0)
+                .map(|(index, _)| *index)
+                .collect();
+            if ready.is_empty() {
+                // Resources after a cycle are waiting too; report one that is on it.
+                let mut waiting: Vec<_> = in_degree.keys().copied().collect();
+                waiting.sort();
+                return Err(waiting
+                    .iter()
+                    .find_map(|index| cycle::cycle_through(graph, *index))
+                    .map_or_else(
+                        || anyhow!("Dependency cycle"),
+                        |path| anyhow!("Dependency cycle: {}", cycle::describe(graph, &path)),
+                    ));
+            }
+            ready.sort();
+
+            let mut taken_groups = HashSet::new();
+            let batch: Vec<_> = ready
+                .into_iter()
+                .filter(|index| match self.concurrency_group(*index) {
+                    Some(group) => taken_groups.insert(group),
+                    None => true,
+                })
+                .collect();
+
+            for index in &batch {
+                in_degree.remove(index);
+                for next in graph.neighbors_directed(*index, Direction::Outgoing) {
+                    if let Some(degree) = in_degree.get_mut(&next) {
+                        *degree -= 1;
+                    }
+                }
+            }
+            batches.push(batch);
+        }
+        Ok(batches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_puppet_manifest;
+    use crate::parser::pp::Manifest;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_batches_respect_concurrency_groups() -> Result<()> {
+        let input = r#"
+            exec { "apt-get install nginx": concurrency_group => 'apt' }
+            exec { "apt-get install ssh": concurrency_group => 'apt' }
+            exec { "/bin/true": }
+            service { "nginx": }
+            Exec["apt-get install nginx"] -> Service["nginx"]
+        "#;
+        let plan = parse_puppet_manifest(&Manifest::from_str(input)?)?;
+        let ids: Vec<Vec<String>> = plan
+            .batches()?
+            .iter()
+            .map(|batch| batch.iter().map(|i| plan.plan()[*i].id()).collect())
+            .collect();
+        assert_eq!(
+            ids,
+            vec![
+                vec!["Exec[apt-get install nginx]", "Exec[/bin/true]"],
+                vec!["Exec[apt-get install ssh]", "Service[nginx]"],
+            ],
+            "apt members never share a batch"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_deferred_refreshes_are_collapsed() -> Result<()> {
+        let input = r#"
+            file { "/etc/nginx/a.conf": }
+            file { "/etc/nginx/b.conf": }
+            service { "nginx": }
+            file { "/var/www/index.html": }
+            [File["/etc/nginx/a.conf"], File["/etc/nginx/b.conf"]] ~> Service["nginx"]
+            Service["nginx"] -> File["/var/www/index.html"]
+        "#;
+        let plan = parse_puppet_manifest(&Manifest::from_str(input)?)?;
+        let describe = |steps: Vec<Step>| -> Vec<String> {
+            steps
+                .into_iter()
+                .map(|step| match step {
+                    Step::Apply(i) => format!("apply {}", plan.plan()[i].id()),
+                    Step::ApplyBatch(batch) => format!("apply {} at once", batch.len()),
+                    Step::Refresh {
+                        target,
+                        triggered_by,
+                    } => format!(
+                        "refresh {} ({} triggers)",
+                        plan.plan()[target].id(),
+                        triggered_by.len()
+                    ),
+                })
+                .collect()
+        };
+
+        let deferred = describe(plan.schedule(RefreshMode::Deferred)?);
+        assert_eq!(deferred.len(), 5);
+        assert_eq!(
+            deferred.last().map(String::as_str),
+            Some("refresh Service[nginx] (2 triggers)"),
+            "Refreshes run after every resource is applied"
+        );
+
+        let interleaved = describe(plan.schedule(RefreshMode::Interleaved)?);
+        let position = |steps: &[String], step: &str| steps.iter().position(|s| s == step);
+        assert_eq!(
+            position(&interleaved, "refresh Service[nginx] (2 triggers)"),
+            position(&interleaved, "apply Service[nginx]").map(|p| p + 1)
+        );
+
+        assert_eq!(
+            describe(plan.parallel_schedule(RefreshMode::Interleaved)?),
+            [
+                "apply 2 at once",
+                "apply 1 at once",
+                "refresh Service[nginx] (2 triggers)",
+                "apply 1 at once",
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+use super::cycle::cycle_through;
+use crate::Plan;
+use petgraph::{
+    algo::toposort,
+    visit::{EdgeRef, IntoEdgeReferences},
+};
+use std::collections::HashMap;
+use std::fmt;
+
+/// A broken structural invariant of a [`Plan`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// A node is not reachable through the id index.
+    UnindexedNode { id: String },
+    /// An index entry points at a node that does not exist.
+    DanglingIndexEntry { id: String },
+    /// An index entry points at a node with a different id.
+    MisindexedNode { key: String, id: String },
+    /// An edge refers to a node that does not exist.
+    DanglingEdge { source: usize, target: usize },
+    /// Several nodes share the same id.
+    DuplicateId { id: String, count: usize },
+    /// The graph contains a cycle, through these resources back to the first.
+    Cycle { path: Vec<String> },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnindexedNode { id } => write!(f, "node {id} is missing from the id index"),
+            Self::DanglingIndexEntry { id } => {
+                write!(f, "index entry {id} points at a missing node")
+            }
+            Self::MisindexedNode { key, id } => {
+                write!(f, "index entry {key} points at node {id}")
+            }
+            Self::DanglingEdge { source, target } => {
+                write!(f, "edge {source} -> {target} has a missing endpoint")
+            }
+            Self::DuplicateId { id, count } => write!(f, "{count} nodes share the id {id}"),
+            Self::Cycle { path } => write!(f, "graph has a cycle: {}", path.join(" -> ")),
+        }
+    }
+}
+
+impl Plan {
+    /// Checks the structural invariants of the plan, returning every violation found.
+    pub fn verify(&self) -> Vec<Violation> {
+        let graph = self.graph.inner();
+        let mut violations = Vec::new();
+
+        let mut ids: HashMap<String, usize> = HashMap::new();
+        for index in graph.node_indices() {
+            let id = graph[index].id();
+            *ids.entry(id.clone()).or_default() += 1;
+            if self.index.get(&id) != Some(&index) {
+                violations.push(Violation::UnindexedNode { id });
+            }
+        }
+        let mut duplicates: Vec<_> = ids.into_iter().filter(|(_, count)| *count > 1).collect();
+        duplicates.sort();
+        for (id, count) in duplicates {
+            violations.push(Violation::DuplicateId { id, count });
+        }
+
+        let mut entries: Vec<_> = self.index.iter().collect();
+        entries.sort();
+        for (key, index) in entries {
+            match graph.node_weight(*index) {
+                None => violations.push(Violation::DanglingIndexEntry { id: key.clone() }),
+                Some(node) if node.id() != *key => violations.push(Violation::MisindexedNode {
+                    key: key.clone(),
+                    id: node.id(),
+                }),
+                Some(_) => {}
+            }
+        }
+
+        for edge in graph.edge_references() {
+            if !graph.contains_node(edge.source()) || !graph.contains_node(edge.target()) {
+                violations.push(Violation::DanglingEdge {
+                    source: edge.source().index(),
+                    target: edge.target().index(),
+                });
+            }
+        }
+
+        if let Err(cycle) = toposort(graph, None) {
+            let path = cycle_through(graph, cycle.node_id()).unwrap_or_default();
+            violations.push(Violation::Cycle {
+                path: path.iter().map(|index| graph[*index].id()).collect(),
+            });
+        }
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_puppet_manifest;
+    use crate::parser::pp::Manifest;
+    use anyhow::Result;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_verify() -> Result<()> {
+        let input = include_str!("../../res/test.pp");
+        let mut plan = parse_puppet_manifest(&Manifest::from_str(input)?)?;
+        assert!(plan.verify().is_empty(), "Parsed plans are consistent");
+
+        let nginx = plan.node("Service[nginx]").unwrap_or_default();
+        plan.index.remove("Service[nginx]");
+        plan.index.insert("Service[gone]".to_string(), nginx);
+        assert_eq!(
+            plan.verify(),
+            vec![
+                Violation::UnindexedNode {
+                    id: "Service[nginx]".to_string()
+                },
+                Violation::MisindexedNode {
+                    key: "Service[gone]".to_string(),
+                    id: "Service[nginx]".to_string()
+                },
+            ]
+        );
+        Ok(())
+    }
+}
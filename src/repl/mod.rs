@@ -0,0 +1,290 @@
+use crate::{
+    CompileOptions, DollyError, Plan,
+    facts::Facts,
+    parse_puppet_manifest_with_options,
+    parser::diagnostic::{Diagnostics, SYNTAX_ERROR},
+    parser::pp::{Manifest, PuppetExpr, ResourceRef},
+};
+use anyhow::{Result, anyhow};
+use std::io::{BufRead, Write};
+use std::str::FromStr;
+
+const HELP: &str = "\
+Enter resource declarations and relations; they are added to the session once they parse.
+Commands:
+  :graph          print the plan as dot
+  :sorted         print resources in apply order
+  :deps <ref>     print direct and transitive dependencies of a resource
+  :explain <ref>  print everything known about a resource
+  :manifest       print the accumulated manifest
+  :facts          print the facts the session compiles with
+  :fact <n>=<v>   set a fact
+  :eval <expr>    print the value of an expression, or whether a condition holds
+  :reset          discard the session
+  :help           print this help
+  :quit           leave the repl";
+
+/// Incrementally builds a manifest from pasted input and answers queries on the plan.
+#[derive(Debug, Default)]
+pub struct Repl {
+    source: String,
+    pending: String,
+    facts: Facts,
+}
+
+pub enum Outcome {
+    Output(String),
+    Incomplete,
+    Quit,
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A session compiling and evaluating expressions with `facts`.
+    pub fn with_facts(facts: Facts) -> Self {
+        Self {
+            facts,
+            ..Self::default()
+        }
+    }
+
+    pub fn eval(&mut self, line: &str) -> Result<Outcome> {
+        let trimmed = line.trim();
+        if self.pending.is_empty() && trimmed.starts_with(':') {
+            return self.command(trimmed);
+        }
+        if trimmed.is_empty() && self.pending.is_empty() {
+            return Ok(Outcome::Output(String::new()));
+        }
+
+        self.pending.push_str(line);
+        self.pending.push('\n');
+        let candidate = format!("{}{}", self.source, self.pending);
+        match Manifest::from_str(&candidate) {
+            Ok(manifest) => {
+                self.compile(&manifest)
+                    .inspect_err(|_| self.pending.clear())?;
+                self.source = candidate;
+                self.pending.clear();
+                Ok(Outcome::Output(String::new()))
+            }
+            Err(e) if !trimmed.is_empty() && is_incomplete(&e, candidate.len()) => {
+                Ok(Outcome::Incomplete)
+            }
+            Err(e) => {
+                self.pending.clear();
+                Err(e.into())
+            }
+        }
+    }
+
+    fn command(&mut self, line: &str) -> Result<Outcome> {
+        let (command, arg) = line.split_once(' ').unwrap_or((line, ""));
+        let arg = arg.trim();
+        let output = match command {
+            ":quit" | ":q" => return Ok(Outcome::Quit),
+            ":help" => HELP.to_string(),
+            ":reset" => {
+                self.source.clear();
+                String::new()
+            }
+            ":manifest" => self.manifest()?.to_string(),
+            ":facts" => self
+                .facts
+                .0
+                .iter()
+                .map(|(name, value)| format!("{name} => {value}"))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            ":fact" => {
+                self.facts.merge(Facts::from_overrides([arg])?);
+                String::new()
+            }
+            ":eval" => self.evaluate(arg)?,
+            ":graph" => format!("{:?}", self.plan()?.dot()),
+            ":sorted" => self
+                .plan()?
+                .sorted_weights()?
+                .values()
+                .map(|node| node.id())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            ":deps" => {
+                let explanation = self.plan()?.explain(&normalize_ref(arg)?)?;
+                let mut lines = Vec::new();
+                for (id, relation) in explanation.dependencies {
+                    lines.push(format!("{id} {relation}"));
+                }
+                for id in explanation.transitive_dependencies {
+                    if !lines.iter().any(|l| l.starts_with(&format!("{id} "))) {
+                        lines.push(format!("{id} (transitive)"));
+                    }
+                }
+                lines.join("\n")
+            }
+            ":explain" => self.plan()?.explain(&normalize_ref(arg)?)?.to_string(),
+            unknown => return Err(anyhow!("Unknown command: {unknown} (try :help)")),
+        };
+        Ok(Outcome::Output(output))
+    }
+
+    fn manifest(&self) -> Result<Manifest> {
+        Ok(Manifest::from_str(&self.source)?)
+    }
+
+    fn plan(&self) -> Result<Plan> {
+        self.compile(&self.manifest()?)
+    }
+
+    fn compile(&self, manifest: &Manifest) -> Result<Plan> {
+        let options = CompileOptions {
+            facts: self.facts.clone(),
+            ..CompileOptions::default()
+        };
+        Ok(parse_puppet_manifest_with_options(manifest, &options)?)
+    }
+
+    /// The value of `expression` with the session facts, evaluated as the value of an
+    /// attribute or, failing that, as the condition of an `if`.
+    fn evaluate(&self, expression: &str) -> Result<String> {
+        if expression.is_empty() {
+            return Err(anyhow!("Usage: :eval <expression>"));
+        }
+        let value = format!("{PROBE} {{ 'value': value => {expression} }}");
+        let condition = format!(
+            "if {expression} {{ {PROBE} {{ 'true': }} }} else {{ {PROBE} {{ 'false': }} }}"
+        );
+        let manifest = match Manifest::from_str(&value) {
+            Ok(manifest) => manifest,
+            Err(e) => Manifest::from_str(&condition).map_err(|_| e)?,
+        };
+        let evaluated = manifest.evaluate(&self.facts)?;
+        evaluated
+            .manifest
+            .resources()
+            .find_map(|resource| match resource {
+                PuppetExpr::Resource {
+                    title, attributes, ..
+                } => Some(match attributes.iter().find(|a| a.name == "value") {
+                    Some(attribute) => attribute.value.to_string(),
+                    None => title.to_string(),
+                }),
+                _ => None,
+            })
+            .ok_or_else(|| anyhow!("{expression} evaluates to nothing"))
+    }
+}
+
+/// The resource type `:eval` wraps expressions in; it is evaluated, never compiled.
+const PROBE: &str = "dolly_repl_probe";
+
+/// Turns `File['/x']` into the `File[/x]` id used by the plan.
+fn normalize_ref(reference: &str) -> Result<String> {
+    Ok(reference.parse::<ResourceRef>()?.id())
+}
+
+/// Whether parsing failed only because the input ended, as it does midway through a
+/// multi-line statement.
+fn is_incomplete(error: &DollyError, len: usize) -> bool {
+    let Some(Diagnostics(diagnostics)) = error.diagnostics() else {
+        return false;
+    };
+    diagnostics
+        .iter()
+        .any(|d| d.message.starts_with(SYNTAX_ERROR) && d.span.end >= len)
+}
+
+pub fn run(facts: Facts, input: impl BufRead, mut output: impl Write) -> Result<()> {
+    let mut repl = Repl::with_facts(facts);
+    write!(output, "dolly> ")?;
+    output.flush()?;
+    for line in input.lines() {
+        match repl.eval(&line?) {
+            Ok(Outcome::Quit) => break,
+            Ok(Outcome::Incomplete) => write!(output, "...... ")?,
+            Ok(Outcome::Output(text)) => {
+                if !text.is_empty() {
+                    writeln!(output, "{}", text.trim_end())?;
+                }
+                write!(output, "dolly> ")?;
+            }
+            Err(e) => {
+                writeln!(output, "error: {e}")?;
+                write!(output, "dolly> ")?;
+            }
+        }
+        output.flush()?;
+    }
+    writeln!(output)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(outcome: Outcome) -> String {
+        match outcome {
+            Outcome::Output(text) => text,
+            Outcome::Incomplete => "<incomplete>".to_string(),
+            Outcome::Quit => "<quit>".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_repl_incremental_session() -> Result<()> {
+        let mut repl = Repl::new();
+        assert_eq!(output(repl.eval("file { '/tmp/one':")?), "<incomplete>");
+        assert_eq!(output(repl.eval("  mode => '0644',")?), "<incomplete>");
+        assert_eq!(output(repl.eval("}")?), "");
+        repl.eval("service { 'nginx': }")?;
+        repl.eval("File['/tmp/one'] -> Service['nginx']")?;
+
+        assert_eq!(
+            output(repl.eval(":sorted")?),
+            "File[/tmp/one]\nService[nginx]"
+        );
+        assert_eq!(
+            output(repl.eval(":deps Service['nginx']")?),
+            "File[/tmp/one] ->"
+        );
+        assert!(
+            repl.eval("Service['nginx'] -> File['/tmp/missing']")
+                .is_err(),
+            "Undefined references are rejected"
+        );
+        assert_eq!(
+            output(repl.eval(":sorted")?),
+            "File[/tmp/one]\nService[nginx]",
+            "Rejected input does not change the session"
+        );
+        assert_eq!(output(repl.eval(":quit")?), "<quit>");
+        Ok(())
+    }
+
+    #[test]
+    fn test_repl_evaluates_with_facts() -> Result<()> {
+        let mut repl = Repl::with_facts(Facts::from_overrides(["os.family=Debian"])?);
+        repl.eval(":fact hostname=web1")?;
+        assert_eq!(
+            output(repl.eval(":facts")?),
+            "hostname => web1\nos.family => Debian"
+        );
+        assert_eq!(
+            output(repl.eval(":eval \"${facts.hostname}.example.com\"")?),
+            "web1.example.com"
+        );
+        assert_eq!(
+            output(repl.eval(":eval $facts['os']['family'] == 'Debian'")?),
+            "true"
+        );
+        assert_eq!(output(repl.eval(":eval $::osfamily == 'RedHat'")?), "false");
+
+        repl.eval("if $facts['os']['family'] == 'Debian' { package { 'apt': } }")?;
+        assert_eq!(output(repl.eval(":sorted")?), "Package[apt]");
+        Ok(())
+    }
+}
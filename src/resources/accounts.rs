@@ -0,0 +1,142 @@
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::process::Command;
+use std::sync::{Mutex, OnceLock};
+
+/// The account databases owners and groups are resolved in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Database {
+    Passwd,
+    Group,
+}
+
+impl fmt::Display for Database {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Passwd => write!(f, "passwd"),
+            Self::Group => write!(f, "group"),
+        }
+    }
+}
+
+/// A user or a group: its name and its uid or gid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Account {
+    pub name: String,
+    pub id: u32,
+    /// The primary gid of a user; `None` for groups.
+    pub gid: Option<u32>,
+}
+
+/// Found accounts by database and by name or id. Accounts that are not found are looked
+/// up again, since a User or Group applied earlier in the run may have created them.
+fn cache() -> &'static Mutex<HashMap<(Database, String), Account>> {
+    static CACHE: OnceLock<Mutex<HashMap<(Database, String), Account>>> = OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+impl Database {
+    /// The account named or numbered `key`, through NSS with `getent` so that LDAP or
+    /// sssd accounts resolve too, or through `/etc/passwd` and `/etc/group` without it.
+    pub fn lookup(self, key: &str) -> Option<Account> {
+        let cache_key = (self, key.to_string());
+        if let Some(account) = cache().lock().ok()?.get(&cache_key) {
+            return Some(account.clone());
+        }
+        let account = match Command::new("getent")
+            .args([&self.to_string(), key])
+            .output()
+        {
+            Ok(output) => String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .find_map(|line| self.parse_entry(line)),
+            Err(_) => fs::read_to_string(format!("/etc/{self}"))
+                .ok()?
+                .lines()
+                .filter_map(|line| self.parse_entry(line))
+                .find(|account| account.name == key || account.id.to_string() == key),
+        }?;
+        let mut cache = cache().lock().ok()?;
+        cache.insert((self, account.name.clone()), account.clone());
+        cache.insert((self, account.id.to_string()), account.clone());
+        Some(account)
+    }
+
+    /// The id of a user or group name. Numeric ids are taken as they are, whether or not
+    /// an account has them.
+    pub fn id(self, name: &str) -> Result<u32> {
+        if let Ok(id) = name.parse() {
+            return Ok(id);
+        }
+        self.lookup(name)
+            .map(|account| account.id)
+            .ok_or_else(|| anyhow!("Unknown name {name} in {self}"))
+    }
+
+    /// The name of the account with `id`, or the id itself if it has none.
+    pub fn name(self, id: u32) -> String {
+        let id = id.to_string();
+        self.lookup(&id).map_or(id, |account| account.name)
+    }
+
+    /// `name:password:id:...`, the format passwd and group entries share. Passwd entries
+    /// go on with the user's primary gid.
+    fn parse_entry(self, line: &str) -> Option<Account> {
+        let mut fields = line.split(':');
+        let name = fields.next()?.to_string();
+        let id = fields.nth(1)?.parse().ok()?;
+        let gid = match self {
+            Self::Passwd => Some(fields.next()?.parse().ok()?),
+            Self::Group => None,
+        };
+        Some(Account { name, id, gid })
+    }
+}
+
+/// The primary gid of the user named or numbered `user`.
+pub fn primary_gid(user: &str) -> Result<u32> {
+    Database::Passwd
+        .lookup(user)
+        .and_then(|account| account.gid)
+        .ok_or_else(|| anyhow!("Unknown name {user} in passwd"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_account_lookups() -> Result<()> {
+        assert_eq!(Database::Passwd.id("root")?, 0);
+        assert_eq!(Database::Group.id("root")?, 0);
+        assert_eq!(Database::Passwd.name(0), "root");
+        assert_eq!(
+            Database::Passwd.id("4242")?,
+            4242,
+            "Numeric ids need no account"
+        );
+        assert_eq!(Database::Passwd.name(4242_4242), "42424242");
+        assert_eq!(
+            cache()
+                .lock()
+                .map_err(|_| anyhow!("Poisoned cache"))?
+                .get(&(Database::Passwd, "0".into()))
+                .cloned(),
+            Some(Account {
+                name: "root".into(),
+                id: 0,
+                gid: Some(0),
+            }),
+            "Found accounts are cached by id as well as by name"
+        );
+        let Err(e) = Database::Group.id("no-such-group") else {
+            return Err(anyhow!("Unknown groups should fail"));
+        };
+        assert_eq!(e.to_string(), "Unknown name no-such-group in group");
+        assert_eq!(primary_gid("root")?, 0);
+        assert!(primary_gid("no-such-user").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,53 @@
+use std::env;
+use std::path::Path;
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackageManager {
+    Apt,
+    Dnf,
+    Pacman,
+}
+
+impl PackageManager {
+    pub fn command(&self) -> &'static str {
+        match self {
+            Self::Apt => "apt-get",
+            Self::Dnf => "dnf",
+            Self::Pacman => "pacman",
+        }
+    }
+}
+
+/// What the current system offers to resource providers, probed once per run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    pub systemd: bool,
+    pub package_manager: Option<PackageManager>,
+}
+
+impl Capabilities {
+    pub fn probe() -> Self {
+        let path = env::var("PATH").unwrap_or_default();
+        Self {
+            systemd: Path::new("/run/systemd/system").is_dir(),
+            package_manager: [
+                PackageManager::Apt,
+                PackageManager::Dnf,
+                PackageManager::Pacman,
+            ]
+            .into_iter()
+            .find(|pm| find_in_path(&path, pm.command())),
+        }
+    }
+
+    /// The capabilities of this system, probed on first use.
+    pub fn cached() -> &'static Self {
+        static CAPABILITIES: OnceLock<Capabilities> = OnceLock::new();
+        CAPABILITIES.get_or_init(Self::probe)
+    }
+}
+
+fn find_in_path(path: &str, command: &str) -> bool {
+    env::split_paths(path).any(|dir| dir.join(command).is_file())
+}
@@ -0,0 +1,36 @@
+use super::resource::{Attributes, ChangeReport, Ensure, PropertyChange, Resource};
+use anyhow::Result;
+
+/// A declared class. It manages nothing itself: the resources it contains are ordered
+/// before it, so relations to the class apply to everything inside.
+#[derive(Debug, Clone)]
+pub struct Class {
+    pub title: String,
+    pub attributes: Attributes,
+}
+
+impl Resource for Class {
+    fn rtype(&self) -> &str {
+        "Class"
+    }
+
+    fn title(&self) -> String {
+        self.title.clone()
+    }
+
+    fn attributes(&self) -> &Attributes {
+        &self.attributes
+    }
+
+    fn check(&self, _ensure: Ensure) -> Result<Vec<PropertyChange>> {
+        Ok(vec![])
+    }
+
+    fn ensure(&self, ensure: Ensure) -> Result<ChangeReport> {
+        Ok(ChangeReport::new(vec![PropertyChange::new(
+            "ensure",
+            None::<Ensure>,
+            ensure,
+        )]))
+    }
+}
@@ -0,0 +1,31 @@
+use crate::facts::Facts;
+use std::fmt;
+
+/// Restricts a resource type or provider to nodes where `fact` has one of `values`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Confine {
+    pub fact: String,
+    pub values: Vec<String>,
+}
+
+impl Confine {
+    pub fn new(fact: &str, values: &[&str]) -> Self {
+        Self {
+            fact: fact.to_string(),
+            values: values.iter().map(|v| v.to_string()).collect(),
+        }
+    }
+
+    /// Fact values are compared case-insensitively; a missing fact never matches.
+    pub fn matches(&self, facts: &Facts) -> bool {
+        facts
+            .get(&self.fact)
+            .is_some_and(|value| self.values.iter().any(|v| v.eq_ignore_ascii_case(value)))
+    }
+}
+
+impl fmt::Display for Confine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} in [{}]", self.fact, self.values.join(", "))
+    }
+}
@@ -0,0 +1,80 @@
+//! Unified diffs of file content for reports, so that a content change says what changed
+//! rather than only that the checksum did.
+
+use similar::TextDiff;
+
+/// Content larger than this, on either side, is not diffed.
+pub const MAX_DIFF_BYTES: usize = 1024 * 1024;
+/// Diffs are cut after this many lines.
+pub const MAX_DIFF_LINES: usize = 200;
+/// How far into the content to look for the NUL bytes that mark it binary, as git does.
+const BINARY_PROBE_BYTES: usize = 8000;
+
+/// What a diff of sensitive content is replaced with.
+pub const REDACTED: &str = "content is Sensitive, diff redacted";
+
+/// The changes from `current` to `desired` of the file at `path`, as `diff -u` prints
+/// them, or a one-line reason when they cannot be shown.
+pub fn unified_diff(path: &str, current: &[u8], desired: &[u8]) -> String {
+    if current.len().max(desired.len()) > MAX_DIFF_BYTES {
+        return format!(
+            "content too large to diff ({} -> {} bytes)",
+            current.len(),
+            desired.len()
+        );
+    }
+    let (Some(current), Some(desired)) = (text(current), text(desired)) else {
+        return format!("Binary files {path} differ");
+    };
+    let diff = TextDiff::from_lines(current, desired);
+    let diff = diff
+        .unified_diff()
+        .header(path, path)
+        .missing_newline_hint(true)
+        .to_string();
+    let lines: Vec<_> = diff.lines().collect();
+    match lines.len() > MAX_DIFF_LINES {
+        true => format!(
+            "{}\n... {} more lines\n",
+            lines[..MAX_DIFF_LINES].join("\n"),
+            lines.len() - MAX_DIFF_LINES
+        ),
+        false => diff,
+    }
+}
+
+/// `bytes` as text, unless they hold a NUL byte early on or are not UTF-8.
+fn text(bytes: &[u8]) -> Option<&str> {
+    match bytes.iter().take(BINARY_PROBE_BYTES).any(|byte| *byte == 0) {
+        true => None,
+        false => std::str::from_utf8(bytes).ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_diffs_are_capped_and_binary_aware() -> Result<()> {
+        let diff = unified_diff("/etc/motd", b"hello\nworld\n", b"hello\nthere\n");
+        assert_eq!(
+            diff,
+            "--- /etc/motd\n+++ /etc/motd\n@@ -1,2 +1,2 @@\n hello\n-world\n+there\n"
+        );
+        assert_eq!(
+            unified_diff("/bin/x", b"\x7fELF\0\0", b"\x7fELF\0\x01"),
+            "Binary files /bin/x differ"
+        );
+
+        let long: String = (0..1000).map(|i| format!("{i}\n")).collect();
+        let diff = unified_diff("/a", b"", long.as_bytes());
+        assert_eq!(diff.lines().count(), MAX_DIFF_LINES + 1);
+        assert!(diff.ends_with(&format!("... {} more lines\n", 1003 - MAX_DIFF_LINES)));
+
+        let huge = vec![b'x'; MAX_DIFF_BYTES + 1];
+        assert!(unified_diff("/a", b"x", &huge).starts_with("content too large"));
+        Ok(())
+    }
+}
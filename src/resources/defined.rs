@@ -0,0 +1,37 @@
+use super::resource::{Attributes, ChangeReport, Ensure, PropertyChange, Resource};
+use anyhow::Result;
+
+/// An instance of a defined type. Like a class it manages nothing itself: the resources
+/// its body expands to are ordered before it.
+#[derive(Debug, Clone)]
+pub struct Defined {
+    pub rtype: String,
+    pub title: String,
+    pub attributes: Attributes,
+}
+
+impl Resource for Defined {
+    fn rtype(&self) -> &str {
+        &self.rtype
+    }
+
+    fn title(&self) -> String {
+        self.title.clone()
+    }
+
+    fn attributes(&self) -> &Attributes {
+        &self.attributes
+    }
+
+    fn check(&self, _ensure: Ensure) -> Result<Vec<PropertyChange>> {
+        Ok(vec![])
+    }
+
+    fn ensure(&self, ensure: Ensure) -> Result<ChangeReport> {
+        Ok(ChangeReport::new(vec![PropertyChange::new(
+            "ensure",
+            None::<Ensure>,
+            ensure,
+        )]))
+    }
+}
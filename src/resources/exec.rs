@@ -1,16 +1,350 @@
-use super::resource::{Ensure, Resource};
+use super::accounts::{Database, primary_gid};
+use super::output::{LogLine, capture};
+use super::resource::{ApplyContext, Attributes, ChangeReport, Ensure, PropertyChange, Resource};
+use crate::apply::deferred::capture_output;
+use crate::parser::pp::Attribute;
+use crate::parser::units::parse_size;
+use crate::system::{Spawned, System};
+use anyhow::{Context, Result, anyhow};
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Environment variables kept when an Exec runs with a clean environment.
+const KEPT_ENV: &[&str] = &["PATH", "HOME", "LANG", "TERM"];
+
+/// Restrictions for running Exec commands. A global policy can be overridden per resource.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExecPolicy {
+    pub user: Option<String>,
+    /// The group to run as; the primary group of `user` when not set.
+    pub group: Option<String>,
+    pub clean_environment: Option<bool>,
+    /// Wall-clock time after which the command is killed.
+    pub timeout: Option<Duration>,
+    /// `ulimit -t`, in seconds.
+    pub cpu_seconds: Option<u64>,
+    /// `ulimit -v`, in kilobytes.
+    pub memory_kb: Option<u64>,
+    /// `ulimit -n`.
+    pub open_files: Option<u64>,
+    /// Bytes of stdout/stderr kept per stream; the tail is kept. Unlimited when `None`.
+    /// Every line is still streamed to the log.
+    pub max_output: Option<usize>,
+}
+
+impl ExecPolicy {
+    /// The Exec attributes a policy is read from.
+    pub const ATTRIBUTES: &[&str] = &[
+        "user",
+        "group",
+        "clean_environment",
+        "timeout",
+        "ulimit_cpu",
+        "ulimit_memory",
+        "ulimit_nofile",
+        "max_output",
+    ];
+
+    pub fn from_attributes(attributes: &[Attribute]) -> Result<Self> {
+        let mut policy = Self::default();
+        for attr in attributes {
+            let value = || {
+                attr.value
+                    .as_literal()
+                    .ok_or_else(|| anyhow!("Exec {} must be a literal", attr.name))
+            };
+            let size = || -> Result<u64> {
+                parse_size(&value()?).with_context(|| format!("Exec {}", attr.name))
+            };
+            match attr.name.as_str() {
+                "user" => policy.user = attr.parse_as("Exec")?,
+                "group" => policy.group = attr.parse_as("Exec")?,
+                "clean_environment" => policy.clean_environment = attr.parse_as("Exec")?,
+                "timeout" => policy.timeout = attr.parse_as("Exec")?,
+                "ulimit_cpu" => {
+                    policy.cpu_seconds = Some(attr.parse_as::<Duration>("Exec")?.as_secs())
+                }
+                "ulimit_memory" => policy.memory_kb = Some(size()? / 1024),
+                "ulimit_nofile" => policy.open_files = attr.parse_as("Exec")?,
+                "max_output" => policy.max_output = Some(size()? as usize),
+                _ => {}
+            }
+        }
+        Ok(policy)
+    }
+
+    /// This policy with every setting `other` specifies taking precedence.
+    pub fn merged(&self, other: &Self) -> Self {
+        Self {
+            user: other.user.clone().or_else(|| self.user.clone()),
+            group: other.group.clone().or_else(|| self.group.clone()),
+            clean_environment: other.clean_environment.or(self.clean_environment),
+            timeout: other.timeout.or(self.timeout),
+            cpu_seconds: other.cpu_seconds.or(self.cpu_seconds),
+            memory_kb: other.memory_kb.or(self.memory_kb),
+            open_files: other.open_files.or(self.open_files),
+            max_output: other.max_output.or(self.max_output),
+        }
+    }
+
+    fn command(&self, command: &str) -> Result<Command> {
+        let mut script = String::new();
+        for (flag, limit) in [
+            ("-t", self.cpu_seconds),
+            ("-v", self.memory_kb),
+            ("-n", self.open_files),
+        ] {
+            if let Some(limit) = limit {
+                script.push_str(&format!("ulimit {flag} {limit} && "));
+            }
+        }
+        script.push_str("exec /bin/sh -c \"$1\"");
+
+        let mut cmd = Command::new("/bin/sh");
+        cmd.args(["-c", &script, "sh", command]);
+        if self.clean_environment == Some(true) {
+            cmd.env_clear();
+            for key in KEPT_ENV {
+                if let Ok(value) = std::env::var(key) {
+                    cmd.env(key, value);
+                }
+            }
+        }
+        match (&self.group, &self.user) {
+            (Some(group), _) => {
+                cmd.gid(Database::Group.id(group)?);
+            }
+            (None, Some(user)) => {
+                cmd.gid(primary_gid(user)?);
+            }
+            (None, None) => {}
+        }
+        if let Some(user) = &self.user {
+            cmd.uid(Database::Passwd.id(user)?);
+        }
+        Ok(cmd)
+    }
+}
+
+/// Result of running an Exec command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecOutput {
+    pub status: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    /// Whether stdout or stderr were cut to the policy's `max_output`.
+    pub truncated: bool,
+}
+
+impl ExecOutput {
+    pub fn success(&self) -> bool {
+        self.status == Some(0)
+    }
+}
+
+/// What an Exec runs and when, checked when the manifest is compiled.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExecSpec {
+    /// The command to run; the title when not set.
+    pub command: Option<String>,
+    pub cwd: Option<PathBuf>,
+    /// Extra variables, set after a clean environment is applied.
+    pub environment: Vec<(String, String)>,
+    /// A file the command creates; the command does not run once it exists.
+    pub creates: Option<PathBuf>,
+    /// The command runs only if this one succeeds.
+    pub onlyif: Option<String>,
+    /// The command runs only if this one fails.
+    pub unless: Option<String>,
+    /// Exit codes that count as success. Only 0 when empty.
+    pub returns: Vec<i32>,
+    /// Whether stdout is kept for later resources, as `Deferred('exec_output', [title])`.
+    pub capture_output: bool,
+}
+
+impl ExecSpec {
+    pub fn from_attributes(attributes: &[Attribute]) -> Result<Self> {
+        let mut spec = Self::default();
+        for attr in attributes {
+            // One literal, string or integer, or an array of them.
+            let values = || -> Result<Vec<String>> {
+                attr.value
+                    .as_array()
+                    .into_iter()
+                    .map(|value| {
+                        value
+                            .as_literal()
+                            .ok_or_else(|| anyhow!("Exec {} must be literals", attr.name))
+                    })
+                    .collect()
+            };
+            match attr.name.as_str() {
+                "command" => spec.command = attr.parse_as("Exec")?,
+                "cwd" => spec.cwd = attr.parse_as("Exec")?,
+                "environment" => {
+                    for value in values()? {
+                        for line in value.lines().filter(|line| !line.trim().is_empty()) {
+                            let (name, value) = line.trim().split_once('=').ok_or_else(|| {
+                                anyhow!("Exec environment must be NAME=VALUE, got {line}")
+                            })?;
+                            spec.environment.push((name.to_string(), value.to_string()));
+                        }
+                    }
+                }
+                "creates" => spec.creates = attr.parse_as("Exec")?,
+                "onlyif" => spec.onlyif = attr.parse_as("Exec")?,
+                "unless" => spec.unless = attr.parse_as("Exec")?,
+                "capture_output" => spec.capture_output = attr.parse_as("Exec")?,
+                "returns" => {
+                    spec.returns = values()?
+                        .iter()
+                        .flat_map(|value| value.split([',', ' ']))
+                        .filter(|code| !code.is_empty())
+                        .map(|code| {
+                            code.parse()
+                                .with_context(|| format!("Exec returns must be numbers: {code}"))
+                        })
+                        .collect::<Result<_>>()?;
+                }
+                _ => {}
+            }
+        }
+        Ok(spec)
+    }
+
+    fn accepts(&self, status: Option<i32>) -> bool {
+        match status {
+            Some(code) if self.returns.is_empty() => code == 0,
+            Some(code) => self.returns.contains(&code),
+            None => false,
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Exec {
     pub title: String,
+    pub attributes: Attributes,
+    pub spec: ExecSpec,
+    pub sandbox: ExecPolicy,
+    /// Where the command runs and `creates` is looked for.
+    pub system: System,
 }
 
 impl Exec {
-    fn ensure_present(&self) {
-        println!("Ensure present: {}", self.title);
+    /// Runs the command under `policy`, with this resource's own settings taking precedence.
+    pub fn run(&self, policy: &ExecPolicy) -> Result<ExecOutput> {
+        self.run_streaming(policy, &mut |_| {})
+    }
+
+    /// Like [`Exec::run`], passing every output line to `on_line` as it is produced.
+    pub fn run_streaming(
+        &self,
+        policy: &ExecPolicy,
+        on_line: &mut dyn FnMut(LogLine),
+    ) -> Result<ExecOutput> {
+        self.spawn(self.command_line(), policy, on_line)
+    }
+
+    /// The command this exec runs.
+    pub fn command_line(&self) -> &str {
+        self.spec.command.as_deref().unwrap_or(&self.title)
     }
-    fn ensure_absent(&self) {
-        println!("Ensure absent: {}", self.title);
+
+    /// Runs `command` in this exec's directory and environment.
+    fn spawn(
+        &self,
+        command: &str,
+        policy: &ExecPolicy,
+        on_line: &mut dyn FnMut(LogLine),
+    ) -> Result<ExecOutput> {
+        let policy = policy.merged(&self.sandbox);
+        let mut cmd = policy.command(command)?;
+        if let Some(cwd) = &self.spec.cwd {
+            cmd.current_dir(cwd);
+        }
+        cmd.envs(
+            self.spec
+                .environment
+                .iter()
+                .map(|(name, value)| (name, value)),
+        );
+        let Spawned {
+            stdout,
+            stderr,
+            mut process,
+        } = self
+            .system
+            .processes
+            .spawn(&mut cmd)
+            .with_context(|| format!("spawning {command}"))?;
+        // A timeout too long to add to the clock is no deadline at all.
+        let deadline = policy
+            .timeout
+            .and_then(|timeout| Instant::now().checked_add(timeout));
+        let (tails, status) = thread::scope(|scope| {
+            let waiter = scope.spawn(move || process.wait_until(deadline));
+            let tails = capture(stdout, stderr, &self.id(), policy.max_output, on_line);
+            let status = waiter
+                .join()
+                .map_err(|_| anyhow!("waiting for {} panicked", self.title))
+                .and_then(|status| Ok(status?));
+            (tails, status)
+        });
+        let (stdout, stderr) = tails;
+        let status = status.with_context(|| format!("waiting for {}", self.title))?;
+        Ok(ExecOutput {
+            status: status.code(),
+            truncated: stdout.truncated() || stderr.truncated(),
+            stdout: stdout.into_string(),
+            stderr: stderr.into_string(),
+        })
+    }
+
+    /// Why the command would not run: `creates` exists, `onlyif` fails or `unless`
+    /// succeeds, running the checks under `policy`. `None` when it should run.
+    pub fn skip_reason(&self, policy: &ExecPolicy) -> Result<Option<String>> {
+        if let Some(creates) = &self.spec.creates
+            && self.system.fs.exists(creates)
+        {
+            return Ok(Some(format!("{} exists", creates.display())));
+        }
+        if let Some(onlyif) = &self.spec.onlyif
+            && !self.spawn(onlyif, policy, &mut |_| {})?.success()
+        {
+            return Ok(Some(format!("onlyif {onlyif} failed")));
+        }
+        if let Some(unless) = &self.spec.unless
+            && self.spawn(unless, policy, &mut |_| {})?.success()
+        {
+            return Ok(Some(format!("unless {unless} succeeded")));
+        }
+        Ok(None)
+    }
+
+    /// Runs the command under `policy` and fails, with its stderr, unless it exits with an
+    /// accepted code.
+    pub fn execute(&self, policy: &ExecPolicy) -> Result<ExecOutput> {
+        self.accept(self.run(policy)?)
+    }
+
+    /// `output`, or with its stderr the failure it is unless its exit code is accepted.
+    fn accept(&self, output: ExecOutput) -> Result<ExecOutput> {
+        if self.spec.accepts(output.status) {
+            return Ok(output);
+        }
+        let stderr = output.stderr.trim_end();
+        let failure = match output.status {
+            Some(code) => format!("{} returned {code}", self.command_line()),
+            None => format!("{} was killed by a signal", self.command_line()),
+        };
+        match stderr.is_empty() {
+            true => Err(anyhow!(failure)),
+            false => Err(anyhow!("{failure}: {stderr}")),
+        }
     }
 }
 
@@ -23,14 +357,288 @@ impl Resource for Exec {
         self.title.clone()
     }
 
-    fn ensure(&self, ensure: super::resource::Ensure) {
+    fn attributes(&self) -> &Attributes {
+        &self.attributes
+    }
+
+    fn preview(&self, ensure: Ensure) -> Vec<String> {
         match ensure {
-            Ensure::Present => {
-                self.ensure_present();
-            }
-            Ensure::Absent => {
-                self.ensure_absent();
-            }
+            Ensure::Absent => vec![],
+            _ => vec![format!("would run: {}", self.command_line())],
+        }
+    }
+
+    fn check(&self, ensure: Ensure) -> Result<Vec<PropertyChange>> {
+        self.check_in(ensure, &ApplyContext::default())
+    }
+
+    fn ensure(&self, ensure: Ensure) -> Result<ChangeReport> {
+        self.ensure_in(ensure, &ApplyContext::default())
+    }
+
+    /// An exec is in sync when `creates`, `onlyif` or `unless` say it need not run.
+    fn check_in(&self, ensure: Ensure, context: &ApplyContext) -> Result<Vec<PropertyChange>> {
+        if ensure == Ensure::Absent || self.skip_reason(&context.exec_policy)?.is_some() {
+            return Ok(vec![]);
         }
+        let expected = self.spec.returns.first().copied().unwrap_or(0);
+        Ok(vec![PropertyChange::new(
+            "returns",
+            Some("notrun"),
+            expected,
+        )])
+    }
+
+    fn ensure_in(&self, ensure: Ensure, context: &ApplyContext) -> Result<ChangeReport> {
+        if ensure == Ensure::Absent {
+            return Ok(ChangeReport::unchanged());
+        }
+        let output =
+            self.run_streaming(&context.exec_policy, &mut |line| context.log.write(&line))?;
+        context.keep_output(&output);
+        let output = self.accept(output)?;
+        if self.spec.capture_output {
+            capture_output(&self.title, &output.stdout)?;
+        }
+        let returns = output
+            .status
+            .map_or("signal".to_string(), |code| code.to_string());
+        Ok(ChangeReport::new(vec![PropertyChange::new(
+            "returns",
+            Some("notrun"),
+            returns,
+        )]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resources::output::Stream;
+    use std::fs;
+
+    fn exec(command: &str, sandbox: ExecPolicy) -> Exec {
+        Exec {
+            title: command.to_string(),
+            attributes: Attributes::new(),
+            spec: ExecSpec::default(),
+            sandbox,
+            system: System::default(),
+        }
+    }
+
+    #[test]
+    fn test_run_captures_output() -> Result<()> {
+        let output = exec("echo out; echo err >&2; exit 3", ExecPolicy::default())
+            .run(&ExecPolicy::default())?;
+        assert_eq!(output.status, Some(3));
+        assert_eq!(output.stdout, "out\n");
+        assert_eq!(output.stderr, "err\n");
+        assert!(!output.truncated);
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_applies_policy() -> Result<()> {
+        let global = ExecPolicy {
+            clean_environment: Some(true),
+            max_output: Some(4),
+            ..ExecPolicy::default()
+        };
+        let own = ExecPolicy {
+            open_files: Some(64),
+            ..ExecPolicy::default()
+        };
+        let output = exec("echo ${CARGO:-unset}; ulimit -n", own).run(&global)?;
+        assert!(output.success());
+        assert_eq!(output.stdout, "\n64\n", "Only the tail is kept");
+        assert!(output.truncated);
+
+        let own = ExecPolicy {
+            clean_environment: Some(false),
+            ..ExecPolicy::default()
+        };
+        assert_eq!(
+            global.merged(&own).clean_environment,
+            Some(false),
+            "The resource's own setting wins"
+        );
+        let output = exec("echo ${CARGO:-unset}", own).run(&global)?;
+        assert_ne!(output.stdout, "unset\n");
+
+        let root = ExecPolicy {
+            user: Some("root".to_string()),
+            ..ExecPolicy::default()
+        };
+        // Switching users needs root; elsewhere the spawn fails.
+        if let Ok(output) = exec("id -g", ExecPolicy::default()).run(&root)
+            && output.success()
+        {
+            assert_eq!(output.stdout, "0\n", "The user's primary group is used");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_streams_labelled_lines() -> Result<()> {
+        let policy = ExecPolicy {
+            max_output: Some(6),
+            ..ExecPolicy::default()
+        };
+        let mut lines = Vec::new();
+        let output = exec("echo one; echo two; echo oops >&2", ExecPolicy::default())
+            .run_streaming(&policy, &mut |line| lines.push(line))?;
+        assert_eq!(output.stdout, "e\ntwo\n");
+        let stdout: Vec<_> = lines
+            .iter()
+            .filter(|l| l.stream == Stream::Stdout)
+            .map(|l| l.line.as_str())
+            .collect();
+        assert_eq!(stdout, vec!["one", "two"], "The log has every line");
+        assert!(
+            lines
+                .iter()
+                .any(|l| l.stream == Stream::Stderr && l.line == "oops")
+        );
+        assert!(
+            lines
+                .iter()
+                .all(|l| l.resource.starts_with("Exec[echo one"))
+        );
+
+        lines.clear();
+        let output = exec("printf 0123456789abc", ExecPolicy::default())
+            .run_streaming(&policy, &mut |line| lines.push(line))?;
+        assert_eq!(output.stdout, "789abc");
+        let pieces: Vec<_> = lines.iter().map(|l| l.line.as_str()).collect();
+        assert_eq!(
+            pieces,
+            ["012345", "6789ab", "c"],
+            "Long lines are read in pieces"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_policy_units_and_timeout() -> Result<()> {
+        let manifest: crate::parser::pp::Manifest = r#"
+            exec { "sleep 5":
+                timeout       => '200ms',
+                ulimit_memory => '512M',
+                max_output    => '1k',
+            }
+            exec { "sleep 5": timeout => '5 minutes' }
+        "#
+        .parse()?;
+        let attributes: Vec<_> = manifest
+            .resources()
+            .map(|r| match r {
+                crate::parser::pp::PuppetExpr::Resource { attributes, .. } => attributes,
+                _ => unreachable!(),
+            })
+            .collect();
+        let policy = ExecPolicy::from_attributes(attributes[0])?;
+        assert_eq!(policy.timeout, Some(Duration::from_millis(200)));
+        assert_eq!(policy.memory_kb, Some(512 * 1024));
+        assert_eq!(policy.max_output, Some(1024));
+        let Err(e) = ExecPolicy::from_attributes(attributes[1]) else {
+            return Err(anyhow!("Bad units should fail"));
+        };
+        assert!(format!("{e:#}").contains("invalid duration unit"), "{e:#}");
+
+        let started = Instant::now();
+        let result = exec("sleep 5", policy).run(&ExecPolicy::default());
+        assert!(result.is_err(), "Command should time out");
+        assert!(started.elapsed() < Duration::from_secs(4));
+        Ok(())
+    }
+
+    #[test]
+    fn test_exec_spec_accepts_arrays() -> Result<()> {
+        let manifest: crate::parser::pp::Manifest = r#"
+            exec { "one": returns => [0, 2], environment => ['A=1', 'B=2'] }
+            exec { "two": returns => 3, environment => "A=1
+            B=2" }
+        "#
+        .parse()?;
+        let specs: Vec<ExecSpec> = manifest
+            .resources()
+            .map(|r| match r {
+                crate::parser::pp::PuppetExpr::Resource { attributes, .. } => {
+                    ExecSpec::from_attributes(attributes)
+                }
+                _ => unreachable!(),
+            })
+            .collect::<Result<_>>()?;
+        let environment = vec![
+            ("A".to_string(), "1".to_string()),
+            ("B".to_string(), "2".to_string()),
+        ];
+        assert_eq!(specs[0].returns, [0, 2]);
+        assert_eq!(specs[0].environment, environment);
+        assert_eq!(specs[1].returns, [3]);
+        assert_eq!(specs[1].environment, environment);
+        Ok(())
+    }
+
+    #[test]
+    fn test_exec_conditions_and_returns() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("dolly-exec-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir)?;
+        let manifest: crate::parser::pp::Manifest = format!(
+            r#"
+            exec {{ "make marker":
+                command     => 'touch "$MARKER"; echo made',
+                cwd         => '{}',
+                environment => 'MARKER=marker',
+                creates     => '{}/marker',
+            }}
+            exec {{ "/bin/false": onlyif => '/bin/false' }}
+            exec {{ "/bin/false": unless => 'test -d /' }}
+            exec {{ "exit 2": returns => '0, 2' }}
+            exec {{ "echo oops >&2; exit 3": }}
+            "#,
+            dir.display(),
+            dir.display()
+        )
+        .parse()?;
+        let execs: Vec<Exec> = manifest
+            .resources()
+            .map(|r| match r {
+                crate::parser::pp::PuppetExpr::Resource {
+                    title, attributes, ..
+                } => Ok(Exec {
+                    spec: ExecSpec::from_attributes(attributes)?,
+                    ..exec(&title.to_string(), ExecPolicy::default())
+                }),
+                _ => unreachable!(),
+            })
+            .collect::<Result<_>>()?;
+
+        assert_eq!(execs[0].check(Ensure::Present)?.len(), 1);
+        let policy = ExecPolicy::default();
+        assert_eq!(execs[0].execute(&policy)?.stdout, "made\n");
+        assert!(
+            dir.join("marker").exists(),
+            "Runs in cwd with its environment"
+        );
+        assert!(
+            execs[0].check(Ensure::Present)?.is_empty(),
+            "Does not run again once it created its file"
+        );
+        assert!(execs[1].check(Ensure::Present)?.is_empty());
+        assert_eq!(
+            execs[2].skip_reason(&policy)?,
+            Some("unless test -d / succeeded".to_string())
+        );
+        assert_eq!(execs[3].execute(&policy)?.status, Some(2));
+        let Err(e) = execs[4].execute(&policy) else {
+            return Err(anyhow!("Exit code 3 is a failure"));
+        };
+        assert_eq!(e.to_string(), "echo oops >&2; exit 3 returned 3: oops");
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
     }
 }
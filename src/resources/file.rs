@@ -1,16 +1,477 @@
-use super::resource::{Ensure, Resource};
+use super::accounts::Database;
+use super::content_diff::{REDACTED, unified_diff};
+use super::file_mode::FileMode;
+use super::resource::{Attributes, ChangeReport, Ensure, PropertyChange, Resource};
+use super::selinux::{self, SelinuxSpec};
+use super::xattr::XattrSpec;
+use crate::apply::DeferredResolver;
+use crate::parser::pp::{AttrValue, Attribute};
+use crate::system::{FileType, System};
+use anyhow::{Context, Result, anyhow};
+use std::fmt;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The kind of file a File resource manages, from its `ensure` attribute.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FileEnsure {
+    /// Anything may exist at the path; a plain file is created when nothing does.
+    #[default]
+    Present,
+    File,
+    Directory,
+    Link,
+    Absent,
+}
+
+impl fmt::Display for FileEnsure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Present => "present",
+            Self::File => "file",
+            Self::Directory => "directory",
+            Self::Link => "link",
+            Self::Absent => "absent",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl FileEnsure {
+    fn satisfied_by(self, current: Self) -> bool {
+        match self {
+            Self::Present => current != Self::Absent,
+            desired => desired == current,
+        }
+    }
+}
+
+/// The desired state of a file, checked when the manifest is compiled.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileSpec {
+    pub ensure: FileEnsure,
+    /// Either a literal or a deferred value resolved when the file is checked.
+    pub content: Option<AttrValue>,
+    /// A local file whose content is copied.
+    pub source: Option<PathBuf>,
+    /// What a link points to.
+    pub target: Option<PathBuf>,
+    /// Permissions, octal or symbolic.
+    pub mode: Option<FileMode>,
+    /// A user name or uid.
+    pub owner: Option<String>,
+    /// A group name or gid.
+    pub group: Option<String>,
+    /// Whether a directory in the way may be removed recursively.
+    pub force: bool,
+    /// Whether content is written over the file itself rather than renamed into place, for
+    /// files that cannot be replaced, e.g. a bind-mounted `/etc/resolv.conf`.
+    pub write_in_place: bool,
+    /// The SELinux context, where SELinux is enabled.
+    pub selinux: SelinuxSpec,
+    pub xattrs: XattrSpec,
+    /// Whether an immutable file (`chattr +i`) may have the flag cleared while it is
+    /// changed, and set again after.
+    pub clear_immutable: bool,
+}
+
+impl FileSpec {
+    pub fn from_attributes(attributes: &[Attribute]) -> Result<Self> {
+        let mut spec = Self::default();
+        for attr in attributes {
+            let value = || {
+                attr.value
+                    .as_literal()
+                    .ok_or_else(|| anyhow!("File {} must be a literal", attr.name))
+            };
+            match attr.name.as_str() {
+                "ensure" => {
+                    spec.ensure = match value()?.as_str() {
+                        "present" => FileEnsure::Present,
+                        "file" => FileEnsure::File,
+                        "directory" => FileEnsure::Directory,
+                        "link" => FileEnsure::Link,
+                        "absent" => FileEnsure::Absent,
+                        other => return Err(anyhow!("Invalid File ensure: {other}")),
+                    }
+                }
+                "content" => spec.content = Some(attr.value.clone()),
+                "source" => {
+                    let source = value()?;
+                    let path = source.strip_prefix("file://").unwrap_or(&source);
+                    if !path.starts_with('/') {
+                        return Err(anyhow!(
+                            "File source must be a local absolute path, got {source}"
+                        ));
+                    }
+                    spec.source = Some(PathBuf::from(path));
+                }
+                "target" => spec.target = attr.parse_as("File")?,
+                "mode" => spec.mode = attr.parse_as("File")?,
+                "owner" => spec.owner = attr.parse_as("File")?,
+                "group" => spec.group = attr.parse_as("File")?,
+                "force" => spec.force = attr.parse_as("File")?,
+                "write_in_place" => spec.write_in_place = attr.parse_as("File")?,
+                "clear_immutable" => spec.clear_immutable = attr.parse_as("File")?,
+                _ => {}
+            }
+        }
+        spec.selinux = SelinuxSpec::from_attributes(attributes)?;
+        spec.xattrs = XattrSpec::from_attributes(attributes)?;
+        if spec.content.is_some() && spec.source.is_some() {
+            return Err(anyhow!("File content and source are mutually exclusive"));
+        }
+        if spec.ensure == FileEnsure::Present {
+            if spec.target.is_some() {
+                spec.ensure = FileEnsure::Link;
+            } else if spec.content.is_some() || spec.source.is_some() {
+                spec.ensure = FileEnsure::File;
+            }
+        }
+        if spec.ensure == FileEnsure::Link && spec.target.is_none() {
+            return Err(anyhow!("File ensure => link needs a target"));
+        }
+        Ok(spec)
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct File {
     pub title: String,
+    pub attributes: Attributes,
+    pub spec: FileSpec,
+    /// Where the file is inspected and changed.
+    pub system: System,
 }
 
 impl File {
-    fn ensure_present(&self) {
-        println!("Ensure present: {}", self.title);
+    /// Collapses repeated slashes and drops a trailing one: `/etc//nginx/` is `/etc/nginx`.
+    pub fn normalize_title(title: &str) -> String {
+        let mut normalized = String::with_capacity(title.len());
+        for c in title.chars() {
+            if !(c == '/' && normalized.ends_with('/')) {
+                normalized.push(c);
+            }
+        }
+        if normalized.len() > 1 && normalized.ends_with('/') {
+            normalized.pop();
+        }
+        normalized
+    }
+
+    fn path(&self) -> &Path {
+        Path::new(&self.title)
+    }
+
+    fn desired(&self, ensure: Ensure) -> FileEnsure {
+        match ensure {
+            Ensure::Absent => FileEnsure::Absent,
+            Ensure::Directory => FileEnsure::Directory,
+            Ensure::Link => FileEnsure::Link,
+            _ => self.spec.ensure,
+        }
+    }
+
+    fn current(&self) -> Result<FileEnsure> {
+        match self.system.fs.symlink_metadata(self.path()) {
+            Ok(metadata) => Ok(match metadata.file_type {
+                FileType::Symlink => FileEnsure::Link,
+                FileType::Directory => FileEnsure::Directory,
+                FileType::File => FileEnsure::File,
+            }),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(FileEnsure::Absent),
+            Err(e) => Err(e).with_context(|| format!("Cannot inspect {}", self.title)),
+        }
+    }
+
+    fn target(&self) -> &Path {
+        self.spec.target.as_deref().unwrap_or(Path::new(""))
+    }
+
+    /// The bytes the file should contain, if the manifest says.
+    fn content(&self) -> Result<Option<Vec<u8>>> {
+        if let Some(content) = &self.spec.content {
+            let content = DeferredResolver::new()
+                .resolve(content)?
+                .as_literal()
+                .ok_or_else(|| anyhow!("File content of {} is not a string", self.title))?;
+            return Ok(Some(content.into_bytes()));
+        }
+        match &self.spec.source {
+            Some(source) => self
+                .system
+                .fs
+                .read(source)
+                .map(Some)
+                .with_context(|| format!("Cannot read source {}", source.display())),
+            None => Ok(None),
+        }
+    }
+
+    /// Properties that differ on an existing file of the right kind.
+    fn property_changes(&self, kind: FileEnsure) -> Result<Vec<PropertyChange>> {
+        let fs = &self.system.fs;
+        let metadata = fs
+            .symlink_metadata(self.path())
+            .with_context(|| format!("Cannot inspect {}", self.title))?;
+        let mut changes = vec![];
+        if kind == FileEnsure::File
+            && let Some(desired) = self.content()?
+        {
+            let current = fs
+                .read(self.path())
+                .with_context(|| format!("Cannot read {}", self.title))?;
+            if current != desired {
+                let diff = match self.spec.content {
+                    Some(AttrValue::Sensitive(_)) => REDACTED.to_string(),
+                    _ => unified_diff(&self.title, &current, &desired),
+                };
+                changes.push(
+                    PropertyChange::new("content", Some(checksum(&current)), checksum(&desired))
+                        .with_diff(diff),
+                );
+            }
+        }
+        if kind == FileEnsure::Link && self.spec.target.is_some() {
+            let current = fs.read_link(self.path())?;
+            if current != self.target() {
+                changes.push(PropertyChange::new(
+                    "target",
+                    Some(current.display()),
+                    self.target().display(),
+                ));
+            }
+        }
+        if kind != FileEnsure::Link
+            && let Some(mode) = &self.spec.mode
+        {
+            let current = metadata.mode;
+            let desired = mode.applied_to(current, kind == FileEnsure::Directory);
+            if current != desired {
+                changes.push(PropertyChange::new(
+                    "mode",
+                    Some(format!("{current:04o}")),
+                    format!("{desired:04o}"),
+                ));
+            }
+        }
+        if let Some(owner) = &self.spec.owner {
+            let uid = Database::Passwd.id(owner)?;
+            if metadata.uid != uid {
+                changes.push(PropertyChange::new(
+                    "owner",
+                    Some(Database::Passwd.name(metadata.uid)),
+                    Database::Passwd.name(uid),
+                ));
+            }
+        }
+        if let Some(group) = &self.spec.group {
+            let gid = Database::Group.id(group)?;
+            if metadata.gid != gid {
+                changes.push(PropertyChange::new(
+                    "group",
+                    Some(Database::Group.name(metadata.gid)),
+                    Database::Group.name(gid),
+                ));
+            }
+        }
+        if !self.spec.selinux.is_empty()
+            && selinux::enabled(&*self.system.fs)
+            && let Some(current) = selinux::context(&*self.system.processes, self.path())?
+        {
+            changes.extend(self.spec.selinux.changes(&current));
+        }
+        if kind != FileEnsure::Link && !self.spec.xattrs.is_empty() {
+            changes.extend(
+                self.spec
+                    .xattrs
+                    .changes(&*self.system.processes, self.path())?,
+            );
+        }
+        Ok(changes)
+    }
+
+    fn remove(&self, current: FileEnsure) -> Result<()> {
+        let fs = &self.system.fs;
+        let removed = match current {
+            FileEnsure::Absent => return Ok(()),
+            FileEnsure::Directory if self.spec.force => fs.remove_dir_all(self.path()),
+            FileEnsure::Directory => fs.remove_dir(self.path()),
+            _ => fs.remove_file(self.path()),
+        };
+        removed.with_context(|| match current {
+            FileEnsure::Directory if !self.spec.force => {
+                format!("Cannot remove directory {}, set force => true", self.title)
+            }
+            _ => format!("Cannot remove {}", self.title),
+        })
+    }
+
+    fn create(&self, kind: FileEnsure) -> Result<()> {
+        let created = match kind {
+            FileEnsure::Directory => self.system.fs.create_dir(self.path()),
+            FileEnsure::Link => self.system.fs.symlink(self.target(), self.path()),
+            _ => self
+                .write(&self.content()?.unwrap_or_default())
+                .map_err(std::io::Error::other),
+        };
+        created.with_context(|| format!("Cannot create {kind} {}", self.title))?;
+        if !self.spec.selinux.ignore_defaults && selinux::enabled(&*self.system.fs) {
+            selinux::restore(&*self.system.processes, self.path())?;
+        }
+        Ok(())
+    }
+
+    /// Writes the content to a temporary file next to the path, synced and given its
+    /// permissions, owner and SELinux context before it is renamed over the path, so that
+    /// neither a crash nor a concurrent reader ever sees a partial file.
+    fn write(&self, content: &[u8]) -> Result<()> {
+        let path = self.path();
+        let fs = &self.system.fs;
+        if self.spec.write_in_place {
+            return Ok(fs.write(path, content)?);
+        }
+        let name = path
+            .file_name()
+            .ok_or_else(|| anyhow!("{} is not a file path", self.title))?;
+        let temp = path.with_file_name(format!(
+            ".{}.dolly-{}",
+            name.to_string_lossy(),
+            std::process::id()
+        ));
+        let written = self
+            .write_temp(&temp, content)
+            .and_then(|()| Ok(fs.rename(&temp, path)?));
+        if written.is_err() {
+            let _ = fs.remove_file(&temp);
+        }
+        written?;
+        // The rename itself is only durable once the directory is synced.
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs.sync_dir(dir)?;
+        }
+        Ok(())
     }
-    fn ensure_absent(&self) {
-        println!("Ensure absent: {}", self.title);
+
+    /// Writes `temp` with the metadata the file should keep or get: what the manifest
+    /// says, what the replaced file had otherwise.
+    fn write_temp(&self, temp: &Path, content: &[u8]) -> Result<()> {
+        let (fs, processes) = (&self.system.fs, &*self.system.processes);
+        let existing = fs.metadata(self.path()).ok();
+        fs.create_new(temp, content)?;
+        let created = fs.metadata(temp)?;
+        let base = existing.as_ref().unwrap_or(&created);
+        let uid = match &self.spec.owner {
+            Some(owner) => Database::Passwd.id(owner)?,
+            None => base.uid,
+        };
+        let gid = match &self.spec.group {
+            Some(group) => Database::Group.id(group)?,
+            None => base.gid,
+        };
+        // Before the permissions, since changing the owner clears setuid and setgid.
+        if (uid, gid) != (created.uid, created.gid) {
+            fs.chown(temp, Some(uid), Some(gid))?;
+        }
+        let mode = match &self.spec.mode {
+            Some(mode) => mode.applied_to(base.mode, false),
+            None => base.mode,
+        };
+        fs.set_mode(temp, mode)?;
+        // Replacing the file drops its attributes and capabilities.
+        if !self.spec.xattrs.xattrs.is_empty() {
+            self.spec.xattrs.apply_xattrs(processes, temp)?;
+        }
+        self.spec.xattrs.apply_capabilities(processes, temp)?;
+        if existing.is_some()
+            && selinux::enabled(&**fs)
+            && let Ok(output) = processes.output(
+                Command::new("chcon")
+                    .arg("--reference")
+                    .arg(self.path())
+                    .arg(temp),
+            )
+            && !output.status.success()
+        {
+            return Err(anyhow!("Cannot copy the SELinux context of {}", self.title));
+        }
+        Ok(())
+    }
+
+    fn sync_properties(&self, kind: FileEnsure) -> Result<()> {
+        let path = self.path();
+        let (fs, processes) = (&self.system.fs, &*self.system.processes);
+        for change in self.property_changes(kind)? {
+            let synced = match change.property.as_str() {
+                "content" => {
+                    self.write(&self.content()?.unwrap_or_default())
+                        .with_context(|| format!("Cannot set content of {}", self.title))?;
+                    Ok(())
+                }
+                "target" => fs
+                    .remove_file(path)
+                    .and_then(|()| fs.symlink(self.target(), path)),
+                "mode" => match (&self.spec.mode, fs.metadata(path)) {
+                    (Some(mode), Ok(metadata)) => fs.set_mode(
+                        path,
+                        mode.applied_to(metadata.mode, kind == FileEnsure::Directory),
+                    ),
+                    (_, metadata) => metadata.map(|_| ()),
+                },
+                "xattrs" => {
+                    self.spec.xattrs.apply_xattrs(processes, path)?;
+                    Ok(())
+                }
+                "capabilities" => {
+                    self.spec.xattrs.apply_capabilities(processes, path)?;
+                    Ok(())
+                }
+                "seluser" | "selrole" | "seltype" | "selrange" => {
+                    self.spec.selinux.apply(processes, path)?;
+                    Ok(())
+                }
+                "owner" | "group" => {
+                    let uid = match &self.spec.owner {
+                        Some(owner) => Some(Database::Passwd.id(owner)?),
+                        None => None,
+                    };
+                    let gid = match &self.spec.group {
+                        Some(group) => Some(Database::Group.id(group)?),
+                        None => None,
+                    };
+                    match kind {
+                        FileEnsure::Link => fs.lchown(path, uid, gid),
+                        _ => fs.chown(path, uid, gid),
+                    }
+                }
+                _ => Ok(()),
+            };
+            synced.with_context(|| format!("Cannot set {} of {}", change.property, self.title))?;
+        }
+        Ok(())
+    }
+
+    /// Brings the file to the desired state, once it may be changed.
+    fn converge(&self, ensure: Ensure) -> Result<()> {
+        let desired = self.desired(ensure);
+        let current = self.current()?;
+        if desired == FileEnsure::Absent {
+            return self.remove(current);
+        }
+        let kind = match desired.satisfied_by(current) {
+            true => current,
+            false => {
+                self.remove(current)?;
+                let kind = match desired {
+                    FileEnsure::Present => FileEnsure::File,
+                    kind => kind,
+                };
+                self.create(kind)?;
+                kind
+            }
+        };
+        self.sync_properties(kind)
     }
 }
 
@@ -23,14 +484,316 @@ impl Resource for File {
         self.title.clone()
     }
 
-    fn ensure(&self, ensure: super::resource::Ensure) {
-        match ensure {
-            Ensure::Present => {
-                self.ensure_present();
-            }
-            Ensure::Absent => {
-                self.ensure_absent();
+    fn attributes(&self) -> &Attributes {
+        &self.attributes
+    }
+
+    fn preview(&self, ensure: Ensure) -> Vec<String> {
+        match self.desired(ensure) {
+            FileEnsure::Present | FileEnsure::File => {
+                vec![format!("would write: {}", self.title)]
             }
+            FileEnsure::Directory => vec![format!("would create directory: {}", self.title)],
+            FileEnsure::Link => vec![format!(
+                "would link: {} -> {}",
+                self.title,
+                self.target().display()
+            )],
+            FileEnsure::Absent => vec![format!("would remove: {}", self.title)],
+        }
+    }
+
+    fn check(&self, ensure: Ensure) -> Result<Vec<PropertyChange>> {
+        let desired = self.desired(ensure);
+        let current = self.current()?;
+        if !desired.satisfied_by(current) {
+            return Ok(vec![PropertyChange::new("ensure", Some(current), desired)]);
+        }
+        match current {
+            FileEnsure::Absent => Ok(vec![]),
+            kind => self.property_changes(kind),
+        }
+    }
+
+    fn ensure(&self, ensure: Ensure) -> Result<ChangeReport> {
+        let changes = self.check(ensure.clone())?;
+        if !is_immutable(&self.system, self.path()) {
+            self.converge(ensure)?;
+            return Ok(ChangeReport::new(changes));
+        }
+        if !self.spec.clear_immutable {
+            return Err(anyhow!(
+                "{} is immutable (chattr +i), set clear_immutable => true to change it",
+                self.title
+            ));
+        }
+        set_immutable(&self.system, self.path(), false)?;
+        let converged = self.converge(ensure);
+        if !matches!(self.current()?, FileEnsure::Absent | FileEnsure::Link) {
+            set_immutable(&self.system, self.path(), true)?;
         }
+        converged.map(|()| ChangeReport::new(changes))
+    }
+}
+
+/// Whether `path` has the immutable attribute, which filesystems without attributes and
+/// links never have.
+fn is_immutable(system: &System, path: &Path) -> bool {
+    if system
+        .fs
+        .symlink_metadata(path)
+        .is_ok_and(|metadata| metadata.is_symlink())
+    {
+        return false;
+    }
+    system
+        .processes
+        .output(Command::new("lsattr").arg("-d").arg(path))
+        .is_ok_and(|output| {
+            output.status.success()
+                && String::from_utf8_lossy(&output.stdout)
+                    .split_whitespace()
+                    .next()
+                    .is_some_and(|flags| flags.contains('i'))
+        })
+}
+
+fn set_immutable(system: &System, path: &Path, immutable: bool) -> Result<()> {
+    let flag = if immutable { "+i" } else { "-i" };
+    let output = system
+        .processes
+        .output(Command::new("chattr").arg(flag).arg(path))
+        .with_context(|| format!("chattr is needed to change {}", path.display()))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Cannot chattr {flag} {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// A short, stable fingerprint of file content for reports (64-bit FNV-1a).
+fn checksum(bytes: &[u8]) -> String {
+    let hash = bytes.iter().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    });
+    format!("{{fnv}}{hash:016x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::pp::PuppetString;
+    use std::fs;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    fn file(path: &Path, attributes: &[(&str, &str)]) -> Result<File> {
+        let attributes: Vec<Attribute> = attributes
+            .iter()
+            .map(|(name, value)| Attribute {
+                name: name.to_string(),
+                value: AttrValue::String(PuppetString::literal(value)),
+            })
+            .collect();
+        Ok(File {
+            title: path.display().to_string(),
+            attributes: Attributes::new(),
+            spec: FileSpec::from_attributes(&attributes)?,
+            system: System::default(),
+        })
+    }
+
+    #[test]
+    fn test_content_changes_are_diffed() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("dolly-file-diff-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir)?;
+        let conf = dir.join("app.conf");
+        fs::write(&conf, "user = app\nport = 80\n")?;
+
+        let changes =
+            file(&conf, &[("content", "user = app\nport = 8080\n")])?.check(Ensure::Present)?;
+        let diff = changes[0].diff.as_deref().unwrap_or_default();
+        assert!(diff.contains("@@ -1,2 +1,2 @@\n user = app\n-port = 80\n+port = 8080\n"));
+
+        let secret = File {
+            title: conf.display().to_string(),
+            attributes: Attributes::new(),
+            spec: FileSpec::from_attributes(&[Attribute {
+                name: "content".to_string(),
+                value: AttrValue::Sensitive(Box::new(AttrValue::String(PuppetString::literal(
+                    "password = hunter2\n",
+                )))),
+            }])?,
+            system: System::default(),
+        };
+        let changes = secret.check(Ensure::Present)?;
+        assert_eq!(changes[0].diff.as_deref(), Some(REDACTED));
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_converges() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("dolly-file-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir)?;
+
+        let conf = dir.join("app.conf");
+        let resource = file(&conf, &[("content", "port = 80\n"), ("mode", "0600")])?;
+        assert_eq!(
+            resource.check(Ensure::Present)?,
+            vec![PropertyChange::new("ensure", Some("absent"), "file")]
+        );
+        resource.ensure(Ensure::Present)?;
+        assert_eq!(fs::read_to_string(&conf)?, "port = 80\n");
+        assert_eq!(fs::metadata(&conf)?.permissions().mode() & 0o7777, 0o600);
+        assert!(
+            resource.check(Ensure::Present)?.is_empty(),
+            "A converged file is in sync"
+        );
+
+        fs::write(&conf, "port = 8080\n")?;
+        fs::set_permissions(&conf, fs::Permissions::from_mode(0o644))?;
+        let changes: Vec<_> = resource
+            .check(Ensure::Present)?
+            .into_iter()
+            .map(|change| change.property)
+            .collect();
+        assert_eq!(changes, vec!["content", "mode"]);
+        resource.ensure(Ensure::Present)?;
+        assert!(resource.check(Ensure::Present)?.is_empty());
+
+        let shared = file(&conf, &[("mode", "g+w,o-r")])?;
+        assert_eq!(
+            shared.check(Ensure::Present)?,
+            vec![PropertyChange::new("mode", Some("0600"), "0620")]
+        );
+        shared.ensure(Ensure::Present)?;
+        assert_eq!(fs::metadata(&conf)?.permissions().mode() & 0o7777, 0o620);
+        assert!(shared.check(Ensure::Present)?.is_empty());
+
+        let copy = file(
+            &dir.join("copy.conf"),
+            &[("source", &format!("file://{}", conf.display()))],
+        )?;
+        copy.ensure(Ensure::Present)?;
+        assert_eq!(fs::read_to_string(dir.join("copy.conf"))?, "port = 80\n");
+
+        let sites = file(&dir.join("sites"), &[("ensure", "directory")])?;
+        sites.ensure(Ensure::Present)?;
+        assert!(dir.join("sites").is_dir());
+
+        let link = dir.join("current");
+        let current = file(&link, &[("ensure", "link"), ("target", "app.conf")])?;
+        current.ensure(Ensure::Present)?;
+        assert_eq!(fs::read_link(&link)?, PathBuf::from("app.conf"));
+        let moved = file(&link, &[("target", "copy.conf")])?;
+        assert_eq!(
+            moved.check(Ensure::Present)?,
+            vec![PropertyChange::new("target", Some("app.conf"), "copy.conf")]
+        );
+        moved.ensure(Ensure::Present)?;
+        assert_eq!(fs::read_link(&link)?, PathBuf::from("copy.conf"));
+
+        let gone = file(&conf, &[("ensure", "absent")])?;
+        gone.ensure(Ensure::Present)?;
+        assert!(!conf.exists());
+        assert!(gone.check(Ensure::Present)?.is_empty());
+
+        let not_empty = file(&dir.join("sites"), &[("ensure", "file")])?;
+        fs::write(dir.join("sites/default"), "")?;
+        assert!(
+            not_empty.ensure(Ensure::Present).is_err(),
+            "A non-empty directory is only replaced with force"
+        );
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_writes_atomically() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("dolly-atomic-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir)?;
+        let conf = dir.join("app.conf");
+        fs::write(&conf, "old\n")?;
+        fs::set_permissions(&conf, fs::Permissions::from_mode(0o640))?;
+        let inode = fs::metadata(&conf)?.ino();
+
+        file(&conf, &[("content", "new\n")])?.ensure(Ensure::Present)?;
+        let metadata = fs::metadata(&conf)?;
+        assert_eq!(fs::read_to_string(&conf)?, "new\n");
+        assert_ne!(metadata.ino(), inode, "The file is replaced, not rewritten");
+        assert_eq!(
+            metadata.permissions().mode() & 0o7777,
+            0o640,
+            "The replacement keeps the permissions"
+        );
+        assert_eq!(
+            fs::read_dir(&dir)?.count(),
+            1,
+            "No temporary file is left behind"
+        );
+
+        let inode = metadata.ino();
+        file(
+            &conf,
+            &[("content", "in place\n"), ("write_in_place", "true")],
+        )?
+        .ensure(Ensure::Present)?;
+        assert_eq!(fs::read_to_string(&conf)?, "in place\n");
+        assert_eq!(fs::metadata(&conf)?.ino(), inode);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_immutable_files() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("dolly-immutable-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir)?;
+        let conf = dir.join("resolv.conf");
+        fs::write(&conf, "old\n")?;
+        // Setting the flag needs CAP_LINUX_IMMUTABLE and a filesystem with attributes.
+        let host = System::default();
+        if set_immutable(&host, &conf, true).is_err() {
+            return Ok(fs::remove_dir_all(&dir)?);
+        }
+        assert!(is_immutable(&host, &conf));
+
+        let Err(e) = file(&conf, &[("content", "new\n")])?.ensure(Ensure::Present) else {
+            return Err(anyhow!("An immutable file is only changed on request"));
+        };
+        assert_eq!(
+            e.to_string(),
+            format!(
+                "{} is immutable (chattr +i), set clear_immutable => true to change it",
+                conf.display()
+            )
+        );
+        file(&conf, &[("content", "new\n"), ("clear_immutable", "true")])?
+            .ensure(Ensure::Present)?;
+        assert_eq!(fs::read_to_string(&conf)?, "new\n");
+        assert!(is_immutable(&host, &conf), "The flag is restored");
+
+        set_immutable(&host, &conf, false)?;
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_spec_errors() {
+        let path = Path::new("/tmp/x");
+        assert!(file(path, &[("ensure", "running")]).is_err());
+        assert!(file(path, &[("mode", "rw-r--r--")]).is_err());
+        assert!(file(path, &[("mode", "17777")]).is_err());
+        assert!(file(path, &[("ensure", "link")]).is_err());
+        assert!(file(path, &[("content", "x"), ("source", "/etc/hosts")]).is_err());
+        assert!(file(path, &[("source", "puppet:///modules/x")]).is_err());
     }
 }
@@ -0,0 +1,195 @@
+use crate::parser::pp::AttrValue;
+use crate::parser::value::{FromValue, IntoValue};
+use anyhow::{Result, anyhow};
+use std::fmt;
+use std::str::FromStr;
+
+/// The permissions of a File, either octal (`'0644'`, `0644`) or symbolic (`u=rw,go=r`)
+/// as in chmod. Symbolic modes may be relative (`g+w`, `o-rwx`), so they only give the
+/// desired permissions once applied to the current ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileMode {
+    Octal(u32),
+    Symbolic(Vec<Clause>),
+}
+
+/// One comma-separated clause of a symbolic mode, e.g. `go-w`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Clause {
+    who: String,
+    actions: Vec<(char, String)>,
+}
+
+impl FileMode {
+    /// The permission bits the file should have given its `current` ones.
+    pub fn applied_to(&self, current: u32, directory: bool) -> u32 {
+        let mut mode = current & 0o7777;
+        let Self::Symbolic(clauses) = self else {
+            return self.octal().unwrap_or(mode);
+        };
+        for clause in clauses {
+            let mask = clause.who_mask();
+            for (op, perms) in &clause.actions {
+                let executable = directory || mode & 0o111 != 0;
+                let bits = perms.chars().fold(0, |bits, perm| {
+                    bits | match perm {
+                        'r' => 0o444,
+                        'w' => 0o222,
+                        'x' => 0o111,
+                        'X' if executable => 0o111,
+                        's' => 0o6000,
+                        't' => 0o1000,
+                        _ => 0,
+                    }
+                }) & mask;
+                mode = match op {
+                    '+' => mode | bits,
+                    '-' => mode & !bits,
+                    _ => mode & !mask | bits,
+                };
+            }
+        }
+        mode
+    }
+
+    /// The bits of an octal mode.
+    pub fn octal(&self) -> Option<u32> {
+        match self {
+            Self::Octal(mode) => Some(*mode),
+            Self::Symbolic(_) => None,
+        }
+    }
+
+    fn parse_octal(s: &str) -> Result<u32> {
+        u32::from_str_radix(s, 8)
+            .ok()
+            .filter(|mode| *mode <= 0o7777)
+            .ok_or_else(|| anyhow!("must be an octal or symbolic mode, got {s}"))
+    }
+}
+
+impl Clause {
+    /// The bits the clause may change: the setuid, setgid and sticky bits go with the user,
+    /// the group and the others.
+    fn who_mask(&self) -> u32 {
+        if self.who.is_empty() {
+            return 0o7777;
+        }
+        self.who.chars().fold(0, |mask, who| {
+            mask | match who {
+                'u' => 0o4700,
+                'g' => 0o2070,
+                'o' => 0o1007,
+                _ => 0o7777,
+            }
+        })
+    }
+}
+
+impl FromStr for FileMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.chars().all(|c| c.is_ascii_digit()) {
+            return Self::parse_octal(s).map(Self::Octal);
+        }
+        let invalid = || anyhow!("must be an octal or symbolic mode, got {s}");
+        let clauses = s
+            .split(',')
+            .map(|clause| {
+                let split = clause.find(['+', '-', '=']).ok_or_else(invalid)?;
+                let (who, mut rest) = clause.split_at(split);
+                if !who.chars().all(|c| "ugoa".contains(c)) {
+                    return Err(invalid());
+                }
+                let mut actions = vec![];
+                while let Some(op) = rest.chars().next() {
+                    let perms = &rest[1..];
+                    let end = perms.find(['+', '-', '=']).unwrap_or(perms.len());
+                    if !perms[..end].chars().all(|c| "rwxXst".contains(c)) {
+                        return Err(invalid());
+                    }
+                    actions.push((op, perms[..end].to_string()));
+                    rest = &perms[end..];
+                }
+                Ok(Clause {
+                    who: who.to_string(),
+                    actions,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self::Symbolic(clauses))
+    }
+}
+
+impl fmt::Display for FileMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Octal(mode) => write!(f, "{mode:04o}"),
+            Self::Symbolic(clauses) => {
+                for (i, clause) in clauses.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", clause.who)?;
+                    for (op, perms) in &clause.actions {
+                        write!(f, "{op}{perms}")?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// An unquoted `0644` is already an octal integer.
+impl FromValue for FileMode {
+    fn from_value(value: &AttrValue) -> Result<Self> {
+        match value {
+            AttrValue::Integer(mode) => u32::try_from(*mode)
+                .ok()
+                .filter(|mode| *mode <= 0o7777)
+                .map(Self::Octal)
+                .ok_or_else(|| anyhow!("must be an octal or symbolic mode, got {mode:o}")),
+            value => String::from_value(value)?.parse(),
+        }
+    }
+}
+
+impl IntoValue for FileMode {
+    fn into_value(self) -> AttrValue {
+        self.to_string().into_value()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_modes() -> Result<()> {
+        let mode = |s: &str| s.parse::<FileMode>();
+        assert_eq!(mode("0644")?, FileMode::Octal(0o644));
+        assert_eq!(mode("u=rw,go=r")?.applied_to(0o777, false), 0o644);
+        assert_eq!(mode("go-w")?.applied_to(0o666, false), 0o644);
+        assert_eq!(mode("a+X")?.applied_to(0o644, true), 0o755);
+        assert_eq!(mode("a+X")?.applied_to(0o644, false), 0o644);
+        assert_eq!(mode("a+X")?.applied_to(0o744, false), 0o755);
+        assert_eq!(mode("u+s,+t")?.applied_to(0o755, false), 0o5755);
+        assert_eq!(mode("o=")?.applied_to(0o1777, true), 0o770);
+        assert_eq!(mode("u=rwx-w,g=rx")?.applied_to(0, false), 0o550);
+        assert_eq!(mode("ug=rw,o=")?.to_string(), "ug=rw,o=");
+        assert_eq!(FileMode::Octal(0o2750).to_string(), "2750");
+
+        for invalid in ["rw-r--r--", "17777", "u=rwz", "k+r", "u=rw,"] {
+            let Err(e) = mode(invalid) else {
+                return Err(anyhow!("{invalid} should not parse"));
+            };
+            assert_eq!(
+                e.to_string(),
+                format!("must be an octal or symbolic mode, got {invalid}")
+            );
+        }
+        Ok(())
+    }
+}
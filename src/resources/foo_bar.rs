@@ -1,16 +1,26 @@
-use super::resource::{Ensure, Resource};
+use super::resource::{Attributes, ChangeReport, Ensure, PropertyChange, Resource};
+use anyhow::Result;
 
 #[derive(Debug, Clone)]
 pub struct FooBar {
     pub title: String,
+    pub attributes: Attributes,
 }
 
 impl FooBar {
-    fn ensure_present(&self) {
-        println!("Ensure present: {}", self.title);
+    fn ensure_present(&self) -> Result<ChangeReport> {
+        Ok(ChangeReport::new(vec![PropertyChange::new(
+            "ensure",
+            None::<Ensure>,
+            Ensure::Present,
+        )]))
     }
-    fn ensure_absent(&self) {
-        println!("Ensure absent: {}", self.title);
+    fn ensure_absent(&self) -> Result<ChangeReport> {
+        Ok(ChangeReport::new(vec![PropertyChange::new(
+            "ensure",
+            None::<Ensure>,
+            Ensure::Absent,
+        )]))
     }
 }
 
@@ -23,14 +33,14 @@ impl Resource for FooBar {
         self.title.clone()
     }
 
-    fn ensure(&self, ensure: super::resource::Ensure) {
+    fn attributes(&self) -> &Attributes {
+        &self.attributes
+    }
+
+    fn ensure(&self, ensure: super::resource::Ensure) -> Result<ChangeReport> {
         match ensure {
-            Ensure::Present => {
-                self.ensure_present();
-            }
-            Ensure::Absent => {
-                self.ensure_absent();
-            }
+            Ensure::Absent => self.ensure_absent(),
+            _ => self.ensure_present(),
         }
     }
 }
@@ -0,0 +1,475 @@
+//! Local groups and their members.
+
+use super::Confine;
+use super::resource::{Attributes, ChangeReport, Ensure, PropertyChange, Resource};
+use crate::parser::pp::{AttrValue, Attribute};
+use crate::parser::value::FromValue;
+use crate::system::{HostProcesses, Processes};
+use anyhow::{Context, Result, anyhow};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::process::Command;
+use std::sync::Arc;
+
+/// How `members` is enforced, from `auth_membership`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Membership {
+    /// `true`: members not declared are removed. The default, as in Puppet.
+    #[default]
+    Exact,
+    /// `false`: declared members are added, others are left alone.
+    Minimum,
+}
+
+impl FromValue for Membership {
+    fn from_value(value: &AttrValue) -> Result<Self> {
+        match bool::from_value(value) {
+            Ok(true) => Ok(Self::Exact),
+            Ok(false) => Ok(Self::Minimum),
+            Err(_) => match value.as_literal().as_deref() {
+                Some("exact") => Ok(Self::Exact),
+                Some("minimum") => Ok(Self::Minimum),
+                _ => Err(anyhow!(
+                    "must be true (exact) or false (minimum), got {value}"
+                )),
+            },
+        }
+    }
+}
+
+/// A group as the system has it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupEntry {
+    pub gid: u32,
+    pub members: BTreeSet<String>,
+}
+
+/// Inspects and changes local groups.
+pub trait GroupProvider: fmt::Debug + Send + Sync {
+    fn get(&self, name: &str) -> Result<Option<GroupEntry>>;
+    fn create(&self, name: &str, gid: Option<u32>) -> Result<()>;
+    fn set_gid(&self, name: &str, gid: u32) -> Result<()>;
+    /// Replaces the members of the group with `members`.
+    fn set_members(&self, name: &str, members: &BTreeSet<String>) -> Result<()>;
+    fn delete(&self, name: &str) -> Result<()>;
+}
+
+/// Manages groups with `groupadd`, `groupmod`, `gpasswd` and `groupdel`, reading them
+/// with `getent`.
+#[derive(Debug, Clone)]
+pub struct Groupadd {
+    processes: Arc<dyn Processes>,
+}
+
+impl Default for Groupadd {
+    fn default() -> Self {
+        Self::new(Arc::new(HostProcesses))
+    }
+}
+
+impl Groupadd {
+    pub fn new(processes: Arc<dyn Processes>) -> Self {
+        Self { processes }
+    }
+
+    fn run(&self, command: &mut Command) -> Result<()> {
+        let output = self
+            .processes
+            .output(command)
+            .with_context(|| format!("Cannot run {command:?}"))?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "{} failed: {}",
+                command.get_program().to_string_lossy(),
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl GroupProvider for Groupadd {
+    fn get(&self, name: &str) -> Result<Option<GroupEntry>> {
+        let output = self
+            .processes
+            .output(Command::new("getent").args(["group", name]))
+            .context("Cannot run getent")?;
+        let line = String::from_utf8_lossy(&output.stdout);
+        let fields: Vec<_> = line.trim_end().split(':').collect();
+        let [_, _, gid, members] = fields.as_slice() else {
+            return Ok(None);
+        };
+        Ok(Some(GroupEntry {
+            gid: gid.parse()?,
+            members: members
+                .split(',')
+                .filter(|member| !member.is_empty())
+                .map(str::to_string)
+                .collect(),
+        }))
+    }
+
+    fn create(&self, name: &str, gid: Option<u32>) -> Result<()> {
+        let mut command = Command::new("groupadd");
+        if let Some(gid) = gid {
+            command.args(["-g", &gid.to_string()]);
+        }
+        self.run(command.arg(name))
+    }
+
+    fn set_gid(&self, name: &str, gid: u32) -> Result<()> {
+        self.run(Command::new("groupmod").args(["-g", &gid.to_string(), name]))
+    }
+
+    fn set_members(&self, name: &str, members: &BTreeSet<String>) -> Result<()> {
+        let members: Vec<_> = members.iter().map(String::as_str).collect();
+        self.run(Command::new("gpasswd").args(["-M", &members.join(","), name]))
+    }
+
+    fn delete(&self, name: &str) -> Result<()> {
+        self.run(Command::new("groupdel").arg(name))
+    }
+}
+
+/// The desired state of a group, checked when the manifest is compiled.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GroupSpec {
+    pub present: bool,
+    pub gid: Option<u32>,
+    /// Users that must be members; `None` leaves membership alone.
+    pub members: Option<BTreeSet<String>>,
+    pub auth_membership: Membership,
+}
+
+impl GroupSpec {
+    pub fn from_attributes(attributes: &[Attribute]) -> Result<Self> {
+        let mut spec = Self {
+            present: true,
+            ..Self::default()
+        };
+        for attr in attributes {
+            match attr.name.as_str() {
+                "ensure" => {
+                    spec.present = match attr.parse_as::<String>("Group")?.as_str() {
+                        "present" => true,
+                        "absent" => false,
+                        other => return Err(anyhow!("Invalid Group ensure: {other}")),
+                    }
+                }
+                "gid" => spec.gid = attr.parse_as("Group")?,
+                "members" => {
+                    spec.members = attr
+                        .parse_as::<Option<Vec<String>>>("Group")?
+                        .map(|members| members.into_iter().collect())
+                }
+                "auth_membership" => spec.auth_membership = attr.parse_as("Group")?,
+                _ => {}
+            }
+        }
+        Ok(spec)
+    }
+}
+
+/// How the members of a group differ from the declared ones. Extra members are only drift
+/// when the membership is exact.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MembershipDrift {
+    pub missing: BTreeSet<String>,
+    pub extra: BTreeSet<String>,
+}
+
+impl MembershipDrift {
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty()
+    }
+}
+
+/// `missing: bob, carol; extra: mallory`, leaving out an empty side.
+impl fmt::Display for MembershipDrift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |members: &BTreeSet<String>| {
+            members
+                .iter()
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let parts: Vec<_> = [("missing", &self.missing), ("extra", &self.extra)]
+            .into_iter()
+            .filter(|(_, members)| !members.is_empty())
+            .map(|(side, members)| format!("{side}: {}", join(members)))
+            .collect();
+        write!(f, "{}", parts.join("; "))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Group {
+    pub title: String,
+    pub attributes: Attributes,
+    pub spec: GroupSpec,
+    pub provider: Arc<dyn GroupProvider>,
+}
+
+impl Group {
+    /// How the current members differ from the declared ones, empty when membership is not
+    /// managed.
+    pub fn drift(&self, current: &BTreeSet<String>) -> MembershipDrift {
+        let Some(members) = &self.spec.members else {
+            return MembershipDrift::default();
+        };
+        MembershipDrift {
+            missing: members.difference(current).cloned().collect(),
+            extra: match self.spec.auth_membership {
+                Membership::Exact => current.difference(members).cloned().collect(),
+                Membership::Minimum => BTreeSet::new(),
+            },
+        }
+    }
+
+    /// The members the group should have given its `current` ones.
+    fn desired_members(&self, current: &BTreeSet<String>) -> BTreeSet<String> {
+        let drift = self.drift(current);
+        current
+            .difference(&drift.extra)
+            .chain(&drift.missing)
+            .cloned()
+            .collect()
+    }
+}
+
+impl Resource for Group {
+    fn rtype(&self) -> &str {
+        "Group"
+    }
+
+    fn title(&self) -> String {
+        self.title.clone()
+    }
+
+    fn attributes(&self) -> &Attributes {
+        &self.attributes
+    }
+
+    fn confines(&self) -> Vec<Confine> {
+        vec![Confine::new("kernel", &["Linux"])]
+    }
+
+    fn preview(&self, ensure: Ensure) -> Vec<String> {
+        match ensure.is_present() && self.spec.present {
+            true => match &self.spec.members {
+                Some(members) => {
+                    let members: Vec<_> = members.iter().map(String::as_str).collect();
+                    vec![
+                        format!("would run: groupadd {}", self.title),
+                        format!("would run: gpasswd -M {} {}", members.join(","), self.title),
+                    ]
+                }
+                None => vec![format!("would run: groupadd {}", self.title)],
+            },
+            _ => vec![format!("would run: groupdel {}", self.title)],
+        }
+    }
+
+    fn check(&self, ensure: Ensure) -> Result<Vec<PropertyChange>> {
+        let present = ensure.is_present() && self.spec.present;
+        let entry = match (self.provider.get(&self.title)?, present) {
+            (None, false) => return Ok(vec![]),
+            (Some(_), false) => {
+                return Ok(vec![PropertyChange::new(
+                    "ensure",
+                    Some("present"),
+                    "absent",
+                )]);
+            }
+            (None, true) => {
+                return Ok(vec![PropertyChange::new(
+                    "ensure",
+                    Some("absent"),
+                    "present",
+                )]);
+            }
+            (Some(entry), true) => entry,
+        };
+        let mut changes = vec![];
+        if let Some(gid) = self.spec.gid
+            && gid != entry.gid
+        {
+            changes.push(PropertyChange::new("gid", Some(entry.gid), gid));
+        }
+        let drift = self.drift(&entry.members);
+        if !drift.is_empty() {
+            let list = |members: &BTreeSet<String>| match members.is_empty() {
+                true => "none".to_string(),
+                false => members
+                    .iter()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            };
+            changes.push(PropertyChange::new(
+                "members",
+                Some(list(&entry.members)),
+                format!("{} ({drift})", list(&self.desired_members(&entry.members))),
+            ));
+        }
+        Ok(changes)
+    }
+
+    fn ensure(&self, ensure: Ensure) -> Result<ChangeReport> {
+        let changes = self.check(ensure.clone())?;
+        let entry = self.provider.get(&self.title)?;
+        if ensure == Ensure::Absent || !self.spec.present {
+            if entry.is_some() {
+                self.provider.delete(&self.title)?;
+            }
+            return Ok(ChangeReport::new(changes));
+        }
+        let current = match entry {
+            Some(entry) => {
+                if let Some(gid) = self.spec.gid
+                    && gid != entry.gid
+                {
+                    self.provider.set_gid(&self.title, gid)?;
+                }
+                entry.members
+            }
+            None => {
+                self.provider.create(&self.title, self.spec.gid)?;
+                BTreeSet::new()
+            }
+        };
+        if !self.drift(&current).is_empty() {
+            self.provider
+                .set_members(&self.title, &self.desired_members(&current))?;
+        }
+        Ok(ChangeReport::new(changes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::pp::PuppetString;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    /// Keeps groups in memory.
+    #[derive(Debug, Default)]
+    struct Fake {
+        groups: Mutex<HashMap<String, GroupEntry>>,
+    }
+
+    impl Fake {
+        fn with(&self, name: &str, f: impl FnOnce(&mut GroupEntry)) -> Result<()> {
+            let mut groups = self.groups.lock().map_err(|_| anyhow!("poisoned"))?;
+            f(groups
+                .get_mut(name)
+                .ok_or_else(|| anyhow!("no group {name}"))?);
+            Ok(())
+        }
+    }
+
+    impl GroupProvider for Fake {
+        fn get(&self, name: &str) -> Result<Option<GroupEntry>> {
+            Ok(self
+                .groups
+                .lock()
+                .map_err(|_| anyhow!("poisoned"))?
+                .get(name)
+                .cloned())
+        }
+        fn create(&self, name: &str, gid: Option<u32>) -> Result<()> {
+            let entry = GroupEntry {
+                gid: gid.unwrap_or(1000),
+                members: BTreeSet::new(),
+            };
+            self.groups
+                .lock()
+                .map_err(|_| anyhow!("poisoned"))?
+                .insert(name.to_string(), entry);
+            Ok(())
+        }
+        fn set_gid(&self, name: &str, gid: u32) -> Result<()> {
+            self.with(name, |entry| entry.gid = gid)
+        }
+        fn set_members(&self, name: &str, members: &BTreeSet<String>) -> Result<()> {
+            self.with(name, |entry| entry.members = members.clone())
+        }
+        fn delete(&self, name: &str) -> Result<()> {
+            self.groups
+                .lock()
+                .map_err(|_| anyhow!("poisoned"))?
+                .remove(name);
+            Ok(())
+        }
+    }
+
+    fn group(provider: &Arc<Fake>, auth_membership: Option<&str>) -> Result<Group> {
+        let mut attributes = vec![Attribute {
+            name: "members".to_string(),
+            value: AttrValue::Array(
+                ["alice", "bob"]
+                    .map(|member| AttrValue::String(PuppetString::literal(member)))
+                    .to_vec(),
+            ),
+        }];
+        if let Some(mode) = auth_membership {
+            attributes.push(Attribute {
+                name: "auth_membership".to_string(),
+                value: AttrValue::String(PuppetString::literal(mode)),
+            });
+        }
+        Ok(Group {
+            title: "developers".to_string(),
+            attributes: Attributes::new(),
+            spec: GroupSpec::from_attributes(&attributes)?,
+            provider: provider.clone(),
+        })
+    }
+
+    #[test]
+    fn test_membership_modes() -> Result<()> {
+        let provider = Arc::new(Fake::default());
+        let exact = group(&provider, None)?;
+        exact.ensure(Ensure::Present)?;
+        assert!(exact.check(Ensure::Present)?.is_empty());
+
+        let members = ["alice", "mallory"].map(str::to_string).into();
+        provider.set_members("developers", &members)?;
+        assert_eq!(
+            exact.check(Ensure::Present)?,
+            vec![PropertyChange::new(
+                "members",
+                Some("alice, mallory"),
+                "alice, bob (missing: bob; extra: mallory)"
+            )],
+            "Exact membership removes members not declared"
+        );
+
+        let minimum = group(&provider, Some("minimum"))?;
+        assert_eq!(
+            minimum.check(Ensure::Present)?,
+            vec![PropertyChange::new(
+                "members",
+                Some("alice, mallory"),
+                "alice, bob, mallory (missing: bob)"
+            )],
+            "Minimum membership keeps other members"
+        );
+        minimum.ensure(Ensure::Present)?;
+        assert!(minimum.check(Ensure::Present)?.is_empty());
+        let current = provider
+            .get("developers")?
+            .ok_or_else(|| anyhow!("The group exists"))?;
+        assert_eq!(exact.drift(&current.members).to_string(), "extra: mallory");
+
+        exact.ensure(Ensure::Present)?;
+        assert_eq!(
+            provider.get("developers")?.map(|entry| entry.members),
+            Some(["alice", "bob"].map(str::to_string).into())
+        );
+        assert!(group(&provider, Some("sometimes")).is_err());
+        Ok(())
+    }
+}
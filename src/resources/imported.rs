@@ -0,0 +1,33 @@
+use super::resource::{Attributes, ChangeReport, Ensure, PropertyChange, Resource};
+use anyhow::{Result, anyhow};
+
+/// A resource known only by its id, e.g. from a graph exported by Puppet. It can be
+/// visualized and analyzed but has no provider.
+#[derive(Debug, Clone)]
+pub struct Imported {
+    pub rtype: String,
+    pub title: String,
+    pub attributes: Attributes,
+}
+
+impl Resource for Imported {
+    fn rtype(&self) -> &str {
+        &self.rtype
+    }
+
+    fn title(&self) -> String {
+        self.title.clone()
+    }
+
+    fn attributes(&self) -> &Attributes {
+        &self.attributes
+    }
+
+    fn check(&self, _ensure: Ensure) -> Result<Vec<PropertyChange>> {
+        Err(anyhow!("{} was imported and cannot be checked", self.id()))
+    }
+
+    fn ensure(&self, _ensure: Ensure) -> Result<ChangeReport> {
+        Err(anyhow!("{} was imported and cannot be applied", self.id()))
+    }
+}
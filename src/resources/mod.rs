@@ -1,43 +1,76 @@
+pub mod accounts;
+pub mod capabilities;
+pub mod class;
+pub mod confine;
+pub mod content_diff;
+pub mod defined;
 pub mod exec;
 pub mod file;
+pub mod file_mode;
 pub mod foo_bar;
+pub mod group;
+pub mod imported;
+pub mod output;
+pub mod package;
+pub mod package_provider;
+pub mod registry;
+pub mod repository;
 pub mod resource;
+pub mod selinux;
 pub mod service;
+pub mod user;
+pub mod version;
+pub mod xattr;
 
-pub use exec::Exec;
-pub use file::File;
+pub use accounts::{Account, Database};
+pub use capabilities::{Capabilities, PackageManager};
+pub use class::Class;
+pub use confine::Confine;
+pub use defined::Defined;
+pub use exec::{Exec, ExecOutput, ExecPolicy, ExecSpec};
+pub use file::{File, FileEnsure, FileSpec};
+pub use file_mode::FileMode;
 pub use foo_bar::FooBar;
+pub use group::{
+    Group, GroupEntry, GroupProvider, GroupSpec, Groupadd, Membership, MembershipDrift,
+};
+pub use imported::Imported;
+pub use output::{LogLine, OutputLog, Stream};
+pub use package::{Package, PackageProvider, PackageSpec};
+pub use package_provider::NativePackages;
+pub use registry::{Factory, ResourceRegistry};
+pub use repository::{AptSource, AptSourceSpec, Yumrepo, YumrepoSpec};
+pub use resource::ApplyContext;
+pub use resource::Attributes;
+pub use resource::ChangeReport;
 pub use resource::Ensure;
+pub use resource::PropertyChange;
 pub use resource::Relation;
 pub use resource::Resource;
-pub use service::Service;
+pub use selinux::{SecurityContext, SelinuxSpec};
+pub use service::{Service, ServiceProvider, ServiceSpec, Systemd, UnitFileState};
+pub use user::{User, UserEntry, UserProvider, UserSpec, Useradd};
+pub use xattr::{FileCapabilities, XattrSpec};
 
-use crate::parser::pp::PuppetExpr;
+use crate::parser::pp::{PuppetExpr, normalize_rtype};
 
-use anyhow::{Result, anyhow};
+use anyhow::Result;
+
+/// The canonical spelling of `title` for resources of `rtype`, so that two spellings of one
+/// system object share an id and cannot be managed as distinct resources.
+pub fn normalize_title(rtype: &str, title: &str) -> String {
+    match rtype {
+        "File" => File::normalize_title(title),
+        "Service" => Service::normalize_title(title),
+        "Class" => normalize_rtype(title),
+        _ => title.to_string(),
+    }
+}
 
 impl TryFrom<&PuppetExpr> for Box<dyn Resource> {
     type Error = anyhow::Error;
+    /// Builds the resource with the built-in types; see [`ResourceRegistry`] for others.
     fn try_from(expr: &PuppetExpr) -> Result<Self> {
-        match expr {
-            PuppetExpr::Resource { rtype, title, .. } => match rtype.as_str() {
-                "File" => Ok(Box::new(File {
-                    title: title.to_string(),
-                })),
-                "Exec" => Ok(Box::new(Exec {
-                    title: title.to_string(),
-                })),
-                "Service" => Ok(Box::new(Service {
-                    title: title.to_string(),
-                })),
-                "Foo::Bar" => Ok(Box::new(FooBar {
-                    title: title.to_string(),
-                })),
-                no_match => Err(anyhow!("unknown rtype: {no_match}")),
-            },
-            PuppetExpr::Relation { .. } => {
-                Err(anyhow!("The expr is not a relation. Expected a resource."))
-            }
-        }
+        ResourceRegistry::default().build(expr)
     }
 }
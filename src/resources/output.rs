@@ -0,0 +1,166 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{BufRead, BufReader, Read};
+use std::sync::{Arc, mpsc};
+use std::thread;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl fmt::Display for Stream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stdout => write!(f, "stdout"),
+            Self::Stderr => write!(f, "stderr"),
+        }
+    }
+}
+
+/// One line of provider output, labelled with the resource that produced it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogLine {
+    pub resource: String,
+    pub stream: Stream,
+    pub line: String,
+}
+
+impl fmt::Display for LogLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} [{}] {}", self.resource, self.stream, self.line)
+    }
+}
+
+/// Where lines of provider output go as they are produced. Lines are dropped when it is
+/// the default.
+#[derive(Clone, Default)]
+pub struct OutputLog(Option<Arc<WriteLine>>);
+
+type WriteLine = dyn Fn(&LogLine) + Send + Sync;
+
+impl OutputLog {
+    pub fn new(write: impl Fn(&LogLine) + Send + Sync + 'static) -> Self {
+        Self(Some(Arc::new(write)))
+    }
+
+    pub fn write(&self, line: &LogLine) {
+        if let Some(write) = &self.0 {
+            write(line);
+        }
+    }
+}
+
+impl fmt::Debug for OutputLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(_) => write!(f, "OutputLog(..)"),
+            None => write!(f, "OutputLog(None)"),
+        }
+    }
+}
+
+/// Keeps the last `max` bytes written to it, or everything when `max` is `None`.
+#[derive(Debug, Default)]
+pub struct Tail {
+    bytes: VecDeque<u8>,
+    max: Option<usize>,
+    truncated: bool,
+}
+
+impl Tail {
+    pub fn new(max: Option<usize>) -> Self {
+        Self {
+            max,
+            ..Self::default()
+        }
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.bytes.extend(bytes);
+        if let Some(max) = self.max
+            && self.bytes.len() > max
+        {
+            self.bytes.drain(..self.bytes.len() - max);
+            self.truncated = true;
+        }
+    }
+
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn into_string(mut self) -> String {
+        String::from_utf8_lossy(self.bytes.make_contiguous()).into_owned()
+    }
+}
+
+/// Streams a child's stdout and stderr line by line into `on_line` while keeping the
+/// tail of each stream. Lines longer than `max` are passed on in pieces of `max` bytes.
+pub fn capture(
+    stdout: impl Read + Send,
+    stderr: impl Read + Send,
+    resource: &str,
+    max: Option<usize>,
+    on_line: &mut dyn FnMut(LogLine),
+) -> (Tail, Tail) {
+    let (sender, receiver) = mpsc::channel();
+    let mut tails = (Tail::new(max), Tail::new(max));
+    thread::scope(|scope| {
+        scope.spawn({
+            let sender = sender.clone();
+            move || forward_lines(stdout, Stream::Stdout, max, sender)
+        });
+        scope.spawn(move || forward_lines(stderr, Stream::Stderr, max, sender));
+
+        for (stream, bytes) in receiver {
+            match stream {
+                Stream::Stdout => tails.0.push(&bytes),
+                Stream::Stderr => tails.1.push(&bytes),
+            }
+            on_line(LogLine {
+                resource: resource.to_string(),
+                stream,
+                line: String::from_utf8_lossy(&bytes).trim_end().to_string(),
+            });
+        }
+    });
+    tails
+}
+
+fn forward_lines(
+    source: impl Read,
+    stream: Stream,
+    max: Option<usize>,
+    sender: mpsc::Sender<(Stream, Vec<u8>)>,
+) {
+    // At most `max` bytes are read at a time, so that a command that never prints a
+    // newline cannot grow the buffer without bound.
+    let limit = max.map_or(u64::MAX, |max| max.max(1) as u64);
+    let mut reader = BufReader::new(source);
+    loop {
+        let mut line = Vec::new();
+        match (&mut reader).take(limit).read_until(b'\n', &mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {
+                // A line exactly `max` bytes long is not followed by an empty piece.
+                if !line.ends_with(b"\n")
+                    && reader
+                        .fill_buf()
+                        .is_ok_and(|next| next.first() == Some(&b'\n'))
+                {
+                    reader.consume(1);
+                    line.push(b'\n');
+                }
+                if sender.send((stream, line)).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,301 @@
+use super::repository::REPOSITORY_TYPES;
+use super::resource::{Attributes, ChangeReport, Ensure, PropertyChange, Resource};
+use super::version::{VersionConstraint, VersionScheme};
+use super::{Capabilities, PackageManager};
+use crate::parser::pp::Attribute;
+use anyhow::{Result, anyhow};
+use std::fmt;
+use std::sync::Arc;
+
+/// Queries and changes installed packages for one package manager.
+pub trait PackageProvider: fmt::Debug + Send + Sync {
+    /// The installed version, `None` when the package is not installed.
+    fn installed_version(&self, package: &str) -> Result<Option<String>>;
+    /// How the package manager orders versions.
+    fn version_scheme(&self) -> VersionScheme;
+    /// Installs `package`, a version matching `version` if given, upgrading or
+    /// downgrading it if need be.
+    fn install(&self, package: &str, version: Option<&VersionConstraint>) -> Result<()>;
+    fn remove(&self, package: &str) -> Result<()>;
+}
+
+/// The desired state of a package, checked when the manifest is compiled.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PackageSpec {
+    /// `ensure => installed` (or `present`, or a version) versus `absent` (or `purged`).
+    pub installed: bool,
+    /// The version `ensure` asks for: `'1.2.3'`, `'1.2.*'` or `'>= 1.2'`.
+    pub version: Option<VersionConstraint>,
+    /// A package manager chosen with `provider => ...` instead of the system's.
+    pub provider: Option<PackageManager>,
+}
+
+impl PackageSpec {
+    pub fn from_attributes(attributes: &[Attribute]) -> Result<Self> {
+        let mut spec = Self {
+            installed: true,
+            ..Self::default()
+        };
+        for attr in attributes {
+            let value = || {
+                attr.value
+                    .as_literal()
+                    .ok_or_else(|| anyhow!("Package {} must be a literal", attr.name))
+            };
+            match attr.name.as_str() {
+                "ensure" => {
+                    spec.installed = match value()?.as_str() {
+                        "installed" | "present" => true,
+                        "absent" | "purged" => false,
+                        "latest" => {
+                            return Err(anyhow!(
+                                "Package ensure latest is not supported, use >= a version"
+                            ));
+                        }
+                        version => {
+                            spec.version = Some(version.parse()?);
+                            true
+                        }
+                    }
+                }
+                "provider" => spec.provider = Some(value()?.parse()?),
+                _ => {}
+            }
+        }
+        Ok(spec)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Package {
+    pub title: String,
+    pub attributes: Attributes,
+    pub spec: PackageSpec,
+    /// `None` when the system has no supported package manager.
+    pub provider: Option<Arc<dyn PackageProvider>>,
+}
+
+impl Package {
+    fn provider(&self) -> Result<&dyn PackageProvider> {
+        self.provider
+            .as_deref()
+            .ok_or_else(|| anyhow!("No supported package manager (apt, dnf, pacman) was found"))
+    }
+
+    fn installed(&self, ensure: &Ensure) -> bool {
+        ensure.is_present() && self.spec.installed
+    }
+
+    /// What `ensure` asks for, as drift reports it.
+    fn desired(&self) -> String {
+        self.spec
+            .version
+            .as_ref()
+            .map_or_else(|| "installed".to_string(), ToString::to_string)
+    }
+}
+
+impl Resource for Package {
+    fn rtype(&self) -> &str {
+        "Package"
+    }
+
+    fn title(&self) -> String {
+        self.title.clone()
+    }
+
+    fn attributes(&self) -> &Attributes {
+        &self.attributes
+    }
+
+    fn autorequire_types(&self) -> &[&str] {
+        &REPOSITORY_TYPES
+    }
+
+    fn check_provider(&self, capabilities: &Capabilities) -> Result<()> {
+        if self.spec.provider.is_none() && capabilities.package_manager.is_none() {
+            return Err(anyhow!(
+                "no supported package manager (apt, dnf, pacman) is installed"
+            ));
+        }
+        Ok(())
+    }
+
+    fn preview(&self, ensure: Ensure) -> Vec<String> {
+        let manager = self
+            .spec
+            .provider
+            .or(Capabilities::cached().package_manager)
+            .map_or("package manager", |manager| manager.command());
+        let package = self
+            .spec
+            .provider
+            .or(Capabilities::cached().package_manager)
+            .and_then(|manager| {
+                manager
+                    .package_arg(&self.title, self.spec.version.as_ref())
+                    .ok()
+            })
+            .unwrap_or_else(|| self.title.clone());
+        match self.installed(&ensure) {
+            true => vec![format!("would run: {manager} install {package}")],
+            false => vec![format!("would run: {manager} remove {}", self.title)],
+        }
+    }
+
+    fn check(&self, ensure: Ensure) -> Result<Vec<PropertyChange>> {
+        let provider = self.provider()?;
+        let current = provider.installed_version(&self.title)?;
+        let scheme = provider.version_scheme();
+        Ok(match (current, self.installed(&ensure)) {
+            (None, true) => vec![PropertyChange::new(
+                "ensure",
+                Some("absent"),
+                self.desired(),
+            )],
+            (Some(version), true)
+                if self
+                    .spec
+                    .version
+                    .as_ref()
+                    .is_some_and(|wanted| !wanted.matches(&version, scheme)) =>
+            {
+                vec![PropertyChange::new("ensure", Some(version), self.desired())]
+            }
+            (Some(version), false) => vec![PropertyChange::new("ensure", Some(version), "absent")],
+            _ => vec![],
+        })
+    }
+
+    fn ensure(&self, ensure: Ensure) -> Result<ChangeReport> {
+        let changes = self.check(ensure.clone())?;
+        if changes.is_empty() {
+            return Ok(ChangeReport::unchanged());
+        }
+        let provider = self.provider()?;
+        if !self.installed(&ensure) {
+            provider.remove(&self.title)?;
+            return Ok(ChangeReport::new(changes));
+        }
+        provider.install(&self.title, self.spec.version.as_ref())?;
+        // The repositories may not have a matching version, e.g. for `>= 2.0`.
+        match self.check(ensure)?.first() {
+            Some(change) => Err(anyhow!(
+                "Package {} is at {} after installing, which is not {}",
+                self.title,
+                change.current.as_deref().unwrap_or("absent"),
+                self.desired()
+            )),
+            None => Ok(ChangeReport::new(changes)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::pp::{AttrValue, PuppetString};
+    use std::sync::Mutex;
+
+    /// Keeps installed packages in memory.
+    #[derive(Debug, Default)]
+    struct Fake {
+        installed: Mutex<Vec<String>>,
+    }
+
+    impl PackageProvider for Fake {
+        fn installed_version(&self, package: &str) -> Result<Option<String>> {
+            let installed = self.installed.lock().map_err(|_| anyhow!("poisoned"))?;
+            Ok(installed
+                .contains(&package.to_string())
+                .then(|| "1.0".to_string()))
+        }
+        fn version_scheme(&self) -> VersionScheme {
+            VersionScheme::Dpkg
+        }
+        fn install(&self, package: &str, _: Option<&VersionConstraint>) -> Result<()> {
+            let mut installed = self.installed.lock().map_err(|_| anyhow!("poisoned"))?;
+            installed.push(package.to_string());
+            Ok(())
+        }
+        fn remove(&self, package: &str) -> Result<()> {
+            let mut installed = self.installed.lock().map_err(|_| anyhow!("poisoned"))?;
+            installed.retain(|p| p != package);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_package_converges_through_provider() -> Result<()> {
+        let provider = Arc::new(Fake::default());
+        let mut nginx = Package {
+            title: "nginx".to_string(),
+            attributes: Attributes::new(),
+            spec: PackageSpec {
+                installed: true,
+                ..PackageSpec::default()
+            },
+            provider: Some(provider.clone()),
+        };
+        assert_eq!(
+            nginx.check(Ensure::Present)?,
+            vec![PropertyChange::new("ensure", Some("absent"), "installed")]
+        );
+        let report = nginx.ensure(Ensure::Present)?;
+        assert_eq!(
+            report.changes,
+            vec![PropertyChange::new("ensure", Some("absent"), "installed")]
+        );
+        assert!(nginx.check(Ensure::Present)?.is_empty());
+        assert!(
+            !nginx.ensure(Ensure::Present)?.changed(),
+            "Nothing to do twice"
+        );
+
+        nginx.spec.version = Some(">= 1.0".parse()?);
+        assert!(nginx.check(Ensure::Present)?.is_empty());
+        nginx.spec.version = Some("1.2.*".parse()?);
+        assert_eq!(
+            nginx.check(Ensure::Present)?,
+            vec![PropertyChange::new("ensure", Some("1.0"), "1.2.*")]
+        );
+        let Err(e) = nginx.ensure(Ensure::Present) else {
+            return Err(anyhow!("The fake only has 1.0"));
+        };
+        assert_eq!(
+            e.to_string(),
+            "Package nginx is at 1.0 after installing, which is not 1.2.*"
+        );
+        assert_eq!(
+            PackageSpec::from_attributes(&[Attribute {
+                name: "ensure".to_string(),
+                value: AttrValue::String(PuppetString::literal("1.2.3-1")),
+            }])?
+            .version,
+            Some(VersionConstraint::Exact("1.2.3-1".to_string()))
+        );
+        assert_eq!(
+            PackageManager::Dnf.package_arg("nginx", Some(&">=1.2".parse()?))?,
+            "nginx >= 1.2"
+        );
+        assert!(
+            PackageManager::Pacman
+                .package_arg("nginx", Some(&"1.2".parse()?))
+                .is_err()
+        );
+
+        nginx.spec.version = None;
+        nginx.spec.installed = false;
+        assert_eq!(
+            nginx.check(Ensure::Present)?,
+            vec![PropertyChange::new("ensure", Some("1.0"), "absent")]
+        );
+        nginx.ensure(Ensure::Present)?;
+        assert_eq!(provider.installed_version("nginx")?, None);
+
+        nginx.provider = None;
+        assert!(nginx.check(Ensure::Present).is_err());
+        assert!(nginx.check_provider(&Capabilities::default()).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+//! Package providers for the package managers dolly detects.
+
+use super::PackageManager;
+use super::package::PackageProvider;
+use super::version::{VersionConstraint, VersionScheme};
+use crate::system::{HostProcesses, Processes};
+use anyhow::{Context, Result, anyhow};
+use std::process::Command;
+use std::str::FromStr;
+use std::sync::Arc;
+
+impl FromStr for PackageManager {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "apt" => Ok(Self::Apt),
+            "dnf" | "yum" => Ok(Self::Dnf),
+            "pacman" => Ok(Self::Pacman),
+            other => Err(anyhow!("Unknown package provider: {other}")),
+        }
+    }
+}
+
+impl PackageManager {
+    /// The command that lists `package` if it is installed.
+    fn query(&self, package: &str) -> Command {
+        let mut command = match self {
+            Self::Apt => Command::new("dpkg-query"),
+            Self::Dnf => Command::new("rpm"),
+            Self::Pacman => Command::new("pacman"),
+        };
+        match self {
+            Self::Apt => command.args(["-W", "-f", "${Status}\t${Version}", package]),
+            Self::Dnf => command.args(["-q", "--qf", "%{VERSION}-%{RELEASE}", package]),
+            Self::Pacman => command.args(["-Q", package]),
+        };
+        command
+    }
+
+    /// How the package manager names `package` at `version`: `nginx=1.2.*` for apt,
+    /// `nginx-1.2.*` or `nginx >= 1.2` for dnf. Pacman only installs what its
+    /// repositories have, so it can only be asked for a minimum version.
+    pub fn package_arg(
+        &self,
+        package: &str,
+        version: Option<&VersionConstraint>,
+    ) -> Result<String> {
+        let Some(version) = version else {
+            return Ok(package.to_string());
+        };
+        match (self, version) {
+            (Self::Apt, VersionConstraint::AtLeast(_))
+            | (Self::Pacman, VersionConstraint::AtLeast(_)) => Ok(package.to_string()),
+            (Self::Apt, pinned) => Ok(format!("{package}={pinned}")),
+            (Self::Dnf, VersionConstraint::AtLeast(minimum)) => {
+                Ok(format!("{package} >= {minimum}"))
+            }
+            (Self::Dnf, pinned) => Ok(format!("{package}-{pinned}")),
+            (Self::Pacman, pinned) => Err(anyhow!(
+                "pacman cannot install version {pinned} of {package}, only >= a version"
+            )),
+        }
+    }
+}
+
+/// Installs packages with the package manager's own commands.
+#[derive(Debug, Clone)]
+pub struct NativePackages {
+    pub manager: PackageManager,
+    processes: Arc<dyn Processes>,
+}
+
+impl From<PackageManager> for NativePackages {
+    /// The package manager of the host.
+    fn from(manager: PackageManager) -> Self {
+        Self::new(manager, Arc::new(HostProcesses))
+    }
+}
+
+impl NativePackages {
+    pub fn new(manager: PackageManager, processes: Arc<dyn Processes>) -> Self {
+        Self { manager, processes }
+    }
+
+    fn run(&self, args: &[&str], package: &str) -> Result<()> {
+        let command = self.manager.command();
+        let output = self
+            .processes
+            .output(
+                Command::new(command)
+                    .args(args)
+                    .arg(package)
+                    .env("DEBIAN_FRONTEND", "noninteractive"),
+            )
+            .with_context(|| format!("Cannot run {command}"))?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "{command} {} {package} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl PackageProvider for NativePackages {
+    fn installed_version(&self, package: &str) -> Result<Option<String>> {
+        let output = self
+            .processes
+            .output(&mut self.manager.query(package))
+            .with_context(|| format!("Cannot query package {package}"))?;
+        if !output.status.success() {
+            return Ok(None);
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Ok(match self.manager {
+            PackageManager::Apt => stdout
+                .split_once('\t')
+                .filter(|(status, _)| status.ends_with(" installed"))
+                .map(|(_, version)| version.trim().to_string()),
+            PackageManager::Dnf => Some(stdout.trim().to_string()),
+            PackageManager::Pacman => stdout.split_whitespace().nth(1).map(str::to_string),
+        })
+    }
+
+    fn version_scheme(&self) -> VersionScheme {
+        match self.manager {
+            PackageManager::Apt => VersionScheme::Dpkg,
+            PackageManager::Dnf | PackageManager::Pacman => VersionScheme::Rpm,
+        }
+    }
+
+    fn install(&self, package: &str, version: Option<&VersionConstraint>) -> Result<()> {
+        let arg = self.manager.package_arg(package, version)?;
+        match (self.manager, version) {
+            (PackageManager::Apt, None) => self.run(&["install", "-y", "-q"], &arg),
+            (PackageManager::Apt, Some(_)) => {
+                self.run(&["install", "-y", "-q", "--allow-downgrades"], &arg)
+            }
+            (PackageManager::Dnf, _) => self.run(&["install", "-y"], &arg),
+            (PackageManager::Pacman, None) => self.run(&["-S", "--noconfirm", "--needed"], &arg),
+            // Without --needed, an installed package is upgraded.
+            (PackageManager::Pacman, Some(_)) => self.run(&["-Sy", "--noconfirm"], &arg),
+        }
+    }
+
+    fn remove(&self, package: &str) -> Result<()> {
+        match self.manager {
+            PackageManager::Apt => self.run(&["remove", "-y", "-q"], package),
+            PackageManager::Dnf => self.run(&["remove", "-y"], package),
+            PackageManager::Pacman => self.run(&["-R", "--noconfirm"], package),
+        }
+    }
+}
@@ -0,0 +1,269 @@
+use super::{
+    AptSource, AptSourceSpec, Attributes, Capabilities, Class, Defined, Exec, ExecPolicy, ExecSpec,
+    File, FileSpec, FooBar, Group, GroupSpec, Groupadd, NativePackages, Package, PackageProvider,
+    PackageSpec, Resource, Service, ServiceSpec, Systemd, User, UserSpec, Useradd, Yumrepo,
+    YumrepoSpec, normalize_title, repository,
+};
+use crate::parser::pp::{Attribute, PuppetExpr, normalize_rtype};
+use crate::system::System;
+use anyhow::{Result, anyhow};
+use indexmap::IndexMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Builds the resource a `PuppetExpr::Resource` of one type declares.
+pub type Factory = Arc<dyn Fn(&PuppetExpr) -> Result<Box<dyn Resource>> + Send + Sync>;
+
+/// The resource types a manifest may declare, each with the factory that builds it. The
+/// default registry holds dolly's built-in types; library consumers add their own:
+///
+/// ```
+/// # use dolly::resources::{FooBar, ResourceRegistry};
+/// # use dolly::parser::pp::PuppetExpr;
+/// let mut registry = ResourceRegistry::default();
+/// registry.register("Mycorp::Widget", |expr: &PuppetExpr| {
+///     let (title, attributes, _) = ResourceRegistry::declaration(expr)?;
+///     Ok(Box::new(FooBar { title, attributes }))
+/// });
+/// assert!(registry.supports("mycorp::widget"));
+/// ```
+#[derive(Clone)]
+pub struct ResourceRegistry {
+    factories: IndexMap<String, Factory>,
+}
+
+impl ResourceRegistry {
+    /// A registry without any type, not even the built-in ones.
+    pub fn empty() -> Self {
+        Self {
+            factories: IndexMap::new(),
+        }
+    }
+
+    /// Registers `factory` for `rtype`, replacing the factory of a type already registered.
+    pub fn register<F>(&mut self, rtype: &str, factory: F) -> &mut Self
+    where
+        F: Fn(&PuppetExpr) -> Result<Box<dyn Resource>> + Send + Sync + 'static,
+    {
+        self.factories
+            .insert(normalize_rtype(rtype), Arc::new(factory));
+        self
+    }
+
+    /// Registers the defined type `rtype`, whose instances are anchors for what their body
+    /// expands to.
+    pub fn register_defined(&mut self, rtype: &str) -> &mut Self {
+        let rtype = normalize_rtype(rtype);
+        self.register(&rtype.clone(), move |expr| {
+            let (title, attributes, _) = Self::declaration(expr)?;
+            Ok(Box::new(Defined {
+                rtype: rtype.clone(),
+                title,
+                attributes,
+            }))
+        })
+    }
+
+    pub fn supports(&self, rtype: &str) -> bool {
+        self.factories.contains_key(&normalize_rtype(rtype))
+    }
+
+    /// Registered types, in registration order.
+    pub fn types(&self) -> impl Iterator<Item = &str> {
+        self.factories.keys().map(String::as_str)
+    }
+
+    /// Builds the resource `expr` declares with the factory registered for its type.
+    pub fn build(&self, expr: &PuppetExpr) -> Result<Box<dyn Resource>> {
+        match expr {
+            PuppetExpr::Resource { rtype, .. } => match self.factories.get(rtype) {
+                Some(factory) => factory(expr),
+                None => Err(anyhow!("unknown rtype: {rtype}")),
+            },
+            PuppetExpr::Relation { .. } => {
+                Err(anyhow!("The expr is not a relation. Expected a resource."))
+            }
+            PuppetExpr::Class { name, .. } => Err(anyhow!(
+                "The class definition {name} is not a resource. Declare the class instead."
+            )),
+            PuppetExpr::Define { name, .. } => Err(anyhow!(
+                "The definition of {name} is not a resource. Declare an instance instead."
+            )),
+            PuppetExpr::Each { span, .. } => Err(anyhow!(
+                "The iteration at {span} is not a resource. Evaluate the manifest first."
+            )),
+            PuppetExpr::Conditional { span, .. } => Err(anyhow!(
+                "The conditional at {span} is not a resource. Evaluate the manifest first."
+            )),
+            PuppetExpr::Assert { span, .. } => Err(anyhow!(
+                "The assertion at {span} is not a resource. Evaluate the manifest first."
+            )),
+            PuppetExpr::Defaults { rtype, span, .. } => Err(anyhow!(
+                "The {rtype} defaults at {span} are not a resource. Evaluate the manifest first."
+            )),
+            PuppetExpr::Include { .. } => Err(anyhow!(
+                "The expr is a class declaration. Expected a resource."
+            )),
+            PuppetExpr::Opaque { text, span } => Err(anyhow!(
+                "Unsupported construct at {span} is not a resource: {text}"
+            )),
+        }
+    }
+
+    /// The normalized title, the declared attributes and the attributes as written, for
+    /// factories to build their resource from.
+    pub fn declaration(expr: &PuppetExpr) -> Result<(String, Attributes, &[Attribute])> {
+        let PuppetExpr::Resource {
+            rtype,
+            title,
+            attributes,
+            ..
+        } = expr
+        else {
+            return Err(anyhow!("Expected a resource, found: {expr}"));
+        };
+        let declared = attributes
+            .iter()
+            .map(|attr| (attr.name.clone(), attr.value.clone()))
+            .collect();
+        Ok((
+            normalize_title(rtype, &title.to_string()),
+            declared,
+            attributes,
+        ))
+    }
+}
+
+impl ResourceRegistry {
+    /// The built-in types, whose providers act through `system`.
+    pub fn with_system(system: &System) -> Self {
+        let mut registry = Self::empty();
+        let processes = system.processes.clone();
+        let services = Arc::new(Systemd::new(processes.clone()));
+        let groups = Arc::new(Groupadd::new(processes.clone()));
+        let users = Arc::new(Useradd::new(processes.clone()));
+        registry
+            .register("File", {
+                let system = system.clone();
+                move |expr| {
+                    let (title, declared, attributes) = Self::declaration(expr)?;
+                    Ok(Box::new(File {
+                        title,
+                        attributes: declared,
+                        spec: FileSpec::from_attributes(attributes)?,
+                        system: system.clone(),
+                    }))
+                }
+            })
+            .register("Exec", {
+                let system = system.clone();
+                move |expr| {
+                    let (title, declared, attributes) = Self::declaration(expr)?;
+                    Ok(Box::new(Exec {
+                        title,
+                        attributes: declared,
+                        spec: ExecSpec::from_attributes(attributes)?,
+                        sandbox: ExecPolicy::from_attributes(attributes)?,
+                        system: system.clone(),
+                    }))
+                }
+            })
+            .register("Service", {
+                let system = system.clone();
+                move |expr| {
+                    let (title, declared, attributes) = Self::declaration(expr)?;
+                    Ok(Box::new(Service {
+                        title,
+                        attributes: declared,
+                        spec: ServiceSpec::from_attributes(attributes)?,
+                        provider: services.clone(),
+                        system: system.clone(),
+                    }))
+                }
+            })
+            .register("Package", move |expr| {
+                let (title, declared, attributes) = Self::declaration(expr)?;
+                let spec = PackageSpec::from_attributes(attributes)?;
+                let provider = spec.provider.or(Capabilities::cached().package_manager);
+                Ok(Box::new(Package {
+                    title,
+                    attributes: declared,
+                    spec,
+                    provider: provider.map(|manager| {
+                        Arc::new(NativePackages::new(manager, processes.clone()))
+                            as Arc<dyn PackageProvider>
+                    }),
+                }))
+            })
+            .register("Apt::Source", {
+                let system = system.clone();
+                move |expr| {
+                    let (title, declared, attributes) = Self::declaration(expr)?;
+                    repository::check_title("Apt::Source", &title)?;
+                    Ok(Box::new(AptSource {
+                        title,
+                        attributes: declared,
+                        spec: AptSourceSpec::from_attributes(attributes)?,
+                        system: system.clone(),
+                    }))
+                }
+            })
+            .register("Yumrepo", {
+                let system = system.clone();
+                move |expr| {
+                    let (title, declared, attributes) = Self::declaration(expr)?;
+                    repository::check_title("Yumrepo", &title)?;
+                    Ok(Box::new(Yumrepo {
+                        title,
+                        attributes: declared,
+                        spec: YumrepoSpec::from_attributes(attributes)?,
+                        system: system.clone(),
+                    }))
+                }
+            })
+            .register("Group", move |expr| {
+                let (title, declared, attributes) = Self::declaration(expr)?;
+                Ok(Box::new(Group {
+                    title,
+                    attributes: declared,
+                    spec: GroupSpec::from_attributes(attributes)?,
+                    provider: groups.clone(),
+                }))
+            })
+            .register("User", {
+                let system = system.clone();
+                move |expr| {
+                    let (title, declared, attributes) = Self::declaration(expr)?;
+                    Ok(Box::new(User {
+                        title,
+                        attributes: declared,
+                        spec: UserSpec::from_attributes(attributes)?,
+                        provider: users.clone(),
+                        system: system.clone(),
+                    }))
+                }
+            })
+            .register("Class", |expr| {
+                let (title, attributes, _) = Self::declaration(expr)?;
+                Ok(Box::new(Class { title, attributes }))
+            })
+            .register("Foo::Bar", |expr| {
+                let (title, attributes, _) = Self::declaration(expr)?;
+                Ok(Box::new(FooBar { title, attributes }))
+            });
+        registry
+    }
+}
+
+impl Default for ResourceRegistry {
+    /// The built-in types, acting on the host.
+    fn default() -> Self {
+        Self::with_system(&System::default())
+    }
+}
+
+impl fmt::Debug for ResourceRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.types()).finish()
+    }
+}
@@ -0,0 +1,601 @@
+//! Package repositories: apt sources and yum repositories, with the GPG keys their
+//! packages are signed with. Packages are ordered after every repository in the plan,
+//! see [`Resource::autorequire_types`].
+
+use super::file::{File, FileEnsure, FileSpec};
+use super::file_mode::FileMode;
+use super::resource::{Attributes, ChangeReport, Ensure, PropertyChange, Resource};
+use crate::parser::pp::Attribute;
+use crate::parser::value::IntoValue;
+use crate::system::System;
+use anyhow::{Context, Result, anyhow};
+use indexmap::IndexMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The repository types, which [`super::Package`] resources autorequire.
+pub const REPOSITORY_TYPES: [&str; 2] = ["Apt::Source", "Yumrepo"];
+
+/// Repository files are named after the title, so it must be a plain file name.
+pub fn check_title(rtype: &str, title: &str) -> Result<()> {
+    let valid = !title.is_empty()
+        && title
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+        && !title.starts_with('.');
+    match valid {
+        true => Ok(()),
+        false => Err(anyhow!(
+            "{rtype} title {title} must only contain letters, digits, -, _ and ."
+        )),
+    }
+}
+
+/// A world-readable file with `content`, or no file at all.
+fn managed_file(system: &System, path: &Path, content: &str, ensure: &Ensure) -> File {
+    File {
+        title: path.display().to_string(),
+        attributes: Attributes::new(),
+        spec: FileSpec {
+            ensure: match ensure {
+                Ensure::Absent => FileEnsure::Absent,
+                _ => FileEnsure::File,
+            },
+            content: Some(content.to_string().into_value()),
+            mode: Some(FileMode::Octal(0o644)),
+            ..FileSpec::default()
+        },
+        system: system.clone(),
+    }
+}
+
+/// Whether a repository `ensure` is present; a key is present as long as its repository.
+fn present(attributes: &[Attribute], rtype: &str) -> Result<bool> {
+    match attributes.iter().find(|attr| attr.name == "ensure") {
+        None => Ok(true),
+        Some(attr) => match attr.parse_as::<String>(rtype)?.as_str() {
+            "present" => Ok(true),
+            "absent" => Ok(false),
+            other => Err(anyhow!("Invalid {rtype} ensure: {other}")),
+        },
+    }
+}
+
+/// An apt source, `/etc/apt/sources.list.d/<title>.list`, signed by its own key when
+/// `key_content` is given.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AptSourceSpec {
+    pub present: bool,
+    pub location: String,
+    /// The distribution codename, `bookworm`.
+    pub release: String,
+    pub repos: Vec<String>,
+    pub architecture: Option<String>,
+    /// The ASCII-armored key the repository is signed with.
+    pub key_content: Option<String>,
+    /// Whether `apt-get update` runs after the source changes, so that packages from it
+    /// can be installed right away.
+    pub notify_update: bool,
+    pub sources_dir: PathBuf,
+    pub keyrings_dir: PathBuf,
+}
+
+impl AptSourceSpec {
+    pub fn from_attributes(attributes: &[Attribute]) -> Result<Self> {
+        let mut spec = Self {
+            present: present(attributes, "Apt::Source")?,
+            location: String::new(),
+            release: String::new(),
+            repos: vec!["main".to_string()],
+            architecture: None,
+            key_content: None,
+            notify_update: true,
+            sources_dir: PathBuf::from("/etc/apt/sources.list.d"),
+            keyrings_dir: PathBuf::from("/etc/apt/keyrings"),
+        };
+        for attr in attributes {
+            match attr.name.as_str() {
+                "location" => spec.location = attr.parse_as("Apt::Source")?,
+                "release" => spec.release = attr.parse_as("Apt::Source")?,
+                "repos" => {
+                    let repos: String = attr.parse_as("Apt::Source")?;
+                    spec.repos = repos.split_whitespace().map(str::to_string).collect();
+                }
+                "architecture" => spec.architecture = attr.parse_as("Apt::Source")?,
+                "key_content" => spec.key_content = attr.parse_as("Apt::Source")?,
+                "notify_update" => spec.notify_update = attr.parse_as("Apt::Source")?,
+                _ => {}
+            }
+        }
+        if spec.present && (spec.location.is_empty() || spec.release.is_empty()) {
+            return Err(anyhow!("Apt::Source needs a location and a release"));
+        }
+        Ok(spec)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AptSource {
+    pub title: String,
+    pub attributes: Attributes,
+    pub spec: AptSourceSpec,
+    /// Where the files are written and `apt-get update` runs.
+    pub system: System,
+}
+
+impl AptSource {
+    fn key_path(&self) -> PathBuf {
+        self.spec.keyrings_dir.join(format!("{}.asc", self.title))
+    }
+
+    fn list_path(&self) -> PathBuf {
+        self.spec.sources_dir.join(format!("{}.list", self.title))
+    }
+
+    /// The one-line-style entry, `deb [signed-by=...] location release repos`.
+    fn entry(&self) -> String {
+        let mut options = vec![];
+        if let Some(architecture) = &self.spec.architecture {
+            options.push(format!("arch={architecture}"));
+        }
+        if self.spec.key_content.is_some() {
+            options.push(format!("signed-by={}", self.key_path().display()));
+        }
+        let options = match options.is_empty() {
+            true => String::new(),
+            false => format!("[{}] ", options.join(" ")),
+        };
+        format!(
+            "deb {options}{} {} {}\n",
+            self.spec.location,
+            self.spec.release,
+            self.spec.repos.join(" ")
+        )
+    }
+
+    fn ensure_of(&self, ensure: &Ensure) -> Ensure {
+        match self.spec.present && ensure.is_present() {
+            true => Ensure::Present,
+            false => Ensure::Absent,
+        }
+    }
+
+    /// The key file first, since the source refers to it.
+    fn files(&self, ensure: &Ensure) -> Vec<(&'static str, File)> {
+        let ensure = self.ensure_of(ensure);
+        let mut files = vec![];
+        if let Some(key) = &self.spec.key_content {
+            files.push((
+                "key",
+                managed_file(&self.system, &self.key_path(), key, &ensure),
+            ));
+        }
+        files.push((
+            "source",
+            managed_file(&self.system, &self.list_path(), &self.entry(), &ensure),
+        ));
+        files
+    }
+}
+
+impl Resource for AptSource {
+    fn rtype(&self) -> &str {
+        "Apt::Source"
+    }
+
+    fn title(&self) -> String {
+        self.title.clone()
+    }
+
+    fn attributes(&self) -> &Attributes {
+        &self.attributes
+    }
+
+    fn preview(&self, ensure: Ensure) -> Vec<String> {
+        let mut preview: Vec<_> = self
+            .files(&ensure)
+            .iter()
+            .flat_map(|(_, file)| file.preview(self.ensure_of(&ensure)))
+            .collect();
+        if self.ensure_of(&ensure) == Ensure::Present && self.spec.notify_update {
+            preview.push("would run: apt-get update".to_string());
+        }
+        preview
+    }
+
+    fn check(&self, ensure: Ensure) -> Result<Vec<PropertyChange>> {
+        let mut changes = vec![];
+        for (property, file) in self.files(&ensure) {
+            if file.check(self.ensure_of(&ensure))?.is_empty() {
+                continue;
+            }
+            let current = self.system.fs.read_to_string(Path::new(&file.title)).ok();
+            let desired = match self.ensure_of(&ensure) {
+                Ensure::Absent => "absent".to_string(),
+                _ if property == "source" => self.entry(),
+                _ => file.title(),
+            };
+            let current = match (property, current) {
+                ("source", Some(entry)) => entry.trim_end().to_string(),
+                (_, Some(_)) => "outdated".to_string(),
+                (_, None) => "absent".to_string(),
+            };
+            changes.push(PropertyChange::new(
+                property,
+                Some(current),
+                desired.trim_end(),
+            ));
+        }
+        Ok(changes)
+    }
+
+    fn ensure(&self, ensure: Ensure) -> Result<ChangeReport> {
+        let changes = self.check(ensure.clone())?;
+        if changes.is_empty() {
+            return Ok(ChangeReport::unchanged());
+        }
+        if self.ensure_of(&ensure) == Ensure::Present && self.spec.key_content.is_some() {
+            self.system
+                .fs
+                .create_dir_all(&self.spec.keyrings_dir)
+                .with_context(|| format!("Cannot create {}", self.spec.keyrings_dir.display()))?;
+        }
+        for (_, file) in self.files(&ensure) {
+            file.ensure(self.ensure_of(&ensure))?;
+        }
+        if self.ensure_of(&ensure) == Ensure::Present && self.spec.notify_update {
+            let output = self
+                .system
+                .processes
+                .output(Command::new("apt-get").args(["update", "-q"]))
+                .context("Cannot run apt-get update")?;
+            if !output.status.success() {
+                return Err(anyhow!(
+                    "apt-get update failed after changing {}: {}",
+                    self.id(),
+                    String::from_utf8_lossy(&output.stderr).trim_end()
+                ));
+            }
+        }
+        Ok(ChangeReport::new(changes))
+    }
+}
+
+/// The settings of a yum repository, in `.repo` order, as Puppet's `yumrepo` names them;
+/// `descr` is written as `name`.
+const YUM_SETTINGS: [&str; 14] = [
+    "descr",
+    "baseurl",
+    "mirrorlist",
+    "metalink",
+    "enabled",
+    "gpgcheck",
+    "repo_gpgcheck",
+    "gpgkey",
+    "priority",
+    "exclude",
+    "includepkgs",
+    "skip_if_unavailable",
+    "module_hotfixes",
+    "sslverify",
+];
+
+/// A yum repository, `/etc/yum.repos.d/<title>.repo` unless `target` says otherwise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct YumrepoSpec {
+    pub present: bool,
+    /// The settings as they are written, booleans as `1` and `0`.
+    pub settings: IndexMap<String, String>,
+    /// The ASCII-armored key the packages are signed with, written under `keys_dir` and
+    /// used as the `gpgkey` unless one is given.
+    pub key_content: Option<String>,
+    pub target: Option<PathBuf>,
+    pub keys_dir: PathBuf,
+}
+
+impl YumrepoSpec {
+    pub fn from_attributes(attributes: &[Attribute]) -> Result<Self> {
+        let mut spec = Self {
+            present: present(attributes, "Yumrepo")?,
+            settings: IndexMap::new(),
+            key_content: None,
+            target: None,
+            keys_dir: PathBuf::from("/etc/pki/rpm-gpg"),
+        };
+        for setting in YUM_SETTINGS {
+            let Some(attr) = attributes.iter().find(|attr| attr.name == setting) else {
+                continue;
+            };
+            let value = match attr.parse_as::<bool>("Yumrepo") {
+                Ok(enabled) => u8::from(enabled).to_string(),
+                Err(_) => attr.parse_as("Yumrepo")?,
+            };
+            let key = match setting {
+                "descr" => "name",
+                setting => setting,
+            };
+            spec.settings.insert(key.to_string(), value);
+        }
+        for attr in attributes {
+            match attr.name.as_str() {
+                "key_content" => spec.key_content = attr.parse_as("Yumrepo")?,
+                "target" => spec.target = attr.parse_as("Yumrepo")?,
+                _ => {}
+            }
+        }
+        let urls = ["baseurl", "mirrorlist", "metalink"];
+        if spec.present && !urls.iter().any(|url| spec.settings.contains_key(*url)) {
+            return Err(anyhow!(
+                "Yumrepo needs a baseurl, a mirrorlist or a metalink"
+            ));
+        }
+        Ok(spec)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Yumrepo {
+    pub title: String,
+    pub attributes: Attributes,
+    pub spec: YumrepoSpec,
+    /// Where the files are written.
+    pub system: System,
+}
+
+impl Yumrepo {
+    fn key_path(&self) -> PathBuf {
+        self.spec
+            .keys_dir
+            .join(format!("RPM-GPG-KEY-{}", self.title))
+    }
+
+    fn repo_path(&self) -> PathBuf {
+        self.spec
+            .target
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(format!("/etc/yum.repos.d/{}.repo", self.title)))
+    }
+
+    /// The settings with the defaults dnf would otherwise pick less safely.
+    fn settings(&self) -> IndexMap<String, String> {
+        let mut settings = IndexMap::from([("name".to_string(), self.title.clone())]);
+        settings.extend(self.spec.settings.clone());
+        if self.spec.key_content.is_some() {
+            settings
+                .entry("gpgkey".to_string())
+                .or_insert_with(|| format!("file://{}", self.key_path().display()));
+            settings
+                .entry("gpgcheck".to_string())
+                .or_insert_with(|| "1".to_string());
+        }
+        settings
+    }
+
+    fn content(&self) -> String {
+        let mut content = format!("[{}]\n", self.title);
+        for (key, value) in self.settings() {
+            content.push_str(&format!("{key}={value}\n"));
+        }
+        content
+    }
+
+    fn ensure_of(&self, ensure: &Ensure) -> Ensure {
+        match self.spec.present && ensure.is_present() {
+            true => Ensure::Present,
+            false => Ensure::Absent,
+        }
+    }
+
+    /// The settings of the repository's section in `content`.
+    fn current_settings(&self, content: &str) -> IndexMap<String, String> {
+        let header = format!("[{}]", self.title);
+        content
+            .lines()
+            .map(str::trim)
+            .skip_while(|line| *line != header)
+            .skip(1)
+            .take_while(|line| !line.starts_with('['))
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+            .collect()
+    }
+}
+
+impl Resource for Yumrepo {
+    fn rtype(&self) -> &str {
+        "Yumrepo"
+    }
+
+    fn title(&self) -> String {
+        self.title.clone()
+    }
+
+    fn attributes(&self) -> &Attributes {
+        &self.attributes
+    }
+
+    fn preview(&self, ensure: Ensure) -> Vec<String> {
+        let ensure = self.ensure_of(&ensure);
+        let mut preview = vec![];
+        if let Some(key) = &self.spec.key_content {
+            preview.extend(
+                managed_file(&self.system, &self.key_path(), key, &ensure).preview(ensure.clone()),
+            );
+        }
+        preview.extend(
+            managed_file(&self.system, &self.repo_path(), &self.content(), &ensure)
+                .preview(ensure.clone()),
+        );
+        preview
+    }
+
+    fn check(&self, ensure: Ensure) -> Result<Vec<PropertyChange>> {
+        let ensure = self.ensure_of(&ensure);
+        let mut changes = vec![];
+        if let Some(key) = &self.spec.key_content
+            && !managed_file(&self.system, &self.key_path(), key, &ensure)
+                .check(ensure.clone())?
+                .is_empty()
+        {
+            let current = match self.system.fs.exists(&self.key_path()) {
+                true => "outdated",
+                false => "absent",
+            };
+            let desired = match ensure {
+                Ensure::Absent => "absent".to_string(),
+                _ => self.key_path().display().to_string(),
+            };
+            changes.push(PropertyChange::new("key", Some(current), desired));
+        }
+        let current = match self.system.fs.read_to_string(&self.repo_path()) {
+            Ok(content) => content,
+            Err(_) if ensure == Ensure::Present => {
+                changes.push(PropertyChange::new("ensure", Some("absent"), "present"));
+                return Ok(changes);
+            }
+            Err(_) => return Ok(changes),
+        };
+        if ensure == Ensure::Absent {
+            changes.push(PropertyChange::new("ensure", Some("present"), "absent"));
+            return Ok(changes);
+        }
+        let mut current = self.current_settings(&current);
+        for (key, desired) in self.settings() {
+            match current.shift_remove(&key) {
+                Some(found) if found == desired => {}
+                found => changes.push(PropertyChange::new(
+                    &key,
+                    Some(found.unwrap_or("absent".into())),
+                    desired,
+                )),
+            }
+        }
+        for (key, found) in current {
+            changes.push(PropertyChange::new(&key, Some(found), "absent"));
+        }
+        Ok(changes)
+    }
+
+    fn ensure(&self, ensure: Ensure) -> Result<ChangeReport> {
+        let changes = self.check(ensure.clone())?;
+        if changes.is_empty() {
+            return Ok(ChangeReport::unchanged());
+        }
+        let ensure = self.ensure_of(&ensure);
+        if let Some(key) = &self.spec.key_content {
+            if ensure == Ensure::Present {
+                self.system
+                    .fs
+                    .create_dir_all(&self.spec.keys_dir)
+                    .with_context(|| format!("Cannot create {}", self.spec.keys_dir.display()))?;
+            }
+            managed_file(&self.system, &self.key_path(), key, &ensure).ensure(ensure.clone())?;
+        }
+        managed_file(&self.system, &self.repo_path(), &self.content(), &ensure).ensure(ensure)?;
+        Ok(ChangeReport::new(changes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_puppet_manifest;
+    use crate::parser::pp::Manifest;
+    use std::fs;
+
+    #[test]
+    fn test_repositories_converge() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("dolly-repos-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir)?;
+
+        let source = AptSource {
+            title: "nginx".to_string(),
+            attributes: Attributes::new(),
+            spec: AptSourceSpec {
+                present: true,
+                location: "https://nginx.org/packages/debian".to_string(),
+                release: "bookworm".to_string(),
+                repos: vec!["main".to_string()],
+                architecture: None,
+                key_content: Some("-----BEGIN PGP PUBLIC KEY BLOCK-----\n".to_string()),
+                notify_update: false,
+                sources_dir: dir.clone(),
+                keyrings_dir: dir.join("keyrings"),
+            },
+            system: System::default(),
+        };
+        let changes = source.check(Ensure::Present)?;
+        assert_eq!(
+            changes
+                .iter()
+                .map(|c| c.property.as_str())
+                .collect::<Vec<_>>(),
+            ["key", "source"]
+        );
+        source.ensure(Ensure::Present)?;
+        assert!(source.check(Ensure::Present)?.is_empty());
+        assert_eq!(
+            fs::read_to_string(dir.join("nginx.list"))?,
+            format!(
+                "deb [signed-by={}] https://nginx.org/packages/debian bookworm main\n",
+                dir.join("keyrings/nginx.asc").display()
+            )
+        );
+
+        let repo = dir.join("epel.repo");
+        let input = format!(
+            r#"yumrepo {{ "epel":
+                descr => "Extra Packages",
+                baseurl => "https://download.example/epel/9/",
+                enabled => true,
+                target => "{}",
+            }}
+            package {{ "htop": }}
+            apt::source {{ "nginx": location => "https://nginx.org", release => "bookworm" }}"#,
+            repo.display()
+        );
+        let plan = parse_puppet_manifest(&input.parse::<Manifest>()?)?;
+        let order: Vec<_> = plan
+            .sorted_weights()?
+            .values()
+            .map(|resource| resource.id())
+            .collect();
+        assert_eq!(
+            order.last().map(String::as_str),
+            Some("Package[htop]"),
+            "Packages come after repositories: {order:?}"
+        );
+        let epel = plan
+            .node("Yumrepo[epel]")
+            .ok_or_else(|| anyhow!("The repository is in the plan"))?;
+        let yumrepo = &plan.plan().inner()[epel];
+        yumrepo.ensure(Ensure::Present)?;
+        assert_eq!(
+            fs::read_to_string(&repo)?,
+            "[epel]\nname=Extra Packages\nbaseurl=https://download.example/epel/9/\nenabled=1\n"
+        );
+        fs::write(
+            &repo,
+            "[epel]\nname=Extra Packages\nenabled=0\nproxy=_none_\n",
+        )?;
+        assert_eq!(
+            yumrepo.check(Ensure::Present)?,
+            vec![
+                PropertyChange::new(
+                    "baseurl",
+                    Some("absent"),
+                    "https://download.example/epel/9/"
+                ),
+                PropertyChange::new("enabled", Some("0"), "1"),
+                PropertyChange::new("proxy", Some("_none_"), "absent"),
+            ]
+        );
+        yumrepo.ensure(Ensure::Absent)?;
+        assert!(!repo.exists());
+
+        assert!(check_title("Yumrepo", "../etc").is_err());
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -1,26 +1,228 @@
+use super::{Capabilities, Confine, ExecOutput, ExecPolicy, OutputLog};
+use crate::parser::pp::AttrValue;
+use crate::parser::value::FromValue;
+use anyhow::{Result, anyhow};
 use core::fmt::Debug as FmtDebug;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
 
-pub trait Resource {
+/// Attributes as declared in the manifest, in declaration order.
+pub type Attributes = IndexMap<String, AttrValue>;
+
+pub trait Resource: Send + Sync {
     fn rtype(&self) -> &str;
 
     fn title(&self) -> String;
 
-    fn ensure(&self, ensure: Ensure);
+    fn attributes(&self) -> &Attributes;
+
+    /// The value of attribute `name` when it is a literal, e.g. `"0644"` for `mode`.
+    fn attribute(&self, name: &str) -> Option<String> {
+        self.attributes().get(name)?.as_literal()
+    }
+
+    /// Compares the system with `ensure` without changing anything, returning what
+    /// [`Resource::ensure`] would change. Empty means already in sync. Resources that
+    /// cannot inspect their current state report `ensure` as changing from unknown.
+    fn check(&self, ensure: Ensure) -> Result<Vec<PropertyChange>> {
+        Ok(vec![PropertyChange::new("ensure", None::<Ensure>, ensure)])
+    }
+
+    /// Brings the system to `ensure`, reporting what changed.
+    fn ensure(&self, ensure: Ensure) -> Result<ChangeReport>;
+
+    /// [`Resource::check`] under the settings of the run it is part of.
+    fn check_in(&self, ensure: Ensure, _context: &ApplyContext) -> Result<Vec<PropertyChange>> {
+        self.check(ensure)
+    }
+
+    /// [`Resource::ensure`] under the settings of the run it is part of.
+    fn ensure_in(&self, ensure: Ensure, _context: &ApplyContext) -> Result<ChangeReport> {
+        self.ensure(ensure)
+    }
+
+    /// Reacts to a change in a resource that notifies this one, e.g. a service restart,
+    /// reporting what it did.
+    fn refresh(&self) -> Result<ChangeReport> {
+        Ok(ChangeReport::unchanged())
+    }
+
+    /// [`Resource::refresh`] under the settings of the run it is part of.
+    fn refresh_in(&self, _context: &ApplyContext) -> Result<ChangeReport> {
+        self.refresh()
+    }
+
+    /// Fails with the reason when no provider for this resource works on the system.
+    fn check_provider(&self, _capabilities: &Capabilities) -> Result<()> {
+        Ok(())
+    }
+
+    /// Facts a node must have for this resource to apply there. Confined-away resources
+    /// stay in the plan and are reported as not applicable.
+    fn confines(&self) -> Vec<Confine> {
+        vec![]
+    }
+
+    /// Types whose resources in the plan this one is applied after, without a relation
+    /// being declared, as packages are after the repositories they may come from.
+    fn autorequire_types(&self) -> &[&str] {
+        &[]
+    }
+
+    /// The commands or syscalls the provider would perform to reach `ensure`.
+    fn preview(&self, _ensure: Ensure) -> Vec<String> {
+        vec![]
+    }
 
     fn id(&self) -> String {
         format!("{}[{}]", self.rtype(), self.title())
     }
 }
 
-#[derive(Debug, Default)]
+/// Settings of an apply run that resources are checked and enforced under.
+#[derive(Debug, Clone, Default)]
+pub struct ApplyContext {
+    /// The global policy Execs run under, their own settings taking precedence.
+    pub exec_policy: ExecPolicy,
+    /// Where the output of commands run while enforcing goes, line by line.
+    pub log: OutputLog,
+    /// The commands run while enforcing, with the tails of their output.
+    pub outputs: Arc<Mutex<Vec<ExecOutput>>>,
+}
+
+impl ApplyContext {
+    /// Keeps `output` for the report entry of the resource being enforced.
+    pub fn keep_output(&self, output: &ExecOutput) {
+        self.outputs
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(output.clone());
+    }
+}
+
+/// The state a resource is brought to, from its `ensure` attribute. Resources without one
+/// are `Present`: in the state their other attributes describe.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub enum Ensure {
+    /// `present`, `installed` or, for files, `file`.
     #[default]
     Present,
+    /// `absent` or `purged`.
     Absent,
+    /// `running`, or `true`.
+    Running,
+    /// `stopped`, or `false`.
+    Stopped,
+    Directory,
+    Link,
+    Latest,
+    /// A version of a package, `'1.2.3'`, `'1.2.*'` or `'>= 1.2'`.
+    Version(String),
+}
+
+impl Ensure {
+    /// Whether the resource exists in some form, as everything but `Absent` asks for.
+    pub fn is_present(&self) -> bool {
+        *self != Self::Absent
+    }
+}
+
+impl fmt::Display for Ensure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Present => write!(f, "present"),
+            Self::Absent => write!(f, "absent"),
+            Self::Running => write!(f, "running"),
+            Self::Stopped => write!(f, "stopped"),
+            Self::Directory => write!(f, "directory"),
+            Self::Link => write!(f, "link"),
+            Self::Latest => write!(f, "latest"),
+            Self::Version(version) => write!(f, "{version}"),
+        }
+    }
+}
+
+impl FromValue for Ensure {
+    fn from_value(value: &AttrValue) -> Result<Self> {
+        let literal = value
+            .as_literal()
+            .ok_or_else(|| anyhow!("must be a literal, got {value}"))?;
+        Ok(match literal.as_str() {
+            "present" | "installed" | "file" => Self::Present,
+            "absent" | "purged" => Self::Absent,
+            "running" | "true" => Self::Running,
+            "stopped" | "false" => Self::Stopped,
+            "directory" => Self::Directory,
+            "link" => Self::Link,
+            "latest" => Self::Latest,
+            version if version.starts_with(|c: char| c.is_ascii_digit() || "<>=".contains(c)) => {
+                Self::Version(version.to_string())
+            }
+            other => return Err(anyhow!("unknown ensure value {other}")),
+        })
+    }
+}
+
+/// One property that differs between the system and the manifest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PropertyChange {
+    pub property: String,
+    /// `None` when the current value cannot be determined.
+    pub current: Option<String>,
+    pub desired: String,
+    /// How the content changes, as a unified diff, for properties that have one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff: Option<String>,
+}
+
+impl PropertyChange {
+    pub fn new(property: &str, current: Option<impl ToString>, desired: impl ToString) -> Self {
+        Self {
+            property: property.to_string(),
+            current: current.map(|current| current.to_string()),
+            desired: desired.to_string(),
+            diff: None,
+        }
+    }
+
+    pub fn with_diff(mut self, diff: String) -> Self {
+        self.diff = Some(diff);
+        self
+    }
+}
+
+/// What [`Resource::ensure`] changed: each property with its old and new value, none when
+/// the resource was already in sync. A failure is the error `ensure` returns instead.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ChangeReport {
+    pub changes: Vec<PropertyChange>,
+}
+
+impl ChangeReport {
+    pub fn new(changes: Vec<PropertyChange>) -> Self {
+        Self { changes }
+    }
+
+    pub fn unchanged() -> Self {
+        Self::default()
+    }
+
+    pub fn changed(&self) -> bool {
+        !self.changes.is_empty()
+    }
+}
+
+impl fmt::Display for PropertyChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let current = self.current.as_deref().unwrap_or("unknown");
+        write!(f, "{}: {current} -> {}", self.property, self.desired)
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Relation {
     Provide,
     Notify,
@@ -0,0 +1,189 @@
+//! SELinux contexts of managed files, read with `stat` and set with `chcon` and
+//! `restorecon`, so that nothing links against libselinux.
+
+use super::resource::PropertyChange;
+use crate::parser::pp::Attribute;
+use crate::system::{FileSystem, Processes};
+use anyhow::{Context, Result, anyhow};
+use std::fmt;
+use std::path::Path;
+use std::process::Command;
+use std::str::FromStr;
+
+/// Whether SELinux is enabled, i.e. its filesystem is mounted. Without it contexts are
+/// neither checked nor set.
+pub fn enabled(fs: &dyn FileSystem) -> bool {
+    fs.exists(Path::new("/sys/fs/selinux/enforce"))
+}
+
+/// A security context, `system_u:object_r:httpd_sys_content_t:s0`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecurityContext {
+    pub user: String,
+    pub role: String,
+    pub rtype: String,
+    /// The MLS/MCS range, which may itself contain colons: `s0:c0.c1023`.
+    pub range: Option<String>,
+}
+
+impl FromStr for SecurityContext {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.trim().splitn(4, ':');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(user), Some(role), Some(rtype)) if !rtype.is_empty() => Ok(Self {
+                user: user.to_string(),
+                role: role.to_string(),
+                rtype: rtype.to_string(),
+                range: parts.next().map(str::to_string),
+            }),
+            _ => Err(anyhow!("Invalid SELinux context: {s}")),
+        }
+    }
+}
+
+impl fmt::Display for SecurityContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.user, self.role, self.rtype)?;
+        if let Some(range) = &self.range {
+            write!(f, ":{range}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The context of `path` itself, not of what a link points to, or `None` if it has none.
+pub fn context(processes: &dyn Processes, path: &Path) -> Result<Option<SecurityContext>> {
+    let output = processes
+        .output(Command::new("stat").args(["-c", "%C"]).arg(path))
+        .with_context(|| format!("Cannot read the SELinux context of {}", path.display()))?;
+    let context = String::from_utf8_lossy(&output.stdout);
+    if !output.status.success() || context.trim() == "?" {
+        return Ok(None);
+    }
+    context.parse().map(Some)
+}
+
+/// Gives `path` the context the policy has for it.
+pub fn restore(processes: &dyn Processes, path: &Path) -> Result<()> {
+    run(processes, Command::new("restorecon").arg(path), path)
+}
+
+fn run(processes: &dyn Processes, command: &mut Command, path: &Path) -> Result<()> {
+    let output = processes
+        .output(command)
+        .with_context(|| format!("Cannot set the SELinux context of {}", path.display()))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Cannot set the SELinux context of {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// The parts of a File's context the manifest manages; the others are left alone.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SelinuxSpec {
+    pub seluser: Option<String>,
+    pub selrole: Option<String>,
+    pub seltype: Option<String>,
+    pub selrange: Option<String>,
+    /// Whether files dolly creates keep the context they are created with instead of the
+    /// one the policy has for them.
+    pub ignore_defaults: bool,
+}
+
+impl SelinuxSpec {
+    /// Reads the `sel*` attributes, ignoring the others.
+    pub fn from_attributes(attributes: &[Attribute]) -> Result<Self> {
+        let mut spec = Self::default();
+        for attr in attributes {
+            match attr.name.as_str() {
+                "seluser" => spec.seluser = attr.parse_as("File")?,
+                "selrole" => spec.selrole = attr.parse_as("File")?,
+                "seltype" => spec.seltype = attr.parse_as("File")?,
+                "selrange" => spec.selrange = attr.parse_as("File")?,
+                "selinux_ignore_defaults" => spec.ignore_defaults = attr.parse_as("File")?,
+                _ => {}
+            }
+        }
+        Ok(spec)
+    }
+
+    fn desired(&self) -> [(&'static str, &'static str, Option<&String>); 4] {
+        [
+            ("seluser", "--user", self.seluser.as_ref()),
+            ("selrole", "--role", self.selrole.as_ref()),
+            ("seltype", "--type", self.seltype.as_ref()),
+            ("selrange", "--range", self.selrange.as_ref()),
+        ]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.desired().iter().all(|(_, _, value)| value.is_none())
+    }
+
+    /// The managed parts that differ from `current`.
+    pub fn changes(&self, current: &SecurityContext) -> Vec<PropertyChange> {
+        let current = [
+            Some(&current.user),
+            Some(&current.role),
+            Some(&current.rtype),
+            current.range.as_ref(),
+        ];
+        self.desired()
+            .into_iter()
+            .zip(current)
+            .filter_map(|((property, _, desired), current)| {
+                let desired = desired?;
+                (current != Some(desired)).then(|| PropertyChange::new(property, current, desired))
+            })
+            .collect()
+    }
+
+    /// Sets the managed parts of the context of `path`.
+    pub fn apply(&self, processes: &dyn Processes, path: &Path) -> Result<()> {
+        let mut command = Command::new("chcon");
+        command.arg("--no-dereference");
+        for (_, flag, value) in self.desired() {
+            if let Some(value) = value {
+                command.arg(format!("{flag}={value}"));
+            }
+        }
+        run(processes, command.arg(path), path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context_drift() -> Result<()> {
+        let current: SecurityContext = "system_u:object_r:etc_t:s0:c0.c1023".parse()?;
+        assert_eq!(current.range.as_deref(), Some("s0:c0.c1023"));
+        assert_eq!(current.to_string(), "system_u:object_r:etc_t:s0:c0.c1023");
+        assert!("etc_t".parse::<SecurityContext>().is_err());
+
+        let spec = SelinuxSpec {
+            seltype: Some("httpd_sys_content_t".to_string()),
+            selrange: Some("s0:c0.c1023".to_string()),
+            ..SelinuxSpec::default()
+        };
+        assert_eq!(
+            spec.changes(&current),
+            vec![PropertyChange::new(
+                "seltype",
+                Some("etc_t"),
+                "httpd_sys_content_t"
+            )],
+            "Only managed parts that differ drift"
+        );
+        assert!(SelinuxSpec::default().is_empty());
+        assert!(SelinuxSpec::default().changes(&current).is_empty());
+        Ok(())
+    }
+}
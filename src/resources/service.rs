@@ -1,17 +1,390 @@
-use super::resource::{Ensure, Resource};
+use super::exec::{Exec, ExecPolicy, ExecSpec};
+use super::resource::{ApplyContext, Attributes, ChangeReport, Ensure, PropertyChange, Resource};
+use super::{Capabilities, Confine};
+use crate::parser::pp::{AttrValue, Attribute};
+use crate::parser::value::{FromValue, IntoValue};
+use crate::system::{HostProcesses, Processes, System};
+use anyhow::{Context, Result, anyhow};
+use std::fmt;
+use std::process::Command;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Whether a service starts at boot, as `enable` asks for it and as `systemctl
+/// is-enabled` reports it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnitFileState {
+    Enabled,
+    Disabled,
+    /// Linked to `/dev/null`, so it cannot be started at all.
+    Masked,
+    /// Without an `[Install]` section, so it is only started by other units and cannot
+    /// be enabled or disabled.
+    Static,
+}
+
+impl fmt::Display for UnitFileState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Enabled => write!(f, "true"),
+            Self::Disabled => write!(f, "false"),
+            Self::Masked => write!(f, "mask"),
+            Self::Static => write!(f, "static"),
+        }
+    }
+}
+
+impl FromValue for UnitFileState {
+    fn from_value(value: &AttrValue) -> Result<Self> {
+        match bool::from_value(value) {
+            Ok(true) => Ok(Self::Enabled),
+            Ok(false) => Ok(Self::Disabled),
+            Err(_) if value.as_literal().as_deref() == Some("mask") => Ok(Self::Masked),
+            Err(_) => Err(anyhow!("must be true, false or mask, got {value}")),
+        }
+    }
+}
+
+/// Inspects and controls services for one init system.
+pub trait ServiceProvider: fmt::Debug + Send + Sync {
+    fn is_running(&self, service: &str) -> Result<bool>;
+    fn is_enabled(&self, service: &str) -> Result<bool>;
+    /// Starts `service`, giving up on it, and killing whatever it runs for it, once
+    /// `deadline` has passed.
+    fn start(&self, service: &str, deadline: Option<Instant>) -> Result<()>;
+    fn stop(&self, service: &str) -> Result<()>;
+    /// Restarts `service`, giving up on it like [`ServiceProvider::start`].
+    fn restart(&self, service: &str, deadline: Option<Instant>) -> Result<()>;
+    fn enable(&self, service: &str) -> Result<()>;
+    fn disable(&self, service: &str) -> Result<()>;
+    fn mask(&self, service: &str) -> Result<()>;
+    fn unmask(&self, service: &str) -> Result<()>;
+
+    /// The unit file state, for init systems that know more than enabled or not.
+    fn unit_file_state(&self, service: &str) -> Result<UnitFileState> {
+        Ok(match self.is_enabled(service)? {
+            true => UnitFileState::Enabled,
+            false => UnitFileState::Disabled,
+        })
+    }
+}
+
+/// Manages services with `systemctl`.
+#[derive(Debug, Clone)]
+pub struct Systemd {
+    processes: Arc<dyn Processes>,
+}
+
+impl Default for Systemd {
+    fn default() -> Self {
+        Self::new(Arc::new(HostProcesses))
+    }
+}
+
+impl Systemd {
+    pub fn new(processes: Arc<dyn Processes>) -> Self {
+        Self { processes }
+    }
+
+    /// Whether `systemctl <verb> --quiet <service>` succeeds.
+    fn query(&self, verb: &str, service: &str) -> Result<bool> {
+        Ok(self
+            .processes
+            .output(Command::new("systemctl").args([verb, "--quiet", service]))
+            .with_context(|| format!("Cannot query the state of {service}"))?
+            .status
+            .success())
+    }
+
+    fn run(&self, verb: &str, service: &str) -> Result<()> {
+        self.run_until(verb, service, None)
+    }
+
+    /// Runs `systemctl <verb> <service>`, killing it once `deadline` has passed.
+    fn run_until(&self, verb: &str, service: &str, deadline: Option<Instant>) -> Result<()> {
+        let output = self
+            .processes
+            .output_until(Command::new("systemctl").args([verb, service]), deadline)
+            .with_context(|| format!("Cannot run systemctl {verb} {service}"))?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "systemctl {verb} {service} failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl ServiceProvider for Systemd {
+    fn is_running(&self, service: &str) -> Result<bool> {
+        self.query("is-active", service)
+    }
+
+    fn is_enabled(&self, service: &str) -> Result<bool> {
+        self.query("is-enabled", service)
+    }
+
+    fn start(&self, service: &str, deadline: Option<Instant>) -> Result<()> {
+        self.run_until("start", service, deadline)
+    }
+
+    fn stop(&self, service: &str) -> Result<()> {
+        self.run("stop", service)
+    }
+
+    fn restart(&self, service: &str, deadline: Option<Instant>) -> Result<()> {
+        self.run_until("restart", service, deadline)
+    }
+
+    fn enable(&self, service: &str) -> Result<()> {
+        self.run("enable", service)
+    }
+
+    fn disable(&self, service: &str) -> Result<()> {
+        self.run("disable", service)
+    }
+
+    fn mask(&self, service: &str) -> Result<()> {
+        self.run("mask", service)
+    }
+
+    fn unmask(&self, service: &str) -> Result<()> {
+        self.run("unmask", service)
+    }
+
+    /// `systemctl is-enabled` fails for most states, so only what it prints counts.
+    fn unit_file_state(&self, service: &str) -> Result<UnitFileState> {
+        let output = self
+            .processes
+            .output(Command::new("systemctl").args(["is-enabled", service]))
+            .with_context(|| format!("Cannot query the state of {service}"))?;
+        Ok(match String::from_utf8_lossy(&output.stdout).trim() {
+            "enabled" | "enabled-runtime" | "alias" | "generated" => UnitFileState::Enabled,
+            "masked" | "masked-runtime" => UnitFileState::Masked,
+            "static" => UnitFileState::Static,
+            _ => UnitFileState::Disabled,
+        })
+    }
+}
+
+/// The desired state of a service, checked when the manifest is compiled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceSpec {
+    /// `ensure => running` or `stopped`.
+    pub running: bool,
+    /// `enable => true`, `false` or `mask`; left alone when not set.
+    pub enable: Option<UnitFileState>,
+    /// A command restarting the service on refresh instead of the provider.
+    pub restart: Option<String>,
+    /// How long starting or restarting may take until the service is running. Each hook
+    /// and the `restart` command are killed when they run longer.
+    pub start_timeout: Option<Duration>,
+    /// Runs before the service is stopped or restarted, e.g. to drain connections.
+    pub pre_stop: Option<String>,
+    /// Runs once the service is running after a start or restart, e.g. a health check;
+    /// the start fails with it.
+    pub post_start: Option<String>,
+}
+
+impl Default for ServiceSpec {
+    fn default() -> Self {
+        Self {
+            running: true,
+            enable: None,
+            restart: None,
+            start_timeout: None,
+            pre_stop: None,
+            post_start: None,
+        }
+    }
+}
+
+impl ServiceSpec {
+    pub fn from_attributes(attributes: &[Attribute]) -> Result<Self> {
+        let mut spec = Self::default();
+        for attr in attributes {
+            let value = || {
+                attr.value
+                    .as_literal()
+                    .ok_or_else(|| anyhow!("Service {} must be a literal", attr.name))
+            };
+            match attr.name.as_str() {
+                "ensure" => {
+                    spec.running = match value()?.as_str() {
+                        "running" | "true" => true,
+                        "stopped" | "false" => false,
+                        other => return Err(anyhow!("Invalid Service ensure: {other}")),
+                    }
+                }
+                "enable" => spec.enable = attr.parse_as("Service")?,
+                "restart" => spec.restart = attr.parse_as("Service")?,
+                "start_timeout" => spec.start_timeout = attr.parse_as("Service")?,
+                "pre_stop" => spec.pre_stop = attr.parse_as("Service")?,
+                "post_start" => spec.post_start = attr.parse_as("Service")?,
+                _ => {}
+            }
+        }
+        if spec.running && spec.enable == Some(UnitFileState::Masked) {
+            return Err(anyhow!(
+                "A masked Service cannot be running, it needs ensure => stopped"
+            ));
+        }
+        Ok(spec)
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Service {
     pub title: String,
+    pub attributes: Attributes,
+    pub spec: ServiceSpec,
+    pub provider: Arc<dyn ServiceProvider>,
+    /// Where hooks run.
+    pub system: System,
 }
 
 impl Service {
-    fn ensure_present(&self) {
-        println!("Ensure present: {}", self.title);
+    /// Service names are matched case-insensitively: `Nginx` is `nginx`.
+    pub fn normalize_title(title: &str) -> String {
+        title.to_lowercase()
+    }
+
+    fn running(&self, ensure: &Ensure) -> bool {
+        match ensure {
+            Ensure::Running => true,
+            Ensure::Stopped | Ensure::Absent => false,
+            _ => self.spec.running,
+        }
+    }
+
+    /// Runs a hook like an Exec, killed after `start_timeout`, streaming its output to the
+    /// log of `context` and keeping it for the report. Reports the hook's exit code.
+    fn run_hook(
+        &self,
+        hook: &str,
+        command: &str,
+        context: &ApplyContext,
+    ) -> Result<PropertyChange> {
+        let exec = Exec {
+            title: command.to_string(),
+            attributes: Attributes::new(),
+            spec: ExecSpec {
+                command: Some(command.to_string()),
+                ..ExecSpec::default()
+            },
+            sandbox: ExecPolicy {
+                timeout: self.spec.start_timeout,
+                ..ExecPolicy::default()
+            },
+            system: self.system.clone(),
+        };
+        let output = exec
+            .run_streaming(&ExecPolicy::default(), &mut |mut line| {
+                line.resource = format!("{} {hook}", self.id());
+                context.log.write(&line);
+            })
+            .with_context(|| format!("{hook} of {}", self.id()))?;
+        context.keep_output(&output);
+        let stderr = output.stderr.trim_end();
+        match (output.success(), output.status) {
+            (true, _) => Ok(PropertyChange::new(hook, Some("notrun"), 0)),
+            (false, Some(code)) => {
+                Err(anyhow!("{hook} of {} returned {code}: {stderr}", self.id()))
+            }
+            (false, None) => Err(anyhow!("{hook} of {} was killed by a signal", self.id())),
+        }
+    }
+
+    /// Runs the hook set for `hook`, if any.
+    fn hook(
+        &self,
+        hook: &str,
+        command: Option<&str>,
+        context: &ApplyContext,
+    ) -> Result<Vec<PropertyChange>> {
+        match command {
+            Some(command) => Ok(vec![self.run_hook(hook, command, context)?]),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Waits for the service to report running, until `deadline`.
+    fn wait_running(&self, verb: &str, deadline: Option<Instant>) -> Result<()> {
+        let Some(deadline) = deadline else {
+            return Ok(());
+        };
+        while !self.provider.is_running(&self.title)? {
+            if Instant::now() >= deadline {
+                return Err(self.timed_out(verb));
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        Ok(())
     }
-    fn ensure_absent(&self) {
-        println!("Ensure absent: {}", self.title);
+
+    fn timed_out(&self, verb: &str) -> anyhow::Error {
+        let timeout = self.spec.start_timeout.unwrap_or_default();
+        anyhow!(
+            "{verb} {} did not finish within {}",
+            self.id(),
+            timeout.into_value()
+        )
     }
+
+    /// When `start_timeout` is up for an action beginning now.
+    fn deadline(&self) -> Option<Instant> {
+        // A timeout too long to add to the clock is no deadline at all.
+        self.spec
+            .start_timeout
+            .and_then(|timeout| Instant::now().checked_add(timeout))
+    }
+
+    /// `error` of a provider action, or that it timed out once `deadline` has passed.
+    fn failed(&self, verb: &str, deadline: Option<Instant>, error: anyhow::Error) -> anyhow::Error {
+        match deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            true => self.timed_out(verb),
+            false => error,
+        }
+    }
+
+    /// Starts the service, reporting the hooks run.
+    fn start(&self, context: &ApplyContext) -> Result<Vec<PropertyChange>> {
+        let deadline = self.deadline();
+        self.provider
+            .start(&self.title, deadline)
+            .map_err(|e| self.failed("Starting", deadline, e))?;
+        self.wait_running("Starting", deadline)?;
+        self.hook("post_start", self.spec.post_start.as_deref(), context)
+    }
+
+    /// Stops the service, reporting the hooks run.
+    fn stop(&self, context: &ApplyContext) -> Result<Vec<PropertyChange>> {
+        let hooks = self.hook("pre_stop", self.spec.pre_stop.as_deref(), context)?;
+        self.provider.stop(&self.title)?;
+        Ok(hooks)
+    }
+
+    /// Restarts the service, reporting the hooks run.
+    fn restart(&self, context: &ApplyContext) -> Result<Vec<PropertyChange>> {
+        let mut hooks = self.hook("pre_stop", self.spec.pre_stop.as_deref(), context)?;
+        let deadline = self.deadline();
+        match &self.spec.restart {
+            Some(command) => hooks.push(self.run_hook("restart", command, context)?),
+            None => self
+                .provider
+                .restart(&self.title, deadline)
+                .map_err(|e| self.failed("Restarting", deadline, e))?,
+        }
+        self.wait_running("Restarting", deadline)?;
+        hooks.extend(self.hook("post_start", self.spec.post_start.as_deref(), context)?);
+        Ok(hooks)
+    }
+}
+
+fn state(running: bool) -> &'static str {
+    if running { "running" } else { "stopped" }
 }
 
 impl Resource for Service {
@@ -23,14 +396,449 @@ impl Resource for Service {
         self.title.clone()
     }
 
-    fn ensure(&self, ensure: super::resource::Ensure) {
-        match ensure {
-            Ensure::Present => {
-                self.ensure_present();
+    fn attributes(&self) -> &Attributes {
+        &self.attributes
+    }
+
+    fn confines(&self) -> Vec<Confine> {
+        vec![Confine::new("kernel", &["Linux"])]
+    }
+
+    fn check_provider(&self, capabilities: &Capabilities) -> Result<()> {
+        if !capabilities.systemd {
+            return Err(anyhow!("systemd is not running on this system"));
+        }
+        Ok(())
+    }
+
+    fn preview(&self, ensure: Ensure) -> Vec<String> {
+        let mut commands = vec![];
+        if let (false, Some(command)) = (self.running(&ensure), &self.spec.pre_stop) {
+            commands.push(format!("would run: {command}"));
+        }
+        commands.push(match self.running(&ensure) {
+            true => format!("would run: systemctl start {}", self.title),
+            false => format!("would run: systemctl stop {}", self.title),
+        });
+        if let (true, Some(command)) = (self.running(&ensure), &self.spec.post_start) {
+            commands.push(format!("would run: {command}"));
+        }
+        let verb = match self.spec.enable {
+            Some(UnitFileState::Enabled) => "enable",
+            Some(UnitFileState::Disabled) => "disable",
+            Some(UnitFileState::Masked) => "mask",
+            Some(UnitFileState::Static) | None => return commands,
+        };
+        commands.push(format!("would run: systemctl {verb} {}", self.title));
+        commands
+    }
+
+    fn refresh(&self) -> Result<ChangeReport> {
+        self.refresh_in(&ApplyContext::default())
+    }
+
+    fn refresh_in(&self, context: &ApplyContext) -> Result<ChangeReport> {
+        if !self.spec.running {
+            return Ok(ChangeReport::unchanged());
+        }
+        let hooks = self
+            .restart(context)
+            .with_context(|| format!("Cannot restart {}", self.id()))?;
+        let mut changes = vec![PropertyChange::new("ensure", Some("running"), "restarted")];
+        changes.extend(hooks);
+        Ok(ChangeReport::new(changes))
+    }
+
+    /// A masked service is unmasked before it is started, and masked after it is stopped.
+    fn check(&self, ensure: Ensure) -> Result<Vec<PropertyChange>> {
+        let mut changes = vec![];
+        let running = self.provider.is_running(&self.title)?;
+        let desired = self.running(&ensure);
+        let mut enable = None;
+        if let Some(wanted) = self.spec.enable {
+            let current = self.provider.unit_file_state(&self.title)?;
+            match (current, wanted) {
+                (UnitFileState::Static, UnitFileState::Enabled) => {}
+                (UnitFileState::Static, UnitFileState::Disabled) => {
+                    return Err(anyhow!(
+                        "{} is a static unit, which cannot be disabled; use enable => mask \
+                         to keep it from starting",
+                        self.id()
+                    ));
+                }
+                (current, wanted) if current != wanted => {
+                    enable = Some(PropertyChange::new("enable", Some(current), wanted))
+                }
+                _ => {}
             }
-            Ensure::Absent => {
-                self.ensure_absent();
+        } else if desired && self.provider.unit_file_state(&self.title)? == UnitFileState::Masked {
+            return Err(anyhow!(
+                "{} is masked, so it cannot be started; unmask it with `systemctl unmask {}` \
+                 or set enable => true",
+                self.id(),
+                self.title
+            ));
+        }
+        let unmask_first = enable
+            .as_ref()
+            .is_some_and(|change| change.current.as_deref() == Some("mask"));
+        if unmask_first {
+            changes.extend(enable.take());
+        }
+        if running != desired {
+            changes.push(PropertyChange::new(
+                "ensure",
+                Some(state(running)),
+                state(desired),
+            ));
+        }
+        changes.extend(enable);
+        Ok(changes)
+    }
+
+    fn ensure(&self, ensure: Ensure) -> Result<ChangeReport> {
+        self.ensure_in(ensure, &ApplyContext::default())
+    }
+
+    /// Reports the hooks run after the change that ran them.
+    fn ensure_in(&self, ensure: Ensure, context: &ApplyContext) -> Result<ChangeReport> {
+        let mut report = vec![];
+        for change in self.check(ensure)? {
+            if change.property == "enable" && change.current.as_deref() == Some("mask") {
+                self.provider.unmask(&self.title)?;
             }
+            let hooks = match (change.property.as_str(), change.desired.as_str()) {
+                ("ensure", "running") => self.start(context)?,
+                ("ensure", _) => self.stop(context)?,
+                ("enable", "true") => {
+                    self.provider.enable(&self.title)?;
+                    vec![]
+                }
+                ("enable", "mask") => {
+                    self.provider.mask(&self.title)?;
+                    vec![]
+                }
+                ("enable", _) => {
+                    self.provider.disable(&self.title)?;
+                    vec![]
+                }
+                _ => vec![],
+            };
+            report.push(change);
+            report.extend(hooks);
         }
+        Ok(ChangeReport::new(report))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::pp::PuppetString;
+    use std::sync::Mutex;
+
+    /// Keeps service state in memory and records every action.
+    #[derive(Debug, Default)]
+    struct Fake {
+        running: Mutex<bool>,
+        enabled: Mutex<bool>,
+        masked: Mutex<bool>,
+        actions: Mutex<Vec<String>>,
+    }
+
+    impl Fake {
+        fn act(&self, action: &str, service: &str) -> Result<()> {
+            self.actions
+                .lock()
+                .map_err(|_| anyhow!("poisoned"))?
+                .push(format!("{action} {service}"));
+            Ok(())
+        }
+
+        fn set(flag: &Mutex<bool>, value: bool) -> Result<()> {
+            *flag.lock().map_err(|_| anyhow!("poisoned"))? = value;
+            Ok(())
+        }
+    }
+
+    impl ServiceProvider for Fake {
+        fn is_running(&self, _service: &str) -> Result<bool> {
+            Ok(*self.running.lock().map_err(|_| anyhow!("poisoned"))?)
+        }
+        fn is_enabled(&self, _service: &str) -> Result<bool> {
+            Ok(*self.enabled.lock().map_err(|_| anyhow!("poisoned"))?)
+        }
+        fn start(&self, service: &str, _deadline: Option<Instant>) -> Result<()> {
+            Self::set(&self.running, true)?;
+            self.act("start", service)
+        }
+        fn stop(&self, service: &str) -> Result<()> {
+            Self::set(&self.running, false)?;
+            self.act("stop", service)
+        }
+        fn restart(&self, service: &str, _deadline: Option<Instant>) -> Result<()> {
+            self.act("restart", service)
+        }
+        fn enable(&self, service: &str) -> Result<()> {
+            Self::set(&self.enabled, true)?;
+            self.act("enable", service)
+        }
+        fn disable(&self, service: &str) -> Result<()> {
+            Self::set(&self.enabled, false)?;
+            self.act("disable", service)
+        }
+        fn mask(&self, service: &str) -> Result<()> {
+            Self::set(&self.masked, true)?;
+            self.act("mask", service)
+        }
+        fn unmask(&self, service: &str) -> Result<()> {
+            Self::set(&self.masked, false)?;
+            self.act("unmask", service)
+        }
+        fn unit_file_state(&self, service: &str) -> Result<UnitFileState> {
+            match *self.masked.lock().map_err(|_| anyhow!("poisoned"))? {
+                true => Ok(UnitFileState::Masked),
+                false if service.ends_with(".socket") => Ok(UnitFileState::Static),
+                false if self.is_enabled(service)? => Ok(UnitFileState::Enabled),
+                false => Ok(UnitFileState::Disabled),
+            }
+        }
+    }
+
+    #[test]
+    fn test_service_converges_through_provider() -> Result<()> {
+        let provider = Arc::new(Fake::default());
+        let service = Service {
+            title: "nginx".to_string(),
+            attributes: Attributes::new(),
+            spec: ServiceSpec {
+                running: true,
+                enable: Some(UnitFileState::Enabled),
+                ..ServiceSpec::default()
+            },
+            provider: provider.clone(),
+            system: System::default(),
+        };
+        assert_eq!(
+            service.check(Ensure::Present)?,
+            vec![
+                PropertyChange::new("ensure", Some("stopped"), "running"),
+                PropertyChange::new("enable", Some(false), true),
+            ]
+        );
+        service.ensure(Ensure::Present)?;
+        assert!(service.check(Ensure::Present)?.is_empty());
+        service.ensure(Ensure::Present)?;
+        service.refresh()?;
+        assert_eq!(
+            *provider.actions.lock().map_err(|_| anyhow!("poisoned"))?,
+            vec!["start nginx", "enable nginx", "restart nginx"],
+            "Nothing is done once in sync"
+        );
+
+        service.ensure(Ensure::Absent)?;
+        assert!(!provider.is_running("nginx")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_service_hooks_and_timeouts() -> Result<()> {
+        let provider = Arc::new(Fake::default());
+        let mut service = Service {
+            title: "app".to_string(),
+            attributes: Attributes::new(),
+            spec: ServiceSpec {
+                restart: Some("echo reloaded".to_string()),
+                start_timeout: Some(Duration::from_millis(500)),
+                pre_stop: Some("echo draining".to_string()),
+                post_start: Some("echo healthy".to_string()),
+                ..ServiceSpec::default()
+            },
+            provider: provider.clone(),
+            system: System::default(),
+        };
+        let context = ApplyContext::default();
+        let ran = |hook: &str| PropertyChange::new(hook, Some("notrun"), 0);
+        assert_eq!(
+            service.ensure_in(Ensure::Present, &context)?.changes,
+            vec![
+                PropertyChange::new("ensure", Some("stopped"), "running"),
+                ran("post_start"),
+            ]
+        );
+        assert_eq!(
+            service.refresh_in(&context)?.changes,
+            vec![
+                PropertyChange::new("ensure", Some("running"), "restarted"),
+                ran("pre_stop"),
+                ran("restart"),
+                ran("post_start"),
+            ]
+        );
+        service.ensure_in(Ensure::Absent, &context)?;
+        let outputs: Vec<_> = context
+            .outputs
+            .lock()
+            .map_err(|_| anyhow!("poisoned"))?
+            .iter()
+            .map(|output| output.stdout.trim_end().to_string())
+            .collect();
+        assert_eq!(
+            outputs,
+            ["healthy", "draining", "reloaded", "healthy", "draining"],
+            "Hook output is kept for the report"
+        );
+        assert_eq!(
+            *provider.actions.lock().map_err(|_| anyhow!("poisoned"))?,
+            vec!["start app", "stop app"],
+            "The restart command replaces the provider's"
+        );
+
+        service.spec.post_start = Some("sleep 5".to_string());
+        let started = Instant::now();
+        let Err(e) = service.ensure(Ensure::Present) else {
+            return Err(anyhow!("A hook running past the timeout fails the start"));
+        };
+        assert!(
+            started.elapsed() < Duration::from_secs(5),
+            "The hook is killed"
+        );
+        assert_eq!(
+            format!("{e:#}"),
+            "post_start of Service[app]: waiting for sleep 5: timed out"
+        );
+        Ok(())
+    }
+
+    /// Runs every command until its deadline, as a hung `systemctl` would.
+    #[derive(Debug)]
+    struct Hanging;
+
+    impl Processes for Hanging {
+        fn output(&self, _command: &mut Command) -> std::io::Result<std::process::Output> {
+            Ok(crate::testing::output(3, ""))
+        }
+
+        fn output_until(
+            &self,
+            command: &mut Command,
+            deadline: Option<Instant>,
+        ) -> std::io::Result<std::process::Output> {
+            let Some(deadline) = deadline else {
+                return self.output(command);
+            };
+            thread::sleep(deadline.saturating_duration_since(Instant::now()));
+            Err(std::io::ErrorKind::TimedOut.into())
+        }
+    }
+
+    #[test]
+    fn test_start_timeout_reaches_the_provider() -> Result<()> {
+        let service = Service {
+            title: "app".to_string(),
+            attributes: Attributes::new(),
+            spec: ServiceSpec {
+                start_timeout: Some(Duration::from_millis(100)),
+                ..ServiceSpec::default()
+            },
+            provider: Arc::new(Systemd::new(Arc::new(Hanging))),
+            system: System::default(),
+        };
+        let Err(e) = service.ensure(Ensure::Present) else {
+            return Err(anyhow!("A start past the timeout fails"));
+        };
+        assert_eq!(
+            e.to_string(),
+            "Starting Service[app] did not finish within 100ms"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_masked_and_static_units() -> Result<()> {
+        let provider = Arc::new(Fake::default());
+        Fake::set(&provider.masked, true)?;
+        let service = |title: &str, spec: ServiceSpec| Service {
+            title: title.to_string(),
+            attributes: Attributes::new(),
+            spec,
+            provider: provider.clone(),
+            system: System::default(),
+        };
+        let running = service("app", ServiceSpec::default());
+        let Err(e) = running.check(Ensure::Present) else {
+            return Err(anyhow!("Starting a masked service is refused up front"));
+        };
+        assert!(e.to_string().starts_with("Service[app] is masked"), "{e}");
+
+        let enabled = service(
+            "app",
+            ServiceSpec {
+                enable: Some(UnitFileState::Enabled),
+                ..ServiceSpec::default()
+            },
+        );
+        assert_eq!(
+            enabled.check(Ensure::Present)?,
+            vec![
+                PropertyChange::new("enable", Some("mask"), "true"),
+                PropertyChange::new("ensure", Some("stopped"), "running"),
+            ],
+            "The unit is unmasked before it is started"
+        );
+        enabled.ensure(Ensure::Present)?;
+
+        let masked = service(
+            "app",
+            ServiceSpec {
+                running: false,
+                enable: Some(UnitFileState::Masked),
+                ..ServiceSpec::default()
+            },
+        );
+        masked.ensure(Ensure::Present)?;
+        assert!(masked.check(Ensure::Present)?.is_empty());
+        assert_eq!(
+            *provider.actions.lock().map_err(|_| anyhow!("poisoned"))?,
+            vec![
+                "unmask app",
+                "enable app",
+                "start app",
+                "stop app",
+                "mask app"
+            ]
+        );
+
+        Fake::set(&provider.masked, false)?;
+        let socket = |enable| {
+            service(
+                "app.socket",
+                ServiceSpec {
+                    running: false,
+                    enable: Some(enable),
+                    ..ServiceSpec::default()
+                },
+            )
+        };
+        assert!(
+            socket(UnitFileState::Enabled)
+                .check(Ensure::Present)?
+                .is_empty()
+        );
+        assert!(
+            socket(UnitFileState::Disabled)
+                .check(Ensure::Present)
+                .is_err()
+        );
+
+        let attributes = [Attribute {
+            name: "enable".to_string(),
+            value: AttrValue::String(PuppetString::literal("mask")),
+        }];
+        assert!(
+            ServiceSpec::from_attributes(&attributes).is_err(),
+            "A masked service cannot be running"
+        );
+        Ok(())
     }
 }
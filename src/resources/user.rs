@@ -0,0 +1,637 @@
+//! Local user accounts with their password hash and SSH authorized keys.
+
+use super::Confine;
+use super::accounts::Database;
+use super::file::{File, FileEnsure, FileSpec};
+use super::file_mode::FileMode;
+use super::resource::{Attributes, ChangeReport, Ensure, PropertyChange, Resource};
+use crate::parser::pp::Attribute;
+use crate::parser::value::IntoValue;
+use crate::system::{HostProcesses, Processes, System};
+use anyhow::{Context, Result, anyhow};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+
+/// What a password change is shown as, so that hashes stay out of reports.
+const REDACTED: &str = "[redacted]";
+
+/// An account as the system has it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserEntry {
+    pub uid: u32,
+    pub gid: u32,
+    pub home: PathBuf,
+    pub shell: String,
+    pub comment: String,
+    /// The hash from the shadow database, `None` when it cannot be read.
+    pub password: Option<String>,
+}
+
+/// Inspects and changes local accounts.
+pub trait UserProvider: fmt::Debug + Send + Sync {
+    fn get(&self, name: &str) -> Result<Option<UserEntry>>;
+    fn create(&self, name: &str, spec: &UserSpec) -> Result<()>;
+    /// Sets the properties that `check` found out of sync, with their desired values.
+    fn modify(&self, name: &str, changes: &[(&str, String)]) -> Result<()>;
+    fn delete(&self, name: &str, remove_home: bool) -> Result<()>;
+}
+
+/// Manages accounts with `useradd`, `usermod` and `userdel`, reading them with `getent`.
+#[derive(Debug, Clone)]
+pub struct Useradd {
+    processes: Arc<dyn Processes>,
+}
+
+impl Default for Useradd {
+    fn default() -> Self {
+        Self::new(Arc::new(HostProcesses))
+    }
+}
+
+impl Useradd {
+    pub fn new(processes: Arc<dyn Processes>) -> Self {
+        Self { processes }
+    }
+
+    fn run(&self, command: &mut Command) -> Result<()> {
+        let output = self
+            .processes
+            .output(command)
+            .with_context(|| format!("Cannot run {command:?}"))?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "{} failed: {}",
+                command.get_program().to_string_lossy(),
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl UserProvider for Useradd {
+    fn get(&self, name: &str) -> Result<Option<UserEntry>> {
+        let output = self
+            .processes
+            .output(Command::new("getent").args(["passwd", name]))
+            .context("Cannot run getent")?;
+        let line = String::from_utf8_lossy(&output.stdout);
+        let fields: Vec<_> = line.trim_end().split(':').collect();
+        let [_, _, uid, gid, comment, home, shell] = fields.as_slice() else {
+            return Ok(None);
+        };
+        let password = self
+            .processes
+            .output(Command::new("getent").args(["shadow", name]))
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| {
+                let line = String::from_utf8_lossy(&output.stdout).into_owned();
+                line.split(':').nth(1).map(str::to_string)
+            });
+        Ok(Some(UserEntry {
+            uid: uid.parse()?,
+            gid: gid.parse()?,
+            home: PathBuf::from(home),
+            shell: shell.to_string(),
+            comment: comment.to_string(),
+            password,
+        }))
+    }
+
+    fn create(&self, name: &str, spec: &UserSpec) -> Result<()> {
+        let mut command = Command::new("useradd");
+        if let Some(uid) = spec.uid {
+            command.args(["-u", &uid.to_string()]);
+        }
+        for (flag, value) in [
+            ("-g", &spec.gid),
+            ("-s", &spec.shell),
+            ("-c", &spec.comment),
+            ("-p", &spec.password),
+        ] {
+            if let Some(value) = value {
+                command.args([flag, value]);
+            }
+        }
+        if let Some(home) = &spec.home {
+            command.arg("-d").arg(home);
+        }
+        command.arg(match spec.managehome {
+            true => "-m",
+            false => "-M",
+        });
+        self.run(command.arg(name))
+    }
+
+    fn modify(&self, name: &str, changes: &[(&str, String)]) -> Result<()> {
+        let mut command = Command::new("usermod");
+        for (property, value) in changes {
+            let flag = match *property {
+                "uid" => "-u",
+                "gid" => "-g",
+                "home" => "-d",
+                "shell" => "-s",
+                "comment" => "-c",
+                "password" => "-p",
+                _ => continue,
+            };
+            command.args([flag, value]);
+        }
+        self.run(command.arg(name))
+    }
+
+    fn delete(&self, name: &str, remove_home: bool) -> Result<()> {
+        let mut command = Command::new("userdel");
+        if remove_home {
+            command.arg("-r");
+        }
+        self.run(command.arg(name))
+    }
+}
+
+/// The desired state of an account, checked when the manifest is compiled.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UserSpec {
+    pub present: bool,
+    pub uid: Option<u32>,
+    /// The primary group, by name or gid.
+    pub gid: Option<String>,
+    pub home: Option<PathBuf>,
+    pub shell: Option<String>,
+    pub comment: Option<String>,
+    /// Whether the home directory is created with the account and removed with it.
+    pub managehome: bool,
+    /// The crypt(3) hash, as `pw_hash` makes it; never the password itself.
+    pub password: Option<String>,
+    /// Lines of `~/.ssh/authorized_keys`: `type key [comment]`.
+    pub ssh_authorized_keys: Vec<String>,
+    /// Whether keys not in `ssh_authorized_keys` are removed.
+    pub purge_ssh_keys: bool,
+}
+
+impl UserSpec {
+    pub fn from_attributes(attributes: &[Attribute]) -> Result<Self> {
+        let mut spec = Self {
+            present: true,
+            ..Self::default()
+        };
+        for attr in attributes {
+            match attr.name.as_str() {
+                "ensure" => {
+                    spec.present = match attr.parse_as::<String>("User")?.as_str() {
+                        "present" => true,
+                        "absent" => false,
+                        other => return Err(anyhow!("Invalid User ensure: {other}")),
+                    }
+                }
+                "uid" => spec.uid = attr.parse_as("User")?,
+                "gid" => spec.gid = attr.parse_as("User")?,
+                "home" => spec.home = attr.parse_as("User")?,
+                "shell" => spec.shell = attr.parse_as("User")?,
+                "comment" => spec.comment = attr.parse_as("User")?,
+                "managehome" => spec.managehome = attr.parse_as("User")?,
+                "password" => spec.password = attr.parse_as("User")?,
+                "ssh_authorized_keys" => spec.ssh_authorized_keys = attr.parse_as("User")?,
+                "purge_ssh_keys" => spec.purge_ssh_keys = attr.parse_as("User")?,
+                _ => {}
+            }
+        }
+        if let Some(password) = &spec.password
+            && !password.starts_with(['$', '!', '*'])
+        {
+            return Err(anyhow!(
+                "User password must be a crypt hash, e.g. pw_hash(Sensitive('...'), \
+                 'SHA-512', 'salt'), not the password itself"
+            ));
+        }
+        for key in &spec.ssh_authorized_keys {
+            if key.split_whitespace().count() < 2 {
+                return Err(anyhow!(
+                    "User ssh_authorized_keys must be 'type key [comment]' lines, got {key}"
+                ));
+            }
+        }
+        Ok(spec)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct User {
+    pub title: String,
+    pub attributes: Attributes,
+    pub spec: UserSpec,
+    pub provider: Arc<dyn UserProvider>,
+    /// Where the authorized keys are written.
+    pub system: System,
+}
+
+/// The key of an authorized_keys line, which identifies it whatever its comment or
+/// options.
+fn key_blob(line: &str) -> Option<&str> {
+    let fields: Vec<_> = line.split_whitespace().collect();
+    fields
+        .iter()
+        .position(|field| field.starts_with("ssh-") || field.starts_with("ecdsa-"))
+        .and_then(|i| fields.get(i + 1))
+        .or_else(|| fields.get(1))
+        .copied()
+}
+
+/// The comments of keys, or how many there are when they have none.
+fn summary(keys: &[String]) -> String {
+    let comments: Vec<_> = keys
+        .iter()
+        .map(|key| key.split_whitespace().nth(2).unwrap_or("(no comment)"))
+        .collect();
+    match comments.is_empty() {
+        true => "none".to_string(),
+        false => comments.join(", "),
+    }
+}
+
+impl User {
+    fn manages_keys(&self) -> bool {
+        !self.spec.ssh_authorized_keys.is_empty() || self.spec.purge_ssh_keys
+    }
+
+    fn authorized_keys(home: &Path) -> PathBuf {
+        home.join(".ssh/authorized_keys")
+    }
+
+    fn current_keys(home: &Path) -> Vec<String> {
+        fs::read_to_string(Self::authorized_keys(home))
+            .unwrap_or_default()
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// The keys the file should hold: the managed ones in place of the same keys, after
+    /// the others unless they are purged.
+    fn desired_keys(&self, current: &[String]) -> Vec<String> {
+        let managed: Vec<_> = self
+            .spec
+            .ssh_authorized_keys
+            .iter()
+            .map(|key| key.trim().to_string())
+            .collect();
+        let mut keys: Vec<_> = match self.spec.purge_ssh_keys {
+            true => vec![],
+            false => current
+                .iter()
+                .filter(|line| !managed.iter().any(|key| key_blob(key) == key_blob(line)))
+                .cloned()
+                .collect(),
+        };
+        keys.extend(managed);
+        keys
+    }
+
+    fn gid(&self) -> Result<Option<u32>> {
+        self.spec
+            .gid
+            .as_deref()
+            .map(|gid| Database::Group.id(gid))
+            .transpose()
+    }
+
+    /// Properties out of sync, with the values to set them to.
+    fn account_changes(&self, entry: &UserEntry) -> Result<Vec<(&'static str, String, String)>> {
+        let mut changes = vec![];
+        if let Some(uid) = self.spec.uid
+            && uid != entry.uid
+        {
+            changes.push(("uid", entry.uid.to_string(), uid.to_string()));
+        }
+        if let Some(gid) = self.gid()?
+            && gid != entry.gid
+        {
+            changes.push(("gid", entry.gid.to_string(), gid.to_string()));
+        }
+        if let Some(home) = &self.spec.home
+            && *home != entry.home
+        {
+            let (current, desired) = (entry.home.display(), home.display());
+            changes.push(("home", current.to_string(), desired.to_string()));
+        }
+        for (property, desired, current) in [
+            ("shell", &self.spec.shell, &entry.shell),
+            ("comment", &self.spec.comment, &entry.comment),
+        ] {
+            if let Some(desired) = desired
+                && desired != current
+            {
+                changes.push((property, current.clone(), desired.clone()));
+            }
+        }
+        if let Some(password) = &self.spec.password
+            && entry.password.as_ref() != Some(password)
+        {
+            changes.push(("password", REDACTED.to_string(), password.clone()));
+        }
+        Ok(changes)
+    }
+
+    fn key_files(&self, entry: &UserEntry, keys: &[String]) -> [File; 2] {
+        let owned = |path: PathBuf, ensure, mode, content: Option<String>| File {
+            title: path.display().to_string(),
+            attributes: Attributes::new(),
+            spec: FileSpec {
+                ensure,
+                content: content.map(IntoValue::into_value),
+                mode: Some(FileMode::Octal(mode)),
+                owner: Some(entry.uid.to_string()),
+                group: Some(entry.gid.to_string()),
+                ..FileSpec::default()
+            },
+            system: self.system.clone(),
+        };
+        let content = keys.iter().map(|key| format!("{key}\n")).collect();
+        [
+            owned(entry.home.join(".ssh"), FileEnsure::Directory, 0o700, None),
+            owned(
+                Self::authorized_keys(&entry.home),
+                FileEnsure::File,
+                0o600,
+                Some(content),
+            ),
+        ]
+    }
+}
+
+impl Resource for User {
+    fn rtype(&self) -> &str {
+        "User"
+    }
+
+    fn title(&self) -> String {
+        self.title.clone()
+    }
+
+    fn attributes(&self) -> &Attributes {
+        &self.attributes
+    }
+
+    fn confines(&self) -> Vec<Confine> {
+        vec![Confine::new("kernel", &["Linux"])]
+    }
+
+    /// Accounts are created once their primary group is.
+    fn autorequire_types(&self) -> &[&str] {
+        &["Group"]
+    }
+
+    fn preview(&self, ensure: Ensure) -> Vec<String> {
+        match ensure.is_present() && self.spec.present {
+            true => {
+                let mut preview = vec![format!("would run: useradd {}", self.title)];
+                if self.manages_keys() {
+                    preview.push(format!("would write the authorized_keys of {}", self.title));
+                }
+                preview
+            }
+            _ => vec![format!("would run: userdel {}", self.title)],
+        }
+    }
+
+    fn check(&self, ensure: Ensure) -> Result<Vec<PropertyChange>> {
+        let present = ensure.is_present() && self.spec.present;
+        let entry = self.provider.get(&self.title)?;
+        let entry = match (entry, present) {
+            (None, false) => return Ok(vec![]),
+            (Some(_), false) => {
+                return Ok(vec![PropertyChange::new(
+                    "ensure",
+                    Some("present"),
+                    "absent",
+                )]);
+            }
+            (None, true) => {
+                return Ok(vec![PropertyChange::new(
+                    "ensure",
+                    Some("absent"),
+                    "present",
+                )]);
+            }
+            (Some(entry), true) => entry,
+        };
+        let mut changes: Vec<_> = self
+            .account_changes(&entry)?
+            .into_iter()
+            .map(|(property, current, desired)| match property {
+                "password" => PropertyChange::new(property, Some(REDACTED), REDACTED),
+                _ => PropertyChange::new(property, Some(current), desired),
+            })
+            .collect();
+        if self.manages_keys() {
+            let current = Self::current_keys(&entry.home);
+            let desired = self.desired_keys(&current);
+            let in_sync = current == desired
+                && self
+                    .key_files(&entry, &desired)
+                    .iter()
+                    .map(|file| file.check(Ensure::Present))
+                    .collect::<Result<Vec<_>>>()?
+                    .iter()
+                    .all(Vec::is_empty);
+            if !in_sync {
+                changes.push(PropertyChange::new(
+                    "ssh_authorized_keys",
+                    Some(summary(&current)),
+                    summary(&desired),
+                ));
+            }
+        }
+        Ok(changes)
+    }
+
+    fn ensure(&self, ensure: Ensure) -> Result<ChangeReport> {
+        let report = ChangeReport::new(self.check(ensure.clone())?);
+        if ensure == Ensure::Absent || !self.spec.present {
+            if self.provider.get(&self.title)?.is_some() {
+                self.provider.delete(&self.title, self.spec.managehome)?;
+            }
+            return Ok(report);
+        }
+        let entry = match self.provider.get(&self.title)? {
+            Some(entry) => {
+                let changes = self.account_changes(&entry)?;
+                if !changes.is_empty() {
+                    let changes: Vec<_> = changes
+                        .into_iter()
+                        .map(|(property, _, desired)| (property, desired))
+                        .collect();
+                    self.provider.modify(&self.title, &changes)?;
+                }
+                entry
+            }
+            None => {
+                self.provider.create(&self.title, &self.spec)?;
+                self.provider
+                    .get(&self.title)?
+                    .ok_or_else(|| anyhow!("{} is missing after it was created", self.id()))?
+            }
+        };
+        if self.manages_keys() {
+            let entry = self.provider.get(&self.title)?.unwrap_or(entry);
+            let keys = self.desired_keys(&Self::current_keys(&entry.home));
+            for file in self.key_files(&entry, &keys) {
+                file.ensure(Ensure::Present)?;
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_puppet_manifest;
+    use crate::parser::pp::{AttrValue, Manifest, PuppetString};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    /// Keeps accounts in memory, with homes under a directory.
+    #[derive(Debug)]
+    struct Fake {
+        homes: PathBuf,
+        users: Mutex<HashMap<String, UserEntry>>,
+    }
+
+    impl UserProvider for Fake {
+        fn get(&self, name: &str) -> Result<Option<UserEntry>> {
+            Ok(self
+                .users
+                .lock()
+                .map_err(|_| anyhow!("poisoned"))?
+                .get(name)
+                .cloned())
+        }
+        fn create(&self, name: &str, spec: &UserSpec) -> Result<()> {
+            let entry = UserEntry {
+                uid: spec.uid.unwrap_or(0),
+                gid: 0,
+                home: spec.home.clone().unwrap_or_else(|| self.homes.join(name)),
+                shell: spec.shell.clone().unwrap_or_else(|| "/bin/sh".to_string()),
+                comment: spec.comment.clone().unwrap_or_default(),
+                password: spec.password.clone().or(Some("!".to_string())),
+            };
+            fs::create_dir_all(&entry.home)?;
+            self.users
+                .lock()
+                .map_err(|_| anyhow!("poisoned"))?
+                .insert(name.to_string(), entry);
+            Ok(())
+        }
+        fn modify(&self, name: &str, changes: &[(&str, String)]) -> Result<()> {
+            let mut users = self.users.lock().map_err(|_| anyhow!("poisoned"))?;
+            let entry = users
+                .get_mut(name)
+                .ok_or_else(|| anyhow!("no user {name}"))?;
+            for (property, value) in changes {
+                match *property {
+                    "shell" => entry.shell = value.clone(),
+                    "password" => entry.password = Some(value.clone()),
+                    other => return Err(anyhow!("cannot change {other}")),
+                }
+            }
+            Ok(())
+        }
+        fn delete(&self, name: &str, _remove_home: bool) -> Result<()> {
+            self.users
+                .lock()
+                .map_err(|_| anyhow!("poisoned"))?
+                .remove(name);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_user_password_and_keys() -> Result<()> {
+        let homes = std::env::temp_dir().join(format!("dolly-users-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&homes);
+        let provider = Arc::new(Fake {
+            homes: homes.clone(),
+            users: Mutex::default(),
+        });
+        let input = r#"
+            user { "alice":
+                shell => "/bin/bash",
+                password => pw_hash(Sensitive('secret'), 'SHA-512', 'saltsalt'),
+                ssh_authorized_keys => ["ssh-ed25519 AAAAC3Nz alice@laptop"],
+                purge_ssh_keys => true,
+            }
+        "#;
+        let plan = parse_puppet_manifest(&input.parse::<Manifest>()?)?;
+        let index = plan
+            .node("User[alice]")
+            .ok_or_else(|| anyhow!("The user is in the plan"))?;
+        let declared = &plan.plan().inner()[index];
+        assert_eq!(
+            declared.attributes()["password"].to_string(),
+            "Sensitive [value redacted]"
+        );
+        let attributes: Vec<_> = declared
+            .attributes()
+            .iter()
+            .map(|(name, value)| Attribute {
+                name: name.clone(),
+                value: value.clone(),
+            })
+            .collect();
+        let user = User {
+            title: "alice".to_string(),
+            attributes: Attributes::new(),
+            spec: UserSpec::from_attributes(&attributes)?,
+            provider: provider.clone(),
+            system: System::default(),
+        };
+        assert!(
+            user.spec
+                .password
+                .as_deref()
+                .is_some_and(|hash| hash.starts_with("$6$saltsalt$"))
+        );
+
+        user.ensure(Ensure::Present)?;
+        assert!(user.check(Ensure::Present)?.is_empty());
+        let keys = homes.join("alice/.ssh/authorized_keys");
+        fs::write(
+            &keys,
+            "ssh-rsa AAAAB3Nz old@host\nssh-ed25519 AAAAC3Nz alice@laptop\n",
+        )?;
+        provider.modify("alice", &[("password", "!".to_string())])?;
+        assert_eq!(
+            user.check(Ensure::Present)?,
+            vec![
+                PropertyChange::new("password", Some(REDACTED), REDACTED),
+                PropertyChange::new(
+                    "ssh_authorized_keys",
+                    Some("old@host, alice@laptop"),
+                    "alice@laptop"
+                ),
+            ],
+            "Hashes are not reported and unmanaged keys are purged"
+        );
+        user.ensure(Ensure::Present)?;
+        assert_eq!(
+            fs::read_to_string(&keys)?,
+            "ssh-ed25519 AAAAC3Nz alice@laptop\n"
+        );
+        assert!(user.check(Ensure::Present)?.is_empty());
+
+        let plain = [Attribute {
+            name: "password".to_string(),
+            value: AttrValue::String(PuppetString::literal("hunter2")),
+        }];
+        assert!(UserSpec::from_attributes(&plain).is_err());
+        fs::remove_dir_all(&homes)?;
+        Ok(())
+    }
+}
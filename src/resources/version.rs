@@ -0,0 +1,253 @@
+//! Package versions: how each package manager orders them and the constraints a
+//! manifest may put on them.
+
+use anyhow::{Result, anyhow};
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+/// The version ordering of a package format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VersionScheme {
+    /// `[epoch:]upstream[-revision]` ordered as `dpkg --compare-versions` does.
+    Dpkg,
+    /// `[epoch:]version[-release]` ordered as `rpmvercmp` does, which pacman's `vercmp`
+    /// follows too.
+    Rpm,
+}
+
+impl VersionScheme {
+    pub fn compare(self, a: &str, b: &str) -> Ordering {
+        let (a_epoch, a_version, a_release) = split_evr(a);
+        let (b_epoch, b_version, b_release) = split_evr(b);
+        let segment = match self {
+            Self::Dpkg => dpkg_compare,
+            Self::Rpm => rpm_compare,
+        };
+        a_epoch
+            .cmp(&b_epoch)
+            .then_with(|| segment(a_version, b_version))
+            .then_with(|| segment(a_release, b_release))
+    }
+}
+
+/// The epoch, version and release of `version`, the epoch defaulting to 0.
+fn split_evr(version: &str) -> (u64, &str, &str) {
+    let (epoch, rest) = match version.split_once(':') {
+        Some((epoch, rest)) if epoch.chars().all(|c| c.is_ascii_digit()) => {
+            (epoch.parse().unwrap_or_default(), rest)
+        }
+        _ => (0, version),
+    };
+    let (version, release) = rest.rsplit_once('-').unwrap_or((rest, ""));
+    (epoch, version, release)
+}
+
+/// Alternating non-digit and digit runs; in the former letters sort before other
+/// characters and `~` before everything, even the end of the version.
+fn dpkg_compare(a: &str, b: &str) -> Ordering {
+    fn order(c: Option<char>) -> i64 {
+        match c {
+            None => 0,
+            Some('~') => -1,
+            Some(c) if c.is_ascii_digit() => 0,
+            Some(c) if c.is_ascii_alphabetic() => c as i64,
+            Some(c) => c as i64 + 256,
+        }
+    }
+    let (mut a, mut b) = (a, b);
+    while !a.is_empty() || !b.is_empty() {
+        let a_text = a.find(|c: char| c.is_ascii_digit()).unwrap_or(a.len());
+        let b_text = b.find(|c: char| c.is_ascii_digit()).unwrap_or(b.len());
+        let (mut a_chars, mut b_chars) = (a[..a_text].chars(), b[..b_text].chars());
+        loop {
+            let (a_char, b_char) = (a_chars.next(), b_chars.next());
+            if a_char.is_none() && b_char.is_none() {
+                break;
+            }
+            match order(a_char).cmp(&order(b_char)) {
+                Ordering::Equal => {}
+                unequal => return unequal,
+            }
+        }
+        (a, b) = (&a[a_text..], &b[b_text..]);
+        let a_digits = a.find(|c: char| !c.is_ascii_digit()).unwrap_or(a.len());
+        let b_digits = b.find(|c: char| !c.is_ascii_digit()).unwrap_or(b.len());
+        match numeric_compare(&a[..a_digits], &b[..b_digits]) {
+            Ordering::Equal => {}
+            unequal => return unequal,
+        }
+        (a, b) = (&a[a_digits..], &b[b_digits..]);
+    }
+    Ordering::Equal
+}
+
+/// Alphanumeric segments, separators ignored: numbers sort after letters and a version
+/// with more segments is newer, unless they start with `~`.
+fn rpm_compare(a: &str, b: &str) -> Ordering {
+    let separator = |c: char| !c.is_ascii_alphanumeric() && c != '~';
+    let (mut a, mut b) = (a, b);
+    loop {
+        (a, b) = (
+            a.trim_start_matches(separator),
+            b.trim_start_matches(separator),
+        );
+        match (a.strip_prefix('~'), b.strip_prefix('~')) {
+            (Some(a_rest), Some(b_rest)) => {
+                (a, b) = (a_rest, b_rest);
+                continue;
+            }
+            (Some(_), None) => return Ordering::Less,
+            (None, Some(_)) => return Ordering::Greater,
+            (None, None) => {}
+        }
+        if a.is_empty() || b.is_empty() {
+            return a.len().min(1).cmp(&b.len().min(1));
+        }
+        let numeric = a.starts_with(|c: char| c.is_ascii_digit());
+        let run = |s: &str| {
+            s.find(|c: char| match numeric {
+                true => !c.is_ascii_digit(),
+                false => !c.is_ascii_alphabetic(),
+            })
+            .unwrap_or(s.len())
+        };
+        let (a_len, b_len) = (run(a), run(b));
+        if b_len == 0 {
+            // `b` has a segment of the other kind: numbers are newer.
+            return match numeric {
+                true => Ordering::Greater,
+                false => Ordering::Less,
+            };
+        }
+        let ordering = match numeric {
+            true => numeric_compare(&a[..a_len], &b[..b_len]),
+            false => a[..a_len].cmp(&b[..b_len]),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+        (a, b) = (&a[a_len..], &b[b_len..]);
+    }
+}
+
+/// Compares digit runs of any length, ignoring leading zeros.
+fn numeric_compare(a: &str, b: &str) -> Ordering {
+    let (a, b) = (a.trim_start_matches('0'), b.trim_start_matches('0'));
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
+
+/// A version a Package's `ensure` asks for: `'1.2.3-1'`, `'1.2.*'` or `'>= 1.2'`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VersionConstraint {
+    /// That version; without a release or revision, any release of it.
+    Exact(String),
+    /// Versions starting with the text before the `*`.
+    Wildcard(String),
+    AtLeast(String),
+}
+
+impl VersionConstraint {
+    pub fn matches(&self, version: &str, scheme: VersionScheme) -> bool {
+        match self {
+            Self::Exact(exact) => {
+                let comparable = match exact.contains('-') {
+                    true => version,
+                    false => version.rsplit_once('-').map_or(version, |(v, _)| v),
+                };
+                scheme.compare(comparable, exact) == Ordering::Equal
+            }
+            Self::Wildcard(prefix) => {
+                let unversioned = match prefix.contains(':') {
+                    true => version,
+                    false => version.split_once(':').map_or(version, |(_, v)| v),
+                };
+                unversioned.starts_with(prefix.as_str())
+            }
+            Self::AtLeast(minimum) => scheme.compare(version, minimum) != Ordering::Less,
+        }
+    }
+}
+
+impl FromStr for VersionConstraint {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let valid = |version: &str| {
+            !version.is_empty()
+                && version
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || ".+-~:_^".contains(c))
+        };
+        let constraint = match (s.strip_prefix(">="), s.strip_suffix('*')) {
+            (Some(minimum), _) => Self::AtLeast(minimum.trim_start().to_string()),
+            (None, Some(prefix)) => Self::Wildcard(prefix.to_string()),
+            (None, None) => Self::Exact(s.to_string()),
+        };
+        let version = match &constraint {
+            Self::Exact(version) | Self::Wildcard(version) | Self::AtLeast(version) => version,
+        };
+        match valid(version) {
+            true => Ok(constraint),
+            false => Err(anyhow!(
+                "Invalid Package ensure: {s}, expected installed, absent, a version, \
+                 a version ending in * or >= a version"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for VersionConstraint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Exact(version) => write!(f, "{version}"),
+            Self::Wildcard(prefix) => write!(f, "{prefix}*"),
+            Self::AtLeast(minimum) => write!(f, ">= {minimum}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_ordering() -> Result<()> {
+        use Ordering::*;
+        for (scheme, a, b, expected) in [
+            (VersionScheme::Dpkg, "1.2.10", "1.2.9", Greater),
+            (VersionScheme::Dpkg, "1.0~rc1", "1.0", Less),
+            (VersionScheme::Dpkg, "1.0", "1.0+deb1", Less),
+            (VersionScheme::Dpkg, "1:0.9", "2.0", Greater),
+            (VersionScheme::Dpkg, "1.0-2", "1.0-10", Less),
+            (VersionScheme::Dpkg, "1.0a", "1.0", Greater),
+            (VersionScheme::Dpkg, "01.02", "1.2", Equal),
+            (VersionScheme::Rpm, "1.2.10", "1.2.9", Greater),
+            (VersionScheme::Rpm, "1.0~rc1", "1.0", Less),
+            (VersionScheme::Rpm, "1.0a", "1.0", Greater),
+            (VersionScheme::Rpm, "1.0.a", "1.0.1", Less),
+            (VersionScheme::Rpm, "1.0_1", "1.0.1", Equal),
+            (VersionScheme::Rpm, "2.4-1.el9", "2.4-1.el8", Greater),
+        ] {
+            assert_eq!(scheme.compare(a, b), expected, "{scheme:?}: {a} vs {b}");
+        }
+
+        let constraint = |s: &str| s.parse::<VersionConstraint>();
+        let dpkg = VersionScheme::Dpkg;
+        assert!(constraint("1.2.*")?.matches("1.2.7-1ubuntu1", dpkg));
+        assert!(constraint("1.2.*")?.matches("2:1.2.7-1", dpkg));
+        assert!(!constraint("1.2.*")?.matches("1.20.1-1", dpkg));
+        assert!(constraint("1.2.3")?.matches("1.2.3-4", dpkg));
+        assert!(!constraint("1.2.3-1")?.matches("1.2.3-4", dpkg));
+        assert!(constraint(">= 1.2")?.matches("1.10", dpkg));
+        assert!(!constraint(">=1.2")?.matches("1.2~beta", dpkg));
+        assert_eq!(constraint(">=1.2")?.to_string(), ">= 1.2");
+
+        let Err(e) = constraint("latest!") else {
+            return Err(anyhow!("Invalid versions should fail"));
+        };
+        assert!(e.to_string().starts_with("Invalid Package ensure: latest!"));
+        Ok(())
+    }
+}
@@ -0,0 +1,236 @@
+//! Extended attributes and Linux capabilities of managed files, read and set with
+//! `getfattr`/`setfattr` and `getcap`/`setcap`.
+
+use super::resource::PropertyChange;
+use crate::parser::pp::{AttrValue, Attribute};
+use crate::parser::value::FromValue;
+use crate::system::Processes;
+use anyhow::{Context, Result, anyhow};
+use indexmap::IndexMap;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::process::{Command, Output};
+use std::str::FromStr;
+
+/// File capabilities, `cap_net_bind_service=ep`: the flags of every capability.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileCapabilities(BTreeMap<String, String>);
+
+/// Parses the `setcap` notation, clauses of capabilities, `=` or `+` and flags separated
+/// by spaces; `none` or nothing is no capability.
+impl FromStr for FileCapabilities {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut capabilities = BTreeMap::new();
+        for clause in s.split_whitespace().filter(|clause| *clause != "none") {
+            let invalid = || anyhow!("must be capabilities like cap_net_raw=ep, got {s}");
+            let (names, flags) = clause.split_once(['=', '+']).ok_or_else(invalid)?;
+            let mut flags: Vec<char> = flags.to_lowercase().chars().collect();
+            if names.is_empty() || !flags.iter().all(|flag| "eip".contains(*flag)) {
+                return Err(invalid());
+            }
+            flags.sort_unstable();
+            flags.dedup();
+            for name in names.split(',') {
+                capabilities.insert(name.to_lowercase(), flags.iter().collect());
+            }
+        }
+        Ok(Self(capabilities))
+    }
+}
+
+impl FromValue for FileCapabilities {
+    fn from_value(value: &AttrValue) -> Result<Self> {
+        String::from_value(value)?.parse()
+    }
+}
+
+/// Capabilities with the same flags are grouped, as `getcap` prints them.
+impl fmt::Display for FileCapabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut by_flags: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for (name, flags) in &self.0 {
+            by_flags.entry(flags).or_default().push(name);
+        }
+        if by_flags.is_empty() {
+            return write!(f, "none");
+        }
+        let clauses: Vec<_> = by_flags
+            .iter()
+            .map(|(flags, names)| format!("{}={flags}", names.join(",")))
+            .collect();
+        write!(f, "{}", clauses.join(" "))
+    }
+}
+
+/// The extended attributes and capabilities a File manages. Attributes the manifest does
+/// not name are left alone.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct XattrSpec {
+    /// Values by attribute name, `user.origin`.
+    pub xattrs: IndexMap<String, String>,
+    pub capabilities: Option<FileCapabilities>,
+}
+
+impl XattrSpec {
+    /// Reads `xattrs` and `capabilities`, ignoring the other attributes.
+    pub fn from_attributes(attributes: &[Attribute]) -> Result<Self> {
+        let mut spec = Self::default();
+        for attr in attributes {
+            match attr.name.as_str() {
+                "xattrs" => spec.xattrs = attr.parse_as("File")?,
+                "capabilities" => spec.capabilities = attr.parse_as("File")?,
+                _ => {}
+            }
+        }
+        Ok(spec)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.xattrs.is_empty() && self.capabilities.is_none()
+    }
+
+    /// The managed attributes and capabilities of `path` that differ.
+    pub fn changes(&self, processes: &dyn Processes, path: &Path) -> Result<Vec<PropertyChange>> {
+        let mut changes = vec![];
+        let (mut current, mut desired) = (vec![], vec![]);
+        for (name, value) in &self.xattrs {
+            let found = xattr(processes, path, name)?;
+            if found.as_ref() != Some(value) {
+                current.push(format!("{name}={}", found.as_deref().unwrap_or("(unset)")));
+                desired.push(format!("{name}={value}"));
+            }
+        }
+        if !desired.is_empty() {
+            changes.push(PropertyChange::new(
+                "xattrs",
+                Some(current.join(" ")),
+                desired.join(" "),
+            ));
+        }
+        if let Some(capabilities) = &self.capabilities {
+            let found = file_capabilities(processes, path)?;
+            if found != *capabilities {
+                changes.push(PropertyChange::new(
+                    "capabilities",
+                    Some(found),
+                    capabilities,
+                ));
+            }
+        }
+        Ok(changes)
+    }
+
+    pub fn apply_xattrs(&self, processes: &dyn Processes, path: &Path) -> Result<()> {
+        for (name, value) in &self.xattrs {
+            let output = run(
+                processes,
+                Command::new("setfattr").args(["-n", name, "-v", value]),
+                path,
+            )?;
+            if !output.status.success() {
+                return Err(failure("set", name, path, &output));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn apply_capabilities(&self, processes: &dyn Processes, path: &Path) -> Result<()> {
+        let Some(capabilities) = &self.capabilities else {
+            return Ok(());
+        };
+        let output = match capabilities.0.is_empty() {
+            true => run(processes, Command::new("setcap").arg("-r"), path)?,
+            false => run(
+                processes,
+                Command::new("setcap").arg(capabilities.to_string()),
+                path,
+            )?,
+        };
+        if !output.status.success() {
+            return Err(failure("set", "capabilities", path, &output));
+        }
+        Ok(())
+    }
+}
+
+fn run(processes: &dyn Processes, command: &mut Command, path: &Path) -> Result<Output> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    processes
+        .output(command.arg(path))
+        .with_context(|| format!("{program} is needed to manage {}", path.display()))
+}
+
+fn failure(action: &str, what: &str, path: &Path, output: &Output) -> anyhow::Error {
+    anyhow!(
+        "Cannot {action} {what} of {}: {}",
+        path.display(),
+        String::from_utf8_lossy(&output.stderr).trim()
+    )
+}
+
+/// The value of the extended attribute `name` of `path`, if it has one.
+fn xattr(processes: &dyn Processes, path: &Path, name: &str) -> Result<Option<String>> {
+    let output = run(
+        processes,
+        Command::new("getfattr").args(["--only-values", "--absolute-names", "-n", name]),
+        path,
+    )?;
+    if output.status.success() {
+        return Ok(Some(String::from_utf8_lossy(&output.stdout).into_owned()));
+    }
+    match String::from_utf8_lossy(&output.stderr).contains("No such attribute") {
+        true => Ok(None),
+        false => Err(failure("read", name, path, &output)),
+    }
+}
+
+/// The capabilities of `path`, from `getcap` printing `path caps` or, in older versions,
+/// `path = caps`, and nothing without any.
+fn file_capabilities(processes: &dyn Processes, path: &Path) -> Result<FileCapabilities> {
+    let output = run(processes, &mut Command::new("getcap"), path)?;
+    if !output.status.success() {
+        return Err(failure("read", "capabilities", path, &output));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let found = stdout
+        .trim()
+        .strip_prefix(&*path.to_string_lossy())
+        .unwrap_or_default()
+        .trim_start()
+        .trim_start_matches("= ");
+    found.parse()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capabilities_notation() -> Result<()> {
+        let capabilities: FileCapabilities = "cap_net_raw+ep CAP_NET_BIND_SERVICE=pe".parse()?;
+        assert_eq!(
+            capabilities.to_string(),
+            "cap_net_bind_service,cap_net_raw=ep"
+        );
+        assert_eq!(
+            capabilities,
+            "cap_net_bind_service,cap_net_raw=ep".parse()?,
+            "Capabilities compare whatever their notation"
+        );
+        assert_eq!("none".parse::<FileCapabilities>()?.to_string(), "none");
+        assert_eq!("".parse::<FileCapabilities>()?, FileCapabilities::default());
+        let Err(e) = "cap_net_raw".parse::<FileCapabilities>() else {
+            return Err(anyhow!("Capabilities without flags should fail"));
+        };
+        assert_eq!(
+            e.to_string(),
+            "must be capabilities like cap_net_raw=ep, got cap_net_raw"
+        );
+        assert!("cap_net_raw=epx".parse::<FileCapabilities>().is_err());
+        assert!(XattrSpec::default().is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+//! Usage counters for platforms embedding dolly: how many manifests were parsed and how
+//! many resources of each type were compiled since the process started. Dolly only counts;
+//! embedders poll a [`Usage`] snapshot and report it however they like:
+//!
+//! ```
+//! let usage = dolly::stats::global().snapshot();
+//! println!("{} resources compiled", usage.resources_compiled);
+//! ```
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock, PoisonError};
+
+/// Counters updated while parsing and compiling. Cheap enough to leave on.
+#[derive(Debug, Default)]
+pub struct Stats {
+    manifests_parsed: AtomicU64,
+    resources_compiled: AtomicU64,
+    types: Mutex<BTreeMap<String, u64>>,
+}
+
+/// The counters at one point in time.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Usage {
+    /// Puppet sources parsed successfully, each imported file counting once.
+    pub manifests_parsed: u64,
+    /// Resources added to a plan.
+    pub resources_compiled: u64,
+    /// The resources compiled, by type.
+    pub types: BTreeMap<String, u64>,
+}
+
+impl Stats {
+    pub(crate) fn manifest_parsed(&self) {
+        self.manifests_parsed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn resource_compiled(&self, rtype: &str) {
+        self.resources_compiled.fetch_add(1, Ordering::Relaxed);
+        let mut types = self.types.lock().unwrap_or_else(PoisonError::into_inner);
+        *types.entry(rtype.to_string()).or_default() += 1;
+    }
+
+    pub fn snapshot(&self) -> Usage {
+        Usage {
+            manifests_parsed: self.manifests_parsed.load(Ordering::Relaxed),
+            resources_compiled: self.resources_compiled.load(Ordering::Relaxed),
+            types: self
+                .types
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone(),
+        }
+    }
+}
+
+/// The counters of this process.
+pub fn global() -> &'static Stats {
+    static STATS: OnceLock<Stats> = OnceLock::new();
+    STATS.get_or_init(Stats::default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_puppet_manifest;
+    use crate::parser::pp::Manifest;
+    use anyhow::Result;
+
+    #[test]
+    fn test_parsing_and_compiling_are_counted() -> Result<()> {
+        let before = global().snapshot();
+        let manifest: Manifest = r#"
+            define stats::probe() {}
+            stats::probe { "a": }
+            stats::probe { "b": }
+            file { "/a": }
+        "#
+        .parse()?;
+        parse_puppet_manifest(&manifest)?;
+        let after = global().snapshot();
+
+        // Other tests count concurrently, so only a lower bound holds for shared types.
+        assert!(after.manifests_parsed > before.manifests_parsed);
+        assert!(after.resources_compiled >= before.resources_compiled + 3);
+        let probes = |usage: &Usage| usage.types.get("Stats::Probe").copied().unwrap_or(0);
+        assert_eq!(probes(&after) - probes(&before), 2);
+        Ok(())
+    }
+}
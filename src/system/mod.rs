@@ -0,0 +1,395 @@
+//! The clock, filesystem and processes dolly acts through. Providers and the apply engine
+//! take them from a [`System`], the host's by default, so that tests can substitute the
+//! fakes of [`crate::testing`] and run the apply pipeline without touching the machine.
+
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Cursor, Read, Write};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// The current time, and waiting.
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now(&self) -> SystemTime;
+    fn sleep(&self, duration: Duration);
+}
+
+/// What a path is, as `lstat` sees it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    File,
+    Directory,
+    Symlink,
+}
+
+/// The metadata of a path that dolly manages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    pub file_type: FileType,
+    /// The permission bits, `0o7777` at most.
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl Metadata {
+    pub fn is_dir(&self) -> bool {
+        self.file_type == FileType::Directory
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.file_type == FileType::Symlink
+    }
+}
+
+impl From<fs::Metadata> for Metadata {
+    fn from(metadata: fs::Metadata) -> Self {
+        let file_type = match metadata.file_type() {
+            t if t.is_symlink() => FileType::Symlink,
+            t if t.is_dir() => FileType::Directory,
+            _ => FileType::File,
+        };
+        Self {
+            file_type,
+            mode: metadata.mode() & 0o7777,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+        }
+    }
+}
+
+/// The files dolly manages and keeps its own state in, such as checkpoints.
+pub trait FileSystem: fmt::Debug + Send + Sync {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()>;
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+    /// Writes a file that must not exist yet, synced to disk before returning.
+    fn create_new(&self, path: &Path, contents: &[u8]) -> io::Result<()>;
+    /// The metadata of `path`, following symlinks.
+    fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+    /// The metadata of `path` itself, even if it is a symlink.
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata>;
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf>;
+    /// Creates a symlink at `path` pointing to `target`.
+    fn symlink(&self, target: &Path, path: &Path) -> io::Result<()>;
+    fn create_dir(&self, path: &Path) -> io::Result<()>;
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+    fn remove_dir(&self, path: &Path) -> io::Result<()>;
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()>;
+    /// Sets the permission bits of `path`, following symlinks.
+    fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()>;
+    /// Changes the owner and group of `path`, following symlinks; `None` leaves one alone.
+    fn chown(&self, path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()>;
+    /// Like [`FileSystem::chown`], changing a symlink itself.
+    fn lchown(&self, path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()>;
+    /// Makes the entries of `dir`, such as a file renamed into it, durable.
+    fn sync_dir(&self, dir: &Path) -> io::Result<()>;
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        String::from_utf8(self.read(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Whether `path` exists, following symlinks.
+    fn exists(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok()
+    }
+}
+
+/// A command started by [`Processes::spawn`], its output read while it runs.
+pub struct Spawned {
+    pub stdout: Box<dyn Read + Send>,
+    pub stderr: Box<dyn Read + Send>,
+    pub process: Box<dyn Process>,
+}
+
+/// A running command.
+pub trait Process: Send {
+    /// Waits for the command to exit, killing it with every process it started and failing
+    /// with [`io::ErrorKind::TimedOut`] once `deadline` has passed.
+    fn wait_until(&mut self, deadline: Option<Instant>) -> io::Result<ExitStatus>;
+}
+
+/// A command that has already exited.
+struct Exited(ExitStatus);
+
+impl Process for Exited {
+    fn wait_until(&mut self, _deadline: Option<Instant>) -> io::Result<ExitStatus> {
+        Ok(self.0)
+    }
+}
+
+/// Runs the commands providers inspect and change the system with.
+pub trait Processes: fmt::Debug + Send + Sync {
+    /// Runs `command` to completion, capturing its output.
+    fn output(&self, command: &mut Command) -> io::Result<Output>;
+
+    /// Like [`Processes::output`], killing the command and failing with
+    /// [`io::ErrorKind::TimedOut`] once `deadline` has passed.
+    fn output_until(&self, command: &mut Command, deadline: Option<Instant>) -> io::Result<Output> {
+        let _ = deadline;
+        self.output(command)
+    }
+
+    /// Starts `command` in a process group of its own, with its output piped. By default
+    /// it runs to completion first and its output is replayed.
+    fn spawn(&self, command: &mut Command) -> io::Result<Spawned> {
+        let output = self.output(command)?;
+        Ok(Spawned {
+            stdout: Box::new(Cursor::new(output.stdout)),
+            stderr: Box::new(Cursor::new(output.stderr)),
+            process: Box::new(Exited(output.status)),
+        })
+    }
+}
+
+/// The host's clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HostClock;
+
+impl Clock for HostClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration)
+    }
+}
+
+/// The host's filesystem.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HostFileSystem;
+
+impl FileSystem for HostFileSystem {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        fs::write(path, contents)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn create_new(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+        file.write_all(contents)?;
+        file.sync_all()
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        fs::metadata(path).map(Metadata::from)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        fs::symlink_metadata(path).map(Metadata::from)
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::read_link(path)
+    }
+
+    fn symlink(&self, target: &Path, path: &Path) -> io::Result<()> {
+        std::os::unix::fs::symlink(target, path)
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir_all(path)
+    }
+
+    fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()> {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))
+    }
+
+    fn chown(&self, path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+        std::os::unix::fs::chown(path, uid, gid)
+    }
+
+    fn lchown(&self, path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+        std::os::unix::fs::lchown(path, uid, gid)
+    }
+
+    fn sync_dir(&self, dir: &Path) -> io::Result<()> {
+        fs::File::open(dir)?.sync_all()
+    }
+}
+
+/// Runs commands on the host.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HostProcesses;
+
+impl Processes for HostProcesses {
+    fn output(&self, command: &mut Command) -> io::Result<Output> {
+        command.output()
+    }
+
+    fn output_until(&self, command: &mut Command, deadline: Option<Instant>) -> io::Result<Output> {
+        let Some(deadline) = deadline else {
+            return command.output();
+        };
+        let mut child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let (stdout, stderr) = (child.stdout.take(), child.stderr.take());
+        thread::scope(|scope| {
+            let stdout = scope.spawn(move || read_all(stdout));
+            let stderr = scope.spawn(move || read_all(stderr));
+            let status = loop {
+                if let Some(status) = child.try_wait()? {
+                    break status;
+                }
+                if Instant::now() >= deadline {
+                    // Killing the command closes its pipes, unless a child it left behind
+                    // holds them.
+                    child.kill()?;
+                    child.wait()?;
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "timed out"));
+                }
+                thread::sleep(Duration::from_millis(10));
+            };
+            let joined = |reader: thread::ScopedJoinHandle<'_, io::Result<Vec<u8>>>| {
+                reader
+                    .join()
+                    .map_err(|_| io::Error::other("reading the output panicked"))?
+            };
+            Ok(Output {
+                status,
+                stdout: joined(stdout)?,
+                stderr: joined(stderr)?,
+            })
+        })
+    }
+
+    fn spawn(&self, command: &mut Command) -> io::Result<Spawned> {
+        let mut child = command
+            .process_group(0)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let (Some(stdout), Some(stderr)) = (child.stdout.take(), child.stderr.take()) else {
+            return Err(io::Error::other("the output is not piped"));
+        };
+        Ok(Spawned {
+            stdout: Box::new(stdout),
+            stderr: Box::new(stderr),
+            process: Box::new(HostProcess(child)),
+        })
+    }
+}
+
+/// A command running on the host, in a process group of its own.
+struct HostProcess(Child);
+
+impl Process for HostProcess {
+    fn wait_until(&mut self, deadline: Option<Instant>) -> io::Result<ExitStatus> {
+        let Some(deadline) = deadline else {
+            return self.0.wait();
+        };
+        loop {
+            if let Some(status) = self.0.try_wait()? {
+                return Ok(status);
+            }
+            if Instant::now() >= deadline {
+                // Kill the whole process group so that no grandchild keeps the output
+                // pipes open.
+                let group = format!("-{}", self.0.id());
+                Command::new("kill")
+                    .args(["-KILL", "--", &group])
+                    .status()?;
+                self.0.wait()?;
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "timed out"));
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+fn read_all(pipe: Option<impl Read>) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![];
+    if let Some(mut pipe) = pipe {
+        pipe.read_to_end(&mut bytes)?;
+    }
+    Ok(bytes)
+}
+
+/// The clock, filesystem and processes to act through.
+#[derive(Debug, Clone)]
+pub struct System {
+    pub clock: Arc<dyn Clock>,
+    pub fs: Arc<dyn FileSystem>,
+    pub processes: Arc<dyn Processes>,
+}
+
+impl Default for System {
+    /// The host's.
+    fn default() -> Self {
+        Self {
+            clock: Arc::new(HostClock),
+            fs: Arc::new(HostFileSystem),
+            processes: Arc::new(HostProcesses),
+        }
+    }
+}
+
+/// `program arg...`, as fakes match commands and errors name them.
+pub fn command_line(command: &Command) -> String {
+    let mut line = command.get_program().to_string_lossy().into_owned();
+    for arg in command.get_args() {
+        line.push(' ');
+        line.push_str(&arg.to_string_lossy());
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_until_kills_the_command() -> io::Result<()> {
+        let output = HostProcesses.output_until(
+            Command::new("echo").arg("quick"),
+            Instant::now().checked_add(Duration::from_secs(5)),
+        )?;
+        assert_eq!(output.stdout, b"quick\n");
+
+        let started = Instant::now();
+        let Err(e) = HostProcesses.output_until(
+            Command::new("sleep").arg("5"),
+            Instant::now().checked_add(Duration::from_millis(100)),
+        ) else {
+            return Err(io::Error::other("sleep 5 should time out"));
+        };
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(4));
+        Ok(())
+    }
+}
@@ -0,0 +1,543 @@
+//! In-memory fakes of the [`System`] dolly acts through, for tests of providers and of
+//! whole apply runs, here and in crates embedding dolly:
+//!
+//! ```
+//! # use dolly::testing::FakeSystem;
+//! let fake = FakeSystem::default();
+//! fake.processes.respond("systemctl is-active", 3, "inactive\n");
+//! let system = fake.system();
+//! ```
+
+use crate::system::{Clock, FileSystem, FileType, Metadata, Processes, System, command_line};
+use std::collections::BTreeMap;
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Output};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// A clock that only moves when told to, or when slept on.
+#[derive(Debug)]
+pub struct FakeClock(Mutex<SystemTime>);
+
+impl FakeClock {
+    pub fn at(time: SystemTime) -> Self {
+        Self(Mutex::new(time))
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *lock(&self.0) += duration;
+    }
+}
+
+impl Default for FakeClock {
+    /// 2024-01-01T00:00:00Z.
+    fn default() -> Self {
+        Self::at(UNIX_EPOCH + Duration::from_secs(1_704_067_200))
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> SystemTime {
+        *lock(&self.0)
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration)
+    }
+}
+
+/// What a path holds in a [`MemoryFileSystem`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    File(Vec<u8>),
+    Directory,
+    Symlink(PathBuf),
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    node: Node,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+}
+
+impl Entry {
+    /// Owned by root, with the permissions a umask of `022` leaves.
+    fn new(node: Node) -> Self {
+        let mode = match node {
+            Node::File(_) => 0o644,
+            Node::Directory => 0o755,
+            Node::Symlink(_) => 0o777,
+        };
+        Self {
+            node,
+            mode,
+            uid: 0,
+            gid: 0,
+        }
+    }
+
+    fn metadata(&self) -> Metadata {
+        let file_type = match self.node {
+            Node::File(_) => FileType::File,
+            Node::Directory => FileType::Directory,
+            Node::Symlink(_) => FileType::Symlink,
+        };
+        Metadata {
+            file_type,
+            mode: self.mode,
+            uid: self.uid,
+            gid: self.gid,
+        }
+    }
+}
+
+type Entries = BTreeMap<PathBuf, Entry>;
+
+/// Files, directories and symlinks kept in memory, by path. Directories are also implied
+/// by the paths under them.
+#[derive(Debug, Default)]
+pub struct MemoryFileSystem(Mutex<Entries>);
+
+impl MemoryFileSystem {
+    /// Every path, in order.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        lock(&self.0).keys().cloned().collect()
+    }
+
+    /// The contents of the file at `path` itself, not of what a symlink points to.
+    pub fn contents(&self, path: &Path) -> Option<Vec<u8>> {
+        match lock(&self.0).get(path) {
+            Some(Entry {
+                node: Node::File(contents),
+                ..
+            }) => Some(contents.clone()),
+            _ => None,
+        }
+    }
+}
+
+fn error(kind: io::ErrorKind, path: &Path) -> io::Error {
+    io::Error::new(kind, format!("{}: {kind}", path.display()))
+}
+
+fn not_found(path: &Path) -> io::Error {
+    error(io::ErrorKind::NotFound, path)
+}
+
+fn has_children(entries: &Entries, path: &Path) -> bool {
+    entries
+        .range(path.to_path_buf()..)
+        .find(|(key, _)| key.as_path() != path)
+        .is_some_and(|(key, _)| key.starts_with(path))
+}
+
+/// The entry at `path` itself, or an implied directory.
+fn lookup(entries: &Entries, path: &Path) -> Option<Entry> {
+    match entries.get(path) {
+        Some(entry) => Some(entry.clone()),
+        None => has_children(entries, path).then(|| Entry::new(Node::Directory)),
+    }
+}
+
+/// `path` with its symlinks followed, relative targets from the link's directory.
+fn resolve(entries: &Entries, path: &Path) -> io::Result<PathBuf> {
+    let mut path = path.to_path_buf();
+    for _ in 0..40 {
+        let Some(Entry {
+            node: Node::Symlink(target),
+            ..
+        }) = entries.get(&path)
+        else {
+            return Ok(path);
+        };
+        path = match path.parent() {
+            Some(dir) => dir.join(target),
+            None => target.clone(),
+        };
+    }
+    Err(io::Error::other(format!(
+        "{}: too many levels of symbolic links",
+        path.display()
+    )))
+}
+
+/// The entry at `path`, an implied directory made explicit so that it can be changed.
+fn entry_mut<'a>(entries: &'a mut Entries, path: &Path) -> io::Result<&'a mut Entry> {
+    let entry = lookup(entries, path).ok_or_else(|| not_found(path))?;
+    Ok(entries.entry(path.to_path_buf()).or_insert(entry))
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let entries = lock(&self.0);
+        let path = resolve(&entries, path)?;
+        match lookup(&entries, &path).map(|entry| entry.node) {
+            Some(Node::File(contents)) => Ok(contents),
+            Some(_) => Err(error(io::ErrorKind::IsADirectory, &path)),
+            None => Err(not_found(&path)),
+        }
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let mut entries = lock(&self.0);
+        let path = resolve(&entries, path)?;
+        match lookup(&entries, &path) {
+            Some(Entry {
+                node: Node::Directory,
+                ..
+            }) => Err(error(io::ErrorKind::IsADirectory, &path)),
+            existing => {
+                let node = Node::File(contents.to_vec());
+                let entry = match existing {
+                    Some(existing) => Entry { node, ..existing },
+                    None => Entry::new(node),
+                };
+                entries.insert(path, entry);
+                Ok(())
+            }
+        }
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut entries = lock(&self.0);
+        let entry = entries.remove(from).ok_or_else(|| not_found(from))?;
+        entries.insert(to.to_path_buf(), entry);
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let mut entries = lock(&self.0);
+        match lookup(&entries, path).map(|entry| entry.node) {
+            Some(Node::Directory) => Err(error(io::ErrorKind::IsADirectory, path)),
+            Some(_) => {
+                entries.remove(path);
+                Ok(())
+            }
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn create_new(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let mut entries = lock(&self.0);
+        if lookup(&entries, path).is_some() {
+            return Err(error(io::ErrorKind::AlreadyExists, path));
+        }
+        entries.insert(
+            path.to_path_buf(),
+            Entry::new(Node::File(contents.to_vec())),
+        );
+        Ok(())
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let entries = lock(&self.0);
+        let path = resolve(&entries, path)?;
+        lookup(&entries, &path)
+            .map(|entry| entry.metadata())
+            .ok_or_else(|| not_found(&path))
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        lookup(&lock(&self.0), path)
+            .map(|entry| entry.metadata())
+            .ok_or_else(|| not_found(path))
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        match lookup(&lock(&self.0), path).map(|entry| entry.node) {
+            Some(Node::Symlink(target)) => Ok(target),
+            Some(_) => Err(error(io::ErrorKind::InvalidInput, path)),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn symlink(&self, target: &Path, path: &Path) -> io::Result<()> {
+        let mut entries = lock(&self.0);
+        if lookup(&entries, path).is_some() {
+            return Err(error(io::ErrorKind::AlreadyExists, path));
+        }
+        let link = Entry::new(Node::Symlink(target.to_path_buf()));
+        entries.insert(path.to_path_buf(), link);
+        Ok(())
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        let mut entries = lock(&self.0);
+        if lookup(&entries, path).is_some() {
+            return Err(error(io::ErrorKind::AlreadyExists, path));
+        }
+        entries.insert(path.to_path_buf(), Entry::new(Node::Directory));
+        Ok(())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut entries = lock(&self.0);
+        for dir in path.ancestors().filter(|dir| dir.parent().is_some()) {
+            match lookup(&entries, dir).map(|entry| entry.node) {
+                Some(Node::Directory) => {}
+                Some(_) => return Err(error(io::ErrorKind::NotADirectory, dir)),
+                None => {
+                    entries.insert(dir.to_path_buf(), Entry::new(Node::Directory));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        let mut entries = lock(&self.0);
+        match lookup(&entries, path).map(|entry| entry.node) {
+            Some(Node::Directory) if has_children(&entries, path) => {
+                Err(error(io::ErrorKind::DirectoryNotEmpty, path))
+            }
+            Some(Node::Directory) => {
+                entries.remove(path);
+                Ok(())
+            }
+            Some(_) => Err(error(io::ErrorKind::NotADirectory, path)),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut entries = lock(&self.0);
+        if lookup(&entries, path).is_none() {
+            return Err(not_found(path));
+        }
+        entries.retain(|key, _| !key.starts_with(path));
+        Ok(())
+    }
+
+    fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()> {
+        let mut entries = lock(&self.0);
+        let path = resolve(&entries, path)?;
+        entry_mut(&mut entries, &path)?.mode = mode & 0o7777;
+        Ok(())
+    }
+
+    fn chown(&self, path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+        let path = resolve(&lock(&self.0), path)?;
+        self.lchown(&path, uid, gid)
+    }
+
+    fn lchown(&self, path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+        let mut entries = lock(&self.0);
+        let entry = entry_mut(&mut entries, path)?;
+        entry.uid = uid.unwrap_or(entry.uid);
+        entry.gid = gid.unwrap_or(entry.gid);
+        Ok(())
+    }
+
+    fn sync_dir(&self, _dir: &Path) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The output of a command exiting with `code` after printing `stdout`.
+pub fn output(code: i32, stdout: &str) -> Output {
+    Output {
+        status: ExitStatus::from_raw(code << 8),
+        stdout: stdout.as_bytes().to_vec(),
+        stderr: match code {
+            0 => vec![],
+            code => format!("exit status {code}").into_bytes(),
+        },
+    }
+}
+
+/// Commands that are recorded instead of run. Each answers with the response of the
+/// longest prefix of its command line that has one, or succeeds silently.
+#[derive(Debug, Default)]
+pub struct FakeProcesses {
+    responses: Mutex<Vec<(String, Output)>>,
+    calls: Mutex<Vec<String>>,
+}
+
+impl FakeProcesses {
+    /// Commands starting with `prefix`, e.g. `systemctl is-active`, exit with `code` after
+    /// printing `stdout`.
+    pub fn respond(&self, prefix: &str, code: i32, stdout: &str) {
+        lock(&self.responses).push((prefix.to_string(), output(code, stdout)));
+    }
+
+    /// The command lines run so far, in order.
+    pub fn calls(&self) -> Vec<String> {
+        lock(&self.calls).clone()
+    }
+}
+
+impl Processes for FakeProcesses {
+    fn output(&self, command: &mut Command) -> io::Result<Output> {
+        let line = command_line(command);
+        lock(&self.calls).push(line.clone());
+        Ok(lock(&self.responses)
+            .iter()
+            .filter(|(prefix, _)| line.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or_else(|| output(0, ""), |(_, output)| output.clone()))
+    }
+}
+
+/// A fake clock, filesystem and processes, kept at hand to script and inspect them.
+#[derive(Debug, Clone, Default)]
+pub struct FakeSystem {
+    pub clock: Arc<FakeClock>,
+    pub fs: Arc<MemoryFileSystem>,
+    pub processes: Arc<FakeProcesses>,
+}
+
+impl FakeSystem {
+    /// The system to hand to providers and the apply engine.
+    pub fn system(&self) -> System {
+        System {
+            clock: self.clock.clone(),
+            fs: self.fs.clone(),
+            processes: self.processes.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::ApplyOptions;
+    use crate::parse_puppet_manifest_with_registry;
+    use crate::parser::pp::Manifest;
+    use crate::resources::ResourceRegistry;
+    use anyhow::Result;
+
+    #[test]
+    fn test_apply_runs_against_fakes() -> Result<()> {
+        let fake = FakeSystem::default();
+        fake.processes
+            .respond("dpkg-query", 0, "install ok installed\t1.24.0-1");
+        fake.processes
+            .respond("systemctl is-active", 3, "inactive\n");
+        fake.processes
+            .respond("systemctl is-enabled", 1, "disabled\n");
+        fake.processes.respond("systemctl enable", 1, "");
+        let manifest: Manifest = r#"
+            package { "nginx": provider => apt }
+            service { "nginx":
+                ensure  => running,
+                enable  => true,
+                require => Package["nginx"],
+            }
+        "#
+        .parse()?;
+        let registry = ResourceRegistry::with_system(&fake.system());
+        let plan = parse_puppet_manifest_with_registry(&manifest, &registry)?;
+        let options = ApplyOptions {
+            system: fake.system(),
+            ..ApplyOptions::default()
+        };
+        let checkpoint = Path::new("/var/lib/dolly/checkpoint.json");
+        let report = plan.apply_with_checkpoint(options, checkpoint, false)?;
+
+        assert!(report.failed(), "{report}");
+        assert_eq!(
+            fake.processes.calls(),
+            [
+                "dpkg-query -W -f ${Status}\t${Version} nginx",
+                "systemctl is-active --quiet nginx",
+                "systemctl is-enabled nginx",
+                "systemctl is-active --quiet nginx",
+                "systemctl is-enabled nginx",
+                "systemctl start nginx",
+                "systemctl enable nginx",
+            ]
+        );
+        assert_eq!(
+            fake.fs.paths(),
+            [checkpoint],
+            "The checkpoint of the failed run is kept, in memory only"
+        );
+        let saved = String::from_utf8(fake.fs.contents(checkpoint).unwrap_or_default())?;
+        assert!(saved.contains("Package[nginx]"), "{saved}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_files_and_execs_apply_against_fakes() -> Result<()> {
+        let fake = FakeSystem::default();
+        let done = Path::new("/srv/dolly-fake/migrated");
+        fake.fs.write(done, b"")?;
+        fake.processes.respond("/bin/sh", 0, "reloaded\n");
+        let manifest: Manifest = r#"
+            file { "/srv/dolly-fake/app": ensure => directory, mode => "0750" }
+            file { "/srv/dolly-fake/app/app.conf":
+                content => "port = 80",
+                mode    => 0600,
+                require => File["/srv/dolly-fake/app"],
+            }
+            file { "/srv/dolly-fake/app/current":
+                ensure  => link,
+                target  => "app.conf",
+                require => File["/srv/dolly-fake/app/app.conf"],
+            }
+            exec { "migrate":
+                command => "/usr/bin/app --migrate",
+                creates => "/srv/dolly-fake/migrated",
+            }
+            exec { "reload":
+                command   => "/usr/bin/app --reload",
+                subscribe => File["/srv/dolly-fake/app/app.conf"],
+            }
+        "#
+        .parse()?;
+        let registry = ResourceRegistry::with_system(&fake.system());
+        let plan = parse_puppet_manifest_with_registry(&manifest, &registry)?;
+        let options = || ApplyOptions {
+            system: fake.system(),
+            ..ApplyOptions::default()
+        };
+        let report = plan.apply(options())?;
+
+        assert!(!report.failed(), "{report}");
+        let conf = Path::new("/srv/dolly-fake/app/app.conf");
+        assert_eq!(fake.fs.contents(conf), Some(b"port = 80".to_vec()));
+        assert_eq!(fake.fs.metadata(conf)?.mode, 0o600);
+        let app = Path::new("/srv/dolly-fake/app");
+        assert_eq!(fake.fs.metadata(app)?.mode, 0o750);
+        assert!(fake.fs.metadata(app)?.is_dir());
+        let current = Path::new("/srv/dolly-fake/app/current");
+        assert_eq!(fake.fs.read_link(current)?, Path::new("app.conf"));
+        assert_eq!(
+            fake.fs.read(current)?,
+            b"port = 80",
+            "The link resolves in memory"
+        );
+        let shells: Vec<_> = fake
+            .processes
+            .calls()
+            .into_iter()
+            .filter(|call| call.starts_with("/bin/sh"))
+            .collect();
+        assert_eq!(
+            shells,
+            [r#"/bin/sh -c exec /bin/sh -c "$1" sh /usr/bin/app --reload"#],
+            "The exec whose file exists is skipped, the other runs once"
+        );
+        assert!(!app.exists(), "Nothing is written to the host's filesystem");
+
+        let report = plan.apply(options())?;
+        assert_eq!(
+            report
+                .changes()
+                .iter()
+                .map(|(id, _)| *id)
+                .collect::<Vec<_>>(),
+            ["Exec[reload]"],
+            "The files are in sync, in memory"
+        );
+        Ok(())
+    }
+}
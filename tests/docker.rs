@@ -0,0 +1,109 @@
+//! Applies the manifests of `tests/golden` with the real providers, in a fresh container
+//! per distribution, twice: the first run must converge, the second must find nothing to
+//! change. What `probe.sh` then prints of the system must match the manifest's `.out`
+//! file. Needs docker, and a dolly binary the images can run:
+//!
+//! ```sh
+//! cargo test --features docker-tests --test docker
+//! ```
+//!
+//! `DOLLY_BLESS=1` rewrites the `.out` files with what the containers printed.
+
+#![cfg(feature = "docker-tests")]
+
+use anyhow::{Context, Result, ensure};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The manifests every distribution applies, by name in `tests/golden`.
+const SAMPLES: &[&str] = &["base"];
+
+struct Distro {
+    image: &'static str,
+    /// Run before dolly, so that the package manager can install anything.
+    setup: &'static str,
+}
+
+const DEBIAN: Distro = Distro {
+    image: "debian:bookworm",
+    setup: "apt-get update -q",
+};
+
+const FEDORA: Distro = Distro {
+    image: "fedora:40",
+    setup: "dnf makecache -q",
+};
+
+const ARCH: Distro = Distro {
+    image: "archlinux:latest",
+    setup: "pacman -Sy --noconfirm",
+};
+
+fn golden() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+/// What the container prints: the exit codes of both runs, then the probed state.
+fn converge(distro: &Distro, sample: &str) -> Result<String> {
+    let script = format!(
+        "{setup} >/dev/null\n\
+         cd /tmp\n\
+         first=0; dolly apply --detailed-exitcodes /golden/{sample}.pp >&2 || first=$?\n\
+         second=0; dolly apply --detailed-exitcodes /golden/{sample}.pp >&2 || second=$?\n\
+         echo \"first run: $first\"\n\
+         echo \"second run: $second\"\n\
+         sh /golden/probe.sh\n",
+        setup = distro.setup,
+    );
+    let output = Command::new("docker")
+        .args(["run", "--rm", "-v"])
+        .arg(format!(
+            "{}:/usr/local/bin/dolly:ro",
+            env!("CARGO_BIN_EXE_dolly")
+        ))
+        .arg("-v")
+        .arg(format!("{}:/golden:ro", golden().display()))
+        .args([distro.image, "sh", "-c", &script])
+        .output()
+        .context("Cannot run docker")?;
+    ensure!(
+        output.status.success(),
+        "{} failed on {}:\n{}",
+        sample,
+        distro.image,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn check(distro: &Distro) -> Result<()> {
+    for sample in SAMPLES {
+        let actual = converge(distro, sample)?;
+        let path = golden().join(format!("{sample}.out"));
+        if env::var_os("DOLLY_BLESS").is_some() {
+            fs::write(&path, &actual)?;
+            continue;
+        }
+        let expected =
+            fs::read_to_string(&path).with_context(|| format!("Cannot read {}", path.display()))?;
+        assert_eq!(actual, expected, "{sample} on {}", distro.image);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_debian_converges() -> Result<()> {
+    check(&DEBIAN)
+}
+
+#[test]
+fn test_fedora_converges() -> Result<()> {
+    check(&FEDORA)
+}
+
+#[test]
+fn test_arch_converges() -> Result<()> {
+    check(&ARCH)
+}
@@ -0,0 +1,35 @@
+group { "deploy":
+    gid => 2001,
+}
+
+user { "deploy":
+    uid        => 2001,
+    gid        => "deploy",
+    home       => "/home/deploy",
+    shell      => "/bin/sh",
+    comment    => "Deploy user",
+    managehome => true,
+    require    => Group["deploy"],
+}
+
+package { "tree": }
+
+file { "/etc/motd":
+    content => "Managed by dolly\n",
+    mode    => "0644",
+    owner   => "root",
+    group   => "root",
+}
+
+file { "/srv/app.conf":
+    content => "port = 8080\n",
+    mode    => "0640",
+    owner   => "deploy",
+    group   => "deploy",
+    require => User["deploy"],
+}
+
+exec { "touch /srv/initialized":
+    creates => "/srv/initialized",
+    require => File["/srv/app.conf"],
+}
@@ -0,0 +1,10 @@
+# Prints the state the golden manifests converge to, the same on every distribution.
+getent group deploy | cut -d: -f1,3
+getent passwd deploy | cut -d: -f1,3,4,5,6,7
+test -d /home/deploy && echo "home: present"
+command -v tree
+for file in /etc/motd /srv/app.conf; do
+    stat -c '%n %a %U:%G' "$file"
+    cat "$file"
+done
+test -e /srv/initialized && echo "initialized: present"